@@ -8,6 +8,4 @@ fn main() {
         "cargo:rustc-link-search=native={}",
         path.as_path().display()
     );
-
-    println!("cargo:rustc-link-lib=static=raylib");
 }
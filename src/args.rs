@@ -0,0 +1,266 @@
+// Command line of csvim:
+//
+//     csvim [OPTIONS] [+N | +/PATTERN] FILE...
+//     csvim convert IN OUT [OPTIONS]
+//
+// Each file is opened in a buffer of its own, `-` is the standard input. `+N` goes to row N
+// (`+` alone to the last one) and `+/PATTERN` to the first cell with PATTERN, once the rows
+// are read. Options take their value as the next argument or after `=`.
+//
+use csvim::cell::Inference;
+use csvim::convert::{self, ConvertError};
+use csvim::encoding::Encoding;
+use csvim::format::{Dialect, Format, parse_quote, parse_separator};
+
+pub const USAGE: &str = "\
+Usage: csvim [OPTIONS] [+N | +/PATTERN] FILE...
+       csvim convert IN OUT [--sheet NAME] [--format NAME] [--out-format NAME] ...
+
+Files can be csv, tsv (also gzip compressed), xlsx, xlsb, xls, ods, fods, SpreadsheetML 2003
+(.xml) or zip archives of csv files (ARCHIVE.zip[:ENTRY]). `-` reads csv from the standard
+input as it arrives, guessing the separator unless --sep is given. UTF-8 csv files from 64 MB
+on are read as they're shown, and opened read-only.
+
+Options:
+  --sep C          csv separator, `tab` for tabs (default from the extension)
+  --quote C        csv quote character, \" or '
+  --encoding NAME  encoding of csv files without a byte order mark
+                   (utf-8, utf-8-bom, utf-16le, utf-16be, latin1, windows-1252)
+  --sheet NAME     worksheet to open, or entry of a zip archive
+  --format NAME    read files as csv, tsv, xlsx, xlsb, xls, ods, fods, xml or zip
+  --no-header      the first row is data, not column names
+  --no-infer       read csv cells as text, without numbers, booleans and dates
+  --readonly       don't allow writing with :w
+  +N               go to row N
+  +/PATTERN        go to the first cell with PATTERN
+  -h, --help       show this help
+  -V, --version    show the version
+";
+
+#[derive(Debug, PartialEq)]
+pub enum Jump {
+    Row(usize),
+    Search(String),
+}
+
+#[derive(Debug)]
+pub struct Args {
+    pub files: Vec<String>,
+    pub dialect: Dialect,
+    pub sheet: Option<String>,
+    pub format: Option<Format>,
+    // Whether the first row has the names of the columns
+    pub header: bool,
+    pub readonly: bool,
+    pub inference: Inference,
+    pub jump: Option<Jump>,
+}
+
+#[derive(Debug)]
+pub enum Command {
+    Open(Args),
+    Convert(convert::Options),
+    Help,
+    Version,
+}
+
+#[derive(Debug)]
+pub enum ArgsError {
+    MissingValue(String),
+    InvalidValue(String, String),
+    UnknownOption(String),
+    NoFiles,
+    Convert(ConvertError),
+}
+
+impl std::fmt::Display for ArgsError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ArgsError::MissingValue(name) => write!(f, "{name} requires a value"),
+            ArgsError::InvalidValue(name, value) => write!(f, "invalid value for {name}: {value}"),
+            ArgsError::UnknownOption(name) => write!(f, "unknown option {name}"),
+            ArgsError::NoFiles => write!(f, "no file given"),
+            ArgsError::Convert(e) => write!(f, "{e}"),
+        }
+    }
+}
+
+impl From<ConvertError> for ArgsError {
+    fn from(e: ConvertError) -> Self {
+        ArgsError::Convert(e)
+    }
+}
+
+fn parse_jump(value: &str) -> Option<Jump> {
+    if value.is_empty() {
+        Some(Jump::Row(usize::MAX))
+    } else if let Some(pattern) = value.strip_prefix('/') {
+        Some(Jump::Search(pattern.to_string()))
+    } else {
+        value.parse().ok().map(Jump::Row)
+    }
+}
+
+// Reads the arguments without the program name
+pub fn parse(args: impl IntoIterator<Item = String>) -> Result<Command, ArgsError> {
+    let mut args = args.into_iter().peekable();
+
+    if args.peek().map(String::as_str) == Some("convert") {
+        args.next();
+
+        return Ok(Command::Convert(convert::parse_args(args)?));
+    }
+
+    let mut parsed = Args {
+        files: Vec::new(),
+        dialect: Dialect::default(),
+        sheet: None,
+        format: None,
+        header: true,
+        readonly: false,
+        inference: Inference::all(),
+        jump: None,
+    };
+
+    let mut only_files = false;
+
+    while let Some(arg) = args.next() {
+        if only_files || arg == "-" || !arg.starts_with(['-', '+']) {
+            parsed.files.push(arg);
+            continue;
+        }
+
+        if let Some(jump) = arg.strip_prefix('+') {
+            let jump = parse_jump(jump)
+                .ok_or_else(|| ArgsError::InvalidValue(String::from("+"), jump.to_string()))?;
+
+            parsed.jump = Some(jump);
+            continue;
+        }
+
+        match arg.as_str() {
+            "--" => only_files = true,
+            "-h" | "--help" => return Ok(Command::Help),
+            "-V" | "--version" => return Ok(Command::Version),
+            "--no-header" => parsed.header = false,
+            "--no-infer" => parsed.inference = Inference::none(),
+            "--readonly" => parsed.readonly = true,
+            _ => {
+                let (name, value) = match arg.split_once('=') {
+                    Some((name, value)) => (name.to_string(), Some(value.to_string())),
+                    None => (arg, None),
+                };
+
+                let value = match value {
+                    Some(value) => value,
+                    None if ["--sep", "--quote", "--encoding", "--sheet", "--format"]
+                        .contains(&name.as_str()) =>
+                    {
+                        args.next()
+                            .ok_or_else(|| ArgsError::MissingValue(name.clone()))?
+                    }
+                    None => return Err(ArgsError::UnknownOption(name)),
+                };
+
+                let invalid = || ArgsError::InvalidValue(name.clone(), value.clone());
+
+                match name.as_str() {
+                    "--sep" => {
+                        parsed.dialect.separator =
+                            Some(parse_separator(&value).ok_or_else(invalid)?)
+                    }
+                    "--quote" => {
+                        parsed.dialect.delimiter = parse_quote(&value).ok_or_else(invalid)?
+                    }
+                    "--encoding" => {
+                        parsed.dialect.encoding =
+                            Some(Encoding::from_label(&value).ok_or_else(invalid)?)
+                    }
+                    "--sheet" => parsed.sheet = Some(value.clone()),
+                    "--format" => {
+                        parsed.format = Some(Format::from_name(&value).ok_or_else(invalid)?)
+                    }
+                    _ => return Err(ArgsError::UnknownOption(name)),
+                }
+            }
+        }
+    }
+
+    if parsed.files.is_empty() {
+        return Err(ArgsError::NoFiles);
+    }
+
+    Ok(Command::Open(parsed))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use csvim::csv::Delimiter;
+
+    fn parse_strs(args: &[&str]) -> Result<Command, ArgsError> {
+        parse(args.iter().map(|arg| arg.to_string()))
+    }
+
+    #[test]
+    fn test_parse() {
+        let Ok(Command::Open(args)) = parse_strs(&[
+            "--sep=;",
+            "--quote",
+            "'",
+            "+42",
+            "a.csv",
+            "--encoding",
+            "latin1",
+            "-",
+            "--no-header",
+            "--readonly",
+            "--format=tsv",
+            "--",
+            "--sheet",
+        ]) else {
+            panic!("not parsed");
+        };
+
+        assert_eq!(args.files, ["a.csv", "-", "--sheet"]);
+        assert_eq!(args.dialect.separator, Some(';'));
+        assert!(matches!(args.dialect.delimiter, Delimiter::SingleQuote));
+        assert_eq!(args.dialect.encoding, Some(Encoding::Latin1));
+        assert_eq!(args.format, Some(Format::Tsv));
+        assert_eq!(args.jump, Some(Jump::Row(42)));
+        assert!(!args.header && args.readonly);
+
+        let Ok(Command::Open(args)) = parse_strs(&["+/total due", "book.xlsx", "--sheet", "Q1"])
+        else {
+            panic!("not parsed");
+        };
+
+        assert_eq!(args.jump, Some(Jump::Search(String::from("total due"))));
+        assert_eq!(args.sheet.as_deref(), Some("Q1"));
+        assert!(args.header);
+
+        assert!(matches!(parse_strs(&["a.csv", "-h"]), Ok(Command::Help)));
+        assert!(matches!(parse_strs(&["--version"]), Ok(Command::Version)));
+        assert!(matches!(
+            parse_strs(&["convert", "a.xlsx", "a.csv"]),
+            Ok(Command::Convert(_))
+        ));
+        assert!(matches!(parse_strs(&[]), Err(ArgsError::NoFiles)));
+        assert!(matches!(
+            parse_strs(&["a.csv", "--sep"]),
+            Err(ArgsError::MissingValue(_))
+        ));
+        assert!(matches!(
+            parse_strs(&["+4x", "a.csv"]),
+            Err(ArgsError::InvalidValue(..))
+        ));
+        assert!(matches!(
+            parse_strs(&["--colour", "a.csv"]),
+            Err(ArgsError::UnknownOption(_))
+        ));
+        assert!(matches!(
+            parse_strs(&["--readonly=yes", "a.csv"]),
+            Err(ArgsError::UnknownOption(_))
+        ));
+    }
+}
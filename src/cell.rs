@@ -0,0 +1,507 @@
+// Typed cell values shared by every reader and writer. Spreadsheet formats store numbers,
+// booleans, dates and errors as such, CSV has only text and the types are inferred from it.
+//
+// Dates follow the spreadsheet serial numbers: days since 1899-12-31 (or 1904-01-01 in the
+// 1904 date system) and the fraction of the day for the time.
+//
+use std::collections::HashMap;
+use std::sync::Arc;
+
+/// A cell value, as a spreadsheet stores it or as inferred from csv text
+#[derive(Clone, Debug, PartialEq)]
+pub enum Cell {
+    Empty,
+    Text(String),
+    Number(f64),
+    Bool(bool),
+    DateTime(DateTime),
+    Error(String),
+    // The formula as written by the application that saved the file and its last result
+    Formula { formula: String, value: Box<Cell> },
+}
+
+pub type CellMap = HashMap<(usize, usize), Cell>;
+
+// Readers call it with the fraction of the sheet read as they go, they stop early and keep the
+// cells read so far when it returns false
+pub type Progress<'a> = &'a mut dyn FnMut(f32) -> bool;
+
+// Cell formatting, only kept by the formats that have it
+#[derive(Clone, Debug, PartialEq)]
+pub struct Style {
+    pub number_format: String,
+}
+
+pub type StyleMap = HashMap<(usize, usize), Arc<Style>>;
+
+// A calendar date, a time of the day or both
+#[derive(Copy, Clone, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub struct DateTime {
+    pub date: Option<(i32, u32, u32)>,
+    pub time: Option<(u32, u32, u32)>,
+}
+
+/// Which types are inferred from the text of CSV cells, everything else is text
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Inference {
+    pub numbers: bool,
+    pub booleans: bool,
+    pub dates: bool,
+}
+
+impl Inference {
+    pub fn all() -> Self {
+        Inference {
+            numbers: true,
+            booleans: true,
+            dates: true,
+        }
+    }
+
+    pub fn none() -> Self {
+        Inference {
+            numbers: false,
+            booleans: false,
+            dates: false,
+        }
+    }
+}
+
+impl Default for Inference {
+    fn default() -> Self {
+        Inference::all()
+    }
+}
+
+impl Cell {
+    pub fn text(value: &str) -> Cell {
+        if value.is_empty() {
+            Cell::Empty
+        } else {
+            Cell::Text(value.to_string())
+        }
+    }
+
+    // A value is only inferred when it's written back exactly as it was read, so saving a
+    // file never changes it: `1.50`, `007` and `TRUE` stay text.
+    pub fn infer(value: &str, inference: &Inference) -> Cell {
+        Cell::infer_typed(value, inference).unwrap_or_else(|| Cell::text(value))
+    }
+
+    // The inferred cell, or None when it's text
+    pub fn infer_typed(value: &str, inference: &Inference) -> Option<Cell> {
+        if value.is_empty() {
+            return Some(Cell::Empty);
+        }
+
+        if inference.numbers
+            && let Ok(number) = value.parse::<f64>()
+            && number.is_finite()
+            && number.to_string() == value
+        {
+            return Some(Cell::Number(number));
+        }
+
+        if inference.booleans {
+            match value {
+                "true" => return Some(Cell::Bool(true)),
+                "false" => return Some(Cell::Bool(false)),
+                _ => {}
+            }
+        }
+
+        if inference.dates
+            && let Some(datetime) = DateTime::parse(value)
+            && datetime.to_string() == value
+        {
+            return Some(Cell::DateTime(datetime));
+        }
+
+        None
+    }
+
+    pub fn is_empty(&self) -> bool {
+        matches!(self, Cell::Empty)
+    }
+
+    // The result of a formula, the cell itself otherwise
+    pub fn value(&self) -> &Cell {
+        match self {
+            Cell::Formula { value, .. } => value.value(),
+            _ => self,
+        }
+    }
+
+    // Numbers, booleans and dates (as serial numbers) can be used in calculations
+    pub fn as_number(&self) -> Option<f64> {
+        match self.value() {
+            Cell::Number(n) => Some(*n),
+            Cell::Bool(b) => Some(if *b { 1.0 } else { 0.0 }),
+            Cell::DateTime(d) => Some(d.to_serial(false)),
+            _ => None,
+        }
+    }
+}
+
+impl std::fmt::Display for Cell {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Cell::Empty => Ok(()),
+            Cell::Text(text) => write!(f, "{text}"),
+            Cell::Number(n) => write!(f, "{n}"),
+            Cell::Bool(b) => write!(f, "{b}"),
+            Cell::DateTime(d) => write!(f, "{d}"),
+            Cell::Error(e) => write!(f, "{e}"),
+            Cell::Formula { value, .. } => write!(f, "{value}"),
+        }
+    }
+}
+
+// Civil date of the days since 1970-01-01, see http://howardhinnant.github.io/date_algorithms.html
+fn civil_from_days(days: i64) -> (i32, u32, u32) {
+    let z = days + 719468;
+    let era = z.div_euclid(146097);
+    let doe = z - era * 146097;
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + if month <= 2 { 1 } else { 0 };
+
+    (year as i32, month as u32, day as u32)
+}
+
+// Days since 1970-01-01 of a civil date, the inverse of `civil_from_days`
+fn days_from_civil(year: i32, month: u32, day: u32) -> i64 {
+    let y = year as i64 - if month <= 2 { 1 } else { 0 };
+    let era = y.div_euclid(400);
+    let yoe = y - era * 400;
+    let mp = (month as i64 + 9) % 12;
+    let doy = (153 * mp + 2) / 5 + day as i64 - 1;
+    let doe = yoe * 365 + yoe / 4 - yoe / 100 + doy;
+
+    era * 146097 + doe - 719468
+}
+
+fn days_in_month(year: i32, month: u32) -> u32 {
+    match month {
+        2 if year % 4 == 0 && (year % 100 != 0 || year % 400 == 0) => 29,
+        2 => 28,
+        4 | 6 | 9 | 11 => 30,
+        _ => 31,
+    }
+}
+
+fn parse_number(text: &str, digits: usize) -> Option<u32> {
+    if text.len() != digits || !text.bytes().all(|b| b.is_ascii_digit()) {
+        return None;
+    }
+
+    text.parse().ok()
+}
+
+// `HH:MM` or `HH:MM:SS`, fractions of a second are dropped
+fn parse_time(text: &str) -> Option<(u32, u32, u32)> {
+    let text = text.split('.').next()?;
+    let mut parts = text.split(':');
+
+    let hour = parse_number(parts.next()?, 2)?;
+    let minute = parse_number(parts.next()?, 2)?;
+    let second = match parts.next() {
+        Some(s) => parse_number(s, 2)?,
+        None => 0,
+    };
+
+    if parts.next().is_some() || hour > 23 || minute > 59 || second > 59 {
+        return None;
+    }
+
+    Some((hour, minute, second))
+}
+
+fn parse_date(text: &str) -> Option<(i32, u32, u32)> {
+    let mut parts = text.split('-');
+
+    let year = parse_number(parts.next()?, 4)? as i32;
+    let month = parse_number(parts.next()?, 2)?;
+    let day = parse_number(parts.next()?, 2)?;
+
+    if parts.next().is_some() || !(1..=12).contains(&month) {
+        return None;
+    }
+
+    // The 1900 date system has a 29th of February that never happened
+    let last_day = if (year, month) == (1900, 2) {
+        29
+    } else {
+        days_in_month(year, month)
+    };
+
+    if day == 0 || day > last_day {
+        return None;
+    }
+
+    Some((year, month, day))
+}
+
+impl DateTime {
+    // ISO 8601 dates and times: `2024-03-01`, `2024-03-01T12:30:00` (or with a space) and
+    // `12:30:00`
+    pub fn parse(text: &str) -> Option<DateTime> {
+        if text.len() > 4 && text.as_bytes()[2] == b':' {
+            return Some(DateTime {
+                date: None,
+                time: Some(parse_time(text)?),
+            });
+        }
+
+        let (date, time) = match text.find(['T', ' ']) {
+            Some(i) => (&text[..i], Some(parse_time(&text[i + 1..])?)),
+            None => (text, None),
+        };
+
+        Some(DateTime {
+            date: Some(parse_date(date)?),
+            time,
+        })
+    }
+
+    // Serial numbers outside of the years 1900 to 9999 aren't dates. In the 1900 date
+    // system the serial numbers below 1 are times of the day.
+    pub fn from_serial(serial: f64, date1904: bool) -> Option<DateTime> {
+        if !(0.0..2958466.0).contains(&serial) {
+            return None;
+        }
+
+        let mut days = serial.floor() as i64;
+        let mut seconds = ((serial - serial.floor()) * 86400.0).round() as u32;
+
+        if seconds == 86400 {
+            days += 1;
+            seconds = 0;
+        }
+
+        let time = (seconds / 3600, seconds % 3600 / 60, seconds % 60);
+
+        if days == 0 && !date1904 {
+            return Some(DateTime {
+                date: None,
+                time: Some(time),
+            });
+        }
+
+        let date = if date1904 {
+            civil_from_days(days - 24107)
+        } else if days == 60 {
+            (1900, 2, 29)
+        } else {
+            civil_from_days(days - if days < 60 { 25568 } else { 25569 })
+        };
+
+        Some(DateTime {
+            date: Some(date),
+            time: if seconds == 0 { None } else { Some(time) },
+        })
+    }
+
+    pub fn to_serial(&self, date1904: bool) -> f64 {
+        let days = match self.date {
+            Some((1900, 2, 29)) if !date1904 => 60,
+            Some((y, m, d)) => {
+                let days = days_from_civil(y, m, d);
+
+                if date1904 {
+                    days + 24107
+                } else if days < -25508 {
+                    days + 25568
+                } else {
+                    days + 25569
+                }
+            }
+            None => 0,
+        };
+
+        let seconds = self.time.map_or(0, |(h, m, s)| h * 3600 + m * 60 + s);
+
+        days as f64 + seconds as f64 / 86400.0
+    }
+}
+
+impl std::fmt::Display for DateTime {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        if let Some((y, m, d)) = self.date {
+            write!(f, "{y:04}-{m:02}-{d:02}")?;
+
+            if self.time.is_some() {
+                write!(f, "T")?;
+            }
+        }
+
+        if let Some((h, m, s)) = self.time {
+            write!(f, "{h:02}:{m:02}:{s:02}")?;
+        }
+
+        Ok(())
+    }
+}
+
+// The largest sheet of Excel and LibreOffice, what files place past it is dropped
+pub const MAX_ROWS: usize = 1 << 20;
+pub const MAX_COLS: usize = 1 << 14;
+
+// Cells are addressed A1 style: the column letters (A, ..., Z, AA, AB, ...) and the row
+// counted from 1. Rows and columns are counted from 0 everywhere else.
+pub fn column_name(mut col: usize) -> String {
+    let mut letters = Vec::new();
+
+    loop {
+        letters.push((b'A' + (col % 26) as u8) as char);
+
+        if col < 26 {
+            break;
+        }
+
+        col = col / 26 - 1;
+    }
+
+    letters.iter().rev().collect()
+}
+
+pub fn address(row: usize, col: usize) -> String {
+    format!("{}{}", column_name(col), row + 1)
+}
+
+// Row and column of an address like `B3` or `aa10`
+pub fn parse_address(address: &str) -> Option<(usize, usize)> {
+    let digits_at = address.find(|c: char| !c.is_ascii_alphabetic())?;
+    let (letters, digits) = address.split_at(digits_at);
+
+    if letters.is_empty() || !digits.bytes().all(|c| c.is_ascii_digit()) {
+        return None;
+    }
+
+    let mut col = 0usize;
+
+    for c in letters.bytes() {
+        col = col
+            .checked_mul(26)?
+            .checked_add((c.to_ascii_uppercase() - b'A') as usize + 1)?;
+    }
+
+    let row = digits.parse::<usize>().ok()?.checked_sub(1)?;
+
+    Some((row, col - 1))
+}
+
+// Number formats 0 to 49 are built in and not stored in the files
+pub fn builtin_number_format(id: u16) -> Option<&'static str> {
+    let format = match id {
+        1 => "0",
+        2 => "0.00",
+        3 => "#,##0",
+        4 => "#,##0.00",
+        9 => "0%",
+        10 => "0.00%",
+        11 => "0.00E+00",
+        12 => "# ?/?",
+        13 => "# ??/??",
+        14 => "mm-dd-yy",
+        15 => "d-mmm-yy",
+        16 => "d-mmm",
+        17 => "mmm-yy",
+        18 => "h:mm AM/PM",
+        19 => "h:mm:ss AM/PM",
+        20 => "h:mm",
+        21 => "h:mm:ss",
+        22 => "m/d/yy h:mm",
+        37 => "#,##0 ;(#,##0)",
+        38 => "#,##0 ;[Red](#,##0)",
+        39 => "#,##0.00;(#,##0.00)",
+        40 => "#,##0.00;[Red](#,##0.00)",
+        45 => "mm:ss",
+        46 => "[h]:mm:ss",
+        47 => "mmss.0",
+        48 => "##0.0E+0",
+        49 => "@",
+        _ => return None,
+    };
+
+    Some(format)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_cells() {
+        let all = Inference::all();
+
+        assert_eq!(Cell::infer("", &all), Cell::Empty);
+        assert_eq!(Cell::infer("42", &all), Cell::Number(42.0));
+        assert_eq!(Cell::infer("-2.5", &all), Cell::Number(-2.5));
+        assert_eq!(Cell::infer("1.50", &all), Cell::text("1.50"));
+        assert_eq!(Cell::infer("007", &all), Cell::text("007"));
+        assert_eq!(Cell::infer("1e3", &all), Cell::text("1e3"));
+        assert_eq!(Cell::infer("NaN", &all), Cell::text("NaN"));
+        assert_eq!(Cell::infer("true", &all), Cell::Bool(true));
+        assert_eq!(Cell::infer("TRUE", &all), Cell::text("TRUE"));
+        assert_eq!(Cell::infer("2023-02-29", &all), Cell::text("2023-02-29"));
+        assert_eq!(Cell::infer("42", &Inference::none()), Cell::text("42"));
+
+        let date = Cell::infer("2024-03-01", &all);
+
+        assert!(matches!(date, Cell::DateTime(_)));
+        assert_eq!(date.as_number(), Some(45352.0));
+
+        let datetime = DateTime::parse("2024-03-01 12:00:00.000").unwrap();
+
+        assert_eq!(datetime.to_string(), "2024-03-01T12:00:00");
+        assert_eq!(datetime.to_serial(false), 45352.5);
+        assert_eq!(DateTime::from_serial(45352.5, false), Some(datetime));
+        assert_eq!(DateTime::parse("18:00").unwrap().to_serial(false), 0.75);
+
+        assert_eq!(address(0, 0), "A1");
+        assert_eq!(address(9, 27), "AB10");
+        assert_eq!(parse_address("AB10"), Some((9, 27)));
+        assert_eq!(parse_address("xfd1048576"), Some((1048575, 16383)));
+        assert_eq!(parse_address("A0"), None);
+        assert_eq!(parse_address("12"), None);
+        assert_eq!(parse_address("B"), None);
+        assert_eq!(parse_address("B2C"), None);
+        assert_eq!(parse_address("É1"), None);
+
+        for serial in [1.0, 59.0, 60.0, 61.0, 0.75, 2958465.0] {
+            let datetime = DateTime::from_serial(serial, false).unwrap();
+
+            assert_eq!(datetime.to_serial(false), serial);
+        }
+
+        assert_eq!(
+            DateTime::from_serial(1.0, false).unwrap().to_string(),
+            "1900-01-01"
+        );
+        assert_eq!(
+            DateTime::from_serial(60.0, false).unwrap().to_string(),
+            "1900-02-29"
+        );
+        assert_eq!(
+            DateTime::from_serial(0.0, true).unwrap().to_string(),
+            "1904-01-01"
+        );
+        assert_eq!(
+            DateTime::from_serial(0.0, true).unwrap().to_serial(true),
+            0.0
+        );
+
+        let formula = Cell::Formula {
+            formula: String::from("of:=1+2"),
+            value: Box::new(Cell::Number(3.0)),
+        };
+
+        assert_eq!(formula.to_string(), "3");
+        assert_eq!(formula.as_number(), Some(3.0));
+        assert_eq!(Cell::Error(String::from("#N/A")).as_number(), None);
+    }
+}
@@ -0,0 +1,402 @@
+// Conversion between the supported formats without opening a window:
+//
+//     csvim convert IN OUT [--sheet NAME] [--sep C] [--quote C] [--encoding NAME] [--no-infer]
+//                          [--format NAME] [--out-sep C] [--out-quote C] [--out-encoding NAME]
+//                          [--out-format NAME]
+//
+// The formats come from the extensions of IN and OUT unless they're given, OUT can also be
+// `-` for csv (or tsv, json and markdown) on the standard output. `--sheet` picks a worksheet
+// of a workbook, or an entry of a zip archive.
+// `--sep`, `--quote` and `--encoding` describe a csv input, the `--out-` ones the csv output.
+//
+use std::io::Write;
+
+use crate::cell::{self, Cell, Inference};
+use crate::csv;
+use crate::encoding::{self, Encoding};
+use crate::format::{self, Dialect, Format, parse_quote, parse_separator};
+use crate::sheet::Sheet;
+
+#[derive(Debug)]
+pub enum ConvertError {
+    Usage(String),
+    Read(String, String),
+    Write(String, String),
+}
+
+impl std::fmt::Display for ConvertError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ConvertError::Usage(message) => write!(f, "{message}"),
+            ConvertError::Read(path, e) => write!(f, "{path}: {e}"),
+            ConvertError::Write(path, e) => write!(f, "{path}: {e}"),
+        }
+    }
+}
+
+impl ConvertError {
+    // Status the process exits with
+    pub fn exit_code(&self) -> i32 {
+        match self {
+            ConvertError::Usage(_) => 2,
+            _ => 1,
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct Options {
+    pub input: String,
+    pub output: String,
+    pub sheet: Option<String>,
+    pub input_format: Option<Format>,
+    pub output_format: Option<Format>,
+    pub input_dialect: Dialect,
+    pub output_dialect: Dialect,
+    pub inference: Inference,
+}
+
+pub const USAGE: &str = "Usage: csvim convert IN OUT [--sheet NAME] [--sep C] [--quote C] \
+                         [--encoding NAME] [--no-infer] [--format NAME] [--out-sep C] \
+                         [--out-quote C] [--out-encoding NAME] [--out-format NAME]";
+
+// Reads the arguments after `convert`
+pub fn parse_args(args: impl IntoIterator<Item = String>) -> Result<Options, ConvertError> {
+    let mut args = args.into_iter();
+
+    let mut paths = Vec::new();
+    let mut sheet = None;
+    let mut input_format = None;
+    let mut output_format = None;
+    let mut input_dialect = Dialect::default();
+    let mut output_dialect = Dialect::default();
+    let mut inference = Inference::all();
+
+    while let Some(arg) = args.next() {
+        let (name, inline) = match arg.split_once('=') {
+            Some((name, value)) if name.starts_with("--") => (name.to_string(), Some(value)),
+            _ => (arg.clone(), None),
+        };
+
+        if name == "--no-infer" {
+            inference = Inference::none();
+            continue;
+        }
+
+        if !name.starts_with("--") {
+            paths.push(arg);
+            continue;
+        }
+
+        let value = match inline {
+            Some(value) => value.to_string(),
+            None => args
+                .next()
+                .ok_or_else(|| ConvertError::Usage(format!("{name} requires a value")))?,
+        };
+
+        let invalid = || ConvertError::Usage(format!("invalid value for {name}: {value}"));
+
+        match name.as_str() {
+            "--sheet" => sheet = Some(value.clone()),
+            "--format" => input_format = Some(Format::from_name(&value).ok_or_else(invalid)?),
+            "--out-format" => output_format = Some(Format::from_name(&value).ok_or_else(invalid)?),
+            "--sep" => input_dialect.separator = Some(parse_separator(&value).ok_or_else(invalid)?),
+            "--out-sep" => {
+                output_dialect.separator = Some(parse_separator(&value).ok_or_else(invalid)?)
+            }
+            "--quote" => input_dialect.delimiter = parse_quote(&value).ok_or_else(invalid)?,
+            "--out-quote" => output_dialect.delimiter = parse_quote(&value).ok_or_else(invalid)?,
+            "--encoding" => {
+                input_dialect.encoding = Some(Encoding::from_label(&value).ok_or_else(invalid)?)
+            }
+            "--out-encoding" => {
+                output_dialect.encoding = Some(Encoding::from_label(&value).ok_or_else(invalid)?)
+            }
+            _ => return Err(ConvertError::Usage(format!("unknown option {name}"))),
+        }
+    }
+
+    let [input, output] =
+        <[String; 2]>::try_from(paths).map_err(|_| ConvertError::Usage(String::from(USAGE)))?;
+
+    Ok(Options {
+        input,
+        output,
+        sheet,
+        input_format,
+        output_format,
+        input_dialect,
+        output_dialect,
+        inference,
+    })
+}
+
+fn read_input(options: &Options) -> Result<Sheet, String> {
+    let path = options.input.as_str();
+    let dialect = options.input_dialect;
+
+    // The standard input is csv, with the separator guessed unless it's given
+    if path == "-" {
+        if !matches!(options.input_format, None | Some(Format::Csv | Format::Tsv))
+            || options.sheet.is_some()
+        {
+            return Err(String::from("only csv can be read from the standard input"));
+        }
+
+        let separator = dialect.separator.or(options
+            .input_format
+            .map(|format| format.default_separator()));
+
+        let csv = csv::read_csv_reader_as_sheet(
+            path,
+            std::io::stdin().lock(),
+            separator,
+            dialect.delimiter,
+            dialect.encoding,
+            &options.inference,
+        )
+        .map_err(|e| e.to_string())?;
+
+        return Ok(csv.sheet);
+    }
+
+    let format = options
+        .input_format
+        .unwrap_or_else(|| format::format_of(path));
+
+    let workbook = format::read_file(
+        path,
+        format,
+        options.sheet.as_deref(),
+        &dialect,
+        &options.inference,
+    )
+    .map_err(|e| e.to_string())?;
+
+    Ok(workbook.sheet)
+}
+
+fn escape_json(out: &mut String, value: &str) {
+    out.push('"');
+
+    for c in value.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            '\n' => out.push_str("\\n"),
+            '\r' => out.push_str("\\r"),
+            '\t' => out.push_str("\\t"),
+            c if (c as u32) < 0x20 => out.push_str(&format!("\\u{:04x}", c as u32)),
+            c => out.push(c),
+        }
+    }
+
+    out.push('"');
+}
+
+fn json_value(out: &mut String, cell: &Cell) {
+    match cell.value() {
+        Cell::Empty => out.push_str("null"),
+        Cell::Number(n) if n.is_finite() => out.push_str(&n.to_string()),
+        Cell::Bool(b) => out.push_str(if *b { "true" } else { "false" }),
+        value => escape_json(out, &value.to_string()),
+    }
+}
+
+// An array with an object for each row, the keys are the cells of the first row. Numbers and
+// booleans are kept, empty cells are null.
+pub fn write_json_string(sheet: &Sheet) -> String {
+    let keys = (0..sheet.cols())
+        .map(|col| match sheet.display(0, col) {
+            key if key.is_empty() => cell::column_name(col),
+            key => key.into_owned(),
+        })
+        .collect::<Vec<_>>();
+
+    let mut out = String::from("[");
+
+    for row in 1..sheet.rows() {
+        out.push_str(if row == 1 { "\n  {" } else { ",\n  {" });
+
+        for (col, key) in keys.iter().enumerate() {
+            if col > 0 {
+                out.push_str(", ");
+            }
+
+            escape_json(&mut out, key);
+            out.push_str(": ");
+            json_value(&mut out, &sheet.get(row, col));
+        }
+
+        out.push('}');
+    }
+
+    out.push_str(if sheet.rows() > 1 { "\n]\n" } else { "]\n" });
+
+    out
+}
+
+fn markdown_cell(out: &mut String, value: &str) {
+    out.push(' ');
+
+    for c in value.chars() {
+        match c {
+            '|' => out.push_str("\\|"),
+            '\n' => out.push_str("<br>"),
+            '\r' => {}
+            c => out.push(c),
+        }
+    }
+
+    out.push_str(" |");
+}
+
+// A table whose header is the first row
+pub fn write_markdown_string(sheet: &Sheet) -> String {
+    let mut out = String::new();
+
+    for row in 0..sheet.rows() {
+        out.push('|');
+
+        for col in 0..sheet.cols() {
+            markdown_cell(&mut out, &sheet.display(row, col));
+        }
+
+        out.push('\n');
+
+        if row == 0 {
+            out.push('|');
+            out.push_str(&" --- |".repeat(sheet.cols()));
+            out.push('\n');
+        }
+    }
+
+    out
+}
+
+// What's written to the standard output, only the text formats can be
+fn stdout_data(sheet: &Sheet, format: Format, dialect: &Dialect) -> Result<Vec<u8>, String> {
+    match format {
+        Format::Csv | Format::Tsv => {
+            let separator = dialect
+                .separator
+                .unwrap_or_else(|| format.default_separator());
+            let text = csv::write_csv_string(sheet, separator, dialect.delimiter);
+
+            encoding::encode(&text, dialect.encoding.unwrap_or(Encoding::Utf8))
+                .map_err(|e| e.to_string())
+        }
+        Format::Json => Ok(write_json_string(sheet).into_bytes()),
+        Format::Markdown => Ok(write_markdown_string(sheet).into_bytes()),
+        _ => Err(format!(
+            "{} can't be written to the standard output",
+            format.name()
+        )),
+    }
+}
+
+pub fn convert(options: &Options) -> Result<(), ConvertError> {
+    let sheet = read_input(options).map_err(|e| ConvertError::Read(options.input.clone(), e))?;
+
+    let dialect = &options.output_dialect;
+
+    let result = if options.output == "-" {
+        stdout_data(
+            &sheet,
+            options.output_format.unwrap_or(Format::Csv),
+            dialect,
+        )
+        .and_then(|data| {
+            std::io::stdout()
+                .write_all(&data)
+                .map_err(|e| e.to_string())
+        })
+    } else {
+        let format = options
+            .output_format
+            .unwrap_or_else(|| Format::from_path(&options.output));
+
+        format::write_file(&options.output, format, &sheet, dialect).map_err(|e| e.to_string())
+    };
+
+    result.map_err(|e| ConvertError::Write(options.output.clone(), e))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::csv::Delimiter;
+
+    #[test]
+    fn test_convert() {
+        let args = [
+            "in.csv",
+            "--sep",
+            "tab",
+            "--quote='",
+            "out.json",
+            "--out-sep=;",
+        ];
+        let options = parse_args(args.map(String::from)).unwrap();
+
+        assert_eq!(
+            (options.input.as_str(), options.output.as_str()),
+            ("in.csv", "out.json")
+        );
+        assert_eq!(options.input_dialect.separator, Some('\t'));
+        assert!(matches!(
+            options.input_dialect.delimiter,
+            Delimiter::SingleQuote
+        ));
+        assert_eq!(options.output_dialect.separator, Some(';'));
+        assert_eq!(Format::from_path("Data.TSV.gz"), Format::Tsv);
+        assert_eq!(Format::from_path("data.txt"), Format::Csv);
+        assert_eq!(Format::from_name("xml"), Some(Format::Xmlss));
+
+        assert!(matches!(
+            parse_args(["a.csv"].map(String::from)),
+            Err(ConvertError::Usage(_))
+        ));
+        assert!(matches!(
+            parse_args(["a.csv", "b.csv", "--sep"].map(String::from)),
+            Err(ConvertError::Usage(_))
+        ));
+        assert!(matches!(
+            parse_args(["a.csv", "b.csv", "--out-encoding", "ebcdic"].map(String::from)),
+            Err(ConvertError::Usage(_))
+        ));
+
+        let mut sheet = Sheet::new();
+
+        sheet.set(0, 0, Cell::text("name"));
+        sheet.set(0, 2, Cell::text("ok"));
+        sheet.set(1, 0, Cell::text("a|\"b\"\nc"));
+        sheet.set(1, 1, Cell::Number(2.5));
+        sheet.set(1, 2, Cell::Bool(true));
+        sheet.set(2, 1, Cell::Error(String::from("#N/A")));
+
+        assert_eq!(
+            write_json_string(&sheet),
+            "[\n  {\"name\": \"a|\\\"b\\\"\\nc\", \"B\": 2.5, \"ok\": true},\n  \
+             {\"name\": null, \"B\": \"#N/A\", \"ok\": null}\n]\n"
+        );
+        assert_eq!(
+            write_markdown_string(&sheet),
+            "| name |  | ok |\n| --- | --- | --- |\n| a\\|\"b\"<br>c | 2.5 | true |\n|  | #N/A |  |\n"
+        );
+        assert_eq!(write_json_string(&Sheet::new()), "[]\n");
+
+        let dialect = Dialect::default();
+
+        assert_eq!(
+            stdout_data(&sheet, Format::Json, &dialect).unwrap(),
+            write_json_string(&sheet).into_bytes()
+        );
+        assert_eq!(
+            stdout_data(&sheet, Format::Tsv, &dialect).unwrap(),
+            csv::write_csv_string(&sheet, '\t', Delimiter::DoubleQuote).into_bytes()
+        );
+        assert!(stdout_data(&sheet, Format::Xlsx, &dialect).is_err());
+    }
+}
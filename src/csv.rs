@@ -1,127 +1,1084 @@
-use std::collections::HashMap;
 use std::fs;
+use std::io::Read;
 
-#[derive(Copy, Clone, Debug)]
+use crate::cell::{Cell, Inference};
+use crate::encoding::{self, Encoding, EncodingError};
+use crate::format::Dialect;
+use crate::sheet::Sheet;
+use crate::xlsx::zip;
+use crate::xlsx::zip::gzip;
+
+/// The quote around csv fields that hold separators, quotes or line ends
+#[derive(Copy, Clone, Debug, PartialEq)]
 pub enum Delimiter {
     SingleQuote,
     DoubleQuote,
 }
 
+impl Delimiter {
+    pub fn as_char(self) -> char {
+        match self {
+            Delimiter::SingleQuote => '\'',
+            Delimiter::DoubleQuote => '"',
+        }
+    }
+}
+
 #[derive(Debug)]
 pub struct CSV {
     pub filename: String,
     pub separator: char,
     pub delimiter: Delimiter,
+    pub encoding: Encoding,
     pub rows: Vec<Vec<String>>,
 }
 
-pub struct CSVMap {
+pub struct CSVSheet {
     pub filename: String,
     pub separator: char,
     pub delimiter: Delimiter,
-    pub map: HashMap<(usize, usize), String>,
+    pub encoding: Encoding,
+    pub sheet: Sheet,
 }
 
-pub fn read_csv_file(filename: &str, separator0: char, string_delimiter: Delimiter) -> CSV {
-    let data0 = fs::read_to_string(filename).unwrap();
-    let data = data0.as_bytes();
+#[derive(Debug)]
+pub enum CSVError {
+    Io(std::io::Error),
+    Gzip(gzip::GzipError),
+    Zip(zip::ZipError),
+    Encoding(EncodingError),
+}
 
-    let delimiter = match string_delimiter {
-        Delimiter::SingleQuote => '\'' as u8,
-        Delimiter::DoubleQuote => '"' as u8,
-    };
+impl std::fmt::Display for CSVError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            CSVError::Io(e) => write!(f, "{e}"),
+            CSVError::Gzip(e) => write!(f, "{e}"),
+            CSVError::Zip(e) => write!(f, "{e}"),
+            CSVError::Encoding(e) => write!(f, "{e}"),
+        }
+    }
+}
 
-    let separator = separator0 as u8;
+impl From<std::io::Error> for CSVError {
+    fn from(e: std::io::Error) -> Self {
+        CSVError::Io(e)
+    }
+}
 
-    let mut rows: Vec<Vec<String>> = Vec::new();
-    let mut current_row: Vec<String> = Vec::new();
+impl From<EncodingError> for CSVError {
+    fn from(e: EncodingError) -> Self {
+        CSVError::Encoding(e)
+    }
+}
+
+impl From<gzip::GzipError> for CSVError {
+    fn from(e: gzip::GzipError) -> Self {
+        CSVError::Gzip(e)
+    }
+}
+
+impl From<zip::ZipError> for CSVError {
+    fn from(e: zip::ZipError) -> Self {
+        CSVError::Zip(e)
+    }
+}
+
+pub fn is_gzip_path(filename: &str) -> bool {
+    filename.to_lowercase().ends_with(".gz")
+}
+
+// Csv and tsv files, the entries of a zip archive that can be read
+pub fn is_csv_path(filename: &str) -> bool {
+    let lower = filename.to_lowercase();
+
+    lower.ends_with(".csv") || lower.ends_with(".tsv")
+}
+
+// Default separator for a file, based on its extension. `data.tsv.gz` is a tsv file.
+pub fn separator_for(filename: &str) -> char {
+    let mut lower = filename.to_lowercase();
+
+    if let Some(stripped) = lower.strip_suffix(".gz") {
+        lower = stripped.to_string();
+    }
+
+    if lower.ends_with(".tsv") || lower.ends_with(".tab") {
+        '\t'
+    } else {
+        ','
+    }
+}
+
+// Gzip compressed files are decompressed first. The encoding comes from the byte order mark,
+// otherwise the given one is used, or UTF-8 and Windows-1252 when it isn't valid UTF-8.
+pub fn read_csv_text(
+    filename: &str,
+    encoding: Option<Encoding>,
+) -> Result<(String, Encoding), CSVError> {
+    let mut data = fs::read(filename)?;
+
+    if is_gzip_path(filename) {
+        data = gzip::decompress(&data)?;
+    }
+
+    Ok(encoding::decode_auto(&data, encoding)?)
+}
+
+pub fn read_csv_file(
+    filename: &str,
+    separator0: char,
+    string_delimiter: Delimiter,
+    encoding: Option<Encoding>,
+) -> Result<CSV, CSVError> {
+    let (text, encoding) = read_csv_text(filename, encoding)?;
+
+    let mut csv = read_csv_data(filename, &text, separator0, string_delimiter);
+    csv.encoding = encoding;
+
+    Ok(csv)
+}
+
+// Fields are quoted the RFC 4180 way: separators and newlines inside quotes are text, the
+// quotes around them are dropped and a doubled quote stands for one. A backslash before a quote
+// also escapes it, unless the quote is followed by what can follow a closing quote (a
+// separator, a newline, another quote or the end), so what `write_field` writes reads back the
+// same.
+fn is_escaped_quote(data: &[u8], i: usize, separator: u8, delimiter: u8) -> bool {
+    data[i] == b'\\'
+        && data.get(i + 1) == Some(&delimiter)
+        && !matches!(data.get(i + 2), None | Some(b'\n' | b'\r'))
+        && data.get(i + 2) != Some(&separator)
+        && data.get(i + 2) != Some(&delimiter)
+}
+
+// A carriage return ends a row with the newline after it, or alone at the end of the text
+fn is_row_end(data: &[u8], i: usize) -> bool {
+    data[i] == b'\n' || (data[i] == b'\r' && matches!(data.get(i + 1), None | Some(b'\n')))
+}
+
+// Calls `field` with the row, the column and the text of each field. Rows end with "\n" or
+// "\r\n".
+fn parse_fields(
+    text: &str,
+    separator: char,
+    string_delimiter: Delimiter,
+    mut field: impl FnMut(usize, usize, &str),
+) {
+    let data = text.as_bytes();
+
+    let delimiter = string_delimiter.as_char() as u8;
+    let separator = separator as u8;
+
+    let mut row = 0;
+    let mut col = 0;
 
     let mut i = 0;
-    let mut start_idx = 0;
+    let mut start = 0;
 
-    let mut reading_delimiter = false;
+    // Fields with quotes are unquoted into `value`, the others are slices of the text
+    let mut value = String::new();
+    let mut quoted = false;
+    let mut in_quotes = false;
 
     while i < data.len() {
         let c = data[i];
 
-        if c == b'\n' {
-            if !reading_delimiter {
-                let value = str::from_utf8(&data[start_idx..i]).unwrap();
+        if in_quotes {
+            if c == delimiter && data.get(i + 1) == Some(&delimiter) {
+                value.push(delimiter as char);
+                i += 2;
+            } else if c == delimiter {
+                in_quotes = false;
+                i += 1;
+            } else if is_escaped_quote(data, i, separator, delimiter) {
+                value.push(delimiter as char);
+                i += 2;
+            } else {
+                let end = data[i + 1..]
+                    .iter()
+                    .position(|&c| c == delimiter || c == b'\\')
+                    .map_or(data.len(), |n| i + 1 + n);
 
-                current_row.push(value.to_string());
+                value.push_str(&text[i..end]);
+                i = end;
+            }
+        } else if c == separator || is_row_end(data, i) {
+            if quoted {
+                field(row, col, &value);
+                value.clear();
+                quoted = false;
+            } else {
+                field(row, col, &text[start..i]);
+            }
 
-                rows.push(current_row);
-                current_row = Vec::new();
+            if c == separator {
+                col += 1;
+            } else {
+                row += 1;
+                col = 0;
+            }
 
-                start_idx = i + 1;
+            i += if c == b'\r' && data.get(i + 1) == Some(&b'\n') {
+                2
+            } else {
+                1
+            };
+            start = i;
+        } else if c == delimiter {
+            if !quoted {
+                value.push_str(&text[start..i]);
+                quoted = true;
             }
+
+            in_quotes = true;
+            i += 1;
+        } else {
+            let end = data[i + 1..]
+                .iter()
+                .position(|&c| c == separator || c == b'\n' || c == b'\r' || c == delimiter)
+                .map_or(data.len(), |n| i + 1 + n);
+
+            if quoted {
+                value.push_str(&text[i..end]);
+            }
+
+            i = end;
+        }
+    }
+
+    // A last row without a newline
+    if start < data.len() {
+        if quoted {
+            field(row, col, &value);
+        } else {
+            field(row, col, &text[start..]);
+        }
+    }
+}
+
+#[derive(Copy, Clone, PartialEq)]
+enum Pending {
+    Nothing,
+    // A quote inside quotes, it's doubled or it closes them
+    Quote,
+    Backslash,
+    // A backslash and a quote inside quotes, see `is_escaped_quote`
+    BackslashQuote,
+}
+
+// Finds where rows end the way `parse_fields` splits them, a byte at a time, so a file can be
+// cut into parts that parse to the same rows. What a quote or a backslash means depends on the
+// bytes after it, the state carries over from one call to the next.
+pub struct RowScanner {
+    separator: u8,
+    delimiter: u8,
+    in_quotes: bool,
+    pending: Pending,
+}
+
+impl RowScanner {
+    pub fn new(separator: char, string_delimiter: Delimiter) -> Self {
+        RowScanner {
+            separator: separator as u8,
+            delimiter: string_delimiter.as_char() as u8,
+            in_quotes: false,
+            pending: Pending::Nothing,
+        }
+    }
+
+    // True when `c` is the newline that ends a row
+    pub fn feed(&mut self, c: u8) -> bool {
+        match std::mem::replace(&mut self.pending, Pending::Nothing) {
+            // A doubled quote
+            Pending::Quote if c == self.delimiter => return false,
+            Pending::Quote => self.in_quotes = false,
+            Pending::Backslash if c == self.delimiter => {
+                self.pending = Pending::BackslashQuote;
+                return false;
+            }
+            // A backslash, then a doubled quote
+            Pending::BackslashQuote if c == self.delimiter => return false,
+            Pending::BackslashQuote if c == b'\n' || c == b'\r' || c == self.separator => {
+                self.in_quotes = false
+            }
+            _ => {}
+        }
+
+        if !self.in_quotes {
+            if c == self.delimiter {
+                self.in_quotes = true;
+            }
+
+            return c == b'\n';
+        }
+
+        if c == self.delimiter {
+            self.pending = Pending::Quote;
         } else if c == b'\\' {
-            if reading_delimiter {
-                if i + 1 < data.len() {
-                    let next_c = data[i + 1];
-                    if next_c == delimiter {
-                        i += 1;
-                    }
+            self.pending = Pending::Backslash;
+        }
+
+        false
+    }
+}
+
+// Size of the reads of `CSVStream`
+const STREAM_CHUNK: usize = 64 << 10;
+
+// Lines looked at to guess the separator
+const SNIFF_LINES: usize = 20;
+
+// Guesses the separator and the quote from the first lines. The separator is the candidate
+// found the same number of times on every line, the quote is a single quote only when there
+// are fields quoted with it and no double quotes.
+pub fn sniff_dialect(sample: &[u8]) -> (char, Delimiter) {
+    let mut lines = sample
+        .split(|&c| c == b'\n')
+        .filter(|line| !line.is_empty())
+        .take(SNIFF_LINES)
+        .collect::<Vec<_>>();
+
+    // The last line may be cut
+    if lines.len() > 1 && !sample.ends_with(b"\n") {
+        lines.pop();
+    }
+
+    let count = |line: &[u8], separator: u8| {
+        let mut in_quotes = false;
+
+        line.iter()
+            .filter(|&&c| {
+                if c == b'"' {
+                    in_quotes = !in_quotes;
                 }
+
+                c == separator && !in_quotes
+            })
+            .count()
+    };
+
+    let separator = [b',', b'\t', b';', b'|']
+        .into_iter()
+        .map(|separator| {
+            let counts = lines
+                .iter()
+                .map(|line| count(line, separator))
+                .collect::<Vec<_>>();
+
+            let consistent = counts
+                .first()
+                .is_some_and(|&first| first > 0 && counts.iter().all(|&count| count == first));
+
+            (consistent, counts.iter().sum::<usize>(), separator)
+        })
+        .filter(|&(_, total, _)| total > 0)
+        .max_by_key(|&(consistent, total, separator)| {
+            // On a tie the earlier candidate wins
+            (consistent, total, separator == b',')
+        })
+        .map_or(',', |(_, _, separator)| separator as char);
+
+    let single_quoted = lines.iter().any(|line| {
+        line.split(|&c| c == separator as u8)
+            .any(|field| field.len() >= 2 && field.starts_with(b"'") && field.ends_with(b"'"))
+    });
+
+    let delimiter = if single_quoted && !sample.contains(&b'"') {
+        Delimiter::SingleQuote
+    } else {
+        Delimiter::DoubleQuote
+    };
+
+    (separator, delimiter)
+}
+
+pub struct CSVPart {
+    pub first_row: usize,
+    pub text: String,
+}
+
+// CSV read from any source a part at a time. Each part ends at the end of a row, so the first
+// rows can be parsed before the source ends. UTF-16 text is read in one part.
+pub struct CSVStream<R> {
+    reader: R,
+    pub separator: char,
+    pub delimiter: Delimiter,
+    pub encoding: Encoding,
+    // Without an encoding given, text that isn't UTF-8 is read as Windows-1252 from there on
+    guess_encoding: bool,
+    scanner: RowScanner,
+    // Bytes read and not parsed yet, the ones before `scanned` went through the scanner
+    pending: Vec<u8>,
+    scanned: usize,
+    last_row_end: usize,
+    rows_in_pending: usize,
+    next_row: usize,
+    eof: bool,
+}
+
+impl<R: Read> CSVStream<R> {
+    // The first bytes are read to find the encoding from the byte order mark and, without a
+    // separator, the first lines to guess the separator and the quote
+    pub fn new(
+        mut reader: R,
+        separator: Option<char>,
+        string_delimiter: Delimiter,
+        encoding: Option<Encoding>,
+    ) -> Result<Self, CSVError> {
+        let mut pending = Vec::new();
+        let mut chunk = vec![0u8; STREAM_CHUNK];
+        let mut eof = false;
+
+        // A byte order mark is at most 3 bytes
+        let sampled = |pending: &[u8]| match separator {
+            Some(_) => pending.len() >= 3,
+            None => {
+                pending.len() >= STREAM_CHUNK
+                    || pending.iter().filter(|&&c| c == b'\n').count() > SNIFF_LINES
             }
-        } else if c == delimiter {
-            if i + 1 < data.len() {
-                let next_c = data[i + 1];
-                if next_c != delimiter {
-                    reading_delimiter = !reading_delimiter;
-                } else {
-                    current_row.push(String::new());
-                    i += 1;
-                }
+        };
+
+        while !sampled(&pending) {
+            let n = reader.read(&mut chunk)?;
+
+            if n == 0 {
+                eof = true;
+                break;
             }
-        } else if c == separator {
-            let value = str::from_utf8(&data[start_idx..i]).unwrap();
 
-            current_row.push(value.to_string());
+            pending.extend_from_slice(&chunk[..n]);
+        }
+
+        let (encoding, guess_encoding) = match (encoding::sniff_bom(&pending), encoding) {
+            (Some((bom_encoding, _)), _) => (bom_encoding, false),
+            (None, Some(encoding)) => (encoding, false),
+            (None, None) => (Encoding::Utf8, true),
+        };
 
-            start_idx = i + 1;
+        if matches!(encoding, Encoding::Utf16Le | Encoding::Utf16Be) {
+            reader.read_to_end(&mut pending)?;
+            eof = true;
         }
 
-        i += 1;
+        let (separator, string_delimiter) = match separator {
+            Some(separator) => (separator, string_delimiter),
+            None if matches!(encoding, Encoding::Utf16Le | Encoding::Utf16Be) => {
+                sniff_dialect(encoding::decode(&pending, encoding)?.as_bytes())
+            }
+            None => sniff_dialect(&pending),
+        };
+
+        Ok(CSVStream {
+            reader,
+            separator,
+            delimiter: string_delimiter,
+            encoding,
+            guess_encoding,
+            scanner: RowScanner::new(separator, string_delimiter),
+            pending,
+            scanned: 0,
+            last_row_end: 0,
+            rows_in_pending: 0,
+            next_row: 0,
+            eof,
+        })
     }
 
-    if start_idx + 1 < i {
-        let value = str::from_utf8(&data[start_idx..i]).unwrap();
+    // Reads up to the end of the next rows, None once everything was returned
+    pub fn next_part(&mut self) -> Result<Option<CSVPart>, CSVError> {
+        let utf16 = matches!(self.encoding, Encoding::Utf16Le | Encoding::Utf16Be);
 
-        current_row.push(value.to_string());
+        let mut chunk = vec![0u8; STREAM_CHUNK];
 
-        rows.push(current_row);
+        loop {
+            if !utf16 {
+                while self.scanned < self.pending.len() {
+                    if self.scanner.feed(self.pending[self.scanned]) {
+                        self.last_row_end = self.scanned + 1;
+                        self.rows_in_pending += 1;
+                    }
+
+                    self.scanned += 1;
+                }
+            }
+
+            if self.last_row_end > 0 || self.eof {
+                break;
+            }
+
+            let n = self.reader.read(&mut chunk)?;
+
+            if n == 0 {
+                self.eof = true;
+            }
+
+            self.pending.extend_from_slice(&chunk[..n]);
+        }
+
+        // The rest after the last row end, once the source ended
+        let end = if self.last_row_end > 0 {
+            self.last_row_end
+        } else {
+            self.pending.len()
+        };
+
+        if end == 0 {
+            return Ok(None);
+        }
+
+        let text = match encoding::decode(&self.pending[..end], self.encoding) {
+            Err(_) if self.guess_encoding => {
+                self.encoding = Encoding::Windows1252;
+                self.guess_encoding = false;
+
+                encoding::decode(&self.pending[..end], self.encoding)?
+            }
+            text => text?,
+        };
+        let first_row = self.next_row;
+
+        self.pending.drain(..end);
+        self.scanned -= end.min(self.scanned);
+        self.next_row += self.rows_in_pending;
+        self.last_row_end = 0;
+        self.rows_in_pending = 0;
+
+        Ok(Some(CSVPart { first_row, text }))
     }
+}
+
+// Parses CSV content that is already in memory, `filename` is only used as a label
+pub fn read_csv_data(
+    filename: &str,
+    data: &str,
+    separator: char,
+    string_delimiter: Delimiter,
+) -> CSV {
+    let mut rows: Vec<Vec<String>> = Vec::new();
+
+    parse_fields(data, separator, string_delimiter, |row, _, value| {
+        if rows.len() <= row {
+            rows.resize_with(row + 1, Vec::new);
+        }
+
+        rows[row].push(value.to_string());
+    });
 
     CSV {
         filename: filename.to_owned(),
-        separator: separator0,
+        separator,
         delimiter: string_delimiter,
+        encoding: Encoding::Utf8,
         rows,
     }
 }
 
-pub fn read_csv_file_as_hashmap(
+// The type of each cell is inferred from its text, see `Cell::infer`. Text goes straight to
+// the sheet, without a `Cell` in between.
+pub fn read_csv_data_as_sheet(
+    filename: &str,
+    data: &str,
+    separator: char,
+    string_delimiter: Delimiter,
+    inference: &Inference,
+) -> CSVSheet {
+    let mut sheet = Sheet::new();
+
+    read_csv_data_into(&mut sheet, 0, data, separator, string_delimiter, inference);
+
+    CSVSheet {
+        filename: filename.to_owned(),
+        separator,
+        delimiter: string_delimiter,
+        encoding: Encoding::Utf8,
+        sheet,
+    }
+}
+
+// Parses into the rows of `sheet` from `first_row` on, to read a file in parts cut by
+// `RowScanner`
+pub fn read_csv_data_into(
+    sheet: &mut Sheet,
+    first_row: usize,
+    data: &str,
+    separator: char,
+    string_delimiter: Delimiter,
+    inference: &Inference,
+) {
+    parse_fields(
+        data,
+        separator,
+        string_delimiter,
+        |row, col, value| match Cell::infer_typed(value, inference) {
+            Some(cell) => sheet.set(first_row + row, col, cell),
+            None => sheet.set_text(first_row + row, col, value),
+        },
+    );
+}
+
+pub fn read_csv_file_as_sheet(
+    filename: &str,
+    separator: char,
+    string_delimiter: Delimiter,
+    encoding: Option<Encoding>,
+    inference: &Inference,
+) -> Result<CSVSheet, CSVError> {
+    let (text, encoding) = read_csv_text(filename, encoding)?;
+
+    let mut csv = read_csv_data_as_sheet(filename, &text, separator, string_delimiter, inference);
+    csv.encoding = encoding;
+
+    Ok(csv)
+}
+
+// Reads CSV from any source, see `CSVStream`. `filename` is only used as a label.
+pub fn read_csv_reader_as_sheet(
+    filename: &str,
+    reader: impl Read,
+    separator: Option<char>,
+    string_delimiter: Delimiter,
+    encoding: Option<Encoding>,
+    inference: &Inference,
+) -> Result<CSVSheet, CSVError> {
+    let mut stream = CSVStream::new(reader, separator, string_delimiter, encoding)?;
+    let mut sheet = Sheet::new();
+
+    while let Some(part) = stream.next_part()? {
+        read_csv_data_into(
+            &mut sheet,
+            part.first_row,
+            &part.text,
+            stream.separator,
+            stream.delimiter,
+            inference,
+        );
+    }
+
+    Ok(CSVSheet {
+        filename: filename.to_owned(),
+        separator: stream.separator,
+        delimiter: stream.delimiter,
+        encoding: stream.encoding,
+        sheet,
+    })
+}
+
+// The encoding comes from the byte order mark, otherwise the one of the dialect (or UTF-8)
+// is used. Without a separator in the dialect the one for the entry's extension is used.
+pub fn read_csv_zip_entry_as_sheet(
+    archive: &zip::Zip,
+    entry: &str,
+    dialect: &Dialect,
+    inference: &Inference,
+) -> Result<CSVSheet, CSVError> {
+    let data = archive.read_file(entry)?;
+    let (text, encoding) = encoding::decode_auto(&data, dialect.encoding)?;
+
+    let csv = read_csv_data_as_sheet(
+        entry,
+        &text,
+        dialect.separator.unwrap_or_else(|| separator_for(entry)),
+        dialect.delimiter,
+        inference,
+    );
+
+    Ok(CSVSheet { encoding, ..csv })
+}
+
+// Quotes the value when needed, doubling the delimiters inside it
+fn write_field(out: &mut String, value: &str, separator: char, delimiter: char) {
+    let needs_quotes = value
+        .chars()
+        .any(|c| c == separator || c == delimiter || c == '\n' || c == '\r');
+
+    if !needs_quotes {
+        out.push_str(value);
+        return;
+    }
+
+    out.push(delimiter);
+
+    for c in value.chars() {
+        if c == delimiter {
+            out.push(delimiter);
+        }
+
+        out.push(c);
+    }
+
+    out.push(delimiter);
+}
+
+// Cells are written as they're shown
+pub fn write_csv_string(sheet: &Sheet, separator: char, string_delimiter: Delimiter) -> String {
+    let delimiter = string_delimiter.as_char();
+
+    let mut out = String::new();
+
+    for i in 0..sheet.rows() {
+        for j in 0..sheet.cols() {
+            if j > 0 {
+                out.push(separator);
+            }
+
+            write_field(&mut out, &sheet.display(i, j), separator, delimiter);
+        }
+
+        out.push('\n');
+    }
+
+    out
+}
+
+// Files ending in `.gz` are gzip compressed
+pub fn write_csv_file(
+    filename: &str,
+    sheet: &Sheet,
+    separator: char,
+    string_delimiter: Delimiter,
+    encoding: Encoding,
+) -> Result<(), CSVError> {
+    let content = encoding::encode(
+        &write_csv_string(sheet, separator, string_delimiter),
+        encoding,
+    )?;
+
+    if is_gzip_path(filename) {
+        // The original name is the file name without the directory and the .gz extension
+        let base = std::path::Path::new(filename)
+            .file_name()
+            .and_then(|n| n.to_str())
+            .unwrap_or(filename);
+        let name = &base[..base.len() - 3];
+
+        fs::write(filename, gzip::compress(&content, Some(name)))?;
+    } else {
+        fs::write(filename, content)?;
+    }
+
+    Ok(())
+}
+
+// Sheet name and its cells
+pub type NamedSheet<'a> = (&'a str, &'a Sheet);
+
+// Writes each sheet as a `<name>.csv` entry of a zip archive
+pub fn write_csv_zip(
     filename: &str,
+    sheets: &[NamedSheet],
     separator: char,
     string_delimiter: Delimiter,
-) -> CSVMap {
-    let csv = read_csv_file(filename, separator, string_delimiter);
+    encoding: Encoding,
+) -> Result<(), CSVError> {
+    let file = fs::File::create(filename)?;
+
+    let mut writer = zip::ZipWriter::new(std::io::BufWriter::new(file));
+
+    for (name, sheet) in sheets {
+        let content = encoding::encode(
+            &write_csv_string(sheet, separator, string_delimiter),
+            encoding,
+        )?;
+
+        writer.add_file(&format!("{name}.csv"), &content, zip::Compression::Deflate)?;
+    }
+
+    writer.finish()?;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Hands out a few bytes at a time, like a pipe
+    struct Trickle<'a>(&'a [u8]);
+
+    impl Read for Trickle<'_> {
+        fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+            let n = self.0.len().min(buf.len()).min(5);
+
+            buf[..n].copy_from_slice(&self.0[..n]);
+            self.0 = &self.0[n..];
+
+            Ok(n)
+        }
+    }
+
+    // Hands out its data in one read, there's nothing more to read yet after that
+    struct Pipe<'a>(&'a [u8]);
+
+    impl Read for Pipe<'_> {
+        fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+            assert!(!self.0.is_empty(), "waiting for more input");
+
+            let n = self.0.len().min(buf.len());
+
+            buf[..n].copy_from_slice(&self.0[..n]);
+            self.0 = &self.0[n..];
+
+            Ok(n)
+        }
+    }
+
+    #[test]
+    fn test_csv_stream() {
+        assert_eq!(
+            sniff_dialect(b"a;b;c\n1;2,5;3\n4;5"),
+            (';', Delimiter::DoubleQuote)
+        );
+        assert_eq!(
+            sniff_dialect(b"a\tb\n\"x\ty\"\t2\n"),
+            ('\t', Delimiter::DoubleQuote)
+        );
+        assert_eq!(
+            sniff_dialect(b"'a b'|c\n'd'|e\n"),
+            ('|', Delimiter::SingleQuote)
+        );
+        assert_eq!(sniff_dialect(b"single\n"), (',', Delimiter::DoubleQuote));
+
+        let data = "\u{feff}id;text\n1;\"two\nlines\"\n2;\"say \"\"hi\"\"\"\n3;é\n4;last";
+
+        let csv = read_csv_reader_as_sheet(
+            "-",
+            Trickle(data.as_bytes()),
+            None,
+            Delimiter::DoubleQuote,
+            None,
+            &Inference::all(),
+        )
+        .unwrap();
+
+        let full = read_csv_data_as_sheet(
+            "-",
+            data.strip_prefix('\u{feff}').unwrap(),
+            ';',
+            Delimiter::DoubleQuote,
+            &Inference::all(),
+        );
+
+        assert_eq!(csv.separator, ';');
+        assert_eq!(csv.encoding, Encoding::Utf8Bom);
+        assert_eq!(
+            csv.sheet.cells().collect::<Vec<_>>(),
+            full.sheet.cells().collect::<Vec<_>>()
+        );
+        assert_eq!(csv.sheet.display(3, 1), "é");
+        assert_eq!(csv.sheet.display(4, 1), "last");
+
+        let utf16 = encoding::encode("a\tb\n1\t2\n", Encoding::Utf16Le).unwrap();
+        let csv = read_csv_reader_as_sheet(
+            "-",
+            utf16.as_slice(),
+            None,
+            Delimiter::DoubleQuote,
+            None,
+            &Inference::none(),
+        )
+        .unwrap();
+
+        assert_eq!(csv.separator, '\t');
+        assert_eq!(csv.sheet.display(1, 1), "2");
+
+        // With the separator given, the first rows don't wait for more lines
+        let mut stream =
+            CSVStream::new(Pipe(b"a;b\n1;2\n"), Some(';'), Delimiter::DoubleQuote, None).unwrap();
+        let part = stream.next_part().unwrap().unwrap();
+
+        assert_eq!(part.text, "a;b\n1;2\n");
 
-    let mut map: HashMap<(usize, usize), String> = HashMap::new();
+        // Text that isn't UTF-8 is read as Windows-1252
+        let csv = read_csv_reader_as_sheet(
+            "-",
+            Trickle(b"id,name\n1,caf\xe9\n2,\x80\n"),
+            None,
+            Delimiter::DoubleQuote,
+            None,
+            &Inference::none(),
+        )
+        .unwrap();
 
-    for (i, row) in csv.rows.iter().enumerate() {
-        for (j, col) in row.iter().enumerate() {
-            if col.len() > 0 {
-                let _ = map.insert((i, j), col.to_string());
+        assert_eq!(csv.encoding, Encoding::Windows1252);
+        assert_eq!(csv.sheet.display(1, 1), "café");
+        assert_eq!(csv.sheet.display(2, 1), "€");
+    }
+
+    #[test]
+    fn test_write_read() {
+        let rows = |text: &str| read_csv_data("-", text, ',', Delimiter::DoubleQuote).rows;
+
+        assert_eq!(
+            rows("x,\"a,b\",y\nq,\"\",z\n"),
+            [["x", "a,b", "y"], ["q", "", "z"]]
+        );
+        assert_eq!(
+            rows("\"say \"\"hi\"\"\",\"escaped \\\" quote\",\"ends \\\",1"),
+            [["say \"hi\"", "escaped \" quote", "ends \\", "1"]]
+        );
+        assert_eq!(rows("a,b\n1,2"), [["a", "b"], ["1", "2"]]);
+        assert_eq!(rows("a,b\r\n1,\"x\"\r"), [["a", "b"], ["1", "x"]]);
+
+        // Rows ending with "\r\n" read the same whole or streamed, and write back without quotes
+        let crlf = "a,b\r\n1,\"x\"\r\n2,3\r\n";
+        let read =
+            read_csv_data_as_sheet("-", crlf, ',', Delimiter::DoubleQuote, &Inference::all());
+        let streamed = read_csv_reader_as_sheet(
+            "-",
+            Trickle(crlf.as_bytes()),
+            Some(','),
+            Delimiter::DoubleQuote,
+            None,
+            &Inference::all(),
+        )
+        .unwrap();
+
+        assert_eq!(read.sheet.get(1, 1), Cell::text("x"));
+        assert_eq!(read.sheet.get(2, 1), Cell::Number(3.0));
+        assert_eq!(
+            streamed.sheet.cells().collect::<Vec<_>>(),
+            read.sheet.cells().collect::<Vec<_>>()
+        );
+        assert_eq!(
+            write_csv_string(&read.sheet, ',', Delimiter::DoubleQuote),
+            "a,b\n1,x\n2,3\n"
+        );
+
+        let values = [
+            "a,b",
+            "",
+            "say \"hi\"",
+            "two\nlines",
+            "back\\",
+            "a\\\"b",
+            "\\\"x",
+            "semi;colon",
+            "'single'",
+            "é",
+        ];
+
+        let mut sheet = Sheet::new();
+
+        for (i, value) in values.iter().enumerate() {
+            sheet.set(i / 3, i % 3, Cell::text(value));
+        }
+
+        for (separator, delimiter) in [(',', Delimiter::DoubleQuote), (';', Delimiter::SingleQuote)]
+        {
+            let text = write_csv_string(&sheet, separator, delimiter);
+            let read = read_csv_data_as_sheet("-", &text, separator, delimiter, &Inference::none());
+
+            assert_eq!(
+                (read.sheet.rows(), read.sheet.cols()),
+                (sheet.rows(), sheet.cols())
+            );
+
+            for (i, value) in values.iter().enumerate() {
+                assert_eq!(
+                    read.sheet.get(i / 3, i % 3),
+                    sheet.get(i / 3, i % 3),
+                    "{value}"
+                );
             }
+
+            assert_eq!(write_csv_string(&read.sheet, separator, delimiter), text);
+
+            let mut scanner = RowScanner::new(separator, delimiter);
+
+            assert_eq!(
+                text.bytes().filter(|&c| scanner.feed(c)).count(),
+                sheet.rows()
+            );
+        }
+    }
+
+    #[test]
+    fn test_read_zip_entry() {
+        let mut writer = zip::ZipWriter::new(Vec::new());
+
+        let entries: [(&str, &[u8]); 3] = [
+            ("latin.csv", b"caf\xe9,1\n"),
+            ("semicolon.csv", b"'a;b';c\n"),
+            ("bom.csv", b"\xff\xfea\x00,\x00\xe9\x00\n\x00"),
+        ];
+
+        for (name, data) in entries {
+            writer
+                .add_file(name, data, zip::Compression::Deflate)
+                .unwrap();
         }
+
+        let archive = zip::Zip::from_data(writer.finish().unwrap()).unwrap();
+        let inference = Inference::all();
+
+        let latin1 = Dialect {
+            encoding: Some(Encoding::Latin1),
+            ..Dialect::default()
+        };
+
+        let csv = read_csv_zip_entry_as_sheet(&archive, "latin.csv", &latin1, &inference).unwrap();
+        assert_eq!(csv.sheet.display(0, 0), "café");
+        assert_eq!(csv.encoding, Encoding::Latin1);
+
+        // The byte order mark wins over the dialect
+        let csv = read_csv_zip_entry_as_sheet(&archive, "bom.csv", &latin1, &inference).unwrap();
+        assert_eq!(csv.sheet.display(0, 1), "é");
+        assert_eq!(csv.encoding, Encoding::Utf16Le);
+
+        // Without an encoding, text that isn't UTF-8 is read as Windows-1252
+        let csv =
+            read_csv_zip_entry_as_sheet(&archive, "latin.csv", &Dialect::default(), &inference)
+                .unwrap();
+        assert_eq!(csv.sheet.display(0, 0), "café");
+        assert_eq!(csv.encoding, Encoding::Windows1252);
+
+        let utf8 = Dialect {
+            encoding: Some(Encoding::Utf8),
+            ..Dialect::default()
+        };
+
+        let e = read_csv_zip_entry_as_sheet(&archive, "latin.csv", &utf8, &inference);
+        assert!(matches!(e, Err(CSVError::Encoding(_))));
+
+        let dialect = Dialect {
+            separator: Some(';'),
+            delimiter: Delimiter::SingleQuote,
+            encoding: None,
+        };
+
+        let csv =
+            read_csv_zip_entry_as_sheet(&archive, "semicolon.csv", &dialect, &inference).unwrap();
+        assert_eq!(csv.separator, ';');
+        assert_eq!(csv.sheet.display(0, 0), "a;b");
+        assert_eq!(csv.sheet.display(0, 1), "c");
     }
 
-    CSVMap {
-        filename: csv.filename,
-        separator: csv.separator,
-        delimiter: csv.delimiter,
-        map,
+    #[test]
+    fn test_write_zip() {
+        let path = std::env::temp_dir().join(format!("csvim-zip-{}.zip", std::process::id()));
+        let path = path.to_str().unwrap();
+
+        let mut first = Sheet::new();
+        first.set(0, 0, Cell::text("é"));
+
+        let mut second = Sheet::new();
+        second.set(0, 1, Cell::Number(2.0));
+
+        write_csv_zip(
+            path,
+            &[("first", &first), ("second", &second)],
+            ';',
+            Delimiter::DoubleQuote,
+            Encoding::Windows1252,
+        )
+        .unwrap();
+
+        let archive = zip::Zip::from_data(fs::read(path).unwrap()).unwrap();
+        fs::remove_file(path).unwrap();
+
+        assert_eq!(archive.file_names(), ["first.csv", "second.csv"]);
+        assert_eq!(archive.read_file("first.csv").unwrap(), b"\xe9\n");
+        assert_eq!(archive.read_file("second.csv").unwrap(), b";2\n");
     }
 }
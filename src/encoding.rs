@@ -0,0 +1,304 @@
+// Text encodings of the files we read and write. Everything is converted to UTF-8 when
+// loading and back to the original encoding when saving.
+//
+// Without a byte order mark the encoding must be given, UTF-8 is the default.
+//
+/// Encoding of csv text
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum Encoding {
+    Utf8,
+
+    // UTF-8 starting with a byte order mark, as Excel writes it
+    Utf8Bom,
+
+    // UTF-16 is always written with a byte order mark
+    Utf16Le,
+    Utf16Be,
+
+    // ISO 8859-1, every byte is the code point with the same value
+    Latin1,
+    Windows1252,
+}
+
+#[derive(Debug)]
+pub enum EncodingError {
+    Unsupported(String),
+    InvalidData { encoding: Encoding, offset: usize },
+    Unmappable { encoding: Encoding, character: char },
+}
+
+impl std::fmt::Display for EncodingError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            EncodingError::Unsupported(label) => write!(f, "unsupported encoding {label}"),
+            EncodingError::InvalidData { encoding, offset } => {
+                write!(f, "invalid {} data at byte {offset}", encoding.name())
+            }
+            EncodingError::Unmappable {
+                encoding,
+                character,
+            } => write!(f, "{character:?} can't be written as {}", encoding.name()),
+        }
+    }
+}
+
+const UTF8_BOM: &[u8] = &[0xef, 0xbb, 0xbf];
+const UTF16LE_BOM: &[u8] = &[0xff, 0xfe];
+const UTF16BE_BOM: &[u8] = &[0xfe, 0xff];
+
+// Code points of the bytes 0x80 to 0x9f, the five bytes that Windows-1252 leaves undefined
+// are mapped to the C1 control with the same value
+static WINDOWS_1252_HIGH: [u16; 32] = [
+    0x20ac, 0x0081, 0x201a, 0x0192, 0x201e, 0x2026, 0x2020, 0x2021, 0x02c6, 0x2030, 0x0160, 0x2039,
+    0x0152, 0x008d, 0x017d, 0x008f, 0x0090, 0x2018, 0x2019, 0x201c, 0x201d, 0x2022, 0x2013, 0x2014,
+    0x02dc, 0x2122, 0x0161, 0x203a, 0x0153, 0x009d, 0x017e, 0x0178,
+];
+
+impl Encoding {
+    // Accepts the usual names, as in `--encoding` or `<?xml encoding="..."?>`
+    pub fn from_label(label: &str) -> Option<Encoding> {
+        let label = label.trim().to_lowercase().replace('_', "-");
+
+        match label.as_str() {
+            "utf-8" | "utf8" => Some(Encoding::Utf8),
+            "utf-8-sig" | "utf-8-bom" | "utf8-bom" => Some(Encoding::Utf8Bom),
+            "utf-16" | "utf-16le" | "utf16" | "utf16le" | "ucs-2" | "unicode" => {
+                Some(Encoding::Utf16Le)
+            }
+            "utf-16be" | "utf16be" => Some(Encoding::Utf16Be),
+            "latin1" | "latin-1" | "iso-8859-1" | "iso8859-1" | "l1" => Some(Encoding::Latin1),
+            "windows-1252" | "cp1252" | "win1252" | "ansi" => Some(Encoding::Windows1252),
+            _ => None,
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Encoding::Utf8 => "UTF-8",
+            Encoding::Utf8Bom => "UTF-8 with BOM",
+            Encoding::Utf16Le => "UTF-16LE",
+            Encoding::Utf16Be => "UTF-16BE",
+            Encoding::Latin1 => "ISO-8859-1",
+            Encoding::Windows1252 => "Windows-1252",
+        }
+    }
+}
+
+// Encoding given by the byte order mark and the size of the mark
+pub fn sniff_bom(data: &[u8]) -> Option<(Encoding, usize)> {
+    if data.starts_with(UTF8_BOM) {
+        Some((Encoding::Utf8Bom, UTF8_BOM.len()))
+    } else if data.starts_with(UTF16LE_BOM) {
+        Some((Encoding::Utf16Le, UTF16LE_BOM.len()))
+    } else if data.starts_with(UTF16BE_BOM) {
+        Some((Encoding::Utf16Be, UTF16BE_BOM.len()))
+    } else {
+        None
+    }
+}
+
+fn decode_utf16(data: &[u8], encoding: Encoding) -> Result<String, EncodingError> {
+    if !data.len().is_multiple_of(2) {
+        return Err(EncodingError::InvalidData {
+            encoding,
+            offset: data.len() - 1,
+        });
+    }
+
+    let units = data.chunks(2).map(|c| match encoding {
+        Encoding::Utf16Be => u16::from_be_bytes([c[0], c[1]]),
+        _ => u16::from_le_bytes([c[0], c[1]]),
+    });
+
+    let mut result = String::with_capacity(data.len() / 2);
+
+    for (i, c) in char::decode_utf16(units).enumerate() {
+        match c {
+            Ok(c) => result.push(c),
+            Err(_) => {
+                return Err(EncodingError::InvalidData {
+                    encoding,
+                    offset: 2 * i,
+                });
+            }
+        }
+    }
+
+    Ok(result)
+}
+
+// Decodes the data, a byte order mark of the same encoding is skipped
+pub fn decode(data: &[u8], encoding: Encoding) -> Result<String, EncodingError> {
+    let data = match sniff_bom(data) {
+        Some((bom_encoding, len)) if bom_encoding == encoding => &data[len..],
+        Some((Encoding::Utf8Bom, len)) if encoding == Encoding::Utf8 => &data[len..],
+        _ => data,
+    };
+
+    match encoding {
+        Encoding::Utf8 | Encoding::Utf8Bom => match str::from_utf8(data) {
+            Ok(text) => Ok(text.to_string()),
+            Err(e) => Err(EncodingError::InvalidData {
+                encoding,
+                offset: e.valid_up_to(),
+            }),
+        },
+        Encoding::Utf16Le | Encoding::Utf16Be => decode_utf16(data, encoding),
+        Encoding::Latin1 => Ok(data.iter().map(|&b| b as char).collect()),
+        Encoding::Windows1252 => Ok(data
+            .iter()
+            .map(|&b| match b {
+                0x80..=0x9f => char::from_u32(WINDOWS_1252_HIGH[(b - 0x80) as usize] as u32)
+                    .unwrap_or(char::REPLACEMENT_CHARACTER),
+                _ => b as char,
+            })
+            .collect()),
+    }
+}
+
+// The byte order mark wins over the given encoding. Without both it is UTF-8, or
+// Windows-1252 when the data isn't valid UTF-8.
+pub fn decode_auto(
+    data: &[u8],
+    encoding: Option<Encoding>,
+) -> Result<(String, Encoding), EncodingError> {
+    let encoding = match (sniff_bom(data), encoding) {
+        (Some((bom_encoding, _)), _) => bom_encoding,
+        (None, Some(encoding)) => encoding,
+        (None, None) => match decode(data, Encoding::Utf8) {
+            Ok(text) => return Ok((text, Encoding::Utf8)),
+            Err(_) => Encoding::Windows1252,
+        },
+    };
+
+    Ok((decode(data, encoding)?, encoding))
+}
+
+pub fn encode(text: &str, encoding: Encoding) -> Result<Vec<u8>, EncodingError> {
+    let mut result = Vec::with_capacity(text.len() + 3);
+
+    match encoding {
+        Encoding::Utf8 => result.extend_from_slice(text.as_bytes()),
+        Encoding::Utf8Bom => {
+            result.extend_from_slice(UTF8_BOM);
+            result.extend_from_slice(text.as_bytes());
+        }
+        Encoding::Utf16Le => {
+            result.extend_from_slice(UTF16LE_BOM);
+            result.extend(text.encode_utf16().flat_map(|u| u.to_le_bytes()));
+        }
+        Encoding::Utf16Be => {
+            result.extend_from_slice(UTF16BE_BOM);
+            result.extend(text.encode_utf16().flat_map(|u| u.to_be_bytes()));
+        }
+        Encoding::Latin1 | Encoding::Windows1252 => {
+            for character in text.chars() {
+                let code = character as u32;
+
+                let byte = if encoding == Encoding::Windows1252 && (0x80..0xa0).contains(&code) {
+                    // These code points are used by the bytes defined in Windows-1252
+                    None
+                } else if code < 0x100 {
+                    Some(code as u8)
+                } else if encoding == Encoding::Windows1252 {
+                    WINDOWS_1252_HIGH
+                        .iter()
+                        .position(|&c| c as u32 == code)
+                        .map(|i| 0x80 + i as u8)
+                } else {
+                    None
+                };
+
+                // The undefined bytes map to themselves
+                let byte = byte.or_else(|| {
+                    (encoding == Encoding::Windows1252
+                        && matches!(code, 0x81 | 0x8d | 0x8f | 0x90 | 0x9d))
+                    .then_some(code as u8)
+                });
+
+                match byte {
+                    Some(b) => result.push(b),
+                    None => {
+                        return Err(EncodingError::Unmappable {
+                            encoding,
+                            character,
+                        });
+                    }
+                }
+            }
+        }
+    }
+
+    Ok(result)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_encodings() {
+        let text = "Café “quoted” – 10€ 𝄞";
+
+        for encoding in [
+            Encoding::Utf8,
+            Encoding::Utf8Bom,
+            Encoding::Utf16Le,
+            Encoding::Utf16Be,
+        ] {
+            let data = encode(text, encoding).unwrap();
+
+            assert_eq!(
+                decode_auto(&data, None).unwrap(),
+                (text.to_string(), encoding)
+            );
+        }
+
+        assert_eq!(
+            encode("é", Encoding::Utf16Le).unwrap(),
+            [0xff, 0xfe, 0xe9, 0x00]
+        );
+        assert_eq!(
+            encode("é", Encoding::Utf16Be).unwrap(),
+            [0xfe, 0xff, 0x00, 0xe9]
+        );
+
+        let cp1252 = b"Caf\xe9 \x93quoted\x94 \x96 10\x80 \x81";
+        let decoded = decode(cp1252, Encoding::Windows1252).unwrap();
+
+        assert_eq!(decoded, "Café “quoted” – 10€ \u{81}");
+        assert_eq!(encode(&decoded, Encoding::Windows1252).unwrap(), cp1252);
+
+        assert_eq!(
+            decode_auto(cp1252, None).unwrap(),
+            (decoded, Encoding::Windows1252)
+        );
+        assert!(decode_auto(cp1252, Some(Encoding::Utf8)).is_err());
+
+        assert_eq!(decode(b"\x93\xe9", Encoding::Latin1).unwrap(), "\u{93}é");
+        assert_eq!(encode("\u{93}é", Encoding::Latin1).unwrap(), b"\x93\xe9");
+
+        assert!(matches!(
+            encode("€", Encoding::Latin1),
+            Err(EncodingError::Unmappable {
+                character: '€', ..
+            })
+        ));
+        assert!(matches!(
+            encode("\u{93}", Encoding::Windows1252),
+            Err(EncodingError::Unmappable { .. })
+        ));
+        assert!(matches!(
+            decode(b"ok\xe9", Encoding::Utf8),
+            Err(EncodingError::InvalidData { offset: 2, .. })
+        ));
+        assert!(matches!(
+            decode(&[0x3d, 0xd8, 0x41, 0x00], Encoding::Utf16Le),
+            Err(EncodingError::InvalidData { offset: 0, .. })
+        ));
+
+        assert_eq!(Encoding::from_label("CP1252"), Some(Encoding::Windows1252));
+        assert_eq!(Encoding::from_label("UTF_16BE"), Some(Encoding::Utf16Be));
+        assert_eq!(Encoding::from_label("ebcdic"), None);
+    }
+}
@@ -0,0 +1,952 @@
+// Readers and writers of the supported formats behind common traits:
+//
+//     SheetReader      text formats with a single sheet, read with a csv dialect
+//     WorkbookReader   formats with worksheets, one of them is read by its name. The csv
+//                      files of a zip archive are its worksheets.
+//     SheetWriter      formats a sheet can be saved as
+//
+// `FORMATS` says what each format can do, the window and `convert` only go through it. A file
+// is recognized by its first bytes when they tell (zip and OLE containers, byte order marks,
+// XML spreadsheets), otherwise by its extension.
+//
+use std::io::Read;
+
+use crate::cell::{Inference, Progress};
+use crate::csv::{self, CSVError, Delimiter};
+use crate::encoding::Encoding;
+use crate::ods::{self, ODSError};
+use crate::sheet::Sheet;
+use crate::xls::{self, XLSError};
+use crate::xlsb::{self, XLSBError};
+use crate::xlsx::zip::{self, ZipError};
+use crate::xlsx::{self, XLSXError};
+use crate::xmlss::{self, XMLSSError};
+use crate::{convert, encoding};
+
+const ZIP_MAGIC: &[u8] = b"PK\x03\x04";
+const OLE_MAGIC: &[u8] = &[0xd0, 0xcf, 0x11, 0xe0, 0xa1, 0xb1, 0x1a, 0xe1];
+
+// Bytes read to recognize a file
+const SAMPLE_SIZE: usize = 4096;
+
+const ODF_NAMESPACE: &str = "urn:oasis:names:tc:opendocument:xmlns:office:1.0";
+const XMLSS_NAMESPACE: &str = "urn:schemas-microsoft-com:office:spreadsheet";
+
+/// The supported file formats, [`sheet_reader`], [`workbook_reader`] and [`writer`] tell
+/// what can be done with each
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum Format {
+    Csv,
+    Tsv,
+    Xlsx,
+    Xlsb,
+    Xls,
+    Ods,
+    Fods,
+    Xmlss,
+    Zip,
+    Json,
+    Markdown,
+}
+
+impl Format {
+    // Names for `--format`, the usual extension of each format
+    pub fn from_name(name: &str) -> Option<Format> {
+        match name.to_lowercase().trim_start_matches('.') {
+            "csv" => Some(Format::Csv),
+            "tsv" | "tab" => Some(Format::Tsv),
+            "xlsx" => Some(Format::Xlsx),
+            "xlsb" => Some(Format::Xlsb),
+            "xls" => Some(Format::Xls),
+            "ods" => Some(Format::Ods),
+            "fods" => Some(Format::Fods),
+            "xml" | "xmlss" => Some(Format::Xmlss),
+            "zip" => Some(Format::Zip),
+            "json" => Some(Format::Json),
+            "md" | "markdown" => Some(Format::Markdown),
+            _ => None,
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Format::Csv => "csv",
+            Format::Tsv => "tsv",
+            Format::Xlsx => "xlsx",
+            Format::Xlsb => "xlsb",
+            Format::Xls => "xls",
+            Format::Ods => "ods",
+            Format::Fods => "fods",
+            Format::Xmlss => "xml",
+            Format::Zip => "zip",
+            Format::Json => "json",
+            Format::Markdown => "md",
+        }
+    }
+
+    // Files with an unknown extension are csv, `data.tsv.gz` is a tsv file
+    pub fn from_path(path: &str) -> Format {
+        let lower = path.to_lowercase();
+        let lower = lower.strip_suffix(".gz").unwrap_or(&lower);
+
+        std::path::Path::new(lower)
+            .extension()
+            .and_then(|extension| extension.to_str())
+            .and_then(Format::from_name)
+            .unwrap_or(Format::Csv)
+    }
+
+    pub fn default_separator(self) -> char {
+        if self == Format::Tsv { '\t' } else { ',' }
+    }
+}
+
+/// How csv text is read or written
+#[derive(Copy, Clone, Debug)]
+pub struct Dialect {
+    /// Without it the one for the extension is used
+    pub separator: Option<char>,
+    pub delimiter: Delimiter,
+    /// Without it text is read as UTF-8 unless a byte order mark tells otherwise, and
+    /// written as UTF-8
+    pub encoding: Option<Encoding>,
+}
+
+impl Default for Dialect {
+    fn default() -> Self {
+        Dialect {
+            separator: None,
+            delimiter: Delimiter::DoubleQuote,
+            encoding: None,
+        }
+    }
+}
+
+/// Separators for `--sep`, `tab` stands for a tab
+pub fn parse_separator(value: &str) -> Option<char> {
+    match value {
+        "\\t" | "tab" => Some('\t'),
+        _ => {
+            let mut chars = value.chars();
+
+            match (chars.next(), chars.next()) {
+                (Some(c), None) if c.is_ascii() => Some(c),
+                _ => None,
+            }
+        }
+    }
+}
+
+/// Quotes for `--quote`
+pub fn parse_quote(value: &str) -> Option<Delimiter> {
+    match value {
+        "\"" => Some(Delimiter::DoubleQuote),
+        "'" => Some(Delimiter::SingleQuote),
+        _ => None,
+    }
+}
+
+/// An error of any reader or writer, or a format that can't do what's asked
+#[derive(Debug)]
+pub enum FormatError {
+    Io(std::io::Error),
+    Csv(CSVError),
+    Zip(ZipError),
+    Xlsx(XLSXError),
+    Xlsb(XLSBError),
+    Xls(XLSError),
+    Ods(ODSError),
+    Xmlss(XMLSSError),
+    Unsupported(String),
+}
+
+impl std::fmt::Display for FormatError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            FormatError::Io(e) => write!(f, "{e}"),
+            FormatError::Csv(e) => write!(f, "{e}"),
+            FormatError::Zip(e) => write!(f, "{e}"),
+            FormatError::Xlsx(e) => write!(f, "{e}"),
+            FormatError::Xlsb(e) => write!(f, "{e}"),
+            FormatError::Xls(e) => write!(f, "{e}"),
+            FormatError::Ods(e) => write!(f, "{e}"),
+            FormatError::Xmlss(e) => write!(f, "{e}"),
+            FormatError::Unsupported(message) => write!(f, "{message}"),
+        }
+    }
+}
+
+impl From<std::io::Error> for FormatError {
+    fn from(e: std::io::Error) -> Self {
+        FormatError::Io(e)
+    }
+}
+
+impl From<CSVError> for FormatError {
+    fn from(e: CSVError) -> Self {
+        FormatError::Csv(e)
+    }
+}
+
+impl From<ZipError> for FormatError {
+    fn from(e: ZipError) -> Self {
+        FormatError::Zip(e)
+    }
+}
+
+impl From<XLSXError> for FormatError {
+    fn from(e: XLSXError) -> Self {
+        FormatError::Xlsx(e)
+    }
+}
+
+impl From<XLSBError> for FormatError {
+    fn from(e: XLSBError) -> Self {
+        FormatError::Xlsb(e)
+    }
+}
+
+impl From<XLSError> for FormatError {
+    fn from(e: XLSError) -> Self {
+        FormatError::Xls(e)
+    }
+}
+
+impl From<ODSError> for FormatError {
+    fn from(e: ODSError) -> Self {
+        FormatError::Ods(e)
+    }
+}
+
+impl From<XMLSSError> for FormatError {
+    fn from(e: XMLSSError) -> Self {
+        FormatError::Xmlss(e)
+    }
+}
+
+/// A sheet read from a file, whatever its format
+pub struct Workbook {
+    pub filename: String,
+    /// Name of the worksheet read, empty for formats without them
+    pub worksheet: String,
+    pub sheet: Sheet,
+    /// Encoding of text formats, so they can be written back the same way
+    pub encoding: Option<Encoding>,
+}
+
+/// Reader of the text formats, which have a single sheet
+pub trait SheetReader: Sync {
+    /// The dialect comes with its separator
+    fn read_sheet(
+        &self,
+        path: &str,
+        dialect: &Dialect,
+        inference: &Inference,
+    ) -> Result<Workbook, FormatError>;
+}
+
+/// Reader of the formats with worksheets
+pub trait WorkbookReader: Sync {
+    /// Reads the worksheet named `sheet`, the first one without a name. The dialect and the
+    /// inference are only used by the csv files of zip archives.
+    fn read_workbook(
+        &self,
+        path: &str,
+        sheet: Option<&str>,
+        dialect: &Dialect,
+        inference: &Inference,
+    ) -> Result<Workbook, FormatError> {
+        self.read_workbook_with_progress(path, sheet, dialect, inference, &mut |_| true)
+    }
+
+    /// Reads like [`WorkbookReader::read_workbook`], calling `progress` with the fraction of
+    /// the worksheet read as it goes. Reading stops early, with the cells read so far, when
+    /// `progress` returns false.
+    fn read_workbook_with_progress(
+        &self,
+        path: &str,
+        sheet: Option<&str>,
+        dialect: &Dialect,
+        inference: &Inference,
+        progress: Progress,
+    ) -> Result<Workbook, FormatError>;
+}
+
+/// Writer of a format a sheet can be saved as
+pub trait SheetWriter: Sync {
+    /// The dialect comes with its separator, it's only used by csv formats
+    fn write_sheet(&self, path: &str, sheet: &Sheet, dialect: &Dialect) -> Result<(), FormatError>;
+}
+
+struct CsvFile;
+struct ZipFile;
+struct XlsxFile;
+struct XlsbFile;
+struct XlsFile;
+struct OdsFile;
+struct FodsFile;
+struct XmlssFile;
+struct JsonFile;
+struct MarkdownFile;
+
+impl SheetReader for CsvFile {
+    fn read_sheet(
+        &self,
+        path: &str,
+        dialect: &Dialect,
+        inference: &Inference,
+    ) -> Result<Workbook, FormatError> {
+        let csv = csv::read_csv_file_as_sheet(
+            path,
+            dialect.separator.unwrap_or(','),
+            dialect.delimiter,
+            dialect.encoding,
+            inference,
+        )?;
+
+        Ok(Workbook {
+            filename: csv.filename,
+            worksheet: String::new(),
+            sheet: csv.sheet,
+            encoding: Some(csv.encoding),
+        })
+    }
+}
+
+impl SheetWriter for CsvFile {
+    fn write_sheet(&self, path: &str, sheet: &Sheet, dialect: &Dialect) -> Result<(), FormatError> {
+        Ok(csv::write_csv_file(
+            path,
+            sheet,
+            dialect.separator.unwrap_or(','),
+            dialect.delimiter,
+            dialect.encoding.unwrap_or(Encoding::Utf8),
+        )?)
+    }
+}
+
+// Name of the sheet in the formats that have them
+fn sheet_name(path: &str) -> &str {
+    std::path::Path::new(path)
+        .file_stem()
+        .and_then(|s| s.to_str())
+        .unwrap_or("sheet")
+}
+
+impl WorkbookReader for ZipFile {
+    fn read_workbook_with_progress(
+        &self,
+        path: &str,
+        sheet: Option<&str>,
+        dialect: &Dialect,
+        inference: &Inference,
+        _progress: Progress,
+    ) -> Result<Workbook, FormatError> {
+        let zip = zip::Zip::from_file(path)?;
+
+        let entry = match sheet {
+            Some(entry) => entry.to_string(),
+            None => zip
+                .file_names()
+                .into_iter()
+                .find(|name| csv::is_csv_path(name))
+                .map(str::to_string)
+                .ok_or_else(|| FormatError::Unsupported(format!("{path}: no csv file in it")))?,
+        };
+
+        let csv = csv::read_csv_zip_entry_as_sheet(&zip, &entry, dialect, inference)?;
+
+        Ok(Workbook {
+            filename: path.to_string(),
+            worksheet: entry,
+            sheet: csv.sheet,
+            encoding: Some(csv.encoding),
+        })
+    }
+}
+
+impl SheetWriter for ZipFile {
+    fn write_sheet(&self, path: &str, sheet: &Sheet, dialect: &Dialect) -> Result<(), FormatError> {
+        Ok(csv::write_csv_zip(
+            path,
+            &[(sheet_name(path), sheet)],
+            dialect.separator.unwrap_or(','),
+            dialect.delimiter,
+            dialect.encoding.unwrap_or(Encoding::Utf8),
+        )?)
+    }
+}
+
+impl WorkbookReader for XlsxFile {
+    fn read_workbook_with_progress(
+        &self,
+        path: &str,
+        sheet: Option<&str>,
+        _dialect: &Dialect,
+        _inference: &Inference,
+        progress: Progress,
+    ) -> Result<Workbook, FormatError> {
+        let xlsx = xlsx::read_xlsx_file_as_hashmap(path, sheet, progress)?;
+
+        Ok(Workbook {
+            filename: xlsx.filename,
+            worksheet: xlsx.worksheet,
+            sheet: Sheet::from_cells(xlsx.map, xlsx.styles),
+            encoding: None,
+        })
+    }
+}
+
+impl WorkbookReader for XlsbFile {
+    fn read_workbook_with_progress(
+        &self,
+        path: &str,
+        sheet: Option<&str>,
+        _dialect: &Dialect,
+        _inference: &Inference,
+        progress: Progress,
+    ) -> Result<Workbook, FormatError> {
+        let xlsb = xlsb::read_xlsb_file_as_hashmap(path, sheet, progress)?;
+
+        Ok(Workbook {
+            filename: xlsb.filename,
+            worksheet: xlsb.worksheet,
+            sheet: Sheet::from_cells(xlsb.map, xlsb.styles),
+            encoding: None,
+        })
+    }
+}
+
+impl WorkbookReader for XlsFile {
+    fn read_workbook_with_progress(
+        &self,
+        path: &str,
+        sheet: Option<&str>,
+        _dialect: &Dialect,
+        _inference: &Inference,
+        progress: Progress,
+    ) -> Result<Workbook, FormatError> {
+        let xls = xls::read_xls_file_as_hashmap(path, sheet, progress)?;
+
+        Ok(Workbook {
+            filename: xls.filename,
+            worksheet: xls.worksheet,
+            sheet: Sheet::from_cells(xls.map, xls.styles),
+            encoding: None,
+        })
+    }
+}
+
+impl WorkbookReader for OdsFile {
+    fn read_workbook_with_progress(
+        &self,
+        path: &str,
+        sheet: Option<&str>,
+        _dialect: &Dialect,
+        _inference: &Inference,
+        progress: Progress,
+    ) -> Result<Workbook, FormatError> {
+        let ods = ods::read_ods_file_as_hashmap(path, sheet, progress)?;
+
+        Ok(Workbook {
+            filename: ods.filename,
+            worksheet: ods.worksheet,
+            sheet: Sheet::from(ods.map),
+            encoding: None,
+        })
+    }
+}
+
+impl SheetWriter for OdsFile {
+    fn write_sheet(
+        &self,
+        path: &str,
+        sheet: &Sheet,
+        _dialect: &Dialect,
+    ) -> Result<(), FormatError> {
+        Ok(ods::write_ods_file(path, &[(sheet_name(path), sheet)])?)
+    }
+}
+
+// The content of an ods file, without the zip around it
+impl WorkbookReader for FodsFile {
+    fn read_workbook_with_progress(
+        &self,
+        path: &str,
+        sheet: Option<&str>,
+        _dialect: &Dialect,
+        _inference: &Inference,
+        progress: Progress,
+    ) -> Result<Workbook, FormatError> {
+        let data = std::fs::read(path)?;
+        let ods = ods::read_ods_content(path, &data, sheet, progress)?;
+
+        Ok(Workbook {
+            filename: ods.filename,
+            worksheet: ods.worksheet,
+            sheet: Sheet::from(ods.map),
+            encoding: None,
+        })
+    }
+}
+
+impl WorkbookReader for XmlssFile {
+    fn read_workbook_with_progress(
+        &self,
+        path: &str,
+        sheet: Option<&str>,
+        _dialect: &Dialect,
+        _inference: &Inference,
+        _progress: Progress,
+    ) -> Result<Workbook, FormatError> {
+        let xmlss = xmlss::read_xmlss_file_as_hashmap(path, sheet)?;
+
+        Ok(Workbook {
+            filename: xmlss.filename,
+            worksheet: xmlss.worksheet,
+            sheet: Sheet::from(xmlss.map),
+            encoding: None,
+        })
+    }
+}
+
+impl SheetWriter for JsonFile {
+    fn write_sheet(
+        &self,
+        path: &str,
+        sheet: &Sheet,
+        _dialect: &Dialect,
+    ) -> Result<(), FormatError> {
+        Ok(std::fs::write(path, convert::write_json_string(sheet))?)
+    }
+}
+
+impl SheetWriter for MarkdownFile {
+    fn write_sheet(
+        &self,
+        path: &str,
+        sheet: &Sheet,
+        _dialect: &Dialect,
+    ) -> Result<(), FormatError> {
+        Ok(std::fs::write(path, convert::write_markdown_string(sheet))?)
+    }
+}
+
+struct FormatEntry {
+    format: Format,
+    sheet_reader: Option<&'static dyn SheetReader>,
+    workbook_reader: Option<&'static dyn WorkbookReader>,
+    writer: Option<&'static dyn SheetWriter>,
+}
+
+static FORMATS: &[FormatEntry] = &[
+    FormatEntry {
+        format: Format::Csv,
+        sheet_reader: Some(&CsvFile),
+        workbook_reader: None,
+        writer: Some(&CsvFile),
+    },
+    FormatEntry {
+        format: Format::Tsv,
+        sheet_reader: Some(&CsvFile),
+        workbook_reader: None,
+        writer: Some(&CsvFile),
+    },
+    FormatEntry {
+        format: Format::Xlsx,
+        sheet_reader: None,
+        workbook_reader: Some(&XlsxFile),
+        writer: None,
+    },
+    FormatEntry {
+        format: Format::Xlsb,
+        sheet_reader: None,
+        workbook_reader: Some(&XlsbFile),
+        writer: None,
+    },
+    FormatEntry {
+        format: Format::Xls,
+        sheet_reader: None,
+        workbook_reader: Some(&XlsFile),
+        writer: None,
+    },
+    FormatEntry {
+        format: Format::Ods,
+        sheet_reader: None,
+        workbook_reader: Some(&OdsFile),
+        writer: Some(&OdsFile),
+    },
+    FormatEntry {
+        format: Format::Fods,
+        sheet_reader: None,
+        workbook_reader: Some(&FodsFile),
+        writer: None,
+    },
+    FormatEntry {
+        format: Format::Xmlss,
+        sheet_reader: None,
+        workbook_reader: Some(&XmlssFile),
+        writer: None,
+    },
+    FormatEntry {
+        format: Format::Zip,
+        sheet_reader: None,
+        workbook_reader: Some(&ZipFile),
+        writer: Some(&ZipFile),
+    },
+    FormatEntry {
+        format: Format::Json,
+        sheet_reader: None,
+        workbook_reader: None,
+        writer: Some(&JsonFile),
+    },
+    FormatEntry {
+        format: Format::Markdown,
+        sheet_reader: None,
+        workbook_reader: None,
+        writer: Some(&MarkdownFile),
+    },
+];
+
+fn entry(format: Format) -> Option<&'static FormatEntry> {
+    FORMATS.iter().find(|entry| entry.format == format)
+}
+
+/// The reader of a text format, None for the other formats
+pub fn sheet_reader(format: Format) -> Option<&'static dyn SheetReader> {
+    entry(format).and_then(|entry| entry.sheet_reader)
+}
+
+/// The reader of a format with worksheets, None for the other formats
+pub fn workbook_reader(format: Format) -> Option<&'static dyn WorkbookReader> {
+    entry(format).and_then(|entry| entry.workbook_reader)
+}
+
+/// The writer of a format, None when sheets can't be saved in it
+pub fn writer(format: Format) -> Option<&'static dyn SheetWriter> {
+    entry(format).and_then(|entry| entry.writer)
+}
+
+// The dialect with the separator of the format when it has none
+fn with_separator(format: Format, dialect: &Dialect) -> Dialect {
+    Dialect {
+        separator: Some(
+            dialect
+                .separator
+                .unwrap_or_else(|| format.default_separator()),
+        ),
+        ..*dialect
+    }
+}
+
+/// Reads a file with the reader of its format, `sheet` is the worksheet of a workbook
+pub fn read_file(
+    path: &str,
+    format: Format,
+    sheet: Option<&str>,
+    dialect: &Dialect,
+    inference: &Inference,
+) -> Result<Workbook, FormatError> {
+    if let Some(reader) = workbook_reader(format) {
+        return reader.read_workbook(path, sheet, dialect, inference);
+    }
+
+    let Some(reader) = sheet_reader(format) else {
+        return Err(FormatError::Unsupported(format!(
+            "{} files can't be read",
+            format.name()
+        )));
+    };
+
+    if sheet.is_some() {
+        return Err(FormatError::Unsupported(format!(
+            "{} files have no sheets",
+            format.name()
+        )));
+    }
+
+    let dialect = Dialect {
+        separator: Some(
+            dialect
+                .separator
+                .unwrap_or_else(|| separator_of(path, format)),
+        ),
+        ..*dialect
+    };
+
+    reader.read_sheet(path, &dialect, inference)
+}
+
+/// Writes a file with the writer of its format, csv formats without a separator in the
+/// dialect use the one of the format
+pub fn write_file(
+    path: &str,
+    format: Format,
+    sheet: &Sheet,
+    dialect: &Dialect,
+) -> Result<(), FormatError> {
+    let Some(writer) = writer(format) else {
+        return Err(FormatError::Unsupported(format!(
+            "saving as {} is not supported",
+            format.name()
+        )));
+    };
+
+    writer.write_sheet(path, sheet, &with_separator(format, dialect))
+}
+
+/// The format told by the first bytes of a file. Zip files are all `Format::Zip`, see
+/// `zip_format`. Text is only recognized when it's an XML spreadsheet or has a byte order mark,
+/// which makes it csv.
+pub fn detect(sample: &[u8]) -> Option<Format> {
+    if sample.starts_with(ZIP_MAGIC) {
+        return Some(Format::Zip);
+    }
+
+    if sample.starts_with(OLE_MAGIC) {
+        return Some(Format::Xls);
+    }
+
+    let bom = encoding::sniff_bom(sample);
+
+    let text = match bom {
+        Some((encoding, _)) => encoding::decode(sample, encoding).ok()?,
+        None => String::from_utf8_lossy(sample).into_owned(),
+    };
+
+    if text.trim_start().starts_with('<') {
+        if text.contains(XMLSS_NAMESPACE) {
+            return Some(Format::Xmlss);
+        }
+
+        if text.contains(ODF_NAMESPACE) {
+            return Some(Format::Fods);
+        }
+    }
+
+    bom.map(|_| Format::Csv)
+}
+
+// Tells the formats stored in zip files apart by their parts
+fn zip_format(path: &str) -> Result<Format, ZipError> {
+    let zip = zip::Zip::from_file(path)?;
+    let names = zip.file_names();
+
+    let format = if names.contains(&"xl/workbook.xml") {
+        Format::Xlsx
+    } else if names.contains(&"xl/workbook.bin") {
+        Format::Xlsb
+    } else if names.contains(&"content.xml") {
+        Format::Ods
+    } else {
+        Format::Zip
+    };
+
+    Ok(format)
+}
+
+// The first bytes of a file, None when it can't be read, is empty or is compressed
+fn read_sample(path: &str) -> Option<Vec<u8>> {
+    let mut sample = Vec::new();
+
+    let read = std::fs::File::open(path)
+        .and_then(|file| file.take(SAMPLE_SIZE as u64).read_to_end(&mut sample));
+
+    if read.is_err() || sample.is_empty() || csv::is_gzip_path(path) {
+        return None;
+    }
+
+    Some(sample)
+}
+
+fn has_binary_extension(path: &str) -> bool {
+    matches!(
+        Format::from_path(path),
+        Format::Xlsx | Format::Xlsb | Format::Xls | Format::Ods | Format::Zip
+    )
+}
+
+/// The format of a file from its first bytes, or from its extension when they don't tell. Text
+/// with the extension of a binary format, like csv saved as `.xls`, is csv or tsv as its first
+/// lines tell.
+pub fn format_of(path: &str) -> Format {
+    let by_extension = Format::from_path(path);
+
+    let Some(sample) = read_sample(path) else {
+        return by_extension;
+    };
+
+    match detect(&sample) {
+        Some(Format::Zip) => zip_format(path).unwrap_or(Format::Zip),
+        Some(Format::Csv) | None if has_binary_extension(path) => {
+            match csv::sniff_dialect(&sample) {
+                ('\t', _) => Format::Tsv,
+                _ => Format::Csv,
+            }
+        }
+        // Text, the extension tells csv from tsv, json or markdown
+        Some(Format::Csv) | None => by_extension,
+        Some(format) => format,
+    }
+}
+
+/// The separator of a csv file read without one given, the one of its format. Text with the
+/// extension of a binary format has it guessed from its first lines.
+pub fn separator_of(path: &str, format: Format) -> char {
+    if !has_binary_extension(path) {
+        return format.default_separator();
+    }
+
+    match read_sample(path) {
+        Some(sample) => csv::sniff_dialect(&sample).0,
+        None => format.default_separator(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cell::Cell;
+
+    #[test]
+    fn test_formats() {
+        assert_eq!(detect(b"PK\x03\x04\x14\x00"), Some(Format::Zip));
+        assert_eq!(detect(OLE_MAGIC), Some(Format::Xls));
+        assert_eq!(detect(b"\xef\xbb\xbfa,b\n"), Some(Format::Csv));
+        assert_eq!(detect(b"\xff\xfea\x00,\x00b\x00"), Some(Format::Csv));
+        assert_eq!(detect(b"a,b\n1,2\n"), None);
+        assert_eq!(
+            detect(b"<?xml version=\"1.0\"?>\n<Workbook xmlns=\"urn:schemas-microsoft-com:office:spreadsheet\">"),
+            Some(Format::Xmlss)
+        );
+        assert_eq!(
+            detect(b" <office:document xmlns:office=\"urn:oasis:names:tc:opendocument:xmlns:office:1.0\">"),
+            Some(Format::Fods)
+        );
+
+        for name in [
+            "csv", "tsv", "xlsx", "xlsb", "xls", "ods", "fods", "xml", "zip", "json", "md",
+        ] {
+            let format = Format::from_name(name).unwrap();
+
+            assert_eq!(format.name(), name);
+            assert!(
+                sheet_reader(format).is_some()
+                    || workbook_reader(format).is_some()
+                    || writer(format).is_some()
+            );
+        }
+
+        let mut sheet = Sheet::new();
+
+        sheet.set(0, 0, Cell::text("name"));
+        sheet.set(1, 0, Cell::text("a;b"));
+        sheet.set(1, 1, Cell::Number(2.5));
+
+        let dir = std::env::temp_dir();
+        let path = |name: &str| {
+            dir.join(format!("csvim-format-{}-{name}", std::process::id()))
+                .to_str()
+                .unwrap()
+                .to_string()
+        };
+
+        // An ods file named like an xlsx one, and a tsv file named like an xls one
+        let ods = path("book.xlsx");
+        let tsv = path("data.xls");
+        let zip = path("data.zip");
+        let semicolon = path("semicolon.xlsx");
+
+        write_file(&ods, Format::Ods, &sheet, &Dialect::default()).unwrap();
+        write_file(&tsv, Format::Tsv, &sheet, &Dialect::default()).unwrap();
+        write_file(&zip, Format::Zip, &sheet, &Dialect::default()).unwrap();
+
+        let semicolon_dialect = Dialect {
+            separator: Some(';'),
+            ..Dialect::default()
+        };
+        write_file(&semicolon, Format::Csv, &sheet, &semicolon_dialect).unwrap();
+
+        assert_eq!(format_of(&ods), Format::Ods);
+        assert_eq!(format_of(&tsv), Format::Tsv);
+        assert_eq!(format_of(&zip), Format::Zip);
+        assert_eq!(format_of(&semicolon), Format::Csv);
+        assert_eq!(separator_of(&semicolon, Format::Csv), ';');
+        assert_eq!(separator_of(&path("data.csv"), Format::Csv), ',');
+        assert_eq!(format_of(&path("missing.tsv")), Format::Tsv);
+
+        let workbook = read_file(
+            &ods,
+            format_of(&ods),
+            None,
+            &Dialect::default(),
+            &Inference::all(),
+        )
+        .unwrap();
+
+        assert_eq!(workbook.sheet.display(1, 0), "a;b");
+        assert_eq!(workbook.sheet.get(1, 1), Cell::Number(2.5));
+
+        let dialect = Dialect {
+            separator: Some('\t'),
+            ..Dialect::default()
+        };
+        let workbook = read_file(&tsv, Format::Csv, None, &dialect, &Inference::all()).unwrap();
+
+        assert_eq!(workbook.sheet.display(1, 0), "a;b");
+        assert_eq!(workbook.encoding, Some(Encoding::Utf8));
+        assert!(matches!(
+            read_file(
+                &tsv,
+                Format::Csv,
+                Some("Sheet1"),
+                &dialect,
+                &Inference::all()
+            ),
+            Err(FormatError::Unsupported(_))
+        ));
+        assert!(matches!(
+            write_file(&tsv, Format::Xlsx, &sheet, &dialect),
+            Err(FormatError::Unsupported(_))
+        ));
+
+        let workbook = read_file(
+            &semicolon,
+            format_of(&semicolon),
+            None,
+            &Dialect::default(),
+            &Inference::all(),
+        )
+        .unwrap();
+
+        assert_eq!(workbook.sheet.display(1, 0), "a;b");
+        assert_eq!(workbook.sheet.get(1, 1), Cell::Number(2.5));
+
+        // The csv files of a zip archive are its sheets
+        let workbook = read_file(
+            &zip,
+            Format::Zip,
+            None,
+            &Dialect::default(),
+            &Inference::all(),
+        )
+        .unwrap();
+
+        assert!(workbook.worksheet.ends_with("data.csv"));
+        assert_eq!(workbook.sheet.display(1, 0), "a;b");
+        assert!(matches!(
+            read_file(
+                &zip,
+                Format::Zip,
+                Some("missing.csv"),
+                &Dialect::default(),
+                &Inference::all()
+            ),
+            Err(FormatError::Csv(CSVError::Zip(_)))
+        ));
+
+        for path in [ods, tsv, zip, semicolon] {
+            std::fs::remove_file(path).unwrap();
+        }
+    }
+}
@@ -0,0 +1,468 @@
+// Lazy reading of large CSV files. Opening one doesn't read it: a background thread scans the
+// file for the start of every `BLOCK_ROWS`th row, and the rows are parsed a block at a time
+// when they're shown. The row count grows while the scan goes on.
+//
+//     file     |row 0 ... row 1023|row 1024 ... row 2047|row 2048 ...
+//     offsets   ^                  ^                     ^
+//
+// Rows are found by `csv::RowScanner`, so a block parses to the same rows as the whole file
+// would. Searches run on a thread of their own too, a block at a time, and can be cancelled.
+//
+use std::cell::RefCell;
+use std::fs::File;
+use std::io::{Read, Seek, SeekFrom};
+use std::rc::Rc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread::JoinHandle;
+
+use crate::cell::Inference;
+use crate::csv::{self, CSVError, Delimiter};
+use crate::encoding::Encoding;
+use crate::loader::lock;
+use crate::sheet::Sheet;
+
+const BLOCK_ROWS: usize = 1024;
+const SCAN_CHUNK: usize = 1 << 20;
+const CACHED_BLOCKS: usize = 8;
+
+// Files from this size on are opened lazily
+pub const LAZY_THRESHOLD: u64 = 64 << 20;
+
+const UTF8_BOM: &[u8] = &[0xef, 0xbb, 0xbf];
+
+#[derive(Default)]
+struct Index {
+    // Offset of the first row of each block
+    offsets: Vec<u64>,
+    rows: usize,
+    // End of the last row found, the rows after it aren't known yet
+    last_row_end: u64,
+    scanned: u64,
+    complete: bool,
+    error: Option<String>,
+}
+
+pub struct Progress {
+    pub rows: usize,
+    pub scanned: u64,
+    pub total: u64,
+    pub complete: bool,
+    pub error: Option<String>,
+}
+
+// How the blocks are parsed
+#[derive(Clone)]
+struct BlockReader {
+    filename: String,
+    separator: char,
+    delimiter: Delimiter,
+    inference: Inference,
+}
+
+#[derive(Default)]
+struct SearchState {
+    found: Vec<(usize, usize)>,
+    done: bool,
+}
+
+// A search of the rows found so far, the matches come in block by block. Dropping it
+// stops the search.
+pub struct Search {
+    state: Arc<Mutex<SearchState>>,
+    cancel: Arc<AtomicBool>,
+}
+
+pub struct LazyCSV {
+    pub filename: String,
+    reader: BlockReader,
+    file: RefCell<File>,
+    file_len: u64,
+    index: Arc<Mutex<Index>>,
+    cancel: Arc<AtomicBool>,
+    scanner: Option<JoinHandle<()>>,
+    // Parsed blocks with the offset where they end, the most recently used first
+    cache: RefCell<Vec<(usize, u64, Rc<Sheet>)>>,
+}
+
+// Lazy reading needs byte offsets that don't depend on the decoding, so only UTF-8 files
+// (with or without a byte order mark) can be read this way
+pub fn is_lazy_candidate(filename: &str, encoding: Option<Encoding>) -> bool {
+    if csv::is_gzip_path(filename)
+        || !matches!(encoding, None | Some(Encoding::Utf8 | Encoding::Utf8Bom))
+    {
+        return false;
+    }
+
+    let Ok(metadata) = std::fs::metadata(filename) else {
+        return false;
+    };
+
+    if metadata.len() < LAZY_THRESHOLD {
+        return false;
+    }
+
+    let mut start = [0u8; 2];
+
+    match File::open(filename).and_then(|mut f| f.read_exact(&mut start)) {
+        Ok(()) => start != [0xff, 0xfe] && start != [0xfe, 0xff],
+        Err(_) => false,
+    }
+}
+
+fn scan(
+    mut file: File,
+    start: u64,
+    separator: char,
+    delimiter: Delimiter,
+    index: &Mutex<Index>,
+    cancel: &AtomicBool,
+) {
+    let mut chunk = vec![0u8; SCAN_CHUNK];
+
+    let mut position = start;
+    let mut row_start = start;
+    let mut rows = 0;
+    let mut scanner = csv::RowScanner::new(separator, delimiter);
+
+    if let Err(e) = file.seek(SeekFrom::Start(start)) {
+        lock(index).error = Some(e.to_string());
+        return;
+    }
+
+    loop {
+        if cancel.load(Ordering::Relaxed) {
+            return;
+        }
+
+        let n = match file.read(&mut chunk) {
+            Ok(0) => break,
+            Ok(n) => n,
+            Err(e) => {
+                lock(index).error = Some(e.to_string());
+                return;
+            }
+        };
+
+        let mut offsets = Vec::new();
+
+        for &c in &chunk[..n] {
+            position += 1;
+
+            if scanner.feed(c) {
+                rows += 1;
+                row_start = position;
+
+                if rows % BLOCK_ROWS == 0 {
+                    offsets.push(row_start);
+                }
+            }
+        }
+
+        let mut index = lock(index);
+
+        index.offsets.extend(offsets);
+        index.rows = rows;
+        index.last_row_end = row_start;
+        index.scanned = position - start;
+    }
+
+    let mut index = lock(index);
+
+    // A last row without a newline
+    if position > row_start {
+        index.rows = rows + 1;
+        index.last_row_end = position;
+    }
+
+    index.scanned = position - start;
+    index.complete = true;
+}
+
+// Start and end offsets of a block, the last block ends at the last row found
+fn block_range(index: &Mutex<Index>, block: usize) -> Option<(u64, u64)> {
+    let index = lock(index);
+
+    let start = *index.offsets.get(block)?;
+    let end = match index.offsets.get(block + 1) {
+        Some(&end) => end,
+        None => index.last_row_end,
+    };
+
+    (end > start).then_some((start, end))
+}
+
+impl BlockReader {
+    fn read_block(&self, file: &mut File, start: u64, end: u64) -> Option<Sheet> {
+        let mut data = vec![0u8; (end - start) as usize];
+
+        file.seek(SeekFrom::Start(start)).ok()?;
+        file.read_exact(&mut data).ok()?;
+
+        let text = String::from_utf8_lossy(&data);
+
+        Some(
+            csv::read_csv_data_as_sheet(
+                &self.filename,
+                &text,
+                self.separator,
+                self.delimiter,
+                &self.inference,
+            )
+            .sheet,
+        )
+    }
+}
+
+// Searches the blocks one after the other, without going through the cache of the window
+fn search(
+    mut file: File,
+    reader: &BlockReader,
+    needle: &str,
+    index: &Mutex<Index>,
+    state: &Mutex<SearchState>,
+    cancel: &AtomicBool,
+) {
+    let mut block = 0;
+
+    while let Some((start, end)) = block_range(index, block) {
+        if cancel.load(Ordering::Relaxed) {
+            break;
+        }
+
+        if let Some(sheet) = reader.read_block(&mut file, start, end) {
+            lock(state).found.extend(
+                sheet
+                    .find(needle)
+                    .into_iter()
+                    .map(|(row, col)| (block * BLOCK_ROWS + row, col)),
+            );
+        }
+
+        block += 1;
+    }
+}
+
+impl Search {
+    // The matches found since the last call, row by row
+    pub fn take_found(&self) -> Vec<(usize, usize)> {
+        std::mem::take(&mut lock(&self.state).found)
+    }
+
+    pub fn is_done(&self) -> bool {
+        lock(&self.state).done
+    }
+}
+
+impl Drop for Search {
+    fn drop(&mut self) {
+        self.cancel.store(true, Ordering::Relaxed);
+    }
+}
+
+impl LazyCSV {
+    pub fn open(
+        filename: &str,
+        separator: char,
+        delimiter: Delimiter,
+        inference: Inference,
+    ) -> Result<LazyCSV, CSVError> {
+        let mut file = File::open(filename)?;
+        let file_len = file.metadata()?.len();
+
+        let mut bom = [0u8; 3];
+        let start = match file.read_exact(&mut bom) {
+            Ok(()) if bom == UTF8_BOM => 3,
+            _ => 0,
+        };
+
+        let index = Arc::new(Mutex::new(Index {
+            offsets: vec![start],
+            last_row_end: start,
+            ..Index::default()
+        }));
+        let cancel = Arc::new(AtomicBool::new(false));
+
+        let scanner = {
+            let file = File::open(filename)?;
+            let index = Arc::clone(&index);
+            let cancel = Arc::clone(&cancel);
+
+            std::thread::spawn(move || scan(file, start, separator, delimiter, &index, &cancel))
+        };
+
+        Ok(LazyCSV {
+            filename: filename.to_string(),
+            reader: BlockReader {
+                filename: filename.to_string(),
+                separator,
+                delimiter,
+                inference,
+            },
+            file: RefCell::new(file),
+            file_len,
+            index,
+            cancel,
+            scanner: Some(scanner),
+            cache: RefCell::new(Vec::new()),
+        })
+    }
+
+    // Rows found so far
+    pub fn rows(&self) -> usize {
+        lock(&self.index).rows
+    }
+
+    pub fn progress(&self) -> Progress {
+        let index = lock(&self.index);
+
+        Progress {
+            rows: index.rows,
+            scanned: index.scanned,
+            total: self.file_len,
+            complete: index.complete,
+            error: index.error.clone(),
+        }
+    }
+
+    fn block(&self, block: usize) -> Option<Rc<Sheet>> {
+        let (start, end) = block_range(&self.index, block)?;
+
+        let mut cache = self.cache.borrow_mut();
+
+        if let Some(i) = cache.iter().position(|&(b, e, _)| b == block && e == end) {
+            let entry = cache.remove(i);
+            let sheet = Rc::clone(&entry.2);
+            cache.insert(0, entry);
+
+            return Some(sheet);
+        }
+
+        let sheet = Rc::new(
+            self.reader
+                .read_block(&mut self.file.borrow_mut(), start, end)?,
+        );
+
+        cache.retain(|&(b, _, _)| b != block);
+        cache.insert(0, (block, end, Rc::clone(&sheet)));
+        cache.truncate(CACHED_BLOCKS);
+
+        Some(sheet)
+    }
+
+    pub fn display(&self, row: usize, col: usize) -> String {
+        match self.block(row / BLOCK_ROWS) {
+            Some(sheet) => sheet.display(row % BLOCK_ROWS, col).into_owned(),
+            None => String::new(),
+        }
+    }
+
+    // Searches the rows found so far on a worker thread
+    pub fn search(&self, needle: &str) -> Search {
+        let search = Search {
+            state: Arc::new(Mutex::new(SearchState::default())),
+            cancel: Arc::new(AtomicBool::new(false)),
+        };
+
+        let file = File::open(&self.filename);
+        let reader = self.reader.clone();
+        let needle = needle.to_string();
+        let index = Arc::clone(&self.index);
+        let state = Arc::clone(&search.state);
+        let cancel = Arc::clone(&search.cancel);
+
+        std::thread::spawn(move || {
+            if let Ok(file) = file {
+                self::search(file, &reader, &needle, &index, &state, &cancel);
+            }
+
+            lock(&state).done = true;
+        });
+
+        search
+    }
+}
+
+impl Drop for LazyCSV {
+    fn drop(&mut self) {
+        self.cancel.store(true, Ordering::Relaxed);
+
+        if let Some(scanner) = self.scanner.take() {
+            let _ = scanner.join();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_lazy_csv() {
+        let mut data = String::from("\u{feff}id,text\n");
+
+        for i in 0..2500 {
+            match i % 4 {
+                0 => data.push_str(&format!("{i},\"two\nlines\"\n")),
+                1 => data.push_str(&format!("{i},\"say \"\"hi\"\"\"\n")),
+                2 => data.push_str(&format!("{i},\"escaped \\\" quote\"\n")),
+                _ => data.push_str(&format!("{i},plain\n")),
+            }
+        }
+
+        data.push_str("last,row");
+
+        let path = std::env::temp_dir().join(format!("csvim-lazy-{}.csv", std::process::id()));
+        std::fs::write(&path, &data).unwrap();
+
+        let filename = path.to_str().unwrap();
+        let lazy = LazyCSV::open(filename, ',', Delimiter::DoubleQuote, Inference::all()).unwrap();
+
+        while !lazy.progress().complete {
+            std::thread::sleep(std::time::Duration::from_millis(1));
+        }
+
+        let full = csv::read_csv_data_as_sheet(
+            filename,
+            data.strip_prefix('\u{feff}').unwrap(),
+            ',',
+            Delimiter::DoubleQuote,
+            &Inference::all(),
+        )
+        .sheet;
+
+        let progress = lazy.progress();
+
+        assert_eq!(progress.rows, full.rows());
+        assert_eq!(progress.rows, 2502);
+        assert_eq!(progress.scanned + 3, progress.total);
+        assert!(progress.error.is_none());
+
+        for row in [0, 1, 2, 3, 1023, 1024, 1025, 2047, 2048, 2500, 2501, 2502] {
+            for col in 0..3 {
+                assert_eq!(
+                    lazy.display(row, col),
+                    full.display(row, col),
+                    "{row},{col}"
+                );
+            }
+        }
+
+        let find = |needle: &str| {
+            let search = lazy.search(needle);
+
+            while !search.is_done() {
+                std::thread::sleep(std::time::Duration::from_millis(1));
+            }
+
+            search.take_found()
+        };
+
+        assert_eq!(lazy.display(2501, 1), "row");
+        assert_eq!(find("\"HI\""), full.find("\"HI\""));
+        assert_eq!(find("last"), [(2501, 0)]);
+
+        drop(lazy);
+        std::fs::remove_file(&path).unwrap();
+    }
+}
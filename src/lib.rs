@@ -0,0 +1,71 @@
+//! Reading and writing of spreadsheets and csv files, the library under the csvim editor. It
+//! has no dependencies and doesn't link raylib, the window is only in the `csvim` binary.
+//!
+//! - [`read`] and [`write()`] open and save a sheet in any supported format. [`format`](mod@format) has the
+//!   readers and writers of each format behind common traits, and tells formats apart by the
+//!   first bytes of the files.
+//! - A [`Sheet`] holds typed [`Cell`]s, addressed by row and column counted from 0.
+//!   [`cell::parse_address`] and [`cell::address`] convert from and to `B3` style addresses.
+//! - A [`Dialect`] describes csv text: separator, quote and encoding. [`Inference`] picks the
+//!   types recognized in csv cells.
+//! - Every reader has an error type of its own, [`FormatError`] holds any of them.
+//!
+//! The zip reader and writer, the XML parser and the inflate and deflate codecs used by the
+//! formats are in [`xlsx::zip`] and [`xlsx::xml`].
+//!
+//! ```no_run
+//! use csvim::cell::parse_address;
+//!
+//! let workbook = csvim::read("report.xlsx", Some("Q1"))?;
+//! let (row, col) = parse_address("D11").unwrap();
+//!
+//! println!("{}", workbook.sheet.display(row, col));
+//!
+//! csvim::write("report.csv", &workbook.sheet)?;
+//! # Ok::<(), csvim::FormatError>(())
+//! ```
+
+pub mod cell;
+pub mod convert;
+pub mod csv;
+pub mod encoding;
+pub mod format;
+pub mod lazy;
+pub mod loader;
+pub mod ods;
+pub mod sheet;
+pub mod xls;
+pub mod xlsb;
+pub mod xlsx;
+pub mod xmlss;
+
+/// Cell values and the types inferred from csv text
+pub use cell::{Cell, Inference};
+/// The quote around csv fields
+pub use csv::Delimiter;
+/// Text encodings of csv files
+pub use encoding::Encoding;
+/// How files are read and written, see [`format`](mod@format)
+pub use format::{Dialect, Format, FormatError, Workbook};
+/// The cells of a sheet
+pub use sheet::Sheet;
+
+/// Reads a sheet of a file, its format comes from its content or its extension. `sheet` names
+/// the worksheet of a workbook, the first one is read without it. Csv files are read with
+/// the separator of their extension and every type inferred, see [`format::read_file`] for
+/// other dialects.
+pub fn read(path: &str, sheet: Option<&str>) -> Result<Workbook, FormatError> {
+    format::read_file(
+        path,
+        format::format_of(path),
+        sheet,
+        &Dialect::default(),
+        &Inference::all(),
+    )
+}
+
+/// Writes a sheet in the format of the extension of `path`, csv in UTF-8 with `,` when the
+/// extension is not known. See [`format::write_file`] for other dialects.
+pub fn write(path: &str, sheet: &Sheet) -> Result<(), FormatError> {
+    format::write_file(path, Format::from_path(path), sheet, &Dialect::default())
+}
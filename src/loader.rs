@@ -0,0 +1,311 @@
+// Loading of files on a worker thread, so the window opens right away. The worker fills a
+// sheet shared with the window, which shows the rows read so far, and reports how far it got.
+//
+// Csv files are parsed in parts cut at row ends, each part shows up as soon as it's parsed.
+// The other formats show up at the end, their readers report how far they got and stop when
+// the load is cancelled.
+//
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex, MutexGuard, PoisonError};
+
+use crate::cell::Inference;
+use crate::csv::{self, Delimiter};
+use crate::encoding::Encoding;
+use crate::format::Dialect;
+use crate::sheet::Sheet;
+
+// Bytes of csv text parsed between two updates of the window
+const CSV_PART: usize = 4 << 20;
+
+pub enum Status {
+    // Fraction done, when it's known
+    Loading(Option<f32>),
+    // With the dialect csv text was read with
+    Done(Option<Dialect>),
+    Failed(String),
+}
+
+#[derive(Default)]
+struct State {
+    progress: Option<f32>,
+    result: Option<Result<Option<Dialect>, String>>,
+}
+
+// A worker that panicked while holding a lock leaves what it read so far, which is kept
+pub fn lock<T>(mutex: &Mutex<T>) -> MutexGuard<'_, T> {
+    mutex.lock().unwrap_or_else(PoisonError::into_inner)
+}
+
+// What the worker reads into and reports through
+pub struct Task {
+    sheet: Arc<Mutex<Sheet>>,
+    state: Arc<Mutex<State>>,
+    cancel: Arc<AtomicBool>,
+}
+
+impl Task {
+    pub fn is_cancelled(&self) -> bool {
+        self.cancel.load(Ordering::Relaxed)
+    }
+
+    pub fn set_progress(&self, fraction: f32) {
+        lock(&self.state).progress = Some(fraction);
+    }
+
+    pub fn sheet(&self) -> MutexGuard<'_, Sheet> {
+        lock(&self.sheet)
+    }
+}
+
+pub struct Loader {
+    pub filename: String,
+    sheet: Arc<Mutex<Sheet>>,
+    state: Arc<Mutex<State>>,
+    cancel: Arc<AtomicBool>,
+}
+
+// Parses the text in parts of about `part_size` bytes
+fn load_csv(
+    task: &Task,
+    text: &str,
+    separator: char,
+    delimiter: Delimiter,
+    inference: &Inference,
+    part_size: usize,
+) {
+    let mut scanner = csv::RowScanner::new(separator, delimiter);
+
+    let mut start = 0;
+    let mut first_row = 0;
+    let mut rows = 0;
+
+    for (i, c) in text.bytes().enumerate() {
+        if !scanner.feed(c) {
+            continue;
+        }
+
+        rows += 1;
+
+        if i + 1 - start < part_size {
+            continue;
+        }
+
+        if task.is_cancelled() {
+            return;
+        }
+
+        let part = &text[start..i + 1];
+        csv::read_csv_data_into(
+            &mut task.sheet(),
+            first_row,
+            part,
+            separator,
+            delimiter,
+            inference,
+        );
+        task.set_progress((i + 1) as f32 / text.len() as f32);
+
+        start = i + 1;
+        first_row = rows;
+    }
+
+    let part = &text[start..];
+    csv::read_csv_data_into(
+        &mut task.sheet(),
+        first_row,
+        part,
+        separator,
+        delimiter,
+        inference,
+    );
+}
+
+impl Loader {
+    // Runs `load` on a worker thread, it returns the dialect of text formats
+    pub fn spawn(
+        filename: &str,
+        load: impl FnOnce(&Task) -> Result<Option<Dialect>, String> + Send + 'static,
+    ) -> Loader {
+        let task = Task {
+            sheet: Arc::new(Mutex::new(Sheet::new())),
+            state: Arc::new(Mutex::new(State::default())),
+            cancel: Arc::new(AtomicBool::new(false)),
+        };
+
+        let loader = Loader {
+            filename: filename.to_string(),
+            sheet: Arc::clone(&task.sheet),
+            state: Arc::clone(&task.state),
+            cancel: Arc::clone(&task.cancel),
+        };
+
+        std::thread::spawn(move || {
+            // A reader that panics on a malformed file fails the load instead of leaving it
+            // loading forever
+            let result =
+                panic::catch_unwind(AssertUnwindSafe(|| load(&task))).unwrap_or_else(|e| {
+                    let what = match e.downcast_ref::<&str>() {
+                        Some(what) => what.to_string(),
+                        None => e.downcast_ref::<String>().cloned().unwrap_or_default(),
+                    };
+
+                    Err(format!("reading failed: {what}"))
+                });
+
+            lock(&task.state).result = Some(result);
+        });
+
+        loader
+    }
+
+    pub fn csv(
+        filename: &str,
+        separator: char,
+        delimiter: Delimiter,
+        encoding: Option<Encoding>,
+        inference: Inference,
+    ) -> Loader {
+        let path = filename.to_string();
+
+        Loader::spawn(filename, move |task| {
+            let (text, encoding) =
+                csv::read_csv_text(&path, encoding).map_err(|e| e.to_string())?;
+
+            load_csv(task, &text, separator, delimiter, &inference, CSV_PART);
+
+            Ok(Some(Dialect {
+                separator: Some(separator),
+                delimiter,
+                encoding: Some(encoding),
+            }))
+        })
+    }
+
+    pub fn status(&self) -> Status {
+        let state = lock(&self.state);
+
+        match &state.result {
+            None => Status::Loading(state.progress),
+            Some(Ok(dialect)) => Status::Done(*dialect),
+            Some(Err(e)) => Status::Failed(e.clone()),
+        }
+    }
+
+    // Stops the worker, what was read so far is kept
+    pub fn cancel(&self) {
+        self.cancel.store(true, Ordering::Relaxed);
+    }
+
+    // Takes the sheet out of the loader once it's done or cancelled
+    pub fn take_sheet(&self) -> Sheet {
+        std::mem::take(&mut *lock(&self.sheet))
+    }
+
+    pub fn rows(&self) -> usize {
+        lock(&self.sheet).rows()
+    }
+
+    pub fn display(&self, row: usize, col: usize) -> String {
+        lock(&self.sheet).display(row, col).into_owned()
+    }
+
+    pub fn find(&self, needle: &str) -> Vec<(usize, usize)> {
+        lock(&self.sheet).find(needle)
+    }
+}
+
+impl Drop for Loader {
+    fn drop(&mut self) {
+        self.cancel();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cell::Cell;
+
+    #[test]
+    fn test_loader() {
+        let text = "id,text\n1,\"two\nlines\"\n2,\"say \"\"hi\"\"\"\n3,plain\n4,last";
+
+        let loader = Loader::spawn("parts.csv", |task| {
+            load_csv(
+                task,
+                text,
+                ',',
+                Delimiter::DoubleQuote,
+                &Inference::all(),
+                10,
+            );
+            Ok(Some(Dialect {
+                encoding: Some(Encoding::Utf8),
+                ..Dialect::default()
+            }))
+        });
+
+        while let Status::Loading(_) = loader.status() {
+            std::thread::sleep(std::time::Duration::from_millis(1));
+        }
+
+        let full = csv::read_csv_data_as_sheet(
+            "parts.csv",
+            text,
+            ',',
+            Delimiter::DoubleQuote,
+            &Inference::all(),
+        )
+        .sheet;
+
+        assert!(matches!(
+            loader.status(),
+            Status::Done(Some(Dialect {
+                encoding: Some(Encoding::Utf8),
+                ..
+            }))
+        ));
+        assert_eq!(loader.rows(), 5);
+        assert_eq!(loader.find("LINES"), [(1, 1)]);
+
+        let sheet = loader.take_sheet();
+
+        assert_eq!(
+            sheet.cells().collect::<Vec<_>>(),
+            full.cells().collect::<Vec<_>>()
+        );
+        assert_eq!(loader.rows(), 0);
+
+        let loader = Loader::spawn("missing.xlsx", |task| {
+            while !task.is_cancelled() {
+                std::thread::sleep(std::time::Duration::from_millis(1));
+            }
+
+            Err(String::from("cancelled"))
+        });
+
+        assert!(matches!(loader.status(), Status::Loading(None)));
+
+        loader.cancel();
+
+        while let Status::Loading(_) = loader.status() {
+            std::thread::sleep(std::time::Duration::from_millis(1));
+        }
+
+        assert!(matches!(loader.status(), Status::Failed(e) if e == "cancelled"));
+
+        let loader = Loader::spawn("bad.xlsx", |task| {
+            task.sheet().set(0, 0, Cell::Text("read".to_string()));
+            let _sheet = task.sheet();
+
+            panic!("bad offset")
+        });
+
+        while let Status::Loading(_) = loader.status() {
+            std::thread::sleep(std::time::Duration::from_millis(1));
+        }
+
+        assert!(matches!(loader.status(), Status::Failed(e) if e.contains("bad offset")));
+        assert_eq!(loader.display(0, 0), "read");
+    }
+}
@@ -1,6 +1,7 @@
-pub mod csv;
+pub mod args;
 pub mod rl;
-pub mod xlsx;
+
+use csvim::{cell, convert, csv, format, lazy, loader, sheet, xlsx};
 
 struct Buffer {
     data: [u8; 64],
@@ -98,24 +99,507 @@ const CELL_PAD: i32 = 3;
 const FONT_DATA: &[u8; 101748] = include_bytes!("../Inconsolata-Regular.ttf");
 const BOLD_FONT_DATA: &[u8; 102148] = include_bytes!("../Inconsolata-Bold.ttf");
 
-type CellMap = std::collections::HashMap<(usize, usize), String>;
+// Splits "archive.zip:path/inside.csv" into the archive and the entry path
+fn split_archive_path(path: &str) -> Option<(&str, &str)> {
+    if std::path::Path::new(path).exists() {
+        return None;
+    }
+
+    let idx = path
+        .as_bytes()
+        .windows(5)
+        .position(|w| w.eq_ignore_ascii_case(b".zip:"))?;
 
-fn main() {
-    if std::env::args().len() < 2 {
-        println!("Usage: csvim FILENAME");
-        return;
+    Some((&path[..idx + 4], &path[idx + 5..]))
+}
+
+// Cells of the open file. Large csv files are read as their rows are shown, the others are
+// loaded in the background and shown as they arrive.
+enum Cells {
+    Sheet(sheet::Sheet),
+    Lazy(lazy::LazyCSV),
+    Loading(loader::Loader),
+}
+
+impl Cells {
+    // Rows known so far, a lazy file is still being indexed
+    fn rows(&self) -> usize {
+        match self {
+            Cells::Sheet(sheet) => sheet.rows(),
+            Cells::Lazy(lazy) => lazy.rows(),
+            Cells::Loading(loader) => loader.rows(),
+        }
+    }
+
+    fn display(&self, row: usize, col: usize) -> std::borrow::Cow<'_, str> {
+        match self {
+            Cells::Sheet(sheet) => sheet.display(row, col),
+            Cells::Lazy(lazy) => std::borrow::Cow::Owned(lazy.display(row, col)),
+            Cells::Loading(loader) => std::borrow::Cow::Owned(loader.display(row, col)),
+        }
+    }
+
+    // Whether all the rows are known
+    fn is_complete(&self) -> bool {
+        match self {
+            Cells::Sheet(_) => true,
+            Cells::Lazy(lazy) => lazy.progress().complete,
+            Cells::Loading(_) => false,
+        }
+    }
+
+    // The matches, a large file is searched on a worker thread and its matches come later
+    fn find(&self, needle: &str) -> (Vec<(usize, usize)>, Option<lazy::Search>) {
+        match self {
+            Cells::Sheet(sheet) => (sheet.find(needle), None),
+            Cells::Lazy(lazy) => (Vec::new(), Some(lazy.search(needle))),
+            Cells::Loading(loader) => (loader.find(needle), None),
+        }
+    }
+
+    // Row count for the status bar, with the progress of the indexing
+    fn status(&self) -> String {
+        match self {
+            Cells::Sheet(sheet) => format!("{} rows", sheet.rows()),
+            Cells::Lazy(lazy) => {
+                let progress = lazy.progress();
+
+                if let Some(e) = progress.error {
+                    format!("{} rows ({e})", progress.rows)
+                } else if progress.complete {
+                    format!("{} rows", progress.rows)
+                } else {
+                    let percent = progress.scanned * 100 / progress.total.max(1);
+
+                    format!("{} rows ({percent}%)", progress.rows)
+                }
+            }
+            Cells::Loading(loader) => match loader.status() {
+                loader::Status::Loading(Some(progress)) => format!(
+                    "{} rows, loading {}% (Esc cancels)",
+                    loader.rows(),
+                    (progress * 100.0) as u32
+                ),
+                _ => format!("{} rows, loading (Esc cancels)", loader.rows()),
+            },
+        }
+    }
+}
+
+enum PickerAction {
+    Nothing,
+    Quit,
+    Open(Box<sheet::Sheet>),
+}
+
+// Lists the entries of a zip archive so one of them can be opened
+struct EntryPicker {
+    archive_name: String,
+    zip: xlsx::zip::Zip,
+    entries: Vec<String>,
+    selected: usize,
+    offset: usize,
+    message: String,
+    dialect: format::Dialect,
+    inference: cell::Inference,
+}
+
+impl EntryPicker {
+    fn new(
+        archive_name: &str,
+        zip: xlsx::zip::Zip,
+        dialect: format::Dialect,
+        inference: cell::Inference,
+    ) -> Self {
+        let entries = zip
+            .file_names()
+            .into_iter()
+            .filter(|name| !name.ends_with('/'))
+            .map(|name| name.to_string())
+            .collect();
+
+        Self {
+            archive_name: archive_name.to_string(),
+            zip,
+            entries,
+            selected: 0,
+            offset: 0,
+            message: String::from("Select a CSV or TSV file and press Enter"),
+            dialect,
+            inference,
+        }
+    }
+
+    fn update(&mut self, visible_rows: usize) -> PickerAction {
+        if rl::is_key_pressed_or_repeated(rl::KeyboardKey::J)
+            || rl::is_key_pressed_or_repeated(rl::KeyboardKey::Down)
+        {
+            self.selected = (self.selected + 1).min(self.entries.len().saturating_sub(1));
+        } else if rl::is_key_pressed_or_repeated(rl::KeyboardKey::K)
+            || rl::is_key_pressed_or_repeated(rl::KeyboardKey::Up)
+        {
+            self.selected = self.selected.saturating_sub(1);
+        } else if rl::is_key_pressed(rl::KeyboardKey::Q) {
+            return PickerAction::Quit;
+        } else if rl::is_key_pressed(rl::KeyboardKey::Enter)
+            && let Some(entry) = self.entries.get(self.selected)
+        {
+            if !csv::is_csv_path(entry) {
+                self.message = format!("{entry} is not a CSV or TSV file");
+            } else {
+                match csv::read_csv_zip_entry_as_sheet(
+                    &self.zip,
+                    entry,
+                    &self.dialect,
+                    &self.inference,
+                ) {
+                    Ok(csv) => return PickerAction::Open(Box::new(csv.sheet)),
+                    Err(e) => self.message = e.to_string(),
+                }
+            }
+        }
+
+        if self.selected < self.offset {
+            self.offset = self.selected;
+        } else if visible_rows > 0 && self.selected >= self.offset + visible_rows {
+            self.offset = self.selected + 1 - visible_rows;
+        }
+
+        PickerAction::Nothing
+    }
+
+    fn draw(&self, font: &rl::Font, font_bold: &rl::Font, row_height: i32, header_height: i32) {
+        let screen_width = rl::get_screen_width();
+        let screen_height = rl::get_screen_height();
+
+        rl::draw_rectangle(0, 0, screen_width, header_height, rl::Color::DEEPGRAY2);
+        font_bold.draw_text(
+            self.archive_name.as_str(),
+            CELL_PAD as f32,
+            ((header_height - row_height) / 2 + 2) as f32,
+            rl::Color::RAYWHITE,
+        );
+
+        let visible_rows = ((screen_height - header_height - row_height) / row_height).max(0);
+
+        for (i, entry) in self
+            .entries
+            .iter()
+            .enumerate()
+            .skip(self.offset)
+            .take(visible_rows as usize)
+        {
+            let y = header_height + (i - self.offset) as i32 * row_height;
+
+            if i == self.selected {
+                rl::draw_rectangle(0, y, screen_width, row_height, rl::Color::DARKSEAGREEN);
+                font_bold.draw_text(entry, CELL_PAD as f32, (y + 2) as f32, rl::Color::BLACK);
+            } else {
+                let color = if csv::is_csv_path(entry) {
+                    rl::Color::RAYWHITE
+                } else {
+                    rl::Color::DIMGRAY
+                };
+
+                font.draw_text(entry, CELL_PAD as f32, (y + 2) as f32, color);
+            }
+        }
+
+        rl::draw_rectangle(
+            0,
+            screen_height - row_height,
+            screen_width,
+            row_height,
+            rl::Color::DEEPGRAY2,
+        );
+        font.draw_text(
+            self.message.as_str(),
+            CELL_PAD as f32,
+            (screen_height - row_height + 2) as f32,
+            rl::Color::WHITE,
+        );
     }
+}
 
-    let filepath = std::env::args().nth(1).unwrap();
+// A file open in a buffer. The buffer on screen lives in the variables of `main`, the others
+// wait here until `:bn` or `:bp` brings them back.
+struct OpenFile {
+    name: String,
+    cells: Cells,
+    picker: Option<EntryPicker>,
+    // Where `:w` writes to when no path is given, only set for csv sources
+    save_path: Option<String>,
+    // How csv text was read, `:w` writes it back the same way
+    dialect: format::Dialect,
+    cursor: (i32, i32),
+    offset: (i32, i32),
+}
+
+impl OpenFile {
+    fn new(name: &str, cells: Cells) -> Self {
+        OpenFile {
+            name: name.to_string(),
+            cells,
+            picker: None,
+            save_path: None,
+            dialect: format::Dialect::default(),
+            cursor: (0, 0),
+            offset: (0, 0),
+        }
+    }
 
-    let map: CellMap = if filepath.ends_with(".xlsx") {
-        let xlsx = xlsx::read_xlsx_file_as_hashmap(&filepath, None);
-        xlsx.unwrap().map
-    } else {
-        let csv = csv::read_csv_file_as_hashmap(&filepath, ',', csv::Delimiter::DoubleQuote);
-        csv.map
+    // Moves the contents out, an empty buffer with the same name is left
+    fn take(&mut self) -> OpenFile {
+        let empty = OpenFile::new(&self.name, Cells::Sheet(sheet::Sheet::new()));
+
+        std::mem::replace(self, empty)
+    }
+}
+
+// Opens a file as the command line describes it, the rows are read in the background
+fn open_file(path: &str, args: &args::Args) -> Result<OpenFile, String> {
+    let dialect = args.dialect;
+    let inference = args.inference;
+
+    if path == "-" {
+        // Parsed as it arrives, the buffer has no name until it's written
+        let cells = Cells::Loading(loader::Loader::spawn("-", move |task| {
+            let mut stream = csv::CSVStream::new(
+                std::io::stdin().lock(),
+                dialect.separator,
+                dialect.delimiter,
+                dialect.encoding,
+            )
+            .map_err(|e| e.to_string())?;
+
+            while let Some(part) = stream.next_part().map_err(|e| e.to_string())? {
+                if task.is_cancelled() {
+                    break;
+                }
+
+                csv::read_csv_data_into(
+                    &mut task.sheet(),
+                    part.first_row,
+                    &part.text,
+                    stream.separator,
+                    stream.delimiter,
+                    &inference,
+                );
+            }
+
+            Ok(Some(format::Dialect {
+                separator: Some(stream.separator),
+                delimiter: stream.delimiter,
+                encoding: Some(stream.encoding),
+            }))
+        }));
+
+        return Ok(OpenFile::new("[stdin]", cells));
+    }
+
+    let archive_entry = split_archive_path(path)
+        .map(|(archive, entry)| (archive.to_string(), entry.to_string()))
+        .or_else(|| {
+            let is_zip =
+                args.format.unwrap_or_else(|| format::format_of(path)) == format::Format::Zip;
+
+            args.sheet
+                .clone()
+                .filter(|_| is_zip)
+                .map(|entry| (path.to_string(), entry))
+        });
+
+    if let Some((archive, entry)) = archive_entry {
+        let cells = Cells::Loading(loader::Loader::spawn(path, move |task| {
+            let workbook = format::read_file(
+                &archive,
+                format::Format::Zip,
+                Some(&entry),
+                &dialect,
+                &inference,
+            )
+            .map_err(|e| e.to_string())?;
+
+            *task.sheet() = workbook.sheet;
+
+            Ok(Some(format::Dialect {
+                separator: Some(
+                    dialect
+                        .separator
+                        .unwrap_or_else(|| csv::separator_for(&entry)),
+                ),
+                encoding: workbook.encoding,
+                ..dialect
+            }))
+        }));
+
+        return Ok(OpenFile::new(path, cells));
+    }
+
+    let format = args.format.unwrap_or_else(|| format::format_of(path));
+
+    let mut file = match format {
+        format::Format::Zip => {
+            let zip = xlsx::zip::Zip::from_file(path).map_err(|e| e.to_string())?;
+
+            let mut file = OpenFile::new(path, Cells::Sheet(sheet::Sheet::new()));
+            file.picker = Some(EntryPicker::new(path, zip, dialect, inference));
+
+            file
+        }
+        _ if let Some(reader) = format::workbook_reader(format) => {
+            let owned_path = path.to_string();
+            let sheet_name = args.sheet.clone();
+
+            let cells = Cells::Loading(loader::Loader::spawn(path, move |task| {
+                let workbook = reader
+                    .read_workbook_with_progress(
+                        &owned_path,
+                        sheet_name.as_deref(),
+                        &dialect,
+                        &inference,
+                        &mut |fraction| {
+                            task.set_progress(fraction);
+                            !task.is_cancelled()
+                        },
+                    )
+                    .map_err(|e| e.to_string())?;
+
+                *task.sheet() = workbook.sheet;
+
+                Ok(None)
+            }));
+
+            OpenFile::new(path, cells)
+        }
+        format::Format::Csv | format::Format::Tsv => {
+            let separator = dialect
+                .separator
+                .unwrap_or_else(|| format::separator_of(path, format));
+
+            let cells = if lazy::is_lazy_candidate(path, dialect.encoding) {
+                Cells::Lazy(
+                    lazy::LazyCSV::open(path, separator, dialect.delimiter, inference)
+                        .map_err(|e| e.to_string())?,
+                )
+            } else {
+                Cells::Loading(loader::Loader::csv(
+                    path,
+                    separator,
+                    dialect.delimiter,
+                    dialect.encoding,
+                    inference,
+                ))
+            };
+
+            let mut file = OpenFile::new(path, cells);
+            file.save_path = Some(path.to_string());
+            file.dialect = dialect;
+
+            file
+        }
+        _ if let Some(reader) = format::sheet_reader(format) => {
+            let owned_path = path.to_string();
+            let dialect = format::Dialect {
+                separator: Some(
+                    dialect
+                        .separator
+                        .unwrap_or_else(|| format.default_separator()),
+                ),
+                ..dialect
+            };
+
+            let cells = Cells::Loading(loader::Loader::spawn(path, move |task| {
+                let workbook = reader
+                    .read_sheet(&owned_path, &dialect, &inference)
+                    .map_err(|e| e.to_string())?;
+
+                *task.sheet() = workbook.sheet;
+
+                Ok(Some(format::Dialect {
+                    encoding: workbook.encoding,
+                    ..dialect
+                }))
+            }));
+
+            let mut file = OpenFile::new(path, cells);
+            file.save_path = Some(path.to_string());
+            file.dialect = dialect;
+
+            file
+        }
+        format => return Err(format!("{} files can't be opened", format.name())),
     };
 
+    // The first row of a sheet with a header is shown above the columns
+    let first_row = if args.header { 1 } else { 0 };
+    file.cursor = (first_row, 0);
+    file.offset = (first_row, 0);
+
+    Ok(file)
+}
+
+fn main() {
+    let args = match args::parse(std::env::args().skip(1)) {
+        Ok(args::Command::Open(args)) => args,
+        // `csvim convert IN OUT` runs without a window
+        Ok(args::Command::Convert(options)) => {
+            if let Err(e) = convert::convert(&options) {
+                eprintln!("csvim convert: {e}");
+                std::process::exit(e.exit_code());
+            }
+
+            return;
+        }
+        Ok(args::Command::Help) => {
+            print!("{}", args::USAGE);
+            return;
+        }
+        Ok(args::Command::Version) => {
+            println!("csvim {}", env!("CARGO_PKG_VERSION"));
+            return;
+        }
+        Err(args::ArgsError::NoFiles) => {
+            print!("{}", args::USAGE);
+            return;
+        }
+        Err(e) => {
+            eprintln!("csvim: {e}");
+            eprintln!("Try 'csvim --help' for more information.");
+            std::process::exit(2);
+        }
+    };
+
+    let mut files = Vec::new();
+
+    for path in &args.files {
+        match open_file(path, &args) {
+            Ok(file) => files.push(file),
+            Err(e) => {
+                eprintln!("{path}: {e}");
+                std::process::exit(1);
+            }
+        }
+    }
+
+    // The buffer on screen, see `OpenFile`
+    let mut current = 0;
+
+    let OpenFile {
+        mut cells,
+        mut picker,
+        mut save_path,
+        mut dialect,
+        cursor: (mut current_cell_row, mut current_cell_col),
+        offset: (mut row_offset, mut column_offset),
+        ..
+    } = files[current].take();
+
+    let first_row: i32 = if args.header { 1 } else { 0 };
+
+    // Applied once the rows it needs are read
+    let mut pending_jump = args.jump;
+
     rl::set_config_flags(0x00000004 | 0x00000400);
     rl::init_window(200, 200, "csvim");
 
@@ -131,23 +615,43 @@ fn main() {
     let top_headers_height: i32 = 3 * cell_default_height / 2;
     let left_headers_width: i32 = 100 / 2;
 
-    let mut column_offset: i32 = 0;
-    let mut row_offset: i32 = 0;
-
-    let mut current_cell_row: i32 = 0;
-    let mut current_cell_col: i32 = 0;
-
     let mut buffer = Buffer::new();
 
     let mut inserting = false;
 
+    // The input line is used both for searching (`/`) and for commands (`:`)
+    let mut prompt = '/';
+
     let mut search_buffer = Vec::<char>::new();
     let mut matched_cells: Vec<(usize, usize)> = Vec::new();
     let mut currently_matched = 0;
+    let mut search: Option<lazy::Search> = None;
 
     let mut cursor_offset = 0i32;
 
     while !rl::window_should_close() {
+        if let Cells::Loading(loader) = &cells {
+            match loader.status() {
+                loader::Status::Loading(_) => {}
+                loader::Status::Done(read) => {
+                    // Saving keeps the separator, quote and encoding the text was read with
+                    if let Some(read) = read {
+                        dialect = read;
+                    }
+
+                    cells = Cells::Sheet(loader.take_sheet());
+                }
+                loader::Status::Failed(e) => {
+                    // Only this buffer failed, it keeps the rows read before the error and
+                    // isn't written over the file it couldn't read
+                    eprintln!("{}: {e}", loader.filename);
+
+                    save_path = None;
+                    cells = Cells::Sheet(loader.take_sheet());
+                }
+            }
+        }
+
         let screen_width = rl::get_screen_width();
         let screen_height = rl::get_screen_height();
 
@@ -155,10 +659,94 @@ fn main() {
         let row_count =
             (screen_height - top_headers_height - cell_default_height) / cell_default_height;
 
+        if let Some(jump) = &pending_jump
+            && (cells.is_complete() || matches!(jump, args::Jump::Row(n) if cells.rows() >= *n))
+        {
+            let last = cells.rows().saturating_sub(1);
+
+            let target = match jump {
+                args::Jump::Row(n) => Some((n.saturating_sub(1).min(last), 0)),
+                args::Jump::Search(pattern) => {
+                    (matched_cells, search) = cells.find(&pattern.to_lowercase());
+                    matched_cells.retain(|&(r, _)| r as i32 >= first_row);
+                    currently_matched = 0;
+
+                    matched_cells.first().copied()
+                }
+            };
+
+            if let Some((r, c)) = target {
+                current_cell_row = (r as i32).max(first_row);
+                current_cell_col = c as i32;
+
+                let d_row = current_cell_row - row_offset;
+
+                if d_row < 0 || d_row > row_count - 1 {
+                    row_offset = current_cell_row;
+                }
+
+                let d_col = current_cell_col - column_offset;
+
+                if d_col < 0 || d_col > column_count - 1 {
+                    column_offset = current_cell_col;
+                }
+            }
+
+            pending_jump = None;
+        }
+
+        if let Some(s) = &search {
+            let found = s.take_found();
+            let first = matched_cells.is_empty();
+
+            matched_cells.extend(found.into_iter().filter(|&(r, _)| r as i32 >= first_row));
+
+            // The cursor goes to the first match as soon as it's found
+            if first && let Some(&(r, c)) = matched_cells.first() {
+                currently_matched = 0;
+
+                current_cell_row = r as i32;
+                current_cell_col = c as i32;
+
+                let d_row = current_cell_row - row_offset;
+
+                if d_row < 0 || d_row > row_count - 1 {
+                    row_offset = current_cell_row;
+                }
+
+                let d_col = current_cell_col - column_offset;
+
+                if d_col < 0 || d_col > column_count - 1 {
+                    column_offset = current_cell_col;
+                }
+            }
+
+            if s.is_done() {
+                println!("{} matches", matched_cells.len());
+                search = None;
+            }
+        }
+
         rl::begin_drawing();
 
         rl::clear_background(rl::Color::DEEPGRAY);
 
+        if let Some(p) = picker.as_mut() {
+            match p.update(row_count.max(0) as usize) {
+                PickerAction::Quit => break,
+                PickerAction::Open(s) => {
+                    cells = Cells::Sheet(*s);
+                    picker = None;
+                }
+                PickerAction::Nothing => {
+                    p.draw(&font, &font_bold, cell_default_height, top_headers_height);
+                }
+            }
+
+            rl::end_drawing();
+            continue;
+        }
+
         if !inserting {
             if rl::is_key_pressed_or_repeated(rl::KeyboardKey::H) {
                 current_cell_col = (current_cell_col - 1).max(0);
@@ -179,7 +767,7 @@ fn main() {
                     row_offset += 1;
                 }
             } else if rl::is_key_pressed_or_repeated(rl::KeyboardKey::K) {
-                current_cell_row = (current_cell_row - 1).max(0);
+                current_cell_row = (current_cell_row - 1).max(first_row);
 
                 if current_cell_row < row_offset {
                     row_offset -= 1;
@@ -211,8 +799,31 @@ fn main() {
                 }
             } else if rl::is_key_pressed(rl::KeyboardKey::Q) {
                 break;
+            } else if rl::is_key_pressed(rl::KeyboardKey::Escape) {
+                if search.take().is_some() {
+                    println!("search cancelled, {} matches", matched_cells.len());
+                }
+
+                // The rows read so far are kept, they're only written with `:w FILENAME`
+                if let Cells::Loading(loader) = &cells {
+                    loader.cancel();
+                    println!("loading of {} cancelled", loader.filename);
+
+                    save_path = None;
+                    cells = Cells::Sheet(loader.take_sheet());
+                }
             } else if rl::is_key_pressed(rl::KeyboardKey::Slash) {
                 inserting = true;
+                prompt = '/';
+
+                search_buffer.clear();
+                cursor_offset = 0;
+            } else if rl::is_key_pressed(rl::KeyboardKey::Semicolon)
+                && (rl::is_key_down(rl::KeyboardKey::LeftShift)
+                    || rl::is_key_down(rl::KeyboardKey::RightShift))
+            {
+                inserting = true;
+                prompt = ':';
 
                 search_buffer.clear();
                 cursor_offset = 0;
@@ -239,26 +850,122 @@ fn main() {
                 }
             }
         } else {
-            if rl::is_key_pressed(rl::KeyboardKey::Enter) {
+            if prompt == ':' && rl::is_key_pressed(rl::KeyboardKey::Enter) {
                 inserting = false;
 
-                let search_string = search_buffer.iter().collect::<String>().to_lowercase();
+                let command = search_buffer.iter().collect::<String>();
+                let mut parts = command.split_whitespace();
+
+                let name = parts.next().unwrap_or("");
+                let path = parts.next().map(|p| p.to_string());
+
+                match name {
+                    "w" | "wq" => {
+                        if args.readonly {
+                            eprintln!("{}: opened read-only", files[current].name);
+                        } else if let Cells::Lazy(lazy) = &cells {
+                            eprintln!("{}: large files are opened read-only", lazy.filename);
+                        } else if let Cells::Loading(loader) = &cells {
+                            eprintln!("{}: still loading", loader.filename);
+                        } else if let Cells::Sheet(sheet) = &cells
+                            && let Some(path) = path.or_else(|| save_path.clone())
+                        {
+                            let format = format::Format::from_path(&path);
+                            let source = save_path
+                                .as_deref()
+                                .map_or(format::Format::Csv, format::Format::from_path);
+
+                            // The separator is the one read unless the format changes
+                            let written = format::Dialect {
+                                separator: Some(
+                                    dialect
+                                        .separator
+                                        .filter(|_| format == source)
+                                        .unwrap_or_else(|| format.default_separator()),
+                                ),
+                                ..dialect
+                            };
+
+                            match format::write_file(&path, format, sheet, &written) {
+                                Ok(()) => {
+                                    println!("written {path}");
+                                    dialect = written;
+
+                                    // Unnamed buffers, like the standard input, take the name
+                                    if save_path.is_none() {
+                                        files[current].name = path.clone();
+                                    }
+
+                                    save_path = Some(path);
+
+                                    if name == "wq" {
+                                        break;
+                                    }
+                                }
+                                Err(e) => eprintln!("{path}: {e}"),
+                            }
+                        } else {
+                            eprintln!("no file name");
+                        }
+                    }
+                    "q" => break,
+                    "bn" | "bp" if files.len() > 1 => {
+                        let next = if name == "bn" {
+                            (current + 1) % files.len()
+                        } else {
+                            (current + files.len() - 1) % files.len()
+                        };
+
+                        let name = files[current].name.clone();
+                        files[current] = OpenFile {
+                            name,
+                            cells: std::mem::replace(&mut cells, Cells::Sheet(sheet::Sheet::new())),
+                            picker: picker.take(),
+                            save_path: save_path.take(),
+                            dialect,
+                            cursor: (current_cell_row, current_cell_col),
+                            offset: (row_offset, column_offset),
+                        };
+
+                        let file = files[next].take();
+
+                        cells = file.cells;
+                        picker = file.picker;
+                        save_path = file.save_path;
+                        dialect = file.dialect;
+                        (current_cell_row, current_cell_col) = file.cursor;
+                        (row_offset, column_offset) = file.offset;
+
+                        current = next;
+                        matched_cells.clear();
+                        search = None;
+                        pending_jump = None;
+                    }
+                    "bn" | "bp" => {}
+                    "" => {}
+                    // `:N` goes to row N, as far as the rows known so far
+                    n if let Ok(n) = n.parse::<usize>() => {
+                        let last = cells.rows().saturating_sub(1);
 
-                matched_cells = map
-                    .iter()
-                    .filter(|&(_, value)| value.to_lowercase().rfind(&search_string).is_some())
-                    .map(|(&key, &_)| key)
-                    .collect::<Vec<(usize, usize)>>();
+                        current_cell_row = (n.saturating_sub(1).min(last) as i32).max(first_row);
 
-                matched_cells.sort_by(
-                    |&(r1, c1), &(r2, c2)| {
-                        if r1 == r2 {
-                            c1.cmp(&c2)
-                        } else {
-                            r1.cmp(&r2)
+                        let d_row = current_cell_row - row_offset;
+
+                        if d_row < 0 || d_row > row_count - 1 {
+                            row_offset = current_cell_row;
                         }
-                    },
-                );
+                    }
+                    _ => eprintln!("unknown command: {command}"),
+                }
+            } else if rl::is_key_pressed(rl::KeyboardKey::Escape) {
+                inserting = false;
+            } else if rl::is_key_pressed(rl::KeyboardKey::Enter) {
+                inserting = false;
+
+                let search_string = search_buffer.iter().collect::<String>().to_lowercase();
+
+                (matched_cells, search) = cells.find(&search_string);
+                matched_cells.retain(|&(r, _)| r as i32 >= first_row);
 
                 if matched_cells.len() > 0 {
                     currently_matched = 0;
@@ -281,7 +988,9 @@ fn main() {
                     }
                 }
 
-                println!("{} matches", matched_cells.len());
+                if search.is_none() {
+                    println!("{} matches", matched_cells.len());
+                }
             } else if rl::is_key_pressed_or_repeated(rl::KeyboardKey::Left) {
                 cursor_offset = (cursor_offset - 1).max(0);
             } else if rl::is_key_pressed_or_repeated(rl::KeyboardKey::Right) {
@@ -327,15 +1036,34 @@ fn main() {
         for i in 0..(column_count + 1) {
             let x = start_x + i * CELL_DEFAULT_WIDTH;
 
-            buffer.write_letters_base26((column_offset + i) as u32);
+            // With a header the columns are named after the first row
+            let title = if first_row > 0 {
+                cells.display(0, (column_offset + i) as usize)
+            } else {
+                std::borrow::Cow::Borrowed("")
+            };
+
+            if title.is_empty() {
+                buffer.write_letters_base26((column_offset + i) as u32);
+            } else {
+                buffer.write_str(&title);
+            }
 
             let w = font.measure_text(buffer.as_str());
+
+            rl::begin_scissor_mode(
+                x + CELL_PAD,
+                0,
+                CELL_DEFAULT_WIDTH - 2 * CELL_PAD,
+                top_headers_height,
+            );
             font.draw_text(
                 buffer.as_str(),
-                x as f32 + (CELL_DEFAULT_WIDTH as f32 - w) / 2.0,
+                x as f32 + ((CELL_DEFAULT_WIDTH as f32 - w) / 2.0).max(CELL_PAD as f32),
                 ((top_headers_height - 16) / 2) as f32,
                 rl::Color::RAYWHITE,
             );
+            rl::end_scissor_mode();
 
             rl::draw_vertical_line(x, 0, screen_height, rl::Color::RAYWHITE);
         }
@@ -358,13 +1086,13 @@ fn main() {
 
         for j in 0..(row_count + 1) {
             for i in 0..(column_count + 1) {
-                let pos = ((row_offset + j) as usize, (column_offset + i) as usize);
+                let value = cells.display((row_offset + j) as usize, (column_offset + i) as usize);
 
-                if let Some(value) = map.get(&pos) {
+                if !value.is_empty() {
                     let x = start_x + i * CELL_DEFAULT_WIDTH + CELL_PAD;
                     let y = start_y + j * cell_default_height;
 
-                    buffer.write_str(value.as_str());
+                    buffer.write_str(&value);
 
                     rl::begin_scissor_mode(
                         x,
@@ -410,15 +1138,54 @@ fn main() {
             rl::Color::WHITE,
         );
 
+        let mut status = if files.len() > 1 {
+            format!(
+                "{} [{}/{}]  {}",
+                files[current].name,
+                current + 1,
+                files.len(),
+                cells.status()
+            )
+        } else {
+            cells.status()
+        };
+
+        if search.is_some() {
+            status.push_str(&format!(
+                ", searching: {} matches (Esc cancels)",
+                matched_cells.len()
+            ));
+        }
+        let w2 = font.measure_text(&status);
+
+        font.draw_text(
+            &status,
+            screen_width as f32 - w - w2 - 25.0,
+            (screen_height - cell_default_height + 2) as f32,
+            rl::Color::LIGHTGRAY,
+        );
+
+        if let Cells::Loading(loader) = &cells
+            && let loader::Status::Loading(Some(progress)) = loader.status()
+        {
+            rl::draw_rectangle(
+                0,
+                screen_height - cell_default_height,
+                (screen_width as f32 * progress) as i32,
+                2,
+                rl::Color::GREENYELLOW,
+            );
+        }
+
         if !inserting {
-            let pos = (current_cell_row as usize, current_cell_col as usize);
+            let value = cells.display(current_cell_row as usize, current_cell_col as usize);
 
-            if let Some(value) = map.get(&pos) {
+            if !value.is_empty() {
                 let x =
                     start_x + (current_cell_col - column_offset) * CELL_DEFAULT_WIDTH + CELL_PAD;
                 let y = start_y + (current_cell_row - row_offset) * cell_default_height;
 
-                buffer.write_str(value.as_str());
+                buffer.write_str(&value);
 
                 rl::begin_scissor_mode(
                     x,
@@ -439,13 +1206,14 @@ fn main() {
         } else {
             let data = search_buffer.iter().collect::<String>();
 
-            let w2 = font.measure_text("/");
+            let prompt = if prompt == ':' { ":" } else { "/" };
+            let w2 = font.measure_text(prompt);
 
             let x = CELL_PAD;
             let y = screen_height - cell_default_height + 2;
 
             rl::begin_scissor_mode(x, y, screen_width - 2 * CELL_PAD, cell_default_height);
-            font.draw_text("/", x as f32, y as f32, rl::Color::WHITE);
+            font.draw_text(prompt, x as f32, y as f32, rl::Color::WHITE);
             font.draw_text(data.as_str(), x as f32 + w2, y as f32, rl::Color::WHITE);
             rl::end_scissor_mode();
 
@@ -0,0 +1,823 @@
+// OpenDocument spreadsheets (.ods), a zip archive whose `content.xml` has the tables:
+//
+//     <office:spreadsheet>
+//       <table:table table:name="Sheet1">
+//         <table:table-column table:number-columns-repeated="3"/>
+//         <table:table-row>
+//           <table:table-cell office:value-type="string"><text:p>Name</text:p></table:table-cell>
+//           <table:table-cell table:number-columns-repeated="2"/>
+//           <table:table-cell office:value-type="float" office:value="2.5">
+//             <text:p>2,50</text:p>
+//           </table:table-cell>
+//         </table:table-row>
+//         <table:table-row table:number-rows-repeated="1048570">...</table:table-row>
+//
+// Cells and rows are positioned one after the other, the `number-*-repeated` attributes stand for
+// that many copies of the same cell or row. Typed cells keep their value in an attribute, the
+// paragraphs only have it formatted for display.
+//
+use std::collections::HashMap;
+use std::str::FromStr;
+
+use crate::cell::{Cell, CellMap, DateTime, MAX_COLS, MAX_ROWS, Progress};
+use crate::csv::NamedSheet;
+use crate::sheet::Sheet;
+use crate::xlsx::xml;
+use crate::xlsx::zip;
+
+pub const OFFICE_NAMESPACE: &str = "urn:oasis:names:tc:opendocument:xmlns:office:1.0";
+pub const TABLE_NAMESPACE: &str = "urn:oasis:names:tc:opendocument:xmlns:table:1.0";
+pub const TEXT_NAMESPACE: &str = "urn:oasis:names:tc:opendocument:xmlns:text:1.0";
+pub const CALCEXT_NAMESPACE: &str =
+    "urn:org:documentfoundation:names:experimental:calc:xmlns:calcext:1.0";
+
+pub const SPREADSHEET_MIMETYPE: &str = "application/vnd.oasis.opendocument.spreadsheet";
+
+#[derive(Debug)]
+pub enum ODSError {
+    Zip(zip::ZipError),
+    Xml(xml::XMLError),
+    NotASpreadsheet,
+    TableNotFound(String),
+}
+
+impl std::fmt::Display for ODSError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ODSError::Zip(e) => write!(f, "{e}"),
+            ODSError::Xml(e) => write!(f, "{e}"),
+            ODSError::NotASpreadsheet => write!(f, "not an OpenDocument spreadsheet"),
+            ODSError::TableNotFound(name) => write!(f, "table {name} not found"),
+        }
+    }
+}
+
+impl From<zip::ZipError> for ODSError {
+    fn from(e: zip::ZipError) -> Self {
+        ODSError::Zip(e)
+    }
+}
+
+impl From<xml::XMLError> for ODSError {
+    fn from(e: xml::XMLError) -> Self {
+        ODSError::Xml(e)
+    }
+}
+
+pub struct ODSMap {
+    pub filename: String,
+    pub worksheet: String,
+    pub map: CellMap,
+}
+
+fn is_table(name: &xml::QName, local_name: &str) -> bool {
+    name.is(TABLE_NAMESPACE, local_name)
+}
+
+fn is_text(name: &xml::QName, local_name: &str) -> bool {
+    name.is(TEXT_NAMESPACE, local_name)
+}
+
+// Longest text of a cell in Excel, bounds the spaces of `text:s`
+const MAX_SPACES: usize = 32767;
+
+fn parse_repeat(value: &str, limit: usize) -> usize {
+    usize::from_str(value.trim()).unwrap_or(1).clamp(1, limit)
+}
+
+// Time values are durations as "PT12H30M05S", the time of the day "12:30:05"
+fn time_value(value: &str) -> Option<DateTime> {
+    let rest = value.strip_prefix("PT")?;
+
+    let (hours, rest) = rest.split_once('H').unwrap_or(("0", rest));
+    let (minutes, rest) = rest.split_once('M').unwrap_or(("0", rest));
+    let seconds = rest.strip_suffix('S').unwrap_or("0");
+
+    DateTime::parse(&format!("{hours:0>2}:{minutes:0>2}:{seconds:0>2}"))
+}
+
+// Typed attributes of the cell being read
+#[derive(Default)]
+struct CellValue {
+    value_type: String,
+    value: Option<String>,
+    date_value: Option<String>,
+    time_value: Option<String>,
+    boolean_value: Option<String>,
+    string_value: Option<String>,
+    formula: Option<String>,
+    // LibreOffice marks the cells with an error, their text is the error
+    is_error: bool,
+}
+
+impl CellValue {
+    // The typed value if there is one, otherwise the text of the paragraphs
+    fn into_cell(self, text: String) -> Cell {
+        let value = match self.value_type.as_str() {
+            _ if self.is_error => Cell::Error(text),
+            "float" | "percentage" | "currency" => {
+                match self.value.as_deref().map(|v| f64::from_str(v.trim())) {
+                    Some(Ok(number)) => Cell::Number(number),
+                    _ => Cell::text(&text),
+                }
+            }
+            "date" => match self.date_value.as_deref().and_then(DateTime::parse) {
+                Some(datetime) => Cell::DateTime(datetime),
+                None => Cell::text(&text),
+            },
+            "time" => match self.time_value.as_deref().and_then(time_value) {
+                Some(datetime) => Cell::DateTime(datetime),
+                None => Cell::text(&text),
+            },
+            "boolean" => match self.boolean_value.as_deref() {
+                Some(value) => Cell::Bool(value == "true"),
+                None => Cell::text(&text),
+            },
+            _ => Cell::text(self.string_value.as_deref().unwrap_or(&text)),
+        };
+
+        match self.formula {
+            Some(formula) => Cell::Formula {
+                formula,
+                value: Box::new(value),
+            },
+            None => value,
+        }
+    }
+}
+
+// Whether the parser is inside a paragraph of a cell, the text of annotations and nested
+// tables is not part of the cell
+fn in_cell_paragraph(open_elements: &[xml::QName]) -> bool {
+    match open_elements
+        .iter()
+        .rposition(|e| is_table(e, "table-cell") || is_table(e, "covered-table-cell"))
+    {
+        Some(i) => open_elements
+            .get(i + 1)
+            .is_some_and(|e| is_text(e, "p") || is_text(e, "h")),
+        None => false,
+    }
+}
+
+// Reads the table with the given name, or the first one, from `content.xml`. Flat OpenDocument
+// files (.fods) have the same tables and can be read as well. `filename` is only used as a label
+pub fn read_ods_content(
+    filename: &str,
+    content: &[u8],
+    table_name: Option<&str>,
+    progress: Progress,
+) -> Result<ODSMap, ODSError> {
+    let content = xml::to_utf8(content)?;
+    let mut parser = xml::PullParser::new(&content);
+
+    let mut map = HashMap::new();
+    let mut found_spreadsheet = false;
+
+    // Tables can be nested inside cells, only the outer ones are sheets
+    let mut table_depth = 0;
+    let mut selected: Option<String> = None;
+    let mut reading = false;
+
+    let mut row = 0;
+    let mut rows_repeated = 1;
+    let mut row_cells: Vec<(usize, Cell)> = Vec::new();
+
+    let mut col = 0;
+    let mut cols_repeated = 1;
+    let mut cell = CellValue::default();
+    let mut text = String::new();
+    let mut paragraphs = 0;
+
+    while let Some(event) = parser.next() {
+        match event? {
+            xml::Event::StartElement(name) if name.is(OFFICE_NAMESPACE, "spreadsheet") => {
+                found_spreadsheet = true;
+            }
+            xml::Event::StartElement(name) if is_table(&name, "table") => {
+                table_depth += 1;
+
+                if table_depth == 1 {
+                    row = 0;
+                }
+            }
+            xml::Event::Attribute { name, value }
+                if table_depth == 1
+                    && is_table(&name, "name")
+                    && parser.path_ends_with(&["table"]) =>
+            {
+                reading = selected.is_none() && table_name.is_none_or(|wanted| wanted == value);
+
+                if reading {
+                    selected = Some(value.into_owned());
+                }
+            }
+            xml::Event::EndElement(name) if is_table(&name, "table") => {
+                table_depth -= 1;
+
+                if table_depth == 0 {
+                    reading = false;
+                }
+            }
+            _ if !reading || table_depth != 1 => {}
+            xml::Event::StartElement(name) if is_table(&name, "table-row") => {
+                rows_repeated = 1;
+                col = 0;
+                row_cells.clear();
+            }
+            xml::Event::Attribute { name, value }
+                if is_table(&name, "number-rows-repeated")
+                    && parser.path_ends_with(&["table-row"]) =>
+            {
+                rows_repeated = parse_repeat(&value, MAX_ROWS);
+            }
+            xml::Event::EndElement(name) if is_table(&name, "table-row") => {
+                // Repeated empty rows are common at the end of a table, only their count matters
+                if !row_cells.is_empty() {
+                    for r in row..(row + rows_repeated).min(MAX_ROWS) {
+                        for (c, value) in &row_cells {
+                            let _ = map.insert((r, *c), value.clone());
+                        }
+                    }
+                }
+
+                row = (row + rows_repeated).min(MAX_ROWS);
+
+                if !progress(parser.position() as f32 / content.len() as f32) {
+                    break;
+                }
+            }
+            xml::Event::StartElement(name)
+                if is_table(&name, "table-cell") || is_table(&name, "covered-table-cell") =>
+            {
+                cols_repeated = 1;
+                cell = CellValue::default();
+                text.clear();
+                paragraphs = 0;
+            }
+            xml::Event::Attribute { name, value }
+                if parser.path_ends_with(&["table-row", "table-cell"])
+                    || parser.path_ends_with(&["table-row", "covered-table-cell"]) =>
+            {
+                if is_table(&name, "number-columns-repeated") {
+                    cols_repeated = parse_repeat(&value, MAX_COLS);
+                } else if is_table(&name, "formula") {
+                    cell.formula = Some(value.into_owned());
+                } else if name.is(CALCEXT_NAMESPACE, "value-type") {
+                    cell.is_error = value == "error";
+                } else if name.namespace.as_deref() == Some(OFFICE_NAMESPACE) {
+                    let value = value.into_owned();
+
+                    match name.local_name {
+                        "value-type" => cell.value_type = value,
+                        "value" => cell.value = Some(value),
+                        "date-value" => cell.date_value = Some(value),
+                        "time-value" => cell.time_value = Some(value),
+                        "boolean-value" => cell.boolean_value = Some(value),
+                        "string-value" => cell.string_value = Some(value),
+                        _ => {}
+                    }
+                }
+            }
+            xml::Event::EndElement(name)
+                if is_table(&name, "table-cell") || is_table(&name, "covered-table-cell") =>
+            {
+                let value = std::mem::take(&mut cell).into_cell(std::mem::take(&mut text));
+
+                if !value.is_empty() {
+                    for c in col..(col + cols_repeated).min(MAX_COLS) {
+                        row_cells.push((c, value.clone()));
+                    }
+                }
+
+                col = (col + cols_repeated).min(MAX_COLS);
+            }
+            // Paragraphs of a cell are its lines
+            xml::Event::StartElement(name)
+                if (is_text(&name, "p") || is_text(&name, "h"))
+                    && in_cell_paragraph(parser.open_elements()) =>
+            {
+                if paragraphs > 0 {
+                    text.push('\n');
+                }
+
+                paragraphs += 1;
+            }
+            xml::Event::StartElement(name) if in_cell_paragraph(parser.open_elements()) => {
+                if is_text(&name, "s") {
+                    text.push(' ');
+                } else if is_text(&name, "tab") {
+                    text.push('\t');
+                } else if is_text(&name, "line-break") {
+                    text.push('\n');
+                }
+            }
+            // `text:s` is one space unless `text:c` gives how many
+            xml::Event::Attribute { name, value }
+                if is_text(&name, "c") && parser.path_ends_with(&["s"]) =>
+            {
+                for _ in 1..parse_repeat(&value, MAX_SPACES) {
+                    text.push(' ');
+                }
+            }
+            xml::Event::Text { value, .. } if in_cell_paragraph(parser.open_elements()) => {
+                text.push_str(&value);
+            }
+            _ => {}
+        }
+    }
+
+    if !found_spreadsheet {
+        return Err(ODSError::NotASpreadsheet);
+    }
+
+    match selected {
+        Some(worksheet) => Ok(ODSMap {
+            filename: filename.to_string(),
+            worksheet,
+            map,
+        }),
+        None => Err(ODSError::TableNotFound(
+            table_name.unwrap_or("").to_string(),
+        )),
+    }
+}
+
+pub fn read_ods_file_as_hashmap(
+    filename: &str,
+    table_name: Option<&str>,
+    progress: Progress,
+) -> Result<ODSMap, ODSError> {
+    let zip = zip::Zip::from_file(filename)?;
+
+    let names = zip.file_names();
+
+    // The mimetype is optional, but when present it must be the spreadsheet one
+    if names.contains(&"mimetype") && zip.read_file("mimetype")? != SPREADSHEET_MIMETYPE.as_bytes()
+    {
+        return Err(ODSError::NotASpreadsheet);
+    }
+
+    if !names.contains(&"content.xml") {
+        return Err(ODSError::NotASpreadsheet);
+    }
+
+    read_ods_content(
+        filename,
+        &zip.read_file("content.xml")?,
+        table_name,
+        progress,
+    )
+}
+
+pub const MANIFEST_NAMESPACE: &str = "urn:oasis:names:tc:opendocument:xmlns:manifest:1.0";
+
+const ODF_VERSION: &str = "1.2";
+
+// "13:05:00" to "PT13H05M00S"
+fn duration_value((hours, minutes, seconds): (u32, u32, u32)) -> String {
+    format!("PT{hours:02}H{minutes:02}M{seconds:02}S")
+}
+
+// White space is collapsed when reading, so every space that doesn't follow a character is
+// written as `text:s` and tabs as `text:tab`
+fn paragraph(line: &str) -> xml::ElementBuilder {
+    let mut p = xml::ElementBuilder::new("text:p");
+    let mut run = String::new();
+    let mut spaces = 0;
+
+    for c in line.chars() {
+        if c == ' ' && spaces == 0 && run.chars().last().is_some_and(|l| l != ' ') {
+            run.push(c);
+            continue;
+        }
+
+        if c == ' ' {
+            spaces += 1;
+            continue;
+        }
+
+        if spaces > 0 || c == '\t' {
+            if !run.is_empty() {
+                p = p.text(&run);
+                run.clear();
+            }
+
+            if spaces > 1 {
+                p = p.child(xml::ElementBuilder::new("text:s").attr("text:c", &spaces.to_string()));
+            } else if spaces == 1 {
+                p = p.child(xml::ElementBuilder::new("text:s"));
+            }
+
+            spaces = 0;
+        }
+
+        if c == '\t' {
+            p = p.child(xml::ElementBuilder::new("text:tab"));
+        } else {
+            run.push(c);
+        }
+    }
+
+    if !run.is_empty() {
+        p = p.text(&run);
+    }
+
+    match spaces {
+        0 => p,
+        1 => p.child(xml::ElementBuilder::new("text:s")),
+        n => p.child(xml::ElementBuilder::new("text:s").attr("text:c", &n.to_string())),
+    }
+}
+
+// A typed cell, the paragraphs have the value as it's shown. Only OpenFormula formulas
+// (`of:=...`) are kept, the others are written as their result.
+fn cell_element(value: &Cell) -> xml::ElementBuilder {
+    let cell = xml::ElementBuilder::new("table:table-cell");
+
+    let cell = match value {
+        Cell::Formula { formula, .. } if formula.starts_with("of:") => {
+            cell.attr("table:formula", formula)
+        }
+        _ => cell,
+    };
+
+    let text = value.to_string();
+
+    let cell = match value.value() {
+        Cell::Number(n) => cell
+            .attr("office:value-type", "float")
+            .attr("office:value", &n.to_string()),
+        Cell::Bool(b) => cell
+            .attr("office:value-type", "boolean")
+            .attr("office:boolean-value", &b.to_string()),
+        Cell::DateTime(DateTime {
+            date: None,
+            time: Some(time),
+        }) => cell
+            .attr("office:value-type", "time")
+            .attr("office:time-value", &duration_value(*time)),
+        Cell::DateTime(datetime) => cell
+            .attr("office:value-type", "date")
+            .attr("office:date-value", &datetime.to_string()),
+        _ => cell.attr("office:value-type", "string"),
+    };
+
+    cell.children(text.split('\n').map(paragraph))
+}
+
+fn repeated(name: &str, attribute: &str, count: usize) -> xml::ElementBuilder {
+    let element = xml::ElementBuilder::new(name);
+
+    if count > 1 {
+        element.attr(attribute, &count.to_string())
+    } else {
+        element
+    }
+}
+
+// Rows and runs of empty cells are written once with a repeat count
+fn table_element(name: &str, sheet: &Sheet) -> xml::Component {
+    let mut rows: Vec<Vec<(usize, Cell)>> = Vec::new();
+
+    for ((row, col), value) in sheet.cells() {
+        if rows.len() <= row {
+            rows.resize_with(row + 1, Vec::new);
+        }
+
+        rows[row].push((col, value));
+    }
+
+    let columns = rows
+        .iter()
+        .flat_map(|cells| cells.iter().map(|(col, _)| col + 1))
+        .max()
+        .unwrap_or(1);
+
+    let mut table = xml::ElementBuilder::new("table:table")
+        .attr("table:name", name)
+        .child(repeated(
+            "table:table-column",
+            "table:number-columns-repeated",
+            columns,
+        ));
+
+    let mut empty_rows = 0;
+
+    for cells in rows.iter() {
+        if cells.is_empty() {
+            empty_rows += 1;
+            continue;
+        }
+
+        if empty_rows > 0 {
+            table = table.child(
+                repeated("table:table-row", "table:number-rows-repeated", empty_rows)
+                    .child(xml::ElementBuilder::new("table:table-cell")),
+            );
+            empty_rows = 0;
+        }
+
+        let mut row = xml::ElementBuilder::new("table:table-row");
+        let mut next_col = 0;
+
+        for &(col, ref value) in cells.iter() {
+            if col > next_col {
+                row = row.child(repeated(
+                    "table:table-cell",
+                    "table:number-columns-repeated",
+                    col - next_col,
+                ));
+            }
+
+            row = row.child(cell_element(value));
+            next_col = col + 1;
+        }
+
+        table = table.child(row);
+    }
+
+    // A table has at least one row
+    if rows.is_empty() {
+        table = table.child(
+            xml::ElementBuilder::new("table:table-row")
+                .child(xml::ElementBuilder::new("table:table-cell")),
+        );
+    }
+
+    table.build()
+}
+
+fn content_document(sheets: &[NamedSheet]) -> xml::Document {
+    let spreadsheet = xml::ElementBuilder::new("office:spreadsheet").children(
+        sheets
+            .iter()
+            .map(|(name, sheet)| table_element(name, sheet)),
+    );
+
+    xml::Document::with_root(
+        xml::ElementBuilder::new("office:document-content")
+            .xmlns("office", OFFICE_NAMESPACE)
+            .xmlns("table", TABLE_NAMESPACE)
+            .xmlns("text", TEXT_NAMESPACE)
+            .attr("office:version", ODF_VERSION)
+            .child(xml::ElementBuilder::new("office:body").child(spreadsheet))
+            .build(),
+    )
+}
+
+fn styles_document() -> xml::Document {
+    xml::Document::with_root(
+        xml::ElementBuilder::new("office:document-styles")
+            .xmlns("office", OFFICE_NAMESPACE)
+            .attr("office:version", ODF_VERSION)
+            .child(xml::ElementBuilder::new("office:styles"))
+            .build(),
+    )
+}
+
+fn manifest_document() -> xml::Document {
+    let entry = |path: &str, media_type: &str| {
+        xml::ElementBuilder::new("manifest:file-entry")
+            .attr("manifest:full-path", path)
+            .attr("manifest:media-type", media_type)
+    };
+
+    xml::Document::with_root(
+        xml::ElementBuilder::new("manifest:manifest")
+            .xmlns("manifest", MANIFEST_NAMESPACE)
+            .attr("manifest:version", ODF_VERSION)
+            .child(entry("/", SPREADSHEET_MIMETYPE).attr("manifest:version", ODF_VERSION))
+            .child(entry("content.xml", "text/xml"))
+            .child(entry("styles.xml", "text/xml"))
+            .build(),
+    )
+}
+
+// Writes each sheet as a table. The `mimetype` goes first and uncompressed, so the type of
+// the file can be told from its first bytes
+pub fn write_ods_file(filename: &str, sheets: &[NamedSheet]) -> Result<(), ODSError> {
+    let file = std::fs::File::create(filename).map_err(zip::ZipError::from)?;
+
+    let mut writer = zip::ZipWriter::new(std::io::BufWriter::new(file));
+
+    writer.add_file(
+        "mimetype",
+        SPREADSHEET_MIMETYPE.as_bytes(),
+        zip::Compression::Stored,
+    )?;
+    writer.add_file(
+        "content.xml",
+        &content_document(sheets).to_bytes(false),
+        zip::Compression::Deflate,
+    )?;
+    writer.add_file(
+        "styles.xml",
+        &styles_document().to_bytes(false),
+        zip::Compression::Deflate,
+    )?;
+    writer.add_file(
+        "META-INF/manifest.xml",
+        &manifest_document().to_bytes(true),
+        zip::Compression::Deflate,
+    )?;
+
+    writer.finish()?;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_read_ods_content() {
+        let content = br#"<?xml version="1.0" encoding="UTF-8"?>
+<office:document-content
+ xmlns:office="urn:oasis:names:tc:opendocument:xmlns:office:1.0"
+ xmlns:table="urn:oasis:names:tc:opendocument:xmlns:table:1.0"
+ xmlns:text="urn:oasis:names:tc:opendocument:xmlns:text:1.0"
+ xmlns:calcext="urn:org:documentfoundation:names:experimental:calc:xmlns:calcext:1.0" office:version="1.2">
+ <office:body>
+  <office:spreadsheet>
+   <table:table table:name="First">
+    <table:table-column table:number-columns-repeated="4"/>
+    <table:table-row>
+     <table:table-cell office:value-type="string"><text:p>Name</text:p></table:table-cell>
+     <table:table-cell table:number-columns-repeated="2"/>
+     <table:table-cell office:value-type="float" office:value="2.5"><text:p>2,50</text:p></table:table-cell>
+    </table:table-row>
+    <table:table-row table:number-rows-repeated="2">
+     <table:table-cell office:value-type="boolean" office:boolean-value="true"><text:p>TRUE</text:p></table:table-cell>
+     <table:table-cell table:number-columns-repeated="2" office:value-type="string"><text:p>x</text:p></table:table-cell>
+    </table:table-row>
+    <table:table-row table:number-rows-repeated="1048570">
+     <table:table-cell table:number-columns-repeated="1024"/>
+    </table:table-row>
+    <table:table-row>
+     <table:table-cell table:number-columns-spanned="2" office:value-type="string"><text:p>two</text:p><text:p>lines<text:s text:c="2"/><text:span>and</text:span><text:s/>span</text:p><office:annotation><text:p>note</text:p></office:annotation></table:table-cell>
+     <table:covered-table-cell/>
+     <table:table-cell office:value-type="date" office:date-value="2024-03-01"><text:p>01/03/24</text:p></table:table-cell>
+     <table:table-cell office:value-type="time" office:time-value="PT13H05M00S"><text:p>13:05</text:p></table:table-cell>
+     <table:table-cell office:value-type="percentage" office:value="0.25"><text:p>25%</text:p></table:table-cell>
+     <table:table-cell table:formula="of:=[.D1]*2" office:value-type="float" office:value="5"><text:p>5</text:p></table:table-cell>
+     <table:table-cell table:formula="of:=1/0" office:value-type="string" office:string-value="" calcext:value-type="error"><text:p>#DIV/0!</text:p></table:table-cell>
+    </table:table-row>
+   </table:table>
+   <table:table table:name="Second">
+    <table:table-row><table:table-cell><text:p>other</text:p></table:table-cell></table:table-row>
+   </table:table>
+  </office:spreadsheet>
+ </office:body>
+</office:document-content>"#;
+
+        let ods = read_ods_content("book.ods", content, None, &mut |_| true).unwrap();
+
+        let mut cells = ods.map.into_iter().collect::<Vec<_>>();
+        cells.sort_by_key(|&(position, _)| position);
+
+        let date = |text| Cell::DateTime(DateTime::parse(text).unwrap());
+        let formula = |formula: &str, value| Cell::Formula {
+            formula: formula.to_string(),
+            value: Box::new(value),
+        };
+
+        assert_eq!(ods.worksheet, "First");
+        assert_eq!(
+            cells,
+            [
+                ((0, 0), Cell::text("Name")),
+                ((0, 3), Cell::Number(2.5)),
+                ((1, 0), Cell::Bool(true)),
+                ((1, 1), Cell::text("x")),
+                ((1, 2), Cell::text("x")),
+                ((2, 0), Cell::Bool(true)),
+                ((2, 1), Cell::text("x")),
+                ((2, 2), Cell::text("x")),
+                ((1048573, 0), Cell::text("two\nlines  and span")),
+                ((1048573, 2), date("2024-03-01")),
+                ((1048573, 3), date("13:05:00")),
+                ((1048573, 4), Cell::Number(0.25)),
+                ((1048573, 5), formula("of:=[.D1]*2", Cell::Number(5.0))),
+                (
+                    (1048573, 6),
+                    formula("of:=1/0", Cell::Error(String::from("#DIV/0!")))
+                ),
+            ]
+        );
+
+        let ods = read_ods_content("book.ods", content, Some("Second"), &mut |_| true).unwrap();
+
+        assert_eq!(ods.worksheet, "Second");
+        assert_eq!(ods.map[&(0, 0)], Cell::text("other"));
+
+        // Reading stops after the row where the progress says so
+        let mut reports = Vec::new();
+        let ods = read_ods_content("book.ods", content, None, &mut |fraction| {
+            reports.push(fraction);
+            false
+        })
+        .unwrap();
+
+        assert_eq!(ods.map.len(), 2);
+        assert!(reports.len() == 1 && reports[0] > 0.0 && reports[0] < 1.0);
+
+        // Repeats stop at the size of the largest sheet
+        let huge = br#"<office:document-content
+ xmlns:office="urn:oasis:names:tc:opendocument:xmlns:office:1.0"
+ xmlns:table="urn:oasis:names:tc:opendocument:xmlns:table:1.0">
+ <office:body><office:spreadsheet><table:table table:name="Huge">
+  <table:table-row>
+   <table:table-cell table:number-columns-repeated="18446744073709551615" office:value-type="float" office:value="1"/>
+   <table:table-cell office:value-type="float" office:value="2"/>
+  </table:table-row>
+  <table:table-row table:number-rows-repeated="18446744073709551615"/>
+  <table:table-row><table:table-cell office:value-type="float" office:value="3"/></table:table-row>
+ </table:table></office:spreadsheet></office:body>
+</office:document-content>"#;
+
+        let ods = read_ods_content("huge.ods", huge, None, &mut |_| true).unwrap();
+
+        assert_eq!(ods.map.len(), MAX_COLS);
+        assert_eq!(ods.map[&(0, MAX_COLS - 1)], Cell::Number(1.0));
+
+        assert!(matches!(
+            read_ods_content("book.ods", content, Some("Third"), &mut |_| true),
+            Err(ODSError::TableNotFound(_))
+        ));
+        assert!(matches!(
+            read_ods_content("page.html", b"<html/>", None, &mut |_| true),
+            Err(ODSError::NotASpreadsheet)
+        ));
+    }
+
+    #[test]
+    fn test_write_ods_content() {
+        let mut map = HashMap::new();
+
+        let date = |text| Cell::DateTime(DateTime::parse(text).unwrap());
+
+        for (pos, value) in [
+            ((0, 0), Cell::text("Name")),
+            ((0, 3), Cell::Number(2.5)),
+            ((1, 1), Cell::text("two\nlines")),
+            ((1, 2), Cell::text(" spaced  out\tand tabbed ")),
+            ((4, 0), Cell::Bool(true)),
+            ((4, 1), date("2024-03-01")),
+            ((4, 2), date("13:05:00")),
+            ((4, 3), Cell::text("007")),
+            ((4, 4), Cell::Number(1e3)),
+            (
+                (4, 5),
+                Cell::Formula {
+                    formula: String::from("of:=[.E5]*2"),
+                    value: Box::new(Cell::Number(2e3)),
+                },
+            ),
+            ((5, 0), Cell::Empty),
+        ] {
+            map.insert(pos, value);
+        }
+
+        let sheet = Sheet::from(map.clone());
+
+        let content = content_document(&[("Data", &sheet)]).to_bytes(false);
+        let xml = String::from_utf8(content.clone()).unwrap();
+
+        assert!(xml.contains(r#"<table:table-column table:number-columns-repeated="6"/>"#));
+        assert!(xml.contains(r#"<table:table-row table:number-rows-repeated="2"><table:table-cell/></table:table-row>"#));
+        assert!(xml.contains(r#"<table:table-cell table:number-columns-repeated="2"/>"#));
+        assert!(
+            xml.contains(r#"<text:p><text:s/>spaced <text:s/>out<text:tab/>and tabbed </text:p>"#)
+        );
+        assert!(xml.contains(r#"office:time-value="PT13H05M00S""#));
+        assert!(xml.contains(r#"office:value="1000" office:value-type="float""#));
+        assert!(xml.contains(r#"table:formula="of:=[.E5]*2""#));
+        assert!(xml.contains(r#"office:value-type="string"><text:p>007</text:p>"#));
+
+        let ods = read_ods_content("data.ods", &content, None, &mut |_| true).unwrap();
+
+        let mut cells = ods.map.into_iter().collect::<Vec<_>>();
+        cells.sort_by_key(|&(position, _)| position);
+
+        let mut expected = map
+            .into_iter()
+            .filter(|(_, value)| !value.is_empty())
+            .collect::<Vec<_>>();
+        expected.sort_by_key(|&(position, _)| position);
+
+        assert_eq!(ods.worksheet, "Data");
+        assert_eq!(cells, expected);
+
+        // Control characters can't be written in XML 1.0, they're dropped
+        let mut sheet = Sheet::new();
+        sheet.set(0, 0, Cell::text("a\u{7}b\u{1b}\u{fffe}c\td"));
+
+        let content = content_document(&[("Bell\u{0}", &sheet)]).to_bytes(false);
+        let ods = read_ods_content("data.ods", &content, None, &mut |_| true).unwrap();
+
+        assert_eq!(ods.worksheet, "Bell");
+        assert_eq!(ods.map[&(0, 0)], Cell::text("abc\td"));
+    }
+}
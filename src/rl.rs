@@ -161,6 +161,7 @@ struct C_Font {
     glyphs: *const c_void,
 }
 
+#[link(name = "raylib", kind = "static")]
 unsafe extern "C" {
     fn InitWindow(width: c_int, height: c_int, name: *const c_char);
     fn CloseWindow();
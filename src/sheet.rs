@@ -0,0 +1,453 @@
+// Cell storage for a sheet. Cells are kept column by column in vectors of compact slots, the
+// text of the cells is interned in one buffer, so a large file costs a few bytes per cell
+// instead of an allocation and a hash entry each.
+//
+// Rows and columns are reached through an indirection: the logical position is an index in
+// `row_order`/`col_order`, whose value is the physical slot. Inserting or removing a row
+// moves indices only, the cells stay where they are.
+//
+use std::borrow::Cow;
+use std::collections::HashMap;
+use std::hash::{DefaultHasher, Hash, Hasher};
+use std::sync::Arc;
+
+use crate::cell::{Cell, CellMap, Style, StyleMap};
+
+// 16 bytes per cell. Dates, errors and formulas are rare and kept aside in `others`.
+#[derive(Copy, Clone, Debug, Default, PartialEq)]
+enum Slot {
+    #[default]
+    Empty,
+    Text(u32),
+    Number(f64),
+    Bool(bool),
+    Other(u32),
+}
+
+// Interned strings, each one is a span of `buffer`
+#[derive(Default)]
+struct Strings {
+    buffer: String,
+    spans: Vec<(usize, usize)>,
+    // Hash of the text to its id. On a collision the text is stored again, which is harmless.
+    ids: HashMap<u64, u32>,
+}
+
+impl Strings {
+    fn get(&self, id: u32) -> &str {
+        let (start, end) = self.spans[id as usize];
+
+        &self.buffer[start..end]
+    }
+
+    // None once the ids run out
+    fn intern(&mut self, text: &str) -> Option<u32> {
+        let mut hasher = DefaultHasher::new();
+        text.hash(&mut hasher);
+        let hash = hasher.finish();
+
+        if let Some(&id) = self.ids.get(&hash)
+            && self.get(id) == text
+        {
+            return Some(id);
+        }
+
+        let id = u32::try_from(self.spans.len()).ok()?;
+        let start = self.buffer.len();
+
+        self.buffer.push_str(text);
+        self.spans.push((start, self.buffer.len()));
+        self.ids.entry(hash).or_insert(id);
+
+        Some(id)
+    }
+}
+
+/// The cells of a sheet by row and column, both counted from 0
+#[derive(Default)]
+pub struct Sheet {
+    // Physical column of each column and physical row of each row
+    col_order: Vec<usize>,
+    row_order: Vec<usize>,
+    next_physical_row: usize,
+    // Columns are only as long as their last cell
+    columns: Vec<Vec<Slot>>,
+    strings: Strings,
+    others: Vec<Cell>,
+    // Slots of `others` whose cell was overwritten or removed, they're reused first
+    free_others: Vec<u32>,
+    // Styles by physical row and column, they stay with their cell when rows move
+    styles: HashMap<(usize, usize), Arc<Style>>,
+}
+
+impl Sheet {
+    pub fn new() -> Self {
+        Sheet::default()
+    }
+
+    pub fn rows(&self) -> usize {
+        self.row_order.len()
+    }
+
+    pub fn cols(&self) -> usize {
+        self.col_order.len()
+    }
+
+    fn slot(&self, row: usize, col: usize) -> Slot {
+        match (self.row_order.get(row), self.col_order.get(col)) {
+            (Some(&r), Some(&c)) => self.columns[c].get(r).copied().unwrap_or_default(),
+            _ => Slot::Empty,
+        }
+    }
+
+    pub fn get(&self, row: usize, col: usize) -> Cell {
+        match self.slot(row, col) {
+            Slot::Empty => Cell::Empty,
+            Slot::Text(id) => Cell::Text(self.strings.get(id).to_string()),
+            Slot::Number(n) => Cell::Number(n),
+            Slot::Bool(b) => Cell::Bool(b),
+            Slot::Other(i) => self.others[i as usize].clone(),
+        }
+    }
+
+    // The style the cell was read with, only some formats have them
+    pub fn style(&self, row: usize, col: usize) -> Option<&Style> {
+        match (self.row_order.get(row), self.col_order.get(col)) {
+            (Some(&r), Some(&c)) => self.styles.get(&(r, c)).map(|style| style.as_ref()),
+            _ => None,
+        }
+    }
+
+    // Styles are only kept inside the sheet
+    pub fn set_style(&mut self, row: usize, col: usize, style: Option<Arc<Style>>) {
+        let (Some(&r), Some(&c)) = (self.row_order.get(row), self.col_order.get(col)) else {
+            return;
+        };
+
+        match style {
+            Some(style) => self.styles.insert((r, c), style),
+            None => self.styles.remove(&(r, c)),
+        };
+    }
+
+    pub fn is_empty_at(&self, row: usize, col: usize) -> bool {
+        self.slot(row, col) == Slot::Empty
+    }
+
+    // The cell as it's shown, text is borrowed from the sheet
+    pub fn display(&self, row: usize, col: usize) -> Cow<'_, str> {
+        match self.slot(row, col) {
+            Slot::Empty => Cow::Borrowed(""),
+            Slot::Text(id) => Cow::Borrowed(self.strings.get(id)),
+            _ => Cow::Owned(self.get(row, col).to_string()),
+        }
+    }
+
+    // Setting a cell past the last row or column grows the sheet
+    pub fn set(&mut self, row: usize, col: usize, cell: Cell) {
+        let slot = match cell {
+            Cell::Empty => Slot::Empty,
+            Cell::Text(text) => self.text_slot(&text),
+            Cell::Number(n) => Slot::Number(n),
+            Cell::Bool(b) => Slot::Bool(b),
+            // A cell written over another one of these takes its slot
+            other => match self.slot(row, col) {
+                Slot::Other(i) => {
+                    self.others[i as usize] = other;
+                    Slot::Other(i)
+                }
+                _ => self.other_slot(other),
+            },
+        };
+
+        self.set_slot(row, col, slot);
+    }
+
+    // Sets a text cell without building a `Cell`, used when reading large files
+    pub fn set_text(&mut self, row: usize, col: usize, text: &str) {
+        let slot = if text.is_empty() {
+            Slot::Empty
+        } else {
+            self.text_slot(text)
+        };
+
+        self.set_slot(row, col, slot);
+    }
+
+    // Past 2^32 distinct strings the text is kept as another cell
+    fn text_slot(&mut self, text: &str) -> Slot {
+        match self.strings.intern(text) {
+            Some(id) => Slot::Text(id),
+            None => self.other_slot(Cell::Text(text.to_string())),
+        }
+    }
+
+    // Past 2^32 of these cells they're kept as their text
+    fn other_slot(&mut self, cell: Cell) -> Slot {
+        if let Some(i) = self.free_others.pop() {
+            self.others[i as usize] = cell;
+
+            return Slot::Other(i);
+        }
+
+        match u32::try_from(self.others.len()) {
+            Ok(i) => {
+                self.others.push(cell);
+                Slot::Other(i)
+            }
+            Err(_) => self
+                .strings
+                .intern(&cell.to_string())
+                .map_or(Slot::Empty, Slot::Text),
+        }
+    }
+
+    fn free_slot(&mut self, slot: Slot) {
+        if let Slot::Other(i) = slot {
+            self.others[i as usize] = Cell::Empty;
+            self.free_others.push(i);
+        }
+    }
+
+    fn set_slot(&mut self, row: usize, col: usize, slot: Slot) {
+        if slot == Slot::Empty && (row >= self.rows() || col >= self.cols()) {
+            return;
+        }
+
+        while self.rows() <= row {
+            self.row_order.push(self.next_physical_row);
+            self.next_physical_row += 1;
+        }
+
+        while self.cols() <= col {
+            self.col_order.push(self.columns.len());
+            self.columns.push(Vec::new());
+        }
+
+        let r = self.row_order[row];
+        let column = &mut self.columns[self.col_order[col]];
+
+        if column.len() <= r {
+            if slot == Slot::Empty {
+                return;
+            }
+
+            column.resize(r + 1, Slot::Empty);
+        }
+
+        let old = std::mem::replace(&mut column[r], slot);
+
+        if old != slot {
+            self.free_slot(old);
+        }
+    }
+
+    // The new row is empty, the rows from `at` move down
+    pub fn insert_row(&mut self, at: usize) {
+        if at > self.rows() {
+            return;
+        }
+
+        self.row_order.insert(at, self.next_physical_row);
+        self.next_physical_row += 1;
+    }
+
+    pub fn insert_col(&mut self, at: usize) {
+        if at > self.cols() {
+            return;
+        }
+
+        self.col_order.insert(at, self.columns.len());
+        self.columns.push(Vec::new());
+    }
+
+    pub fn remove_row(&mut self, at: usize) {
+        if at >= self.rows() {
+            return;
+        }
+
+        let r = self.row_order.remove(at);
+
+        for c in 0..self.columns.len() {
+            if let Some(slot) = self.columns[c].get_mut(r) {
+                let old = std::mem::take(slot);
+                self.free_slot(old);
+            }
+        }
+
+        self.styles.retain(|&(row, _), _| row != r);
+    }
+
+    pub fn remove_col(&mut self, at: usize) {
+        if at >= self.cols() {
+            return;
+        }
+
+        let c = self.col_order.remove(at);
+
+        for slot in std::mem::take(&mut self.columns[c]) {
+            self.free_slot(slot);
+        }
+
+        self.styles.retain(|&(_, col), _| col != c);
+    }
+
+    // The non-empty cells, row by row
+    pub fn cells(&self) -> impl Iterator<Item = ((usize, usize), Cell)> + '_ {
+        (0..self.rows()).flat_map(move |row| {
+            (0..self.cols())
+                .filter(move |&col| !self.is_empty_at(row, col))
+                .map(move |col| ((row, col), self.get(row, col)))
+        })
+    }
+
+    // Positions of the cells whose text has `needle`, ignoring case, row by row. Each
+    // interned string is checked once.
+    pub fn find(&self, needle: &str) -> Vec<(usize, usize)> {
+        let needle = needle.to_lowercase();
+
+        let matching: Vec<bool> = (0..self.strings.spans.len())
+            .map(|id| self.strings.get(id as u32).to_lowercase().contains(&needle))
+            .collect();
+
+        let mut found = Vec::new();
+
+        for row in 0..self.rows() {
+            for col in 0..self.cols() {
+                let matches = match self.slot(row, col) {
+                    Slot::Empty => false,
+                    Slot::Text(id) => matching[id as usize],
+                    _ => self.display(row, col).to_lowercase().contains(&needle),
+                };
+
+                if matches {
+                    found.push((row, col));
+                }
+            }
+        }
+
+        found
+    }
+}
+
+impl Sheet {
+    // The cells and the styles of a reader
+    pub fn from_cells(map: CellMap, styles: StyleMap) -> Self {
+        let mut sheet = Sheet::new();
+
+        let mut cells = map.into_iter().collect::<Vec<_>>();
+        cells.sort_by_key(|&(position, _)| position);
+
+        for ((row, col), cell) in cells {
+            sheet.set(row, col, cell);
+        }
+
+        for ((row, col), style) in styles {
+            sheet.set_style(row, col, Some(style));
+        }
+
+        sheet
+    }
+}
+
+impl From<CellMap> for Sheet {
+    fn from(map: CellMap) -> Self {
+        Sheet::from_cells(map, StyleMap::new())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_sheet() {
+        let mut sheet = Sheet::new();
+
+        sheet.set(0, 0, Cell::text("name"));
+        sheet.set_text(0, 1, "Name");
+        sheet.set(1, 0, Cell::Number(2.5));
+        sheet.set(1, 1, Cell::Bool(true));
+        sheet.set(2, 2, Cell::Error(String::from("#N/A")));
+        sheet.set_text(3, 0, "name");
+        sheet.set(9, 9, Cell::Empty);
+
+        assert_eq!((sheet.rows(), sheet.cols()), (4, 3));
+        assert_eq!(sheet.strings.spans.len(), 2);
+        assert_eq!(sheet.get(1, 0), Cell::Number(2.5));
+        assert_eq!(sheet.get(2, 2), Cell::Error(String::from("#N/A")));
+        assert_eq!(sheet.get(2, 1), Cell::Empty);
+        assert_eq!(sheet.get(100, 100), Cell::Empty);
+        assert_eq!(sheet.display(1, 1), "true");
+        assert_eq!(sheet.find("NAM"), [(0, 0), (0, 1), (3, 0)]);
+        assert_eq!(sheet.find("n/a"), [(2, 2)]);
+
+        sheet.insert_row(1);
+        sheet.insert_col(0);
+
+        assert_eq!((sheet.rows(), sheet.cols()), (5, 4));
+        assert_eq!(sheet.get(0, 1), Cell::text("name"));
+        assert_eq!(sheet.get(1, 1), Cell::Empty);
+        assert_eq!(sheet.get(2, 1), Cell::Number(2.5));
+
+        sheet.set(1, 0, Cell::text("new"));
+        sheet.remove_row(2);
+        sheet.remove_col(1);
+
+        let cells = sheet.cells().collect::<Vec<_>>();
+
+        assert_eq!((sheet.rows(), sheet.cols()), (4, 3));
+        assert_eq!(
+            cells,
+            [
+                ((0, 1), Cell::text("Name")),
+                ((1, 0), Cell::text("new")),
+                ((2, 2), Cell::Error(String::from("#N/A"))),
+            ]
+        );
+
+        sheet.set(1, 0, Cell::Empty);
+
+        assert!(sheet.is_empty_at(1, 0));
+        assert_eq!(sheet.cells().count(), 2);
+
+        // Overwritten and removed cells give their slot of `others` back
+        let mut sheet = Sheet::new();
+
+        for i in 0..10 {
+            sheet.set(0, 0, Cell::Error(format!("#{i}")));
+        }
+
+        sheet.set(1, 1, Cell::Error(String::from("#REF!")));
+        sheet.set(1, 1, Cell::Number(1.0));
+        sheet.set(2, 2, Cell::Error(String::from("#N/A")));
+        sheet.remove_row(0);
+        sheet.set(3, 0, Cell::Error(String::from("#DIV/0!")));
+
+        assert_eq!(sheet.others.len(), 2);
+        assert_eq!(sheet.get(1, 2), Cell::Error(String::from("#N/A")));
+        assert_eq!(sheet.get(3, 0), Cell::Error(String::from("#DIV/0!")));
+
+        let map = CellMap::from([((1, 1), Cell::Number(1.0)), ((0, 3), Cell::text("x"))]);
+        let sheet = Sheet::from(map);
+
+        assert_eq!((sheet.rows(), sheet.cols()), (2, 4));
+        assert_eq!(sheet.get(0, 3), Cell::text("x"));
+
+        let style = Arc::new(Style {
+            number_format: String::from("0.00"),
+        });
+        let mut sheet = Sheet::from_cells(
+            CellMap::from([((1, 1), Cell::Number(1.0))]),
+            StyleMap::from([((1, 1), style.clone())]),
+        );
+
+        assert_eq!(sheet.style(1, 1), Some(style.as_ref()));
+
+        // The style moves with its cell
+        sheet.insert_row(0);
+
+        assert_eq!(sheet.style(1, 1), None);
+        assert_eq!(sheet.style(2, 1), Some(style.as_ref()));
+    }
+}
@@ -0,0 +1,911 @@
+// Excel 97-2003 workbooks (.xls). The workbook is the `Workbook` stream of a compound file,
+// a sequence of BIFF8 records, each one a type, a size and its data:
+//
+//     BOF (globals) ... SST, FORMAT, XF, BOUNDSHEET ... EOF
+//     BOF (sheet) ... LABELSST, NUMBER, RK, MULRK, FORMULA ... EOF
+//     BOF (sheet) ...
+//
+// The globals have the shared strings, the number formats and the position of each sheet in
+// the stream. Records are at most 8224 bytes, longer data goes on in CONTINUE records.
+//
+use std::collections::HashMap;
+use std::sync::Arc;
+
+use crate::cell::{self, Cell, CellMap, DateTime, Progress, Style, StyleMap};
+use crate::xlsx::cfb;
+
+const RECORD_BOF: u16 = 0x0809;
+const RECORD_EOF: u16 = 0x000a;
+const RECORD_CONTINUE: u16 = 0x003c;
+const RECORD_FILEPASS: u16 = 0x002f;
+const RECORD_DATEMODE: u16 = 0x0022;
+const RECORD_BOUNDSHEET: u16 = 0x0085;
+const RECORD_SST: u16 = 0x00fc;
+const RECORD_FORMAT: u16 = 0x041e;
+const RECORD_XF: u16 = 0x00e0;
+const RECORD_LABELSST: u16 = 0x00fd;
+const RECORD_LABEL: u16 = 0x0204;
+const RECORD_NUMBER: u16 = 0x0203;
+const RECORD_RK: u16 = 0x027e;
+const RECORD_MULRK: u16 = 0x00bd;
+const RECORD_BOOLERR: u16 = 0x0205;
+const RECORD_FORMULA: u16 = 0x0006;
+const RECORD_STRING: u16 = 0x0207;
+
+const BIFF8_VERSION: u16 = 0x0600;
+
+// Sheet types of BOUNDSHEET, only worksheets have cells
+const SHEET_WORKSHEET: u8 = 0;
+
+// Records read between two progress reports
+const PROGRESS_RECORDS: usize = 1024;
+
+#[derive(Debug)]
+pub enum XLSError {
+    Cfb(cfb::CFBError),
+    Unsupported(&'static str),
+    Encrypted,
+    Corrupted(&'static str),
+    SheetNotFound(String),
+}
+
+impl std::fmt::Display for XLSError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            XLSError::Cfb(e) => write!(f, "{e}"),
+            XLSError::Unsupported(what) => write!(f, "{what} are not supported"),
+            XLSError::Encrypted => write!(f, "encrypted workbooks are not supported"),
+            XLSError::Corrupted(what) => write!(f, "corrupted workbook: {what}"),
+            XLSError::SheetNotFound(name) => write!(f, "sheet {name} not found"),
+        }
+    }
+}
+
+impl From<cfb::CFBError> for XLSError {
+    fn from(e: cfb::CFBError) -> Self {
+        XLSError::Cfb(e)
+    }
+}
+
+pub struct XLSMap {
+    pub filename: String,
+    pub worksheet: String,
+    pub map: CellMap,
+    pub styles: StyleMap,
+}
+
+fn read_u16(data: &[u8], offset: usize) -> Option<u16> {
+    Some(u16::from_le_bytes(
+        data.get(offset..offset + 2)?.try_into().ok()?,
+    ))
+}
+
+fn read_u32(data: &[u8], offset: usize) -> Option<u32> {
+    Some(u32::from_le_bytes(
+        data.get(offset..offset + 4)?.try_into().ok()?,
+    ))
+}
+
+fn read_f64(data: &[u8], offset: usize) -> Option<f64> {
+    Some(f64::from_le_bytes(
+        data.get(offset..offset + 8)?.try_into().ok()?,
+    ))
+}
+
+struct Record<'a> {
+    offset: usize,
+    kind: u16,
+    data: &'a [u8],
+}
+
+// Splits the stream in records, the stream may be padded after the last one
+fn read_records(stream: &[u8]) -> Vec<Record<'_>> {
+    let mut records = Vec::new();
+    let mut offset = 0;
+
+    while let (Some(kind), Some(size)) = (read_u16(stream, offset), read_u16(stream, offset + 2)) {
+        let Some(data) = stream.get(offset + 4..offset + 4 + size as usize) else {
+            break;
+        };
+
+        records.push(Record { offset, kind, data });
+        offset += 4 + size as usize;
+    }
+
+    records
+}
+
+// Reads the data of a record and its CONTINUE records as if it were one. Only strings notice
+// the boundaries: characters that go on in the next record are preceded by a new flags byte,
+// as they may be stored with a different width.
+struct ContinuedReader<'a> {
+    parts: Vec<&'a [u8]>,
+    part: usize,
+    pos: usize,
+}
+
+impl<'a> ContinuedReader<'a> {
+    fn new(records: &[Record<'a>], index: usize) -> Self {
+        let parts = std::iter::once(records[index].data)
+            .chain(
+                records[index + 1..]
+                    .iter()
+                    .take_while(|r| r.kind == RECORD_CONTINUE)
+                    .map(|r| r.data),
+            )
+            .collect();
+
+        Self {
+            parts,
+            part: 0,
+            pos: 0,
+        }
+    }
+
+    fn from_data(data: &'a [u8]) -> Self {
+        Self {
+            parts: vec![data],
+            part: 0,
+            pos: 0,
+        }
+    }
+
+    fn read_u8(&mut self) -> Option<u8> {
+        while self.pos >= self.parts.get(self.part)?.len() {
+            self.part += 1;
+            self.pos = 0;
+        }
+
+        self.pos += 1;
+
+        Some(self.parts[self.part][self.pos - 1])
+    }
+
+    fn read_u16(&mut self) -> Option<u16> {
+        Some(u16::from_le_bytes([self.read_u8()?, self.read_u8()?]))
+    }
+
+    fn read_u32(&mut self) -> Option<u32> {
+        Some(u32::from_le_bytes([
+            self.read_u8()?,
+            self.read_u8()?,
+            self.read_u8()?,
+            self.read_u8()?,
+        ]))
+    }
+
+    fn skip(&mut self, count: usize) -> Option<()> {
+        for _ in 0..count {
+            self.read_u8()?;
+        }
+
+        Some(())
+    }
+
+    // Compressed characters are one byte, the low byte of the UTF-16 code unit
+    fn read_chars(&mut self, count: usize, mut high_byte: bool) -> Option<String> {
+        let mut units = Vec::with_capacity(count);
+
+        while units.len() < count {
+            let part = *self.parts.get(self.part)?;
+
+            if self.pos >= part.len() {
+                self.part += 1;
+                high_byte = self.parts.get(self.part)?.first()? & 1 != 0;
+                self.pos = 1;
+                continue;
+            }
+
+            if high_byte {
+                units.push(u16::from_le_bytes([
+                    part[self.pos],
+                    *part.get(self.pos + 1)?,
+                ]));
+                self.pos += 2;
+            } else {
+                units.push(part[self.pos] as u16);
+                self.pos += 1;
+            }
+        }
+
+        Some(
+            char::decode_utf16(units)
+                .map(|c| c.unwrap_or(char::REPLACEMENT_CHARACTER))
+                .collect(),
+        )
+    }
+
+    // XLUnicodeString and XLUnicodeRichExtendedString: a 16 bit count of characters and the
+    // flags, the formatting runs and the phonetic data are skipped
+    fn read_string(&mut self) -> Option<String> {
+        let count = self.read_u16()? as usize;
+        self.read_string_with_count(count)
+    }
+
+    // ShortXLUnicodeString, with an 8 bit count
+    fn read_short_string(&mut self) -> Option<String> {
+        let count = self.read_u8()? as usize;
+        self.read_string_with_count(count)
+    }
+
+    fn read_string_with_count(&mut self, count: usize) -> Option<String> {
+        let flags = self.read_u8()?;
+
+        let runs = if flags & 0x08 != 0 {
+            self.read_u16()? as usize
+        } else {
+            0
+        };
+
+        let extended_size = if flags & 0x04 != 0 {
+            self.read_u32()? as usize
+        } else {
+            0
+        };
+
+        let text = self.read_chars(count, flags & 0x01 != 0)?;
+
+        self.skip(4 * runs + extended_size)?;
+
+        Some(text)
+    }
+}
+
+// RK numbers are 30 bits of either an integer or the high part of a float, optionally
+// multiplied by 100
+pub fn rk_value(rk: u32) -> f64 {
+    let value = if rk & 0x02 != 0 {
+        ((rk as i32) >> 2) as f64
+    } else {
+        f64::from_bits(((rk & 0xfffffffc) as u64) << 32)
+    };
+
+    if rk & 0x01 != 0 { value / 100.0 } else { value }
+}
+
+pub fn error_value(code: u8) -> Cell {
+    let name = match code {
+        0x00 => "#NULL!",
+        0x07 => "#DIV/0!",
+        0x0f => "#VALUE!",
+        0x17 => "#REF!",
+        0x1d => "#NAME?",
+        0x24 => "#NUM!",
+        0x2a => "#N/A",
+        _ => "#ERROR!",
+    };
+
+    Cell::Error(name.to_string())
+}
+
+// Built-in formats 14 to 22 and 45 to 47 are dates and times
+pub fn is_builtin_date_format(format: u16) -> bool {
+    matches!(format, 14..=22 | 45..=47)
+}
+
+// A custom format is a date if it has any date or time code outside of quoted text,
+// brackets (colors, conditions) and escaped characters. Only the first section matters.
+pub fn is_date_format(format: &str) -> bool {
+    let mut in_quotes = false;
+    let mut in_brackets = false;
+    let mut escaped = false;
+
+    for c in format.chars() {
+        if escaped {
+            escaped = false;
+            continue;
+        }
+
+        match c {
+            '"' => in_quotes = !in_quotes,
+            _ if in_quotes => {}
+            '\\' | '_' | '*' => escaped = true,
+            '[' => in_brackets = true,
+            ']' => in_brackets = false,
+            _ if in_brackets => {}
+            ';' => break,
+            'y' | 'Y' | 'm' | 'M' | 'd' | 'D' | 'h' | 'H' | 's' | 'S' => return true,
+            _ => {}
+        }
+    }
+
+    false
+}
+
+// The style of the cells with a format, there's none for the General format
+pub fn format_style(format: u16, custom: &HashMap<u16, String>) -> Option<Arc<Style>> {
+    let number_format = match custom.get(&format) {
+        Some(f) => f.as_str(),
+        None => cell::builtin_number_format(format)?,
+    };
+
+    if number_format.eq_ignore_ascii_case("General") {
+        return None;
+    }
+
+    Some(Arc::new(Style {
+        number_format: number_format.to_string(),
+    }))
+}
+
+// Whether a cell format (XF) has a date number format, and its style. Xls, xlsb and xlsx
+// number their formats the same way.
+#[derive(Debug, PartialEq)]
+pub struct CellFormat {
+    pub date: bool,
+    pub style: Option<Arc<Style>>,
+}
+
+impl CellFormat {
+    pub fn new(format: u16, custom: &HashMap<u16, String>) -> Self {
+        CellFormat {
+            date: is_builtin_date_format(format)
+                || custom.get(&format).is_some_and(|f| is_date_format(f)),
+            style: format_style(format, custom),
+        }
+    }
+}
+
+// Numbers with a date format are dates, when they are in range
+pub fn number_value(formats: &[CellFormat], xf: usize, value: f64, date1904: bool) -> Cell {
+    if formats.get(xf).is_some_and(|f| f.date)
+        && let Some(datetime) = DateTime::from_serial(value, date1904)
+    {
+        Cell::DateTime(datetime)
+    } else {
+        Cell::Number(value)
+    }
+}
+
+struct BoundSheet {
+    name: String,
+    offset: usize,
+    sheet_type: u8,
+}
+
+// What's needed from the globals to read the cells of a sheet
+struct Globals {
+    shared_strings: Vec<String>,
+    formats: Vec<CellFormat>,
+    date1904: bool,
+    sheets: Vec<BoundSheet>,
+}
+
+impl Globals {
+    fn number_value(&self, value: f64, xf: usize) -> Cell {
+        number_value(&self.formats, xf, value, self.date1904)
+    }
+}
+
+fn check_bof(record: Option<&Record>) -> Result<(), XLSError> {
+    match record {
+        Some(r) if r.kind == RECORD_BOF => match read_u16(r.data, 0) {
+            Some(BIFF8_VERSION) => Ok(()),
+            _ => Err(XLSError::Unsupported("Excel 5.0/95 workbooks")),
+        },
+        _ => Err(XLSError::Corrupted("missing BOF record")),
+    }
+}
+
+fn read_globals(records: &[Record]) -> Result<Globals, XLSError> {
+    check_bof(records.first())?;
+
+    let mut globals = Globals {
+        shared_strings: Vec::new(),
+        formats: Vec::new(),
+        date1904: false,
+        sheets: Vec::new(),
+    };
+
+    let mut formats: HashMap<u16, String> = HashMap::new();
+    let mut xf_formats: Vec<u16> = Vec::new();
+
+    for (i, record) in records.iter().enumerate().skip(1) {
+        match record.kind {
+            RECORD_EOF => break,
+            RECORD_FILEPASS => return Err(XLSError::Encrypted),
+            RECORD_DATEMODE => globals.date1904 = read_u16(record.data, 0) == Some(1),
+            RECORD_FORMAT => {
+                let mut reader = ContinuedReader::from_data(record.data);
+
+                if let (Some(index), Some(format)) = (reader.read_u16(), reader.read_string()) {
+                    formats.insert(index, format);
+                }
+            }
+            RECORD_XF => xf_formats.push(read_u16(record.data, 2).unwrap_or(0)),
+            RECORD_SST => {
+                let mut reader = ContinuedReader::new(records, i);
+
+                reader.skip(4);
+
+                let count = reader.read_u32().unwrap_or(0);
+
+                for _ in 0..count {
+                    match reader.read_string() {
+                        Some(text) => globals.shared_strings.push(text),
+                        None => return Err(XLSError::Corrupted("truncated shared strings")),
+                    }
+                }
+            }
+            RECORD_BOUNDSHEET => {
+                let mut reader =
+                    ContinuedReader::from_data(&record.data[4.min(record.data.len())..]);
+
+                let _visibility = reader.read_u8();
+                let sheet_type = reader.read_u8();
+                let name = reader.read_short_string();
+
+                if let (Some(offset), Some(sheet_type), Some(name)) =
+                    (read_u32(record.data, 0), sheet_type, name)
+                {
+                    globals.sheets.push(BoundSheet {
+                        name,
+                        offset: offset as usize,
+                        sheet_type,
+                    });
+                }
+            }
+            _ => {}
+        }
+    }
+
+    globals.formats = xf_formats
+        .iter()
+        .map(|&format| CellFormat::new(format, &formats))
+        .collect();
+
+    Ok(globals)
+}
+
+// Row, column and XF index that start every cell record
+fn cell_header(data: &[u8]) -> Option<(usize, usize, usize)> {
+    Some((
+        read_u16(data, 0)? as usize,
+        read_u16(data, 2)? as usize,
+        read_u16(data, 4)? as usize,
+    ))
+}
+
+// The cells of a sheet and the styles of the ones with a number format
+struct SheetCells<'a> {
+    globals: &'a Globals,
+    map: CellMap,
+    styles: StyleMap,
+}
+
+impl SheetCells<'_> {
+    fn insert(&mut self, position: (usize, usize), xf: usize, cell: Cell) {
+        if cell.is_empty() {
+            return;
+        }
+
+        if let Some(style) = self.globals.formats.get(xf).and_then(|f| f.style.as_ref()) {
+            self.styles.insert(position, style.clone());
+        }
+
+        self.map.insert(position, cell);
+    }
+}
+
+// Formulas are compiled to tokens in the file, only their cached result is kept
+fn read_sheet(
+    records: &[Record],
+    start: usize,
+    globals: &Globals,
+    progress: Progress,
+) -> Result<(CellMap, StyleMap), XLSError> {
+    check_bof(records.get(start))?;
+
+    let mut cells = SheetCells {
+        globals,
+        map: HashMap::new(),
+        styles: HashMap::new(),
+    };
+
+    // Formulas with a string result have it in the STRING record that follows them
+    let mut pending_string: Option<((usize, usize), usize)> = None;
+
+    for (i, record) in records.iter().enumerate().skip(start + 1) {
+        let data = record.data;
+
+        if i % PROGRESS_RECORDS == 0 && !progress(i as f32 / records.len() as f32) {
+            break;
+        }
+
+        match record.kind {
+            RECORD_EOF => break,
+            RECORD_LABELSST => {
+                if let (Some((row, col, xf)), Some(index)) = (cell_header(data), read_u32(data, 6))
+                {
+                    let text = globals
+                        .shared_strings
+                        .get(index as usize)
+                        .map(|text| text.as_str())
+                        .unwrap_or("???");
+
+                    cells.insert((row, col), xf, Cell::text(text));
+                }
+            }
+            RECORD_LABEL => {
+                if let Some((row, col, xf)) = cell_header(data)
+                    && let Some(text) = ContinuedReader::from_data(&data[6..]).read_string()
+                {
+                    cells.insert((row, col), xf, Cell::text(&text));
+                }
+            }
+            RECORD_NUMBER => {
+                if let (Some((row, col, xf)), Some(value)) = (cell_header(data), read_f64(data, 6))
+                {
+                    cells.insert((row, col), xf, globals.number_value(value, xf));
+                }
+            }
+            RECORD_RK => {
+                if let (Some((row, col, xf)), Some(rk)) = (cell_header(data), read_u32(data, 6)) {
+                    cells.insert((row, col), xf, globals.number_value(rk_value(rk), xf));
+                }
+            }
+            // A row of RK numbers: row, first column, (XF, RK) for each one, last column
+            RECORD_MULRK => {
+                if let (Some(row), Some(first_col)) = (read_u16(data, 0), read_u16(data, 2)) {
+                    let count = data.len().saturating_sub(6) / 6;
+
+                    for j in 0..count {
+                        let offset = 4 + 6 * j;
+
+                        if let (Some(xf), Some(rk)) =
+                            (read_u16(data, offset), read_u32(data, offset + 2))
+                        {
+                            let xf = xf as usize;
+
+                            cells.insert(
+                                (row as usize, first_col as usize + j),
+                                xf,
+                                globals.number_value(rk_value(rk), xf),
+                            );
+                        }
+                    }
+                }
+            }
+            RECORD_BOOLERR => {
+                if let (Some((row, col, xf)), Some(&value), Some(&is_error)) =
+                    (cell_header(data), data.get(6), data.get(7))
+                {
+                    let cell = match is_error {
+                        0 => Cell::Bool(value != 0),
+                        _ => error_value(value),
+                    };
+
+                    cells.insert((row, col), xf, cell);
+                }
+            }
+            // The cached result is a float, unless the last two bytes are 0xffff and the
+            // first one tells the type
+            RECORD_FORMULA => {
+                let Some((row, col, xf)) = cell_header(data) else {
+                    continue;
+                };
+
+                pending_string = None;
+
+                match data.get(6..14) {
+                    Some(result) if result[6..8] == [0xff, 0xff] => match result[0] {
+                        0 => pending_string = Some(((row, col), xf)),
+                        1 => cells.insert((row, col), xf, Cell::Bool(result[2] != 0)),
+                        2 => cells.insert((row, col), xf, error_value(result[2])),
+                        _ => {}
+                    },
+                    Some(result) => {
+                        let value = f64::from_le_bytes(result.try_into().unwrap());
+                        cells.insert((row, col), xf, globals.number_value(value, xf));
+                    }
+                    None => {}
+                }
+            }
+            RECORD_STRING => {
+                if let Some((position, xf)) = pending_string.take()
+                    && let Some(text) = ContinuedReader::new(records, i).read_string()
+                {
+                    cells.insert(position, xf, Cell::text(&text));
+                }
+            }
+            _ => {}
+        }
+    }
+
+    Ok((cells.map, cells.styles))
+}
+
+// Reads the worksheet with the given name, or the first one, from the workbook stream.
+// `filename` is only used as a label
+pub fn read_workbook_stream(
+    filename: &str,
+    stream: &[u8],
+    sheet_name: Option<&str>,
+    progress: Progress,
+) -> Result<XLSMap, XLSError> {
+    let records = read_records(stream);
+    let globals = read_globals(&records)?;
+
+    let sheet = globals
+        .sheets
+        .iter()
+        .filter(|s| s.sheet_type == SHEET_WORKSHEET)
+        .find(|s| sheet_name.is_none_or(|wanted| wanted == s.name))
+        .ok_or_else(|| XLSError::SheetNotFound(sheet_name.unwrap_or("").to_string()))?;
+
+    let start = records
+        .iter()
+        .position(|r| r.offset == sheet.offset)
+        .ok_or(XLSError::Corrupted("bad sheet offset"))?;
+
+    let (map, styles) = read_sheet(&records, start, &globals, progress)?;
+
+    Ok(XLSMap {
+        filename: filename.to_string(),
+        worksheet: sheet.name.clone(),
+        map,
+        styles,
+    })
+}
+
+pub fn read_xls_data(
+    filename: &str,
+    data: Vec<u8>,
+    sheet_name: Option<&str>,
+    progress: Progress,
+) -> Result<XLSMap, XLSError> {
+    let cfb = cfb::CompoundFile::from_data(data)?;
+
+    let names = cfb.stream_names()?;
+
+    // Excel 5.0 and 95 named the stream "Book" and wrote BIFF5
+    if !names.iter().any(|n| n.eq_ignore_ascii_case("Workbook"))
+        && names.iter().any(|n| n.eq_ignore_ascii_case("Book"))
+    {
+        return Err(XLSError::Unsupported("Excel 5.0/95 workbooks"));
+    }
+
+    let stream = cfb.read_stream("Workbook")?;
+
+    read_workbook_stream(filename, &stream, sheet_name, progress)
+}
+
+pub fn read_xls_file_as_hashmap(
+    filename: &str,
+    sheet_name: Option<&str>,
+    progress: Progress,
+) -> Result<XLSMap, XLSError> {
+    let data = std::fs::read(filename).map_err(cfb::CFBError::from)?;
+
+    read_xls_data(filename, data, sheet_name, progress)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn record(kind: u16, data: &[u8]) -> Vec<u8> {
+        let mut result = Vec::new();
+
+        result.extend_from_slice(&kind.to_le_bytes());
+        result.extend_from_slice(&(data.len() as u16).to_le_bytes());
+        result.extend_from_slice(data);
+
+        result
+    }
+
+    fn cell(row: u16, col: u16, xf: u16, rest: &[u8]) -> Vec<u8> {
+        [
+            &row.to_le_bytes()[..],
+            &col.to_le_bytes(),
+            &xf.to_le_bytes(),
+            rest,
+        ]
+        .concat()
+    }
+
+    fn compressed_string(text: &str) -> Vec<u8> {
+        [
+            &(text.len() as u16).to_le_bytes()[..],
+            &[0],
+            text.as_bytes(),
+        ]
+        .concat()
+    }
+
+    fn bof(sheet_type: u16) -> Vec<u8> {
+        record(
+            RECORD_BOF,
+            &[
+                &BIFF8_VERSION.to_le_bytes()[..],
+                &sheet_type.to_le_bytes(),
+                &[0; 12],
+            ]
+            .concat(),
+        )
+    }
+
+    fn globals(offsets: &[u32]) -> Vec<u8> {
+        let xf = |format: u16| {
+            record(
+                RECORD_XF,
+                &[&[0, 0][..], &format.to_le_bytes(), &[0; 16]].concat(),
+            )
+        };
+
+        let bound_sheet = |offset: u32, sheet_type: u8, name: &str| {
+            record(
+                RECORD_BOUNDSHEET,
+                &[
+                    &offset.to_le_bytes()[..],
+                    &[0, sheet_type, name.len() as u8, 0],
+                    name.as_bytes(),
+                ]
+                .concat(),
+            )
+        };
+
+        // The second string goes on in a CONTINUE record, as UTF-16. The third one has a
+        // formatting run.
+        let sst = [
+            &[3, 0, 0, 0, 3, 0, 0, 0][..],
+            &compressed_string("Name"),
+            &[8, 0, 0],
+            b"Caf",
+        ]
+        .concat();
+
+        let sst_continue = [
+            &[1][..],
+            &"é ’x’"
+                .encode_utf16()
+                .flat_map(|u| u.to_le_bytes())
+                .collect::<Vec<_>>(),
+            &[4, 0, 0x08, 1, 0],
+            b"rich",
+            &[0, 0, 1, 0],
+        ]
+        .concat();
+
+        [
+            bof(0x0005),
+            record(RECORD_DATEMODE, &[0, 0]),
+            record(
+                RECORD_FORMAT,
+                &[&[164, 0][..], &compressed_string("dd/mm/yyyy\\ hh:mm")].concat(),
+            ),
+            xf(0),
+            xf(14),
+            xf(164),
+            xf(2),
+            record(RECORD_SST, &sst),
+            record(RECORD_CONTINUE, &sst_continue),
+            bound_sheet(offsets[0], 2, "Chart"),
+            bound_sheet(offsets[1], SHEET_WORKSHEET, "Data"),
+            bound_sheet(offsets[2], SHEET_WORKSHEET, "Other"),
+            record(RECORD_EOF, &[]),
+        ]
+        .concat()
+    }
+
+    fn workbook_stream() -> Vec<u8> {
+        let mulrk = [
+            &[2, 0, 0, 0][..],
+            &[0, 0],
+            &0x3fe00000u32.to_le_bytes(),
+            &[0, 0],
+            &((7 << 2) | 2u32).to_le_bytes(),
+            &[1, 0],
+        ]
+        .concat();
+
+        let formula = |row, col, result: [u8; 8]| {
+            record(
+                RECORD_FORMULA,
+                &cell(row, col, 0, &[&result[..], &[0; 8]].concat()),
+            )
+        };
+
+        let data = [
+            bof(0x0010),
+            record(RECORD_LABELSST, &cell(0, 0, 0, &0u32.to_le_bytes())),
+            record(RECORD_LABELSST, &cell(0, 1, 0, &1u32.to_le_bytes())),
+            record(RECORD_NUMBER, &cell(1, 0, 3, &2.5f64.to_le_bytes())),
+            record(RECORD_RK, &cell(1, 1, 0, &((42 << 2) | 2u32).to_le_bytes())),
+            record(
+                RECORD_RK,
+                &cell(1, 2, 0, &((1234 << 2) | 3u32).to_le_bytes()),
+            ),
+            record(
+                RECORD_RK,
+                &cell(1, 3, 0, &((-5i32 << 2) as u32 | 2).to_le_bytes()),
+            ),
+            record(RECORD_MULRK, &mulrk),
+            record(RECORD_NUMBER, &cell(3, 0, 1, &45352f64.to_le_bytes())),
+            record(RECORD_NUMBER, &cell(3, 1, 2, &45352.5f64.to_le_bytes())),
+            record(RECORD_BOOLERR, &cell(4, 0, 0, &[1, 0])),
+            record(RECORD_BOOLERR, &cell(4, 1, 0, &[0x07, 1])),
+            formula(5, 0, [0, 0, 0, 0, 0, 0, 0xff, 0xff]),
+            record(RECORD_STRING, &compressed_string("calc")),
+            formula(5, 1, 3f64.to_le_bytes()),
+            formula(5, 2, [1, 0, 1, 0, 0, 0, 0xff, 0xff]),
+            formula(5, 3, [2, 0, 0x2a, 0, 0, 0, 0xff, 0xff]),
+            record(RECORD_LABEL, &cell(6, 0, 0, &compressed_string("label"))),
+            record(RECORD_EOF, &[]),
+        ]
+        .concat();
+
+        let other = [
+            bof(0x0010),
+            record(RECORD_LABELSST, &cell(0, 0, 0, &2u32.to_le_bytes())),
+            record(RECORD_EOF, &[]),
+        ]
+        .concat();
+
+        // The size of the globals doesn't depend on the offsets
+        let start = globals(&[0, 0, 0]).len() as u32;
+        let offsets = [start, start, start + data.len() as u32];
+
+        [globals(&offsets), data, other].concat()
+    }
+
+    #[test]
+    fn test_read_workbook_stream() {
+        let stream = workbook_stream();
+
+        let xls = read_workbook_stream("book.xls", &stream, None, &mut |_| true).unwrap();
+
+        let date = |text| Cell::DateTime(DateTime::parse(text).unwrap());
+
+        let expected = [
+            ((0, 0), Cell::text("Name")),
+            ((0, 1), Cell::text("Café ’x’")),
+            ((1, 0), Cell::Number(2.5)),
+            ((1, 1), Cell::Number(42.0)),
+            ((1, 2), Cell::Number(12.34)),
+            ((1, 3), Cell::Number(-5.0)),
+            ((2, 0), Cell::Number(0.5)),
+            ((2, 1), Cell::Number(7.0)),
+            ((3, 0), date("2024-03-01")),
+            ((3, 1), date("2024-03-01T12:00:00")),
+            ((4, 0), Cell::Bool(true)),
+            ((4, 1), Cell::Error(String::from("#DIV/0!"))),
+            ((5, 0), Cell::text("calc")),
+            ((5, 1), Cell::Number(3.0)),
+            ((5, 2), Cell::Bool(true)),
+            ((5, 3), Cell::Error(String::from("#N/A"))),
+            ((6, 0), Cell::text("label")),
+        ];
+
+        assert_eq!(xls.map.len(), expected.len());
+
+        for (position, cell) in expected {
+            assert_eq!(xls.map[&position], cell);
+        }
+
+        assert_eq!(xls.styles[&(1, 0)].number_format, "0.00");
+        assert_eq!(xls.styles[&(3, 1)].number_format, "dd/mm/yyyy\\ hh:mm");
+        assert!(!xls.styles.contains_key(&(0, 0)));
+
+        assert_eq!(xls.worksheet, "Data");
+
+        let xls = read_workbook_stream("book.xls", &stream, Some("Other"), &mut |_| true).unwrap();
+
+        assert_eq!(xls.map[&(0, 0)], Cell::text("rich"));
+
+        assert!(matches!(
+            read_workbook_stream("book.xls", &stream, Some("Chart"), &mut |_| true),
+            Err(XLSError::SheetNotFound(_))
+        ));
+
+        let encrypted = [bof(0x0005), record(RECORD_FILEPASS, &[0; 6])].concat();
+
+        assert!(matches!(
+            read_workbook_stream("book.xls", &encrypted, None, &mut |_| true),
+            Err(XLSError::Encrypted)
+        ));
+
+        assert!(is_date_format("[$-409]mmm\\ yy;@"));
+        assert!(!is_date_format("[Red]0.00\"ms\""));
+    }
+}
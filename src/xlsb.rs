@@ -0,0 +1,588 @@
+// Excel binary workbooks (.xlsb). The package is the same zip as XLSX, with the relationships
+// still in XML, but the parts are `.bin` streams of BIFF12 records:
+//
+//     xl/workbook.bin          BrtBundleSh for each sheet, with its name and relationship id
+//     xl/sharedStrings.bin     BrtSSTItem for each shared string
+//     xl/styles.bin            BrtFmt number formats and the BrtXF cell formats
+//     xl/worksheets/sheet1.bin BrtRowHdr followed by the cells of the row
+//
+// Each record is its type and its size, both variable length integers of 7 bits per byte
+// where the high bit says another byte follows. The type takes at most 2 bytes, the size 4.
+//
+use std::collections::HashMap;
+
+use crate::cell::{Cell, CellMap, Progress, StyleMap};
+use crate::xls::{self, CellFormat};
+use crate::xlsx;
+use crate::xlsx::xml;
+use crate::xlsx::zip;
+
+const BRT_ROW_HDR: u32 = 0;
+const BRT_CELL_RK: u32 = 2;
+const BRT_CELL_ERROR: u32 = 3;
+const BRT_CELL_BOOL: u32 = 4;
+const BRT_CELL_REAL: u32 = 5;
+const BRT_CELL_ST: u32 = 6;
+const BRT_CELL_ISST: u32 = 7;
+const BRT_FMLA_STRING: u32 = 8;
+const BRT_FMLA_NUM: u32 = 9;
+const BRT_FMLA_BOOL: u32 = 10;
+const BRT_FMLA_ERROR: u32 = 11;
+const BRT_SST_ITEM: u32 = 19;
+const BRT_FMT: u32 = 44;
+const BRT_XF: u32 = 47;
+const BRT_CELL_RSTRING: u32 = 62;
+const BRT_WB_PROP: u32 = 153;
+const BRT_BUNDLE_SH: u32 = 156;
+const BRT_BEGIN_SST: u32 = 159;
+const BRT_BEGIN_CELL_XFS: u32 = 617;
+const BRT_END_CELL_XFS: u32 = 618;
+
+#[derive(Debug)]
+pub enum XLSBError {
+    Zip(zip::ZipError),
+    Xml(xml::XMLError),
+    Corrupted(&'static str),
+    MissingPart(String),
+    SheetNotFound(String),
+}
+
+impl std::fmt::Display for XLSBError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            XLSBError::Zip(e) => write!(f, "{e}"),
+            XLSBError::Xml(e) => write!(f, "{e}"),
+            XLSBError::Corrupted(what) => write!(f, "corrupted workbook: {what}"),
+            XLSBError::MissingPart(name) => write!(f, "missing part {name}"),
+            XLSBError::SheetNotFound(name) => write!(f, "sheet {name} not found"),
+        }
+    }
+}
+
+impl From<zip::ZipError> for XLSBError {
+    fn from(e: zip::ZipError) -> Self {
+        XLSBError::Zip(e)
+    }
+}
+
+impl From<xml::XMLError> for XLSBError {
+    fn from(e: xml::XMLError) -> Self {
+        XLSBError::Xml(e)
+    }
+}
+
+pub struct XLSBMap {
+    pub filename: String,
+    pub worksheet: String,
+    pub map: CellMap,
+    pub styles: StyleMap,
+}
+
+fn read_u8(data: &[u8], offset: usize) -> Option<u8> {
+    data.get(offset).copied()
+}
+
+fn read_u16(data: &[u8], offset: usize) -> Option<u16> {
+    Some(u16::from_le_bytes(
+        data.get(offset..offset + 2)?.try_into().ok()?,
+    ))
+}
+
+fn read_u32(data: &[u8], offset: usize) -> Option<u32> {
+    Some(u32::from_le_bytes(
+        data.get(offset..offset + 4)?.try_into().ok()?,
+    ))
+}
+
+fn read_f64(data: &[u8], offset: usize) -> Option<f64> {
+    Some(f64::from_le_bytes(
+        data.get(offset..offset + 8)?.try_into().ok()?,
+    ))
+}
+
+// XLWideString, a 32 bit count of UTF-16 characters. Returns the offset after it
+fn read_wide_string(data: &[u8], offset: usize) -> Option<(String, usize)> {
+    let count = read_u32(data, offset)? as usize;
+    let end = offset + 4 + 2 * count;
+
+    let units = data
+        .get(offset + 4..end)?
+        .chunks_exact(2)
+        .map(|c| u16::from_le_bytes([c[0], c[1]]));
+
+    let text = char::decode_utf16(units)
+        .map(|c| c.unwrap_or(char::REPLACEMENT_CHARACTER))
+        .collect();
+
+    Some((text, end))
+}
+
+struct Record<'a> {
+    kind: u32,
+    data: &'a [u8],
+}
+
+struct RecordReader<'a> {
+    data: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> RecordReader<'a> {
+    fn new(data: &'a [u8]) -> Self {
+        Self { data, pos: 0 }
+    }
+
+    fn read_varint(&mut self, max_bytes: usize) -> Option<u32> {
+        let mut value = 0;
+
+        for i in 0..max_bytes {
+            let byte = *self.data.get(self.pos)?;
+            self.pos += 1;
+
+            value |= ((byte & 0x7f) as u32) << (7 * i);
+
+            if byte & 0x80 == 0 {
+                return Some(value);
+            }
+        }
+
+        None
+    }
+
+    // A record cut short means the part is corrupted, it is not just the end of the data
+    fn next_record(&mut self) -> Result<Option<Record<'a>>, XLSBError> {
+        if self.pos >= self.data.len() {
+            return Ok(None);
+        }
+
+        let kind = self
+            .read_varint(2)
+            .ok_or(XLSBError::Corrupted("bad record type"))?;
+        let size = self
+            .read_varint(4)
+            .ok_or(XLSBError::Corrupted("bad record size"))? as usize;
+
+        let data = self
+            .data
+            .get(self.pos..self.pos + size)
+            .ok_or(XLSBError::Corrupted("truncated record"))?;
+
+        self.pos += size;
+
+        Ok(Some(Record { kind, data }))
+    }
+}
+
+// BrtSSTItem is a RichStr: flags, the text and the formatting runs that are skipped
+fn read_shared_strings(data: &[u8]) -> Result<Vec<String>, XLSBError> {
+    let mut reader = RecordReader::new(data);
+    let mut shared_strings = Vec::new();
+    let mut found_sst = false;
+
+    while let Some(record) = reader.next_record()? {
+        match record.kind {
+            BRT_BEGIN_SST => found_sst = true,
+            BRT_SST_ITEM => {
+                let (text, _) = read_wide_string(record.data, 1)
+                    .ok_or(XLSBError::Corrupted("bad shared string"))?;
+
+                shared_strings.push(text);
+            }
+            _ => {}
+        }
+    }
+
+    if !found_sst {
+        return Err(XLSBError::MissingPart(String::from("sst")));
+    }
+
+    Ok(shared_strings)
+}
+
+struct Workbook {
+    // Name and relationship id of each sheet, in order
+    sheets: Vec<(String, String)>,
+    date1904: bool,
+}
+
+// BrtBundleSh: state, tab id, the relationship id (null for macro sheets) and the name
+fn read_workbook(data: &[u8]) -> Result<Workbook, XLSBError> {
+    let mut reader = RecordReader::new(data);
+    let mut workbook = Workbook {
+        sheets: Vec::new(),
+        date1904: false,
+    };
+
+    while let Some(record) = reader.next_record()? {
+        match record.kind {
+            BRT_WB_PROP => {
+                workbook.date1904 = read_u32(record.data, 0).is_some_and(|flags| flags & 1 != 0)
+            }
+            BRT_BUNDLE_SH => {
+                let (id, offset) = if read_u32(record.data, 8) == Some(u32::MAX) {
+                    (None, 12)
+                } else {
+                    let (id, offset) = read_wide_string(record.data, 8)
+                        .ok_or(XLSBError::Corrupted("bad sheet relationship"))?;
+
+                    (Some(id), offset)
+                };
+
+                let (name, _) = read_wide_string(record.data, offset)
+                    .ok_or(XLSBError::Corrupted("bad sheet name"))?;
+
+                if let Some(id) = id {
+                    workbook.sheets.push((name, id));
+                }
+            }
+            _ => {}
+        }
+    }
+
+    Ok(workbook)
+}
+
+// The cell formats are the BrtXF between BrtBeginCellXFs and BrtEndCellXFs, the others are
+// the cell style formats
+fn read_cell_formats(data: &[u8]) -> Result<Vec<CellFormat>, XLSBError> {
+    let mut reader = RecordReader::new(data);
+
+    let mut formats: HashMap<u16, String> = HashMap::new();
+    let mut xf_formats: Vec<u16> = Vec::new();
+    let mut in_cell_xfs = false;
+
+    while let Some(record) = reader.next_record()? {
+        match record.kind {
+            BRT_FMT => {
+                if let (Some(index), Some((format, _))) =
+                    (read_u16(record.data, 0), read_wide_string(record.data, 2))
+                {
+                    formats.insert(index, format);
+                }
+            }
+            BRT_BEGIN_CELL_XFS => in_cell_xfs = true,
+            BRT_END_CELL_XFS => in_cell_xfs = false,
+            BRT_XF if in_cell_xfs => xf_formats.push(read_u16(record.data, 2).unwrap_or(0)),
+            _ => {}
+        }
+    }
+
+    Ok(xf_formats
+        .iter()
+        .map(|&format| CellFormat::new(format, &formats))
+        .collect())
+}
+
+// Every cell starts with its column and its cell format (24 bits, the rest are flags).
+// Formulas are compiled to tokens, only their cached result is kept.
+fn read_cells(
+    data: &[u8],
+    shared_strings: &[String],
+    formats: &[CellFormat],
+    date1904: bool,
+    progress: Progress,
+) -> Result<(CellMap, StyleMap), XLSBError> {
+    let size = data.len();
+    let mut reader = RecordReader::new(data);
+    let mut map = HashMap::new();
+    let mut styles = HashMap::new();
+    let mut row = 0;
+
+    let number_value = |value: f64, xf: usize| xls::number_value(formats, xf, value, date1904);
+
+    while let Some(record) = reader.next_record()? {
+        let data = record.data;
+
+        if record.kind == BRT_ROW_HDR {
+            if !progress(reader.pos as f32 / size as f32) {
+                break;
+            }
+
+            row = read_u32(data, 0).ok_or(XLSBError::Corrupted("bad row header"))? as usize;
+            continue;
+        }
+
+        let (Some(col), Some(style)) = (read_u32(data, 0), read_u32(data, 4)) else {
+            continue;
+        };
+
+        let xf = (style & 0xffffff) as usize;
+
+        let value = match record.kind {
+            BRT_CELL_RK => read_u32(data, 8).map(|rk| number_value(xls::rk_value(rk), xf)),
+            BRT_CELL_REAL | BRT_FMLA_NUM => read_f64(data, 8).map(|v| number_value(v, xf)),
+            BRT_CELL_BOOL | BRT_FMLA_BOOL => read_u8(data, 8).map(|b| Cell::Bool(b != 0)),
+            BRT_CELL_ERROR | BRT_FMLA_ERROR => read_u8(data, 8).map(xls::error_value),
+            BRT_CELL_ST | BRT_FMLA_STRING => {
+                read_wide_string(data, 8).map(|(text, _)| Cell::text(&text))
+            }
+            BRT_CELL_RSTRING => read_wide_string(data, 9).map(|(text, _)| Cell::text(&text)),
+            BRT_CELL_ISST => read_u32(data, 8).map(|index| {
+                Cell::text(
+                    shared_strings
+                        .get(index as usize)
+                        .map(|text| text.as_str())
+                        .unwrap_or("???"),
+                )
+            }),
+            _ => continue,
+        };
+
+        match value {
+            Some(Cell::Empty) => {}
+            Some(value) => {
+                let position = (row, col as usize);
+
+                if let Some(style) = formats.get(xf).and_then(|f| f.style.clone()) {
+                    styles.insert(position, style);
+                }
+
+                map.insert(position, value);
+            }
+            None => return Err(XLSBError::Corrupted("truncated cell")),
+        }
+    }
+
+    Ok((map, styles))
+}
+
+pub fn read_xlsb_file_as_hashmap(
+    filename: &str,
+    worksheet_name: Option<&str>,
+    progress: Progress,
+) -> Result<XLSBMap, XLSBError> {
+    let zip = zip::Zip::from_file(filename)?;
+
+    let names = zip.file_names();
+
+    for part in ["xl/workbook.bin", "xl/_rels/workbook.bin.rels"] {
+        if !names.contains(&part) {
+            return Err(XLSBError::MissingPart(part.to_string()));
+        }
+    }
+
+    let workbook = read_workbook(&zip.read_file("xl/workbook.bin")?)?;
+    let rels = xml::Document::from_data(zip.read_file("xl/_rels/workbook.bin.rels")?)?;
+
+    let shared_strings = if names.contains(&"xl/sharedStrings.bin") {
+        read_shared_strings(&zip.read_file("xl/sharedStrings.bin")?)?
+    } else {
+        Vec::new()
+    };
+
+    let formats = if names.contains(&"xl/styles.bin") {
+        read_cell_formats(&zip.read_file("xl/styles.bin")?)?
+    } else {
+        Vec::new()
+    };
+
+    // The worksheet is chosen by its name in the workbook, or it's the first one
+    let (worksheet, id) = workbook
+        .sheets
+        .iter()
+        .find(|(name, _)| worksheet_name.is_none_or(|wanted| wanted == name))
+        .ok_or_else(|| XLSBError::SheetNotFound(worksheet_name.unwrap_or("").to_string()))?;
+
+    let path = xlsx::relationship_target(&rels, id)
+        .ok_or_else(|| XLSBError::MissingPart(format!("relationship {id}")))?;
+
+    if !names.contains(&path.as_str()) {
+        return Err(XLSBError::MissingPart(path));
+    }
+
+    let (map, styles) = read_cells(
+        &zip.read_file(&path)?,
+        &shared_strings,
+        &formats,
+        workbook.date1904,
+        progress,
+    )?;
+
+    Ok(XLSBMap {
+        filename: filename.to_string(),
+        worksheet: worksheet.clone(),
+        map,
+        styles,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cell::DateTime;
+
+    fn varint(mut value: u32) -> Vec<u8> {
+        let mut result = Vec::new();
+
+        loop {
+            let byte = (value & 0x7f) as u8;
+            value >>= 7;
+
+            if value == 0 {
+                result.push(byte);
+                return result;
+            }
+
+            result.push(byte | 0x80);
+        }
+    }
+
+    fn record(kind: u32, data: &[u8]) -> Vec<u8> {
+        [varint(kind), varint(data.len() as u32), data.to_vec()].concat()
+    }
+
+    fn wide_string(text: &str) -> Vec<u8> {
+        let units = text.encode_utf16().collect::<Vec<_>>();
+
+        [
+            (units.len() as u32).to_le_bytes().to_vec(),
+            units.iter().flat_map(|u| u.to_le_bytes()).collect(),
+        ]
+        .concat()
+    }
+
+    fn cell(kind: u32, col: u32, xf: u32, value: &[u8]) -> Vec<u8> {
+        record(
+            kind,
+            &[&col.to_le_bytes()[..], &xf.to_le_bytes(), value].concat(),
+        )
+    }
+
+    #[test]
+    fn test_read_records() {
+        let long = "x".repeat(100);
+
+        let sst = [
+            record(BRT_BEGIN_SST, &[0; 8]),
+            record(BRT_SST_ITEM, &[&[0][..], &wide_string("Name")].concat()),
+            record(BRT_SST_ITEM, &[&[0][..], &wide_string(&long)].concat()),
+            record(160, &[]),
+        ]
+        .concat();
+
+        let shared_strings = read_shared_strings(&sst).unwrap();
+
+        assert_eq!(shared_strings, ["Name", long.as_str()]);
+
+        let workbook = [
+            record(BRT_WB_PROP, &[0; 12]),
+            record(
+                BRT_BUNDLE_SH,
+                &[&[0; 8][..], &wide_string("rId1"), &wide_string("Data")].concat(),
+            ),
+            record(
+                BRT_BUNDLE_SH,
+                &[&[0; 8][..], &u32::MAX.to_le_bytes(), &wide_string("Macro")].concat(),
+            ),
+        ]
+        .concat();
+
+        let workbook = read_workbook(&workbook).unwrap();
+
+        assert_eq!(
+            workbook.sheets,
+            [(String::from("Data"), String::from("rId1"))]
+        );
+        assert!(!workbook.date1904);
+
+        let xf = |format: u16| {
+            record(
+                BRT_XF,
+                &[&[0, 0][..], &format.to_le_bytes(), &[0; 12]].concat(),
+            )
+        };
+
+        let styles = [
+            record(
+                BRT_FMT,
+                &[&[164, 0][..], &wide_string("yyyy-mm-dd hh:mm")].concat(),
+            ),
+            record(626, &[]),
+            xf(14),
+            record(627, &[]),
+            record(BRT_BEGIN_CELL_XFS, &[]),
+            xf(0),
+            xf(14),
+            xf(164),
+            record(BRT_END_CELL_XFS, &[]),
+        ]
+        .concat();
+
+        let formats = read_cell_formats(&styles).unwrap();
+
+        assert_eq!(
+            formats.iter().map(|f| f.date).collect::<Vec<_>>(),
+            [false, true, true]
+        );
+        assert_eq!(formats[0].style, None);
+        assert_eq!(formats[1].style.as_ref().unwrap().number_format, "mm-dd-yy");
+
+        let sheet = [
+            record(BRT_ROW_HDR, &[0; 17]),
+            cell(BRT_CELL_ISST, 0, 0, &0u32.to_le_bytes()),
+            cell(BRT_CELL_ISST, 1, 0, &1u32.to_le_bytes()),
+            record(BRT_ROW_HDR, &[&2u32.to_le_bytes()[..], &[0; 13]].concat()),
+            cell(BRT_CELL_REAL, 0, 0, &2.5f64.to_le_bytes()),
+            cell(BRT_CELL_RK, 1, 0, &((1234 << 2) | 3u32).to_le_bytes()),
+            cell(BRT_CELL_BOOL, 2, 0, &[1]),
+            cell(BRT_CELL_ERROR, 3, 0, &[0x2a]),
+            cell(BRT_CELL_ST, 4, 0, &wide_string("inline")),
+            cell(
+                BRT_FMLA_STRING,
+                5,
+                0,
+                &[&wide_string("calc")[..], &[0; 6]].concat(),
+            ),
+            cell(BRT_FMLA_NUM, 6, 1, &45352f64.to_le_bytes()),
+            cell(BRT_FMLA_NUM, 7, 2, &45352.5f64.to_le_bytes()),
+            cell(
+                BRT_CELL_RSTRING,
+                8,
+                0,
+                &[&[0][..], &wide_string("rich")].concat(),
+            ),
+            cell(1, 9, 0, &[]),
+        ]
+        .concat();
+
+        let (map, styles) =
+            read_cells(&sheet, &shared_strings, &formats, false, &mut |_| true).unwrap();
+
+        let date = |text| Cell::DateTime(DateTime::parse(text).unwrap());
+
+        let expected = [
+            ((0, 0), Cell::text("Name")),
+            ((0, 1), Cell::text(&long)),
+            ((2, 0), Cell::Number(2.5)),
+            ((2, 1), Cell::Number(12.34)),
+            ((2, 2), Cell::Bool(true)),
+            ((2, 3), Cell::Error(String::from("#N/A"))),
+            ((2, 4), Cell::text("inline")),
+            ((2, 5), Cell::text("calc")),
+            ((2, 6), date("2024-03-01")),
+            ((2, 7), date("2024-03-01T12:00:00")),
+            ((2, 8), Cell::text("rich")),
+        ];
+
+        assert_eq!(map.len(), expected.len());
+
+        for (position, cell) in expected {
+            assert_eq!(map[&position], cell);
+        }
+
+        assert_eq!(styles.len(), 2);
+        assert_eq!(styles[&(2, 6)].number_format, "mm-dd-yy");
+        assert_eq!(styles[&(2, 7)].number_format, "yyyy-mm-dd hh:mm");
+
+        assert!(matches!(
+            read_cells(
+                &sheet[..sheet.len() - 3],
+                &shared_strings,
+                &formats,
+                false,
+                &mut |_| true
+            ),
+            Err(XLSBError::Corrupted(_))
+        ));
+    }
+}
@@ -1,229 +1,425 @@
 use std::collections::HashMap;
 use std::str::FromStr;
 
+use crate::cell::{self, Cell, CellMap, DateTime, Progress, StyleMap};
+use crate::xls::{self, CellFormat};
+
+pub mod cfb;
 pub mod xml;
 pub mod zip;
 
-#[inline]
-fn is_digit(c: u8) -> bool {
-    b'0' <= c && c < b'9'
+#[derive(Debug)]
+pub enum XLSXError {
+    Zip(zip::ZipError),
+    Xml(xml::XMLError),
+    MissingPart(String),
 }
 
-#[inline]
-fn is_alpha(c: u8) -> bool {
-    (b'a' <= c && c <= b'z') || (b'A' <= c && c <= b'Z')
+impl std::fmt::Display for XLSXError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            XLSXError::Zip(e) => write!(f, "{e}"),
+            XLSXError::Xml(e) => write!(f, "{e}"),
+            XLSXError::MissingPart(name) => write!(f, "missing part {name}"),
+        }
+    }
 }
 
-fn base26_letters_to_int(v: &str) -> usize {
-    let mut result = 0usize;
+impl From<zip::ZipError> for XLSXError {
+    fn from(e: zip::ZipError) -> Self {
+        XLSXError::Zip(e)
+    }
+}
 
-    for c in v.bytes() {
-        if is_alpha(c) {
-            result = result * 26 + 1 + (c - b'A') as usize;
-        } else {
-            return 0;
-        }
+impl From<xml::XMLError> for XLSXError {
+    fn from(e: xml::XMLError) -> Self {
+        XLSXError::Xml(e)
     }
+}
 
-    result - 1
+// Transitional and strict SpreadsheetML namespaces
+const SPREADSHEETML_NAMESPACES: [&str; 2] = [
+    "http://schemas.openxmlformats.org/spreadsheetml/2006/main",
+    "http://purl.oclc.org/ooxml/spreadsheetml/main",
+];
+
+// Transitional and strict namespaces of the relationship ids
+const RELATIONSHIPS_NAMESPACES: [&str; 2] = [
+    "http://schemas.openxmlformats.org/officeDocument/2006/relationships",
+    "http://purl.oclc.org/ooxml/officeDocument/relationships",
+];
+
+// Elements are matched by namespace and local name, producers are free to choose the prefix
+fn is_sml(name: &xml::QName, local_name: &str) -> bool {
+    SPREADSHEETML_NAMESPACES
+        .iter()
+        .any(|namespace| name.is(namespace, local_name))
 }
 
-fn cell_pos_to_tuple(cell_pos: &str) -> Option<(usize, usize)> {
-    let mut digit_idx = -1;
+// Shared strings are concatenated from every `t` of an `si`, rich text has one for each run.
+// The phonetic hints (`rPh`) are not part of the text.
+fn read_shared_strings(content: &[u8]) -> Result<Vec<String>, XLSXError> {
+    let content = xml::to_utf8(content)?;
+    let mut parser = xml::PullParser::new(&content);
 
-    for (i, c) in cell_pos.bytes().enumerate() {
-        if is_digit(c) {
-            digit_idx = i as isize;
-            break;
-        }
+    let mut shared_strings = Vec::new();
+    let mut current = String::new();
+    let mut found_sst = false;
+
+    while let Some(event) = parser.next() {
+        match event? {
+            xml::Event::StartElement(name) if is_sml(&name, "sst") => found_sst = true,
+            xml::Event::StartElement(name) if is_sml(&name, "si") => current.clear(),
+            xml::Event::EndElement(name) if is_sml(&name, "si") => {
+                shared_strings.push(std::mem::take(&mut current))
+            }
+            xml::Event::Text { value, .. } => {
+                let in_phonetic = parser.open_elements().iter().any(|e| is_sml(e, "rPh"));
 
-        if !is_alpha(c) {
-            return None;
+                if parser.path_ends_with(&["t"]) && !in_phonetic {
+                    current.push_str(&value);
+                }
+            }
+            _ => {}
         }
     }
 
-    if digit_idx < 1 {
-        return None;
+    if !found_sst {
+        return Err(XLSXError::MissingPart(String::from("sst")));
     }
 
-    let (letters, digits) = cell_pos.split_at(digit_idx as usize);
-
-    let row = usize::from_str(digits).unwrap() - 1;
-    let col = base26_letters_to_int(letters);
-
-    Some((row, col))
+    Ok(shared_strings)
 }
 
-fn element_by_name(item: &xml::Component, elem_name: &str) -> bool {
-    match item {
-        xml::Component::Element { name, .. } => name.as_str() == elem_name,
-        _ => false,
+fn cell_value(cell_type: &str, text: &str, shared_strings: &[String]) -> Cell {
+    match cell_type {
+        "n" => match f64::from_str(text) {
+            Ok(number) => Cell::Number(number),
+            Err(_) => Cell::text(text),
+        },
+        "str" => Cell::text(text),
+        "b" => Cell::Bool(text == "1"),
+        "e" => Cell::Error(text.to_string()),
+        "d" => match DateTime::parse(text) {
+            Some(datetime) => Cell::DateTime(datetime),
+            None => Cell::text(text),
+        },
+        "s" => match usize::from_str(text) {
+            Ok(index) if index < shared_strings.len() => Cell::text(&shared_strings[index]),
+            _ => Cell::text("???"),
+        },
+        _ => Cell::text("??"),
     }
 }
 
-fn get_text(item: &xml::Component) -> Option<String> {
-    match item {
-        xml::Component::Text { value, .. } => Some(value.clone()),
-        _ => None,
-    }
-}
+// The number formats are the `numFmt` of `numFmts`, the cell formats are the `xf` of `cellXfs`
+// (those of `cellStyleXfs` belong to the named styles)
+fn read_cell_formats(content: Vec<u8>) -> Result<Vec<CellFormat>, XLSXError> {
+    let styles = xml::Document::from_data(content)?;
 
-fn read_shared_strings(content: Vec<u8>) -> Vec<String> {
-    let doc = xml::Document::from_data(content).unwrap();
+    let format_id = |e: &xml::Component| e.attr("numFmtId").and_then(|id| u16::from_str(id).ok());
 
-    let mut shared_strings = Vec::new();
+    let formats = styles
+        .select("styleSheet/numFmts/numFmt")
+        .into_iter()
+        .filter_map(|f| Some((format_id(f)?, f.attr("formatCode")?.to_string())))
+        .collect::<HashMap<_, _>>();
 
-    let sst = doc
-        .children
-        .iter()
-        .find(|e| element_by_name(e, "sst"))
-        .unwrap();
-
-    for si in sst.filter_elements("si") {
-        let text = 'si_txt: {
-            if let Some(t) = si.find_element("t") {
-                if let Some(txt) = t.children_unchecked().iter().find_map(get_text) {
-                    break 'si_txt txt;
+    Ok(styles
+        .select("styleSheet/cellXfs/xf")
+        .into_iter()
+        .map(|xf| CellFormat::new(format_id(xf).unwrap_or(0), &formats))
+        .collect())
+}
+
+// Reads the cells as the xml is parsed, without building a tree of the document. The
+// inflated xml of the sheet is still held in memory while it's parsed.
+fn read_cells(
+    content: &[u8],
+    shared_strings: &[String],
+    formats: &[CellFormat],
+    date1904: bool,
+    progress: Progress,
+) -> Result<(CellMap, StyleMap), XLSXError> {
+    let content = xml::to_utf8(content)?;
+    let mut parser = xml::PullParser::new(&content);
+
+    let mut map = HashMap::new();
+    let mut styles = HashMap::new();
+    let mut found_worksheet = false;
+
+    // State of the cell (`c` element) being read
+    let mut position = None;
+    let mut xf = 0;
+    let mut cell_type = String::new();
+    let mut text = String::new();
+    let mut formula = String::new();
+    let mut has_value = false;
+
+    while let Some(event) = parser.next() {
+        let depth = parser.open_elements().len();
+
+        match event? {
+            xml::Event::StartElement(name) if depth == 1 && is_sml(&name, "worksheet") => {
+                found_worksheet = true
+            }
+            xml::Event::StartElement(name)
+                if is_sml(&name, "c") && parser.path_ends_with(&["sheetData", "row", "c"]) =>
+            {
+                position = None;
+                xf = 0;
+                cell_type.clear();
+                cell_type.push('n');
+                text.clear();
+                formula.clear();
+                has_value = false;
+            }
+            xml::Event::Attribute { name, value }
+                if name.namespace.is_none() && parser.path_ends_with(&["row", "c"]) =>
+            {
+                if name.local_name == "r" {
+                    position = cell::parse_address(&value);
+                } else if name.local_name == "s" {
+                    xf = usize::from_str(&value).unwrap_or(0);
+                } else if name.local_name == "t" {
+                    cell_type.clear();
+                    cell_type.push_str(&value);
                 }
-            } else {
-                if let Some(r) = si.find_element("r") {
-                    if let Some(t) = r.find_element("t") {
-                        if let Some(txt) = t.children_unchecked().iter().find_map(get_text) {
-                            break 'si_txt txt;
-                        }
-                    }
+            }
+            xml::Event::StartElement(name)
+                if is_sml(&name, "v") && parser.path_ends_with(&["row", "c", "v"]) =>
+            {
+                has_value = true;
+            }
+            xml::Event::Text { value, .. } if parser.path_ends_with(&["row", "c", "v"]) => {
+                text.push_str(&value);
+            }
+            // Cells sharing a formula have it only in the first one
+            xml::Event::Text { value, .. } if parser.path_ends_with(&["row", "c", "f"]) => {
+                formula.push_str(&value);
+            }
+            xml::Event::EndElement(name)
+                if is_sml(&name, "c") && parser.path_ends_with(&["sheetData", "row"]) =>
+            {
+                let Some(pos) = position else {
+                    continue;
+                };
+
+                let value = match has_value.then(|| cell_value(&cell_type, &text, shared_strings)) {
+                    Some(Cell::Number(n)) => xls::number_value(formats, xf, n, date1904),
+                    Some(value) => value,
+                    None => Cell::Empty,
+                };
+
+                if (!formula.is_empty() || !value.is_empty())
+                    && let Some(style) = formats.get(xf).and_then(|f| f.style.clone())
+                {
+                    styles.insert(pos, style);
                 }
+
+                if !formula.is_empty() {
+                    let formula = std::mem::take(&mut formula);
+
+                    map.insert(
+                        pos,
+                        Cell::Formula {
+                            formula,
+                            value: Box::new(value),
+                        },
+                    );
+                } else if !value.is_empty() {
+                    map.insert(pos, value);
+                }
+            }
+            xml::Event::EndElement(name)
+                if is_sml(&name, "row")
+                    && parser.path_ends_with(&["sheetData"])
+                    && !progress(parser.position() as f32 / content.len() as f32) =>
+            {
+                break;
             }
+            _ => {}
+        }
+    }
 
-            String::new()
-        };
+    if !found_worksheet {
+        return Err(XLSXError::MissingPart(String::from("worksheet")));
+    }
+
+    Ok((map, styles))
+}
 
-        shared_strings.push(text);
+// Resolves a relationship target, relative to the `xl/` folder unless it starts with '/'
+fn part_path(target: &str) -> String {
+    match target.strip_prefix('/') {
+        Some(absolute) => absolute.to_string(),
+        None => format!("xl/{target}"),
     }
+}
 
-    shared_strings
+// Path in the archive of the part a relationship of the workbook points to
+pub fn relationship_target(rels: &xml::Document, id: &str) -> Option<String> {
+    rels.select_first(&format!("Relationships/Relationship[@Id='{id}']"))
+        .and_then(|rel| rel.attr("Target"))
+        .map(part_path)
 }
 
-fn read_cells(content: Vec<u8>, shared_strings: Vec<String>) -> HashMap<(usize, usize), String> {
-    let doc = xml::Document::from_data(content).unwrap();
+#[derive(Default)]
+struct Workbook {
+    // Names of the sheets and their parts, in the order they appear in the workbook
+    sheets: Vec<(String, String)>,
+    date1904: bool,
+}
 
-    let worksheet = doc
-        .children
-        .iter()
-        .find(|e| element_by_name(e, "worksheet"))
-        .unwrap();
+fn read_workbook(zip: &zip::Zip) -> Result<Workbook, XLSXError> {
+    let workbook = xml::Document::from_data(zip.read_file("xl/workbook.xml")?)?;
+    let rels = xml::Document::from_data(zip.read_file("xl/_rels/workbook.xml.rels")?)?;
 
-    let mut map = HashMap::new();
+    let date1904 = workbook
+        .select_first("workbook/workbookPr")
+        .and_then(|properties| properties.attr("date1904"))
+        .is_some_and(|value| value == "1" || value == "true");
 
-    for sheet_data in worksheet.filter_elements("sheetData") {
-        for row in sheet_data.filter_elements("row") {
-            for c in row.filter_elements("c") {
-                if let Some(v) = c.find_element("v") {
-                    match c {
-                        xml::Component::Element { attributes, .. } => {
-                            if attributes.contains_key("r") && attributes.contains_key("t") {
-                                let text = v
-                                    .children_unchecked()
-                                    .iter()
-                                    .find_map(get_text)
-                                    .unwrap_or(String::new());
-
-                                let cell_value = if let Some(t) = attributes.get("t") {
-                                    if t == "n" || t == "str" {
-                                        text.clone()
-                                    } else if t == "b" {
-                                        if text == "1" {
-                                            String::from("true")
-                                        } else {
-                                            String::from("false")
-                                        }
-                                    } else if t == "s" {
-                                        let index = usize::from_str(text.as_str()).unwrap();
-
-                                        if index < shared_strings.len() {
-                                            shared_strings[index].clone()
-                                        } else {
-                                            String::from("???")
-                                        }
-                                    } else {
-                                        String::from("??")
-                                    }
-                                } else {
-                                    String::from("?")
-                                };
-
-                                if let Some(r) = attributes.get("r") {
-                                    if let Some(tpos) = cell_pos_to_tuple(r.as_str()) {
-                                        let _ = map.insert(tpos, cell_value);
-                                    }
-                                }
-                            }
-                        }
-                        _ => {}
-                    }
-                }
-            }
+    let mut sheets = Vec::new();
+
+    for sheet in workbook.select("workbook/sheets/sheet[@name]") {
+        // The relationship id is `r:id`, with whatever prefix the producer chose
+        let target = RELATIONSHIPS_NAMESPACES
+            .iter()
+            .find_map(|namespace| sheet.attr_ns(namespace, "id"))
+            .and_then(|id| relationship_target(&rels, id));
+
+        if let (Some(name), Some(target)) = (sheet.attr("name"), target) {
+            sheets.push((name.to_string(), target));
         }
     }
 
-    map
+    Ok(Workbook { sheets, date1904 })
 }
 
 pub struct XLSXMap {
     pub filename: String,
     pub worksheet: String,
-    pub map: HashMap<(usize, usize), String>,
+    pub map: CellMap,
+    pub styles: StyleMap,
 }
 
 pub fn read_xlsx_file_as_hashmap(
     filename: &str,
     worksheet_name: Option<&str>,
-) -> Result<XLSXMap, ()> {
-    let zip = zip::Zip::from_file(filename).unwrap();
-
-    let files = zip.extract_files().map_err(|e| {
-        eprintln!("{:?}", e);
-    })?;
+    progress: Progress,
+) -> Result<XLSXMap, XLSXError> {
+    let zip = zip::Zip::from_file(filename)?;
 
-    let shared_strings = files
-        .iter()
-        .find(|f| f.name.as_str() == "xl/sharedStrings.xml")
-        .map(|f| &f.content);
+    let names = zip.file_names();
 
-    let ss = if let Some(s) = shared_strings {
-        read_shared_strings(s.as_str().into())
+    let ss = if names.contains(&"xl/sharedStrings.xml") {
+        read_shared_strings(&zip.read_file("xl/sharedStrings.xml")?)?
     } else {
         Vec::new()
     };
 
-    let mut ws_name = String::new();
+    let workbook =
+        if names.contains(&"xl/workbook.xml") && names.contains(&"xl/_rels/workbook.xml.rels") {
+            read_workbook(&zip)?
+        } else {
+            Workbook::default()
+        };
 
-    let worksheet = if let Some(ws) = worksheet_name {
-        ws_name = format!("xl/worksheets/{ws}.xml");
+    let formats = if names.contains(&"xl/styles.xml") {
+        read_cell_formats(zip.read_file("xl/styles.xml")?)?
+    } else {
+        Vec::new()
+    };
 
-        files.iter().find(|f| f.name == ws_name).map(|f| &f.content)
-    } else if let Some(f) = files
+    let sheets = &workbook.sheets;
+
+    // A worksheet is chosen by its name in the workbook, or by its file name
+    let ws_name = if let Some(ws) = worksheet_name {
+        sheets
+            .iter()
+            .find(|(name, _)| name == ws)
+            .map(|(_, path)| path.clone())
+            .unwrap_or_else(|| format!("xl/worksheets/{ws}.xml"))
+    } else if let Some((_, path)) = sheets.first() {
+        path.clone()
+    } else if let Some(name) = names
         .iter()
-        .find(|f| f.name.starts_with("xl/worksheets/") && f.name.ends_with(".xml"))
+        .find(|n| n.starts_with("xl/worksheets/") && n.ends_with(".xml"))
     {
-        ws_name = f.name.clone();
-
-        Some(&f.content)
+        name.to_string()
     } else {
-        None
+        return Err(XLSXError::MissingPart(String::from("xl/worksheets/*.xml")));
     };
 
-    if let Some(sheet) = worksheet {
-        let map = read_cells(sheet.as_str().into(), ss);
+    if !names.contains(&ws_name.as_str()) {
+        return Err(XLSXError::MissingPart(ws_name));
+    }
 
-        Ok(XLSXMap {
-            filename: filename.to_string(),
-            worksheet: ws_name,
-            map,
-        })
-    } else {
-        eprintln!("not able to read {} contents", filename);
+    let (map, styles) = read_cells(
+        &zip.read_file(&ws_name)?,
+        &ss,
+        &formats,
+        workbook.date1904,
+        progress,
+    )?;
+
+    Ok(XLSXMap {
+        filename: filename.to_string(),
+        worksheet: ws_name,
+        map,
+        styles,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_read_cells() {
+        let styles = br#"<styleSheet xmlns="http://schemas.openxmlformats.org/spreadsheetml/2006/main">
+ <numFmts count="1"><numFmt numFmtId="164" formatCode="yyyy\-mm\-dd\ hh:mm"/></numFmts>
+ <cellStyleXfs count="1"><xf numFmtId="14"/></cellStyleXfs>
+ <cellXfs count="4"><xf numFmtId="0"/><xf numFmtId="14"/><xf numFmtId="164"/><xf numFmtId="2"/></cellXfs>
+</styleSheet>"#;
+
+        let formats = read_cell_formats(styles.to_vec()).unwrap();
+
+        assert_eq!(
+            formats.iter().map(|f| f.date).collect::<Vec<_>>(),
+            [false, true, true, false]
+        );
+
+        let sheet = br#"<worksheet xmlns="http://schemas.openxmlformats.org/spreadsheetml/2006/main">
+ <sheetData>
+  <row r="1"><c r="A1"><v>1.5</v></c><c r="B1" s="1"><v>45352</v></c><c r="C1" s="2"><v>45352.5</v></c></row>
+  <row r="2"><c r="A2" s="3"><v>2</v></c><c r="B2" s="1"><f>B1+1</f><v>45353</v></c><c r="C2" s="1" t="str"><v>x</v></c></row>
+ </sheetData>
+</worksheet>"#;
+
+        let date = |text| Cell::DateTime(DateTime::parse(text).unwrap());
+
+        let (map, styles) = read_cells(sheet, &[], &formats, false, &mut |_| true).unwrap();
+
+        assert_eq!(map[&(0, 0)], Cell::Number(1.5));
+        assert_eq!(map[&(0, 1)], date("2024-03-01"));
+        assert_eq!(map[&(0, 2)], date("2024-03-01 12:00"));
+        assert_eq!(map[&(1, 0)], Cell::Number(2.0));
+        assert_eq!(
+            map[&(1, 1)],
+            Cell::Formula {
+                formula: String::from("B1+1"),
+                value: Box::new(date("2024-03-02")),
+            }
+        );
+        assert_eq!(map[&(1, 2)], Cell::text("x"));
+        assert_eq!(styles[&(1, 0)].number_format, "0.00");
+        assert_eq!(styles[&(0, 2)].number_format, "yyyy\\-mm\\-dd\\ hh:mm");
+        assert!(!styles.contains_key(&(0, 0)));
+
+        let (map, _) = read_cells(sheet, &[], &formats, true, &mut |_| true).unwrap();
 
-        Err(())
+        assert_eq!(map[&(0, 1)], date("2028-03-02"));
     }
 }
@@ -0,0 +1,537 @@
+// Compound File Binary (also known as OLE2 or structured storage), the container of the
+// legacy Office formats. It is a small file system inside the file:
+//
+//  - The file is split in sectors of 512 or 4096 bytes, the first one holds the header.
+//  - The FAT (file allocation table) has the next sector of each sector, streams are chains
+//    of sectors. The sectors of the FAT itself are listed by the DIFAT, whose first 109
+//    entries are in the header.
+//  - The directory is a stream of 128 byte entries, storages (folders) and streams, each
+//    level is a red-black tree linked by the left and right siblings.
+//  - Streams smaller than 4096 bytes are stored in 64 byte mini sectors, inside the mini
+//    stream that belongs to the root entry, with their own mini FAT.
+//
+use std::fs;
+
+const SIGNATURE: [u8; 8] = [0xd0, 0xcf, 0x11, 0xe0, 0xa1, 0xb1, 0x1a, 0xe1];
+
+const HEADER_SIZE: usize = 512;
+const HEADER_DIFAT_ENTRIES: usize = 109;
+const DIRECTORY_ENTRY_SIZE: usize = 128;
+
+// Special values of the FAT, regular sectors are numbered up to MAX_REGULAR_SECTOR
+const MAX_REGULAR_SECTOR: u32 = 0xfffffffa;
+const END_OF_CHAIN: u32 = 0xfffffffe;
+const FREE_SECTOR: u32 = 0xffffffff;
+
+// Sibling or child id meaning there's none
+const NO_STREAM: u32 = 0xffffffff;
+
+const TYPE_STORAGE: u8 = 1;
+const TYPE_STREAM: u8 = 2;
+const TYPE_ROOT: u8 = 5;
+
+#[derive(Debug)]
+pub enum CFBError {
+    Io(std::io::Error),
+    NotACompoundFile,
+    UnsupportedVersion(u16),
+    Corrupted(&'static str),
+    StreamNotFound(String),
+}
+
+impl std::fmt::Display for CFBError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            CFBError::Io(e) => write!(f, "{e}"),
+            CFBError::NotACompoundFile => write!(f, "not a compound file"),
+            CFBError::UnsupportedVersion(version) => {
+                write!(f, "unsupported compound file version {version}")
+            }
+            CFBError::Corrupted(what) => write!(f, "corrupted compound file: {what}"),
+            CFBError::StreamNotFound(path) => write!(f, "{path}: no such stream in file"),
+        }
+    }
+}
+
+impl From<std::io::Error> for CFBError {
+    fn from(e: std::io::Error) -> Self {
+        CFBError::Io(e)
+    }
+}
+
+fn read_u16(data: &[u8], offset: usize) -> Option<u16> {
+    Some(u16::from_le_bytes(
+        data.get(offset..offset + 2)?.try_into().ok()?,
+    ))
+}
+
+fn read_u32(data: &[u8], offset: usize) -> Option<u32> {
+    Some(u32::from_le_bytes(
+        data.get(offset..offset + 4)?.try_into().ok()?,
+    ))
+}
+
+fn read_u64(data: &[u8], offset: usize) -> Option<u64> {
+    Some(u64::from_le_bytes(
+        data.get(offset..offset + 8)?.try_into().ok()?,
+    ))
+}
+
+#[derive(Debug)]
+struct DirectoryEntry {
+    name: String,
+    entry_type: u8,
+    left: u32,
+    right: u32,
+    child: u32,
+    start: u32,
+    size: u64,
+}
+
+impl DirectoryEntry {
+    fn from_data(data: &[u8], major_version: u16) -> Option<Self> {
+        // The name is UTF-16 and its length in bytes includes the terminating null
+        let name_len = (read_u16(data, 64)? as usize).min(64).saturating_sub(2);
+        let units = data[..name_len]
+            .chunks_exact(2)
+            .map(|c| u16::from_le_bytes([c[0], c[1]]));
+
+        // Version 3 files may have garbage in the high part of the size
+        let size = match major_version {
+            3 => read_u32(data, 120)? as u64,
+            _ => read_u64(data, 120)?,
+        };
+
+        Some(Self {
+            name: char::decode_utf16(units)
+                .map(|c| c.unwrap_or(char::REPLACEMENT_CHARACTER))
+                .collect(),
+            entry_type: *data.get(66)?,
+            left: read_u32(data, 68)?,
+            right: read_u32(data, 72)?,
+            child: read_u32(data, 76)?,
+            start: read_u32(data, 116)?,
+            size,
+        })
+    }
+}
+
+// The whole file is kept in memory, streams are read on demand
+pub struct CompoundFile {
+    data: Vec<u8>,
+    sector_size: usize,
+    mini_sector_size: usize,
+    mini_stream_cutoff: u64,
+    fat: Vec<u32>,
+    mini_fat: Vec<u32>,
+    entries: Vec<DirectoryEntry>,
+    mini_stream: Vec<u8>,
+}
+
+impl CompoundFile {
+    pub fn from_file(filepath: &str) -> Result<CompoundFile, CFBError> {
+        let data = fs::read(filepath)?;
+
+        Self::from_data(data)
+    }
+
+    pub fn is_compound_file(data: &[u8]) -> bool {
+        data.starts_with(&SIGNATURE)
+    }
+
+    pub fn from_data(data: Vec<u8>) -> Result<CompoundFile, CFBError> {
+        if data.len() < HEADER_SIZE || !Self::is_compound_file(&data) {
+            return Err(CFBError::NotACompoundFile);
+        }
+
+        let header = |offset| read_u32(&data, offset).ok_or(CFBError::NotACompoundFile);
+
+        let major_version = read_u16(&data, 0x1a).ok_or(CFBError::NotACompoundFile)?;
+
+        let sector_shift = match (major_version, read_u16(&data, 0x1e)) {
+            (3, Some(9)) => 9,
+            (4, Some(12)) => 12,
+            _ => return Err(CFBError::UnsupportedVersion(major_version)),
+        };
+
+        let mini_sector_shift = read_u16(&data, 0x20).ok_or(CFBError::NotACompoundFile)?;
+
+        if mini_sector_shift >= sector_shift {
+            return Err(CFBError::Corrupted("mini sector size"));
+        }
+
+        let mut cfb = CompoundFile {
+            data: Vec::new(),
+            sector_size: 1 << sector_shift,
+            mini_sector_size: 1 << mini_sector_shift,
+            mini_stream_cutoff: header(0x38)? as u64,
+            fat: Vec::new(),
+            mini_fat: Vec::new(),
+            entries: Vec::new(),
+            mini_stream: Vec::new(),
+        };
+
+        let num_fat_sectors = header(0x2c)? as usize;
+        let first_directory_sector = header(0x30)?;
+        let first_mini_fat_sector = header(0x3c)?;
+        let mut difat_sector = header(0x44)?;
+
+        // Sectors of the FAT, from the header and the chain of DIFAT sectors. The last entry
+        // of each DIFAT sector is the next one.
+        let mut fat_sectors: Vec<u32> = (0..HEADER_DIFAT_ENTRIES)
+            .filter_map(|i| read_u32(&data, 0x4c + 4 * i))
+            .collect();
+
+        let entries_per_sector = cfb.sector_size / 4;
+        let mut difat_count = 0;
+
+        while difat_sector <= MAX_REGULAR_SECTOR && fat_sectors.len() < num_fat_sectors {
+            let sector = cfb
+                .sector(&data, difat_sector)
+                .ok_or(CFBError::Corrupted("DIFAT sector"))?;
+
+            fat_sectors.extend((0..entries_per_sector - 1).filter_map(|i| read_u32(sector, 4 * i)));
+            difat_sector = read_u32(sector, 4 * (entries_per_sector - 1)).unwrap_or(END_OF_CHAIN);
+
+            difat_count += 1;
+
+            if difat_count > data.len() / cfb.sector_size {
+                return Err(CFBError::Corrupted("DIFAT chain loops"));
+            }
+        }
+
+        fat_sectors.truncate(num_fat_sectors);
+
+        for &fat_sector in fat_sectors.iter() {
+            let sector = cfb
+                .sector(&data, fat_sector)
+                .ok_or(CFBError::Corrupted("FAT sector"))?;
+
+            cfb.fat
+                .extend((0..entries_per_sector).filter_map(|i| read_u32(sector, 4 * i)));
+        }
+
+        let directory = cfb.read_chain(&data, first_directory_sector, None)?;
+
+        cfb.entries = directory
+            .chunks_exact(DIRECTORY_ENTRY_SIZE)
+            .filter_map(|entry| DirectoryEntry::from_data(entry, major_version))
+            .collect();
+
+        let root = match cfb.entries.first() {
+            Some(root) if root.entry_type == TYPE_ROOT => root,
+            _ => return Err(CFBError::Corrupted("missing root entry")),
+        };
+
+        // The mini stream is in the regular sectors, starting at the root entry
+        if root.size > 0 {
+            let mini_fat = cfb.read_chain(&data, first_mini_fat_sector, None)?;
+
+            cfb.mini_fat = mini_fat
+                .chunks_exact(4)
+                .map(|c| u32::from_le_bytes([c[0], c[1], c[2], c[3]]))
+                .collect();
+            cfb.mini_stream = cfb.read_chain(&data, root.start, Some(root.size))?;
+        }
+
+        cfb.data = data;
+
+        Ok(cfb)
+    }
+
+    fn sector<'a>(&self, data: &'a [u8], sector: u32) -> Option<&'a [u8]> {
+        let start = (sector as usize + 1).checked_mul(self.sector_size)?;
+
+        data.get(start..start + self.sector_size)
+    }
+
+    // Concatenates the sectors of a chain, up to size bytes when given
+    fn read_chain(&self, data: &[u8], start: u32, size: Option<u64>) -> Result<Vec<u8>, CFBError> {
+        let mut result = Vec::new();
+        let mut sector = start;
+
+        // A sector that shows up twice means the chain loops
+        let mut visited = vec![false; self.fat.len()];
+
+        while sector != END_OF_CHAIN && size.is_none_or(|size| (result.len() as u64) < size) {
+            if sector == FREE_SECTOR {
+                return Err(CFBError::Corrupted("free sector in a chain"));
+            }
+
+            match visited.get_mut(sector as usize) {
+                Some(true) => return Err(CFBError::Corrupted("sector chain loops")),
+                Some(seen) => *seen = true,
+                None => return Err(CFBError::Corrupted("sector out of the FAT")),
+            }
+
+            result.extend_from_slice(
+                self.sector(data, sector)
+                    .ok_or(CFBError::Corrupted("sector out of bounds"))?,
+            );
+
+            sector = self.fat[sector as usize];
+        }
+
+        if let Some(size) = size {
+            if (result.len() as u64) < size {
+                return Err(CFBError::Corrupted("stream shorter than its size"));
+            }
+
+            result.truncate(size as usize);
+        }
+
+        Ok(result)
+    }
+
+    fn read_mini_chain(&self, start: u32, size: u64) -> Result<Vec<u8>, CFBError> {
+        let mut result = Vec::new();
+        let mut sector = start;
+
+        let mut visited = vec![false; self.mini_fat.len()];
+
+        while (result.len() as u64) < size {
+            match visited.get_mut(sector as usize) {
+                Some(true) => return Err(CFBError::Corrupted("mini sector chain loops")),
+                Some(seen) => *seen = true,
+                None => return Err(CFBError::Corrupted("mini sector out of the mini FAT")),
+            }
+
+            let offset = sector as usize * self.mini_sector_size;
+
+            result.extend_from_slice(
+                self.mini_stream
+                    .get(offset..offset + self.mini_sector_size)
+                    .ok_or(CFBError::Corrupted("mini sector out of bounds"))?,
+            );
+
+            sector = self.mini_fat[sector as usize];
+        }
+
+        result.truncate(size as usize);
+
+        Ok(result)
+    }
+
+    // Walks the tree of entries in order, with their full paths
+    // Walks the directory tree in order with an explicit stack, as a crafted file can nest
+    // entries deep enough to overflow the call stack. An entry reached twice is a loop.
+    fn paths(&self) -> Result<Vec<(String, usize)>, CFBError> {
+        enum Step {
+            Visit(u32, String),
+            Emit(usize, String),
+        }
+
+        let mut result = Vec::new();
+        let mut visited = vec![false; self.entries.len()];
+
+        let Some(root) = self.entries.first() else {
+            return Ok(result);
+        };
+
+        let mut stack = vec![Step::Visit(root.child, String::new())];
+
+        while let Some(step) = stack.pop() {
+            match step {
+                Step::Visit(id, prefix) => {
+                    if id == NO_STREAM {
+                        continue;
+                    }
+
+                    let Some(entry) = self.entries.get(id as usize) else {
+                        continue;
+                    };
+
+                    if std::mem::replace(&mut visited[id as usize], true) {
+                        return Err(CFBError::Corrupted("directory entries loop"));
+                    }
+
+                    stack.push(Step::Visit(entry.right, prefix.clone()));
+                    stack.push(Step::Emit(id as usize, prefix.clone()));
+                    stack.push(Step::Visit(entry.left, prefix));
+                }
+                Step::Emit(id, prefix) => {
+                    let entry = &self.entries[id];
+                    let path = format!("{prefix}{}", entry.name);
+
+                    if entry.entry_type == TYPE_STORAGE {
+                        stack.push(Step::Visit(entry.child, format!("{path}/")));
+                    }
+
+                    result.push((path, id));
+                }
+            }
+        }
+
+        Ok(result)
+    }
+
+    // Paths of all streams, storages are separated by '/'
+    pub fn stream_names(&self) -> Result<Vec<String>, CFBError> {
+        Ok(self
+            .paths()?
+            .into_iter()
+            .filter(|(_, id)| self.entries[*id].entry_type == TYPE_STREAM)
+            .map(|(path, _)| path)
+            .collect())
+    }
+
+    // Reads a stream by its path, names are compared ignoring case as the format does
+    pub fn read_stream(&self, path: &str) -> Result<Vec<u8>, CFBError> {
+        let id = self
+            .paths()?
+            .into_iter()
+            .find(|(p, id)| {
+                self.entries[*id].entry_type == TYPE_STREAM && p.eq_ignore_ascii_case(path)
+            })
+            .map(|(_, id)| id)
+            .ok_or_else(|| CFBError::StreamNotFound(path.to_string()))?;
+
+        let entry = &self.entries[id];
+
+        if entry.size < self.mini_stream_cutoff {
+            self.read_mini_chain(entry.start, entry.size)
+        } else {
+            self.read_chain(&self.data, entry.start, Some(entry.size))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn put_u32(data: &mut [u8], offset: usize, value: u32) {
+        data[offset..offset + 4].copy_from_slice(&value.to_le_bytes());
+    }
+
+    fn directory_entry(
+        name: &str,
+        entry_type: u8,
+        siblings: (u32, u32, u32),
+        start: u32,
+        size: u32,
+    ) -> Vec<u8> {
+        let mut entry = vec![0; DIRECTORY_ENTRY_SIZE];
+        let units = name.encode_utf16().collect::<Vec<_>>();
+
+        for (i, unit) in units.iter().enumerate() {
+            entry[2 * i..2 * i + 2].copy_from_slice(&unit.to_le_bytes());
+        }
+
+        entry[64..66].copy_from_slice(&(2 * units.len() as u16 + 2).to_le_bytes());
+        entry[66] = entry_type;
+        put_u32(&mut entry, 68, siblings.0);
+        put_u32(&mut entry, 72, siblings.1);
+        put_u32(&mut entry, 76, siblings.2);
+        put_u32(&mut entry, 116, start);
+        put_u32(&mut entry, 120, size);
+
+        entry
+    }
+
+    #[test]
+    fn test_read_streams() {
+        // Sector 0 is the FAT, 1 the directory, 2 the mini FAT, 3 the mini stream and
+        // 4 to 12 the big stream
+        let big = (0..4500).map(|i| (i % 251) as u8).collect::<Vec<_>>();
+        let small = (0..100).map(|i| i as u8).collect::<Vec<_>>();
+
+        let mut data = vec![0; HEADER_SIZE * 14];
+
+        data[..8].copy_from_slice(&SIGNATURE);
+        data[0x1a..0x1c].copy_from_slice(&3u16.to_le_bytes());
+        data[0x1c..0x1e].copy_from_slice(&0xfffeu16.to_le_bytes());
+        data[0x1e..0x20].copy_from_slice(&9u16.to_le_bytes());
+        data[0x20..0x22].copy_from_slice(&6u16.to_le_bytes());
+        put_u32(&mut data, 0x2c, 1);
+        put_u32(&mut data, 0x30, 1);
+        put_u32(&mut data, 0x38, 4096);
+        put_u32(&mut data, 0x3c, 2);
+        put_u32(&mut data, 0x40, 1);
+        put_u32(&mut data, 0x44, END_OF_CHAIN);
+
+        for i in 0..HEADER_DIFAT_ENTRIES {
+            put_u32(
+                &mut data,
+                0x4c + 4 * i,
+                if i == 0 { 0 } else { FREE_SECTOR },
+            );
+        }
+
+        let sector = |n: usize| HEADER_SIZE * (n + 1);
+
+        let mut fat = vec![0xfffffffd, END_OF_CHAIN, END_OF_CHAIN, END_OF_CHAIN];
+        fat.extend(5..13);
+        fat.push(END_OF_CHAIN);
+        fat.resize(128, FREE_SECTOR);
+
+        for (i, next) in fat.iter().enumerate() {
+            put_u32(&mut data, sector(0) + 4 * i, *next);
+        }
+
+        let entries = [
+            directory_entry("Root Entry", TYPE_ROOT, (NO_STREAM, NO_STREAM, 2), 3, 128),
+            directory_entry(
+                "Workbook",
+                TYPE_STREAM,
+                (NO_STREAM, NO_STREAM, NO_STREAM),
+                4,
+                4500,
+            ),
+            directory_entry("Folder", TYPE_STORAGE, (1, NO_STREAM, 3), 0, 0),
+            directory_entry(
+                "Small",
+                TYPE_STREAM,
+                (NO_STREAM, NO_STREAM, NO_STREAM),
+                0,
+                100,
+            ),
+        ]
+        .concat();
+
+        data[sector(1)..sector(1) + entries.len()].copy_from_slice(&entries);
+
+        put_u32(&mut data, sector(2), 1);
+        put_u32(&mut data, sector(2) + 4, END_OF_CHAIN);
+
+        data[sector(3)..sector(3) + 100].copy_from_slice(&small);
+        data[sector(4)..sector(4) + 4500].copy_from_slice(&big);
+
+        let cfb = CompoundFile::from_data(data.clone()).unwrap();
+
+        assert_eq!(cfb.stream_names().unwrap(), ["Workbook", "Folder/Small"]);
+        assert_eq!(cfb.read_stream("workbook").unwrap(), big);
+        assert_eq!(cfb.read_stream("Folder/Small").unwrap(), small);
+        assert!(matches!(
+            cfb.read_stream("Small"),
+            Err(CFBError::StreamNotFound(_))
+        ));
+
+        // A directory entry that is its own left sibling
+        let mut looped = data.clone();
+        put_u32(&mut looped, sector(1) + DIRECTORY_ENTRY_SIZE + 68, 1);
+
+        let cfb = CompoundFile::from_data(looped).unwrap();
+
+        assert!(matches!(cfb.stream_names(), Err(CFBError::Corrupted(_))));
+        assert!(matches!(
+            cfb.read_stream("Workbook"),
+            Err(CFBError::Corrupted(_))
+        ));
+
+        // A chain that loops back to its start
+        put_u32(&mut data, sector(0) + 4 * 8, 4);
+
+        let cfb = CompoundFile::from_data(data).unwrap();
+
+        assert!(matches!(
+            cfb.read_stream("Workbook"),
+            Err(CFBError::Corrupted(_))
+        ));
+
+        assert!(matches!(
+            CompoundFile::from_data(vec![0; 1024]),
+            Err(CFBError::NotACompoundFile)
+        ));
+    }
+}
@@ -1,6 +1,9 @@
-use std::collections::HashMap;
+use std::borrow::Cow;
+use std::collections::{HashMap, VecDeque};
 
-#[derive(Debug)]
+use crate::encoding::{self, Encoding, EncodingError};
+
+#[derive(Debug, Clone, Copy)]
 pub struct Loc {
     pub line: usize,
     pub column: usize,
@@ -12,6 +15,7 @@ pub enum XMLError {
     EndOfFile { when: &'static str },
     Invalid { what: &'static str, loc: Loc },
     InternalError { what: &'static str },
+    Encoding(EncodingError),
 }
 
 impl std::fmt::Display for XMLError {
@@ -29,6 +33,9 @@ impl std::fmt::Display for XMLError {
             XMLError::InternalError { what } => {
                 write!(f, "{what}")
             }
+            XMLError::Encoding(e) => {
+                write!(f, "{e}")
+            }
         }
     }
 }
@@ -53,15 +60,15 @@ fn is_alpha(c: u8) -> bool {
     (b'a' <= c && c <= b'z') || (b'A' <= c && c <= b'Z')
 }
 
-struct Reader {
-    data: Vec<u8>,
+struct Reader<'a> {
+    data: &'a [u8],
     cursor: usize,
     column: usize,
     line: usize,
 }
 
-impl Reader {
-    fn new(data: Vec<u8>) -> Self {
+impl<'a> Reader<'a> {
+    fn new(data: &'a [u8]) -> Self {
         Self {
             data,
             cursor: 0,
@@ -101,6 +108,13 @@ impl Reader {
         self.cursor += 1;
     }
 
+    // Advances n bytes keeping track of the lines, without checking eob
+    fn advance_n(&mut self, n: usize) {
+        for _ in 0..n {
+            self.advance();
+        }
+    }
+
     // Advances without checking lines and eob
     fn raw_advance_n(&mut self, n: usize) {
         self.cursor += n;
@@ -109,17 +123,7 @@ impl Reader {
 
     // Test if the next bytes match the input sequence
     fn sequece_match(&self, seq: &[u8]) -> bool {
-        if seq.len() > self.data.len() - self.cursor {
-            return false;
-        }
-
-        for (i, &c) in seq.iter().enumerate() {
-            if c != self.data[self.cursor + i] {
-                return false;
-            }
-        }
-
-        true
+        self.data[self.cursor..].starts_with(seq)
     }
 
     fn read_byte(&mut self) -> u8 {
@@ -144,6 +148,23 @@ impl Reader {
         }
     }
 
+    // Returns the bytes up to the end marker, the marker itself is not consumed
+    fn read_until(&mut self, end_marker: &[u8], when: &'static str) -> Result<&'a [u8], XMLError> {
+        let rest = &self.data[self.cursor..];
+
+        let len = if end_marker.len() == 1 {
+            rest.iter().position(|&c| c == end_marker[0])
+        } else {
+            rest.windows(end_marker.len()).position(|w| w == end_marker)
+        };
+
+        let len = len.ok_or(XMLError::EndOfFile { when })?;
+
+        self.advance_n(len);
+
+        Ok(&rest[..len])
+    }
+
     fn get_loc(&self) -> Loc {
         Loc {
             line: self.line,
@@ -152,6 +173,13 @@ impl Reader {
     }
 }
 
+fn to_str(bytes: &[u8], loc: Loc) -> Result<&str, XMLError> {
+    str::from_utf8(bytes).map_err(|_| XMLError::Invalid {
+        what: "decoding utf-8 text",
+        loc,
+    })
+}
+
 #[derive(Debug)]
 pub enum Component {
     Comment(String),
@@ -160,9 +188,23 @@ pub enum Component {
         encoding: String,
         standalone: bool,
     },
+    // `<?target data?>`, like `<?mso-application progid="Excel.Sheet"?>`
+    ProcessingInstruction {
+        target: String,
+        data: String,
+    },
     Element {
+        // Qualified name, as written in the document
         name: String,
+
+        // Uri of the namespace the name prefix (or the default namespace) resolves to
+        namespace: Option<String>,
+
         attributes: HashMap<String, String>,
+
+        // Uris of the namespaces the prefixed attribute names resolve to
+        attribute_namespaces: HashMap<String, String>,
+
         children: Vec<Component>,
     },
     Text {
@@ -182,13 +224,6 @@ impl Component {
         }
     }
 
-    pub fn children_unchecked(&self) -> &Vec<Component> {
-        match self {
-            Component::Element { children, .. } => &children,
-            _ => unreachable!(),
-        }
-    }
-
     pub fn filter_elements<'a>(
         &'a self,
         elem_name: &'a str,
@@ -213,11 +248,316 @@ impl Component {
             _ => None,
         }
     }
+
+    pub fn local_name(&self) -> Option<&str> {
+        match self {
+            Component::Element { name, .. } => Some(QName::split(name).1),
+            _ => None,
+        }
+    }
+
+    // Tests an element by its namespace uri and local name, whatever prefix it uses
+    pub fn is_element_ns(&self, elem_namespace: &str, elem_local_name: &str) -> bool {
+        match self {
+            Component::Element {
+                name, namespace, ..
+            } => {
+                namespace.as_deref() == Some(elem_namespace)
+                    && QName::split(name).1 == elem_local_name
+            }
+            _ => false,
+        }
+    }
+
+    pub fn filter_elements_ns<'a>(
+        &'a self,
+        elem_namespace: &'a str,
+        elem_local_name: &'a str,
+    ) -> Box<dyn Iterator<Item = &'a Component> + 'a> {
+        match self {
+            Component::Element { children, .. } => Box::new(
+                children
+                    .iter()
+                    .filter(move |item| item.is_element_ns(elem_namespace, elem_local_name)),
+            ),
+            _ => Box::new(std::iter::empty()),
+        }
+    }
+
+    pub fn find_element_ns(
+        &self,
+        elem_namespace: &str,
+        elem_local_name: &str,
+    ) -> Option<&Component> {
+        match self {
+            Component::Element { children, .. } => children
+                .iter()
+                .find(|item| item.is_element_ns(elem_namespace, elem_local_name)),
+            _ => None,
+        }
+    }
+}
+
+// -- Queries
+//
+// A small subset of XPath to find elements:
+//
+//     doc.select("worksheet/sheetData/row/c[@r]")
+//     sheet.select("//c[@t='s']")
+//
+// A path is a list of steps separated by '/', each step is an element name (or `*`) with
+// optional predicates: `[@attr]`, `[@attr='value']` or a 1-based position `[2]`. A name
+// without a prefix is compared to the local name of the elements, so it matches whatever
+// prefix the document uses. `//` before a step looks for it in all descendants.
+//
+// Paths that can't be parsed don't match anything.
+
+enum Predicate<'p> {
+    HasAttribute(&'p str),
+    AttributeEquals(&'p str, &'p str),
+    Position(usize),
+}
+
+struct Step<'p> {
+    name: &'p str,
+    descendants: bool,
+    predicates: Vec<Predicate<'p>>,
+}
+
+fn parse_predicate(predicate: &str) -> Option<Predicate<'_>> {
+    let predicate = predicate.trim();
+
+    if let Some(attr) = predicate.strip_prefix('@') {
+        match attr.split_once('=') {
+            Some((name, value)) => {
+                let value = value.trim();
+                let quote = value.chars().next().filter(|&c| c == '\'' || c == '"')?;
+                let value = value.strip_prefix(quote)?.strip_suffix(quote)?;
+
+                Some(Predicate::AttributeEquals(name.trim(), value))
+            }
+            None => Some(Predicate::HasAttribute(attr)),
+        }
+    } else {
+        predicate
+            .parse::<usize>()
+            .ok()
+            .filter(|&n| n > 0)
+            .map(Predicate::Position)
+    }
+}
+
+fn parse_path(path: &str) -> Option<Vec<Step<'_>>> {
+    let mut steps = Vec::new();
+    let mut descendants = false;
+
+    for part in path.split('/') {
+        if part.is_empty() {
+            // Empty parts come from "//" (or a leading '/', which is ignored)
+            descendants = !steps.is_empty() || path.starts_with("//");
+            continue;
+        }
+
+        let (name, mut rest) = part.split_once('[').map_or((part, ""), |(n, r)| (n, r));
+        let mut predicates = Vec::new();
+
+        if !rest.is_empty() {
+            rest = rest.strip_suffix(']')?;
+
+            for predicate in rest.split("][") {
+                predicates.push(parse_predicate(predicate)?);
+            }
+        }
+
+        steps.push(Step {
+            name: name.trim(),
+            descendants,
+            predicates,
+        });
+
+        descendants = false;
+    }
+
+    (!steps.is_empty()).then_some(steps)
+}
+
+impl Step<'_> {
+    fn matches_name(&self, c: &Component) -> bool {
+        match c {
+            Component::Element { name, .. } => {
+                self.name == "*"
+                    || if self.name.contains(':') {
+                        name == self.name
+                    } else {
+                        QName::split(name).1 == self.name
+                    }
+            }
+            _ => false,
+        }
+    }
+
+    fn matches_predicates(&self, c: &Component, position: usize) -> bool {
+        self.predicates.iter().all(|p| match p {
+            Predicate::HasAttribute(name) => c.attr(name).is_some(),
+            Predicate::AttributeEquals(name, value) => c.attr(name) == Some(*value),
+            Predicate::Position(n) => *n == position,
+        })
+    }
+
+    // Positions count the elements with a matching name among the candidates
+    fn filter<'a>(
+        &self,
+        candidates: impl Iterator<Item = &'a Component>,
+        result: &mut Vec<&'a Component>,
+    ) {
+        let mut position = 0;
+
+        for candidate in candidates {
+            if self.matches_name(candidate) {
+                position += 1;
+
+                if self.matches_predicates(candidate, position) {
+                    result.push(candidate);
+                }
+            }
+        }
+    }
+
+    // Elements matching the step, from the children (or descendants) of the components
+    fn apply<'a>(&self, components: &[&'a Component]) -> Vec<&'a Component> {
+        let mut result = Vec::new();
+
+        for c in components {
+            if self.descendants {
+                self.filter(c.descendants(), &mut result);
+            } else {
+                self.filter(c.elements(), &mut result);
+            }
+        }
+
+        result
+    }
+}
+
+// Depth-first iterator over the descendants of a component
+pub struct Descendants<'a> {
+    stack: Vec<std::slice::Iter<'a, Component>>,
+}
+
+impl<'a> Iterator for Descendants<'a> {
+    type Item = &'a Component;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            let iter = self.stack.last_mut()?;
+
+            match iter.next() {
+                Some(c) => {
+                    if let Component::Element { children, .. } = c {
+                        self.stack.push(children.iter());
+                    }
+
+                    return Some(c);
+                }
+                None => {
+                    self.stack.pop();
+                }
+            }
+        }
+    }
+}
+
+impl Component {
+    pub fn name(&self) -> Option<&str> {
+        match self {
+            Component::Element { name, .. } => Some(name),
+            _ => None,
+        }
+    }
+
+    pub fn attr(&self, attr_name: &str) -> Option<&str> {
+        match self {
+            Component::Element { attributes, .. } => attributes.get(attr_name).map(|v| v.as_str()),
+            _ => None,
+        }
+    }
+
+    // Attribute by its namespace uri and local name, whatever prefix it uses
+    pub fn attr_ns(&self, attr_namespace: &str, attr_local_name: &str) -> Option<&str> {
+        match self {
+            Component::Element {
+                attributes,
+                attribute_namespaces,
+                ..
+            } => attributes
+                .iter()
+                .find(|(name, _)| {
+                    QName::split(name).1 == attr_local_name
+                        && attribute_namespaces.get(*name).map(String::as_str)
+                            == Some(attr_namespace)
+                })
+                .map(|(_, value)| value.as_str()),
+            _ => None,
+        }
+    }
+
+    // Child elements, skipping texts, comments and so on
+    pub fn elements(&self) -> impl Iterator<Item = &Component> {
+        let children = match self {
+            Component::Element { children, .. } => children.as_slice(),
+            _ => &[],
+        };
+
+        children
+            .iter()
+            .filter(|c| matches!(c, Component::Element { .. }))
+    }
+
+    pub fn descendants(&self) -> Descendants<'_> {
+        let children = match self {
+            Component::Element { children, .. } => children.as_slice(),
+            _ => &[],
+        };
+
+        Descendants {
+            stack: vec![children.iter()],
+        }
+    }
+
+    // All the text inside the component, concatenated in document order
+    pub fn text(&self) -> String {
+        match self {
+            Component::Text { value, .. } => value.clone(),
+            Component::Element { .. } => self
+                .descendants()
+                .filter_map(|c| match c {
+                    Component::Text { value, .. } => Some(value.as_str()),
+                    _ => None,
+                })
+                .collect(),
+            _ => String::new(),
+        }
+    }
+
+    // Elements matching the path, relative to this component children
+    pub fn select(&self, path: &str) -> Vec<&Component> {
+        let Some(steps) = parse_path(path) else {
+            return Vec::new();
+        };
+
+        steps
+            .iter()
+            .fold(vec![self], |components, step| step.apply(&components))
+    }
+
+    pub fn select_first(&self, path: &str) -> Option<&Component> {
+        self.select(path).into_iter().next()
+    }
 }
 
 #[inline]
 fn is_digit(c: u8) -> bool {
-    b'0' <= c && c < b'9'
+    c.is_ascii_digit()
 }
 
 #[inline]
@@ -230,7 +570,7 @@ fn is_name_char(c: u8) -> bool {
     is_start_name_char(c) || is_digit(c) || c == b'-' || c == b'.'
 }
 
-fn parse_name(reader: &mut Reader) -> Result<String, XMLError> {
+fn parse_name<'a>(reader: &mut Reader<'a>) -> Result<&'a str, XMLError> {
     if reader.eob() {
         return Err(XMLError::EndOfFile {
             when: "parsing a name",
@@ -251,10 +591,6 @@ fn parse_name(reader: &mut Reader) -> Result<String, XMLError> {
     while !reader.eob() {
         let c = reader.peek_byte();
 
-        if is_white_space(c) {
-            break;
-        }
-
         if !is_name_char(c) {
             break;
         }
@@ -262,67 +598,121 @@ fn parse_name(reader: &mut Reader) -> Result<String, XMLError> {
         reader.advance();
     }
 
-    let v = str::from_utf8(reader.data[start..reader.cursor].into());
-
-    Ok(v.unwrap().to_string())
+    to_str(&reader.data[start..reader.cursor], reader.get_loc())
 }
 
-static ENTITIES: [(&[u8], u8); 5] = [
-    (b"&quot;", b'"'),
-    (b"&amp;", b'&'),
-    (b"&apos;", b'\''),
-    (b"&lt;", b'<'),
-    (b"&gt;", b'>'),
+static ENTITIES: [(&str, char); 5] = [
+    ("quot", '"'),
+    ("amp", '&'),
+    ("apos", '\''),
+    ("lt", '<'),
+    ("gt", '>'),
 ];
 
-fn parse_text(reader: &mut Reader, end_marker: &[u8]) -> Result<String, XMLError> {
-    if end_marker.len() == 0 {
-        return Err(XMLError::EndOfFile {
-            when: "parsing a text",
-        });
+// Location of the byte at offset inside a text that starts at loc
+fn loc_in_text(loc: Loc, text: &str, offset: usize) -> Loc {
+    let before = &text.as_bytes()[..offset];
+
+    match before.iter().rposition(|&c| c == b'\n') {
+        Some(idx) => Loc {
+            line: loc.line + before.iter().filter(|&&c| c == b'\n').count(),
+            column: offset - idx,
+        },
+        None => Loc {
+            line: loc.line,
+            column: loc.column + offset,
+        },
+    }
+}
+
+// Char ::= #x9 | #xA | #xD | [#x20-#xD7FF] | [#xE000-#xFFFD] | [#x10000-#x10FFFF]
+fn is_xml_char(c: char) -> bool {
+    matches!(c, '\t' | '\n' | '\r' | '\u{20}'..='\u{d7ff}' | '\u{e000}'..='\u{fffd}' | '\u{10000}'..)
+}
+
+// Decodes what is between '&' and ';': a predefined entity, `#10` or `#x2019`
+fn decode_reference(reference: &str) -> Result<char, &'static str> {
+    let code = if let Some(hex) = reference
+        .strip_prefix("#x")
+        .or_else(|| reference.strip_prefix("#X"))
+    {
+        u32::from_str_radix(hex, 16).ok()
+    } else if let Some(decimal) = reference.strip_prefix('#') {
+        decimal.parse::<u32>().ok()
+    } else {
+        return ENTITIES
+            .iter()
+            .find(|(name, _)| *name == reference)
+            .map(|(_, c)| *c)
+            .ok_or("reading an unknown entity");
+    };
+
+    code.and_then(char::from_u32)
+        .filter(|&c| is_xml_char(c))
+        .ok_or("reading a character reference")
+}
+
+// Replaces the entities and character references of a text, normalizing the line ends.
+// Attribute values are also normalized: every literal white space becomes a space, but the
+// ones written as character references are kept.
+//
+// The text is only copied when there is something to replace.
+fn decode_text(raw: &str, loc: Loc, is_attribute: bool) -> Result<Cow<'_, str>, XMLError> {
+    let needs_copy = raw
+        .bytes()
+        .any(|c| c == b'&' || c == b'\r' || (is_attribute && (c == b'\n' || c == b'\t')));
+
+    if !needs_copy {
+        return Ok(Cow::Borrowed(raw));
     }
 
-    let mut result = Vec::new();
+    let mut result = String::with_capacity(raw.len());
+    let mut i = 0;
 
-    while !reader.eob() {
-        if reader.sequece_match(end_marker) {
-            let v = str::from_utf8(&result);
+    while let Some(idx) = raw[i..].find(['&', '\r', '\n', '\t']) {
+        let idx = i + idx;
 
-            return Ok(v.unwrap().to_string());
-        }
+        result.push_str(&raw[i..idx]);
 
-        let b = reader.peek_byte();
+        match raw.as_bytes()[idx] {
+            b'&' => {
+                let invalid = |what| XMLError::Invalid {
+                    what,
+                    loc: loc_in_text(loc, raw, idx),
+                };
 
-        if b == b'&' {
-            let mut found = false;
+                let len = raw[idx..]
+                    .find(';')
+                    .ok_or_else(|| invalid("reading an entity without ';'"))?;
 
-            for (value, replacement) in ENTITIES {
-                if reader.sequece_match(value) {
-                    result.push(replacement);
+                result.push(decode_reference(&raw[idx + 1..idx + len]).map_err(invalid)?);
 
-                    reader.raw_advance_n(value.len());
-                    found = true;
-                    break;
-                }
+                i = idx + len + 1;
             }
+            b'\r' => {
+                result.push(if is_attribute { ' ' } else { '\n' });
 
-            if found {
-                continue;
+                // "\r\n" is a single line end
+                i = if raw[idx + 1..].starts_with('\n') {
+                    idx + 2
+                } else {
+                    idx + 1
+                };
             }
-            // NOTE: Maybe return an error if entity was not found
-        }
-
-        result.push(b);
+            c => {
+                result.push(if is_attribute { ' ' } else { c as char });
 
-        reader.advance();
+                i = idx + 1;
+            }
+        }
     }
 
-    Err(XMLError::EndOfFile {
-        when: "parsing a text the marker was not found",
-    })
+    result.push_str(&raw[i..]);
+
+    Ok(Cow::Owned(result))
 }
 
-fn parse_attribute(reader: &mut Reader) -> Result<(String, String), XMLError> {
+fn parse_attribute<'a>(reader: &mut Reader<'a>) -> Result<(&'a str, Cow<'a, str>), XMLError> {
     let name = parse_name(reader)?;
 
     reader.skip_white_spaces();
@@ -353,329 +743,1375 @@ fn parse_attribute(reader: &mut Reader) -> Result<(String, String), XMLError> {
         });
     }
 
-    let value = parse_text(reader, &[c])?;
+    let loc = reader.get_loc();
+    let value = reader.read_until(&[c], "parsing an attribute value")?;
 
     reader.raw_advance_n(1);
 
-    Ok((name, value))
+    Ok((name, decode_text(to_str(value, loc)?, loc, true)?))
 }
 
-// -- Document
-pub struct Document {
-    pub children: Vec<Component>,
-}
+pub const XML_NAMESPACE: &str = "http://www.w3.org/XML/1998/namespace";
+pub const XMLNS_NAMESPACE: &str = "http://www.w3.org/2000/xmlns/";
 
-impl Document {
-    pub fn from_data(data: Vec<u8>) -> Result<Document, XMLError> {
-        let mut doc = Document {
-            children: Vec::new(),
-        };
+// Element or attribute name with its prefix resolved to a namespace uri
+#[derive(Debug, Clone, PartialEq)]
+pub struct QName<'a> {
+    pub prefix: &'a str,
+    pub local_name: &'a str,
+    pub namespace: Option<Cow<'a, str>>,
+}
 
-        doc.parse(data)?;
+impl<'a> QName<'a> {
+    fn split(name: &'a str) -> (&'a str, &'a str) {
+        name.split_once(':').unwrap_or(("", name))
+    }
 
-        Ok(doc)
+    pub fn is(&self, namespace: &str, local_name: &str) -> bool {
+        self.local_name == local_name && self.namespace.as_deref() == Some(namespace)
     }
+}
 
-    pub fn print_components(&self) {
-        for c in self.children.iter() {
-            println!("{:?}", c);
+impl std::fmt::Display for QName<'_> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        if self.prefix.is_empty() {
+            write!(f, "{}", self.local_name)
+        } else {
+            write!(f, "{}:{}", self.prefix, self.local_name)
         }
     }
+}
 
-    // Adds compoment to list of components, also including to an element children if
-    // there is any in stack
-    fn add_component(
-        &mut self,
-        c: Component,
-        is_open: bool,
-        stack: &mut Vec<Component>,
-    ) -> Result<(), XMLError> {
-        let is_element = match c {
-            Component::Element { .. } => true,
-            _ => false,
-        };
+// Value of `encoding` in the xml declaration, when the document starts with one
+fn declared_encoding(data: &[u8]) -> Option<&str> {
+    if !data.starts_with(b"<?xml") {
+        return None;
+    }
 
-        if is_open && is_element {
-            stack.push(c);
+    let end = data.iter().take(1024).position(|&c| c == b'>')?;
+    let declaration = str::from_utf8(&data[..end]).ok()?;
 
-            return Ok(());
-        }
+    let (_, rest) = declaration.split_once("encoding")?;
+    let rest = rest.trim_start().strip_prefix('=')?.trim_start();
 
-        if let Some(e) = stack.as_mut_slice().last_mut() {
-            match e {
-                Component::Element { children, .. } => children.push(c),
-                _ => {
-                    return Err(XMLError::InternalError {
-                        what: "component in stack is not an element!",
-                    });
+    let quote = rest.chars().next().filter(|&c| c == '"' || c == '\'')?;
+    let rest = &rest[1..];
+
+    rest.find(quote).map(|len| &rest[..len])
+}
+
+// The parser works on UTF-8, documents in other encodings are converted first. The encoding
+// comes from the byte order mark, the first bytes of an UTF-16 document (`<?` without a BOM)
+// or the xml declaration.
+pub fn to_utf8(data: &[u8]) -> Result<Cow<'_, [u8]>, XMLError> {
+    let encoding = if let Some((encoding, _)) = encoding::sniff_bom(data) {
+        encoding
+    } else if data.starts_with(&[b'<', 0, b'?', 0]) {
+        Encoding::Utf16Le
+    } else if data.starts_with(&[0, b'<', 0, b'?']) {
+        Encoding::Utf16Be
+    } else {
+        match declared_encoding(data) {
+            None => Encoding::Utf8,
+            Some(label) => match Encoding::from_label(label) {
+                // The bytes so far were ASCII, so the declaration is not telling the truth
+                Some(Encoding::Utf16Le | Encoding::Utf16Be) => Encoding::Utf8,
+                Some(encoding) => encoding,
+                None => {
+                    return Err(XMLError::Encoding(EncodingError::Unsupported(
+                        label.to_string(),
+                    )));
                 }
-            }
-        } else {
-            self.children.push(c);
+            },
         }
+    };
 
-        Ok(())
+    if encoding == Encoding::Utf8 {
+        return Ok(Cow::Borrowed(data));
     }
 
-    fn parse(&mut self, data: Vec<u8>) -> Result<(), XMLError> {
-        let mut reader = Reader::new(data);
-
-        let mut stack: Vec<Component> = Vec::new();
+    encoding::decode(data, encoding)
+        .map(|text| Cow::Owned(text.into_bytes()))
+        .map_err(XMLError::Encoding)
+}
 
-        while !reader.eob() {
-            reader.skip_white_spaces();
+// -- Pull parser
+//
+// Reads the xml one event at a time, names and texts are borrowed from the input whenever
+// possible. No tree is built, but the input is a slice so the whole document is still in
+// memory, converted to UTF-8 first when it's in another encoding.
+//
+// A start tag is reported as a StartElement followed by one Attribute event per attribute.
+// Empty elements (`<a/>`) also produce an EndElement.
+//
+// Element and attribute names have their namespaces resolved from the `xmlns` declarations
+// in scope. An unprefixed attribute has no namespace and an undeclared prefix resolves to none.
+#[derive(Debug)]
+pub enum Event<'a> {
+    Declaration {
+        version: Cow<'a, str>,
+        encoding: Cow<'a, str>,
+        standalone: bool,
+    },
+    StartElement(QName<'a>),
+    Attribute {
+        name: QName<'a>,
+        value: Cow<'a, str>,
+    },
+    EndElement(QName<'a>),
+    Text {
+        value: Cow<'a, str>,
+        is_cdata: bool,
+    },
+    Comment(&'a str),
+    ProcessingInstruction {
+        target: &'a str,
+        data: &'a str,
+    },
 
-            if reader.sequece_match(b"<?") {
-                reader.raw_advance_n(2);
+    // Conditional sections and DTD
+    Other(&'a str),
+}
 
-                if !reader.sequece_match(b"xml") {
-                    return Err(XMLError::Invalid {
-                        what: "invalid declaration, expecting 'xml'",
-                        loc: reader.get_loc(),
-                    });
-                }
+pub struct PullParser<'a> {
+    reader: Reader<'a>,
 
-                if self.children.len() > 0 {
-                    return Err(XMLError::Invalid {
-                        what: "it must have only one xml declaration and it must be the first element in file",
-                        loc: reader.get_loc(),
-                    });
-                }
+    // Names of the elements not closed yet, the last one is the innermost
+    open_elements: Vec<QName<'a>>,
 
-                reader.raw_advance_n(3);
+    // Prefix declarations in scope, `scopes` has how many of them there were before each
+    // open element
+    namespaces: Vec<(&'a str, Cow<'a, str>)>,
+    scopes: Vec<usize>,
 
-                reader.skip_white_spaces();
+    // Attributes of the last start tag not reported yet, and whether it was an empty element
+    pending_attributes: VecDeque<(&'a str, Cow<'a, str>)>,
+    pending_end: bool,
 
-                let mut version = String::from("1.0");
-                let mut encoding = String::from("UTF-8");
-                let mut standalone = true;
+    started: bool,
+    failed: bool,
+}
 
-                // Must read version, encoding? and standalone? in this order
-                let mut attr_count = 0;
+impl<'a> PullParser<'a> {
+    pub fn new(data: &'a [u8]) -> Self {
+        Self {
+            reader: Reader::new(data),
+            open_elements: Vec::new(),
+            namespaces: Vec::new(),
+            scopes: Vec::new(),
+            pending_attributes: VecDeque::new(),
+            pending_end: false,
+            started: false,
+            failed: false,
+        }
+    }
 
-                loop {
-                    reader.skip_white_spaces();
+    pub fn open_elements(&self) -> &[QName<'a>] {
+        &self.open_elements
+    }
 
-                    match reader.peek_2bytes() {
-                        (0, 0) => {
-                            return Err(XMLError::EndOfFile {
-                                when: "reading tag and its attributes",
-                            });
-                        }
-                        (b'?', b'>') => {
-                            reader.raw_advance_n(2);
-                            break;
-                        }
-                        _ => {
-                            let loc0 = reader.get_loc();
-
-                            let (name, value) = parse_attribute(&mut reader)?;
-
-                            if attr_count == 0 {
-                                if name != "version" {
-                                    return Err(XMLError::Expecting {
-                                        what: "'version' attribute in xml declation",
-                                        loc: loc0,
-                                    });
-                                }
-
-                                version = value;
-                            } else if attr_count == 1 {
-                                if name == "encoding" {
-                                    encoding = value;
-                                } else if name == "standalone" {
-                                    standalone = value.as_str() == "yes";
-                                    attr_count += 1;
-                                } else {
-                                    return Err(XMLError::Expecting {
-                                        what:
-                                            "'encoding' or 'standalone' attribute in xml declation",
-                                        loc: loc0,
-                                    });
-                                }
-                            } else if attr_count == 2 {
-                                if name != "standalone" {
-                                    return Err(XMLError::Expecting {
-                                        what: "'standalone' attribute in xml declation",
-                                        loc: loc0,
-                                    });
-                                }
-                                standalone = value.as_str() == "yes";
-                            } else {
-                                return Err(XMLError::Invalid {
-                                    what: "too many attributes in xml declation",
-                                    loc: loc0,
-                                });
-                            }
+    // Bytes of the document parsed so far
+    pub fn position(&self) -> usize {
+        self.reader.cursor
+    }
 
-                            attr_count += 1;
-                        }
-                    }
-                }
+    // Tests the local names of the innermost open elements, ignoring their prefixes
+    pub fn path_ends_with(&self, local_names: &[&str]) -> bool {
+        local_names.len() <= self.open_elements.len()
+            && self.open_elements[self.open_elements.len() - local_names.len()..]
+                .iter()
+                .zip(local_names)
+                .all(|(e, name)| e.local_name == *name)
+    }
 
-                self.add_component(
-                    Component::Declaration {
-                        version,
-                        encoding,
-                        standalone,
-                    },
-                    false,
-                    &mut stack,
-                )?;
-            } else if reader.sequece_match(b"<!--") {
-                reader.raw_advance_n(4);
+    fn resolve_prefix(&self, prefix: &str) -> Option<Cow<'a, str>> {
+        match prefix {
+            "xml" => Some(Cow::Borrowed(XML_NAMESPACE)),
+            "xmlns" => Some(Cow::Borrowed(XMLNS_NAMESPACE)),
+            _ => self
+                .namespaces
+                .iter()
+                .rev()
+                .find(|(p, _)| *p == prefix)
+                .and_then(|(_, uri)| (!uri.is_empty()).then(|| uri.clone())),
+        }
+    }
 
-                let text = parse_text(&mut reader, b"-->")?;
+    fn element_name(&self, name: &'a str) -> QName<'a> {
+        let (prefix, local_name) = QName::split(name);
 
-                reader.raw_advance_n(3);
+        QName {
+            prefix,
+            local_name,
+            namespace: self.resolve_prefix(prefix),
+        }
+    }
 
-                self.add_component(Component::Comment(text), false, &mut stack)?;
-            } else if reader.sequece_match(b"<![CDATA[") {
-                reader.raw_advance_n(9);
+    fn attribute_name(&self, name: &'a str) -> QName<'a> {
+        let (prefix, local_name) = QName::split(name);
 
-                let text = parse_text(&mut reader, b"]]>")?;
+        let namespace = if name == "xmlns" {
+            Some(Cow::Borrowed(XMLNS_NAMESPACE))
+        } else if prefix.is_empty() {
+            None
+        } else {
+            self.resolve_prefix(prefix)
+        };
 
-                reader.raw_advance_n(3);
+        QName {
+            prefix,
+            local_name,
+            namespace,
+        }
+    }
 
-                self.add_component(
-                    Component::Text {
-                        value: text,
-                        is_cdata: true,
-                    },
-                    false,
-                    &mut stack,
-                )?;
-            } else if reader.sequece_match(b"<!") {
-                reader.raw_advance_n(2);
+    fn close_element(&mut self) -> Option<QName<'a>> {
+        let name = self.open_elements.pop()?;
 
-                let text = parse_text(&mut reader, b">")?;
+        if let Some(count) = self.scopes.pop() {
+            self.namespaces.truncate(count);
+        }
 
-                reader.raw_advance_n(1);
+        Some(name)
+    }
 
-                self.add_component(Component::Other(text), false, &mut stack)?;
-            } else if reader.sequece_match(b"</") {
-                let loc0 = reader.get_loc();
+    pub fn loc(&self) -> Loc {
+        self.reader.get_loc()
+    }
 
-                reader.raw_advance_n(2);
+    fn parse_declaration(&mut self) -> Result<Event<'a>, XMLError> {
+        let reader = &mut self.reader;
 
-                let name = parse_name(&mut reader)?;
+        reader.raw_advance_n(2);
 
-                reader.skip_white_spaces();
+        if self.started {
+            return Err(XMLError::Invalid {
+                what: "it must have only one xml declaration and it must be the first element in file",
+                loc: reader.get_loc(),
+            });
+        }
 
-                if reader.eob() {
-                    return Err(XMLError::EndOfFile {
-                        when: "reading a closing tag",
-                    });
-                }
+        reader.raw_advance_n(3);
 
-                let c = reader.peek_byte();
+        let mut version = Cow::Borrowed("1.0");
+        let mut encoding = Cow::Borrowed("UTF-8");
+        let mut standalone = true;
 
-                if c != b'>' {
-                    return Err(XMLError::Expecting {
-                        what: "'>' to be closing the tag end",
-                        loc: reader.get_loc(),
+        // Must read version, encoding? and standalone? in this order
+        let mut attr_count = 0;
+
+        loop {
+            reader.skip_white_spaces();
+
+            match reader.peek_2bytes() {
+                (0, 0) => {
+                    return Err(XMLError::EndOfFile {
+                        when: "reading tag and its attributes",
                     });
                 }
+                (b'?', b'>') => {
+                    reader.raw_advance_n(2);
+                    break;
+                }
+                _ => {
+                    let loc0 = reader.get_loc();
 
-                reader.advance();
+                    let (name, value) = parse_attribute(reader)?;
 
-                let removed_element;
+                    if attr_count == 0 {
+                        if name != "version" {
+                            return Err(XMLError::Expecting {
+                                what: "'version' attribute in xml declation",
+                                loc: loc0,
+                            });
+                        }
 
-                if let Some(e) = stack.pop() {
-                    match e {
-                        Component::Element { name: ref n, .. } => {
-                            if *n != name {
-                                return Err(XMLError::Invalid {
-                                    what: "unmatched tag end",
-                                    loc: loc0,
-                                });
-                            }
+                        version = value;
+                    } else if attr_count == 1 {
+                        if name == "encoding" {
+                            encoding = value;
+                        } else if name == "standalone" {
+                            standalone = value == "yes";
+                            attr_count += 1;
+                        } else {
+                            return Err(XMLError::Expecting {
+                                what: "'encoding' or 'standalone' attribute in xml declation",
+                                loc: loc0,
+                            });
                         }
-                        _ => {
-                            return Err(XMLError::InternalError {
-                                what: "component in stack is not an element!",
+                    } else if attr_count == 2 {
+                        if name != "standalone" {
+                            return Err(XMLError::Expecting {
+                                what: "'standalone' attribute in xml declation",
+                                loc: loc0,
                             });
                         }
+                        standalone = value == "yes";
+                    } else {
+                        return Err(XMLError::Invalid {
+                            what: "too many attributes in xml declation",
+                            loc: loc0,
+                        });
                     }
 
-                    removed_element = e;
-                } else {
-                    return Err(XMLError::Invalid {
-                        what: "unexpected tag end found",
-                        loc: loc0,
+                    attr_count += 1;
+                }
+            }
+        }
+
+        Ok(Event::Declaration {
+            version,
+            encoding,
+            standalone,
+        })
+    }
+
+    fn parse_processing_instruction(&mut self) -> Result<Event<'a>, XMLError> {
+        let reader = &mut self.reader;
+
+        reader.raw_advance_n(2);
+
+        let loc0 = reader.get_loc();
+        let target = parse_name(reader)?;
+
+        // `xml` in any case is reserved for the declaration
+        if target.eq_ignore_ascii_case("xml") {
+            return Err(XMLError::Invalid {
+                what: "reading a processing instruction named 'xml'",
+                loc: loc0,
+            });
+        }
+
+        reader.skip_white_spaces();
+
+        let loc = reader.get_loc();
+        let data = reader.read_until(b"?>", "parsing a processing instruction")?;
+
+        reader.raw_advance_n(2);
+
+        Ok(Event::ProcessingInstruction {
+            target,
+            data: to_str(data, loc)?,
+        })
+    }
+
+    fn parse_end_tag(&mut self) -> Result<Event<'a>, XMLError> {
+        let reader = &mut self.reader;
+
+        let loc0 = reader.get_loc();
+
+        reader.raw_advance_n(2);
+
+        let name = parse_name(reader)?;
+
+        reader.skip_white_spaces();
+
+        if reader.eob() {
+            return Err(XMLError::EndOfFile {
+                when: "reading a closing tag",
+            });
+        }
+
+        if reader.peek_byte() != b'>' {
+            return Err(XMLError::Expecting {
+                what: "'>' to be closing the tag end",
+                loc: reader.get_loc(),
+            });
+        }
+
+        reader.advance();
+
+        let (prefix, local_name) = QName::split(name);
+
+        match self.open_elements.last() {
+            Some(n) if n.prefix == prefix && n.local_name == local_name => {
+                let name = self.close_element().unwrap();
+
+                Ok(Event::EndElement(name))
+            }
+            Some(_) => Err(XMLError::Invalid {
+                what: "unmatched tag end",
+                loc: loc0,
+            }),
+            None => Err(XMLError::Invalid {
+                what: "unexpected tag end found",
+                loc: loc0,
+            }),
+        }
+    }
+
+    // Reads the whole start tag, the namespaces declared in it apply to the element name
+    fn parse_start_tag(&mut self) -> Result<Event<'a>, XMLError> {
+        let reader = &mut self.reader;
+
+        reader.raw_advance_n(1);
+
+        let name = parse_name(reader)?;
+
+        loop {
+            reader.skip_white_spaces();
+
+            match reader.peek_2bytes() {
+                (0, 0) => {
+                    return Err(XMLError::EndOfFile {
+                        when: "reading tag closing or its attributes",
                     });
                 }
+                (b'>', _) => {
+                    reader.raw_advance_n(1);
+                    break;
+                }
+                (b'/', b'>') => {
+                    reader.raw_advance_n(2);
+                    self.pending_end = true;
+                    break;
+                }
+                _ => {
+                    let attribute = parse_attribute(reader)?;
+                    self.pending_attributes.push_back(attribute);
+                }
+            }
+        }
 
-                if stack.len() > 0 {
-                    self.add_component(removed_element, false, &mut stack)?;
-                } else {
-                    self.children.push(removed_element);
+        self.scopes.push(self.namespaces.len());
+
+        for (attr_name, value) in self.pending_attributes.iter() {
+            if *attr_name == "xmlns" {
+                self.namespaces.push(("", value.clone()));
+            } else if let Some(prefix) = attr_name.strip_prefix("xmlns:") {
+                self.namespaces.push((prefix, value.clone()));
+            }
+        }
+
+        let name = self.element_name(name);
+
+        self.open_elements.push(name.clone());
+
+        Ok(Event::StartElement(name))
+    }
+
+    pub fn next_event(&mut self) -> Result<Option<Event<'a>>, XMLError> {
+        if let Some((name, value)) = self.pending_attributes.pop_front() {
+            let name = self.attribute_name(name);
+
+            return Ok(Some(Event::Attribute { name, value }));
+        }
+
+        if self.pending_end {
+            self.pending_end = false;
+
+            let name = self.close_element().ok_or(XMLError::InternalError {
+                what: "no open element for the tag",
+            })?;
+
+            return Ok(Some(Event::EndElement(name)));
+        }
+
+        if self.open_elements.is_empty() {
+            self.reader.skip_white_spaces();
+        }
+
+        let reader = &mut self.reader;
+
+        if reader.eob() {
+            if !self.open_elements.is_empty() {
+                return Err(XMLError::EndOfFile {
+                    when: "looking for the closing tags",
+                });
+            }
+
+            return Ok(None);
+        }
+
+        // `<?xml-stylesheet` is a processing instruction, not a declaration
+        let is_declaration = reader.sequece_match(b"<?xml")
+            && matches!(reader.data.get(reader.cursor + 5), Some(&c) if is_white_space(c) || c == b'?');
+
+        let event = if is_declaration {
+            self.parse_declaration()?
+        } else if reader.sequece_match(b"<?") {
+            self.parse_processing_instruction()?
+        } else if reader.sequece_match(b"<!--") {
+            reader.raw_advance_n(4);
+
+            let loc = reader.get_loc();
+            let text = reader.read_until(b"-->", "parsing a comment")?;
+
+            reader.raw_advance_n(3);
+
+            Event::Comment(to_str(text, loc)?)
+        } else if reader.sequece_match(b"<![CDATA[") {
+            reader.raw_advance_n(9);
+
+            let loc = reader.get_loc();
+            let text = reader.read_until(b"]]>", "parsing a CDATA section")?;
+
+            reader.raw_advance_n(3);
+
+            Event::Text {
+                value: Cow::Borrowed(to_str(text, loc)?),
+                is_cdata: true,
+            }
+        } else if reader.sequece_match(b"<!") {
+            reader.raw_advance_n(2);
+
+            let loc = reader.get_loc();
+            let text = reader.read_until(b">", "parsing a text the marker was not found")?;
+
+            reader.raw_advance_n(1);
+
+            Event::Other(to_str(text, loc)?)
+        } else if reader.sequece_match(b"</") {
+            self.parse_end_tag()?
+        } else if reader.sequece_match(b"<") {
+            self.parse_start_tag()?
+        } else if !self.open_elements.is_empty() {
+            let loc = reader.get_loc();
+            let text = reader.read_until(b"<", "parsing a text the marker was not found")?;
+
+            Event::Text {
+                value: decode_text(to_str(text, loc)?, loc, false)?,
+                is_cdata: false,
+            }
+        } else {
+            return Err(XMLError::Invalid {
+                what: "text outside of the root element",
+                loc: reader.get_loc(),
+            });
+        };
+
+        self.started = true;
+
+        Ok(Some(event))
+    }
+}
+
+impl<'a> Iterator for PullParser<'a> {
+    type Item = Result<Event<'a>, XMLError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.failed {
+            return None;
+        }
+
+        let result = self.next_event();
+
+        self.failed = result.is_err();
+
+        result.transpose()
+    }
+}
+
+// -- Document
+pub struct Document {
+    pub children: Vec<Component>,
+}
+
+impl Document {
+    pub fn from_data(data: Vec<u8>) -> Result<Document, XMLError> {
+        let mut doc = Document {
+            children: Vec::new(),
+        };
+
+        doc.parse(&to_utf8(&data)?)?;
+
+        Ok(doc)
+    }
+
+    // A document with the usual declaration and the root element
+    pub fn with_root(root: Component) -> Document {
+        Document {
+            children: vec![
+                Component::Declaration {
+                    version: String::from("1.0"),
+                    encoding: String::from("UTF-8"),
+                    standalone: true,
+                },
+                root,
+            ],
+        }
+    }
+
+    pub fn root(&self) -> Option<&Component> {
+        self.children
+            .iter()
+            .find(|c| matches!(c, Component::Element { .. }))
+    }
+
+    // Elements matching the path, the first step is tested against the root element
+    pub fn select(&self, path: &str) -> Vec<&Component> {
+        let Some(steps) = parse_path(path) else {
+            return Vec::new();
+        };
+
+        let Some(root) = self.root() else {
+            return Vec::new();
+        };
+
+        let first = &steps[0];
+        let mut components = Vec::new();
+
+        if first.descendants {
+            first.filter(
+                std::iter::once(root).chain(root.descendants()),
+                &mut components,
+            );
+        } else {
+            first.filter(std::iter::once(root), &mut components);
+        }
+
+        for step in &steps[1..] {
+            components = step.apply(&components);
+        }
+
+        components
+    }
+
+    pub fn select_first(&self, path: &str) -> Option<&Component> {
+        self.select(path).into_iter().next()
+    }
+
+    pub fn print_components(&self) {
+        for c in self.children.iter() {
+            println!("{:?}", c);
+        }
+    }
+
+    // Serializes the document, when pretty is set elements are indented one per line, except
+    // inside elements that have text, where white space is significant
+    pub fn to_bytes(&self, pretty: bool) -> Vec<u8> {
+        let mut out = String::new();
+
+        for c in self.children.iter() {
+            write_component(&mut out, c, pretty.then_some(0), false);
+
+            let is_prolog = matches!(
+                c,
+                Component::Declaration { .. } | Component::ProcessingInstruction { .. }
+            );
+
+            if pretty || is_prolog {
+                out.push('\n');
+            }
+        }
+
+        out.into_bytes()
+    }
+
+    pub fn write<W: std::io::Write>(&self, writer: &mut W, pretty: bool) -> std::io::Result<()> {
+        writer.write_all(&self.to_bytes(pretty))
+    }
+
+    // Adds compoment to the children of the innermost open element, or to the document
+    fn add_component(&mut self, c: Component, stack: &mut [Component]) -> Result<(), XMLError> {
+        if let Some(e) = stack.last_mut() {
+            match e {
+                Component::Element { children, .. } => children.push(c),
+                _ => {
+                    return Err(XMLError::InternalError {
+                        what: "component in stack is not an element!",
+                    });
                 }
-            } else if reader.sequece_match(b"<") {
-                reader.raw_advance_n(1);
+            }
+        } else {
+            self.children.push(c);
+        }
 
-                let mut attributes = HashMap::new();
-                let name = parse_name(&mut reader)?;
+        Ok(())
+    }
 
-                loop {
-                    reader.skip_white_spaces();
+    fn parse(&mut self, data: &[u8]) -> Result<(), XMLError> {
+        let mut stack: Vec<Component> = Vec::new();
 
-                    match reader.peek_2bytes() {
-                        (0, 0) => {
-                            return Err(XMLError::EndOfFile {
-                                when: "reading tag closing or its attributes",
-                            });
-                        }
-                        (b'>', _) => {
-                            reader.raw_advance_n(1);
-
-                            self.add_component(
-                                Component::Element {
-                                    name,
-                                    attributes,
-                                    children: vec![],
-                                },
-                                true,
-                                &mut stack,
-                            )?;
-
-                            break;
-                        }
-                        (b'/', b'>') => {
-                            reader.raw_advance_n(2);
-
-                            self.add_component(
-                                Component::Element {
-                                    name,
-                                    attributes,
-                                    children: vec![],
-                                },
-                                false,
-                                &mut stack,
-                            )?;
-
-                            break;
+        // Whether `xml:space="preserve"` is in scope for each open element
+        let mut preserve: Vec<bool> = Vec::new();
+
+        for event in PullParser::new(data) {
+            let c = match event? {
+                Event::Declaration {
+                    version,
+                    encoding,
+                    standalone,
+                } => Component::Declaration {
+                    version: version.into_owned(),
+                    encoding: encoding.into_owned(),
+                    standalone,
+                },
+                Event::StartElement(name) => {
+                    preserve.push(preserve.last().copied().unwrap_or(false));
+                    stack.push(Component::Element {
+                        name: name.to_string(),
+                        namespace: name.namespace.map(Cow::into_owned),
+                        attributes: HashMap::new(),
+                        attribute_namespaces: HashMap::new(),
+                        children: vec![],
+                    });
+
+                    continue;
+                }
+                Event::Attribute { name, value } => {
+                    if name.is(XML_NAMESPACE, "space")
+                        && let Some(preserved) = preserve.last_mut()
+                    {
+                        *preserved = value == "preserve";
+                    }
+
+                    match stack.last_mut() {
+                        Some(Component::Element {
+                            attributes,
+                            attribute_namespaces,
+                            ..
+                        }) => {
+                            if let Some(namespace) = &name.namespace {
+                                attribute_namespaces
+                                    .insert(name.to_string(), namespace.to_string());
+                            }
+
+                            let _ = attributes.insert(name.to_string(), value.into_owned());
                         }
                         _ => {
-                            let (name, value) = parse_attribute(&mut reader)?;
-                            let _ = attributes.insert(name, value);
+                            return Err(XMLError::InternalError {
+                                what: "attribute outside of an element!",
+                            });
                         }
                     }
+
+                    continue;
                 }
-            } else if stack.len() > 0 {
-                let text = parse_text(&mut reader, b"<")?;
+                Event::EndElement(_) => {
+                    preserve.pop();
+
+                    stack.pop().ok_or(XMLError::InternalError {
+                        what: "no element in stack to be closed!",
+                    })?
+                }
+                Event::Text { value, is_cdata } => {
+                    // Blank text between elements is just indentation, unless it's preserved
+                    if !is_cdata
+                        && value.bytes().all(is_white_space)
+                        && !preserve.last().copied().unwrap_or(false)
+                    {
+                        continue;
+                    }
 
-                self.add_component(
                     Component::Text {
-                        value: text,
-                        is_cdata: false,
-                    },
-                    false,
-                    &mut stack,
-                )?;
-            }
+                        value: value.into_owned(),
+                        is_cdata,
+                    }
+                }
+                Event::Comment(text) => Component::Comment(text.to_string()),
+                Event::ProcessingInstruction { target, data } => Component::ProcessingInstruction {
+                    target: target.to_string(),
+                    data: data.to_string(),
+                },
+                Event::Other(text) => Component::Other(text.to_string()),
+            };
+
+            self.add_component(c, &mut stack)?;
         }
 
         Ok(())
     }
 }
+
+// -- Serialization
+
+// Characters not allowed in XML can't even be written as references, they're dropped
+fn escape_text(out: &mut String, value: &str) {
+    for c in value.chars() {
+        match c {
+            '&' => out.push_str("&amp;"),
+            '<' => out.push_str("&lt;"),
+            '>' => out.push_str("&gt;"),
+            // A literal "\r" would be read back as a line end
+            '\r' => out.push_str("&#13;"),
+            _ if !is_xml_char(c) => {}
+            _ => out.push(c),
+        }
+    }
+}
+
+// Values are always quoted with '"'. White spaces other than ' ' are written as character
+// references, so they survive the attribute-value normalization.
+fn escape_attribute(out: &mut String, value: &str) {
+    for c in value.chars() {
+        match c {
+            '&' => out.push_str("&amp;"),
+            '<' => out.push_str("&lt;"),
+            '"' => out.push_str("&quot;"),
+            '\t' => out.push_str("&#9;"),
+            '\n' => out.push_str("&#10;"),
+            '\r' => out.push_str("&#13;"),
+            _ if !is_xml_char(c) => {}
+            _ => out.push(c),
+        }
+    }
+}
+
+fn write_indent(out: &mut String, depth: Option<usize>) {
+    if let Some(depth) = depth {
+        for _ in 0..depth {
+            out.push_str("  ");
+        }
+    }
+}
+
+// Blank text between elements, usually indentation
+fn is_blank(c: &Component) -> bool {
+    matches!(c, Component::Text { value, is_cdata: false } if value.bytes().all(is_white_space))
+}
+
+// depth is None when not pretty-printing. Pretty-printing replaces the blank texts with its
+// own indentation, except where `xml:space="preserve"` is in scope, which isn't indented.
+fn write_component(out: &mut String, c: &Component, depth: Option<usize>, preserve: bool) {
+    match c {
+        Component::Comment(text) => {
+            out.push_str("<!--");
+            out.push_str(text);
+            out.push_str("-->");
+        }
+        Component::Declaration {
+            version,
+            encoding,
+            standalone,
+        } => {
+            out.push_str("<?xml version=\"");
+            escape_attribute(out, version);
+            out.push_str("\" encoding=\"");
+            escape_attribute(out, encoding);
+            out.push_str(if *standalone {
+                "\" standalone=\"yes\"?>"
+            } else {
+                "\" standalone=\"no\"?>"
+            });
+        }
+        Component::ProcessingInstruction { target, data } => {
+            out.push_str("<?");
+            out.push_str(target);
+
+            if !data.is_empty() {
+                out.push(' ');
+                out.push_str(data);
+            }
+
+            out.push_str("?>");
+        }
+        Component::Element {
+            name,
+            attributes,
+            children,
+            ..
+        } => {
+            out.push('<');
+            out.push_str(name);
+
+            // Namespace declarations first, then the other attributes in a stable order
+            let mut names = attributes.keys().collect::<Vec<_>>();
+            names.sort_by_key(|n| (!(*n == "xmlns" || n.starts_with("xmlns:")), *n));
+
+            for n in names {
+                out.push(' ');
+                out.push_str(n);
+                out.push_str("=\"");
+                escape_attribute(out, &attributes[n]);
+                out.push('"');
+            }
+
+            let preserve = match attributes.get("xml:space").map(String::as_str) {
+                Some("preserve") => true,
+                Some("default") => false,
+                _ => preserve,
+            };
+
+            let children = children
+                .iter()
+                .filter(|c| depth.is_none() || preserve || !is_blank(c))
+                .collect::<Vec<_>>();
+
+            if children.is_empty() {
+                out.push_str("/>");
+                return;
+            }
+
+            out.push('>');
+
+            let has_text = children.iter().any(|c| matches!(c, Component::Text { .. }));
+
+            let child_depth = depth.filter(|_| !has_text && !preserve).map(|d| d + 1);
+
+            for child in children {
+                if child_depth.is_some() {
+                    out.push('\n');
+                }
+
+                write_indent(out, child_depth);
+                write_component(out, child, child_depth, preserve);
+            }
+
+            if child_depth.is_some() {
+                out.push('\n');
+                write_indent(out, depth);
+            }
+
+            out.push_str("</");
+            out.push_str(name);
+            out.push('>');
+        }
+        Component::Text { value, is_cdata } => {
+            if *is_cdata {
+                // "]]>" can't be inside a CDATA section, it's split in two sections
+                out.push_str("<![CDATA[");
+                out.push_str(&value.replace("]]>", "]]]]><![CDATA[>"));
+                out.push_str("]]>");
+            } else {
+                escape_text(out, value);
+            }
+        }
+        Component::Other(text) => {
+            out.push_str("<!");
+            out.push_str(text);
+            out.push('>');
+        }
+    }
+}
+
+// -- Builder
+//
+// Builds elements without spelling out the Component fields:
+//
+//     let row = ElementBuilder::new("row")
+//         .attr("r", "1")
+//         .child(ElementBuilder::new("c").attr("r", "A1").text("42"))
+//         .build();
+pub struct ElementBuilder {
+    name: String,
+    namespace: Option<String>,
+    attributes: HashMap<String, String>,
+    children: Vec<Component>,
+}
+
+impl ElementBuilder {
+    pub fn new(name: &str) -> Self {
+        Self {
+            name: name.to_string(),
+            namespace: None,
+            attributes: HashMap::new(),
+            children: Vec::new(),
+        }
+    }
+
+    // Declares a namespace prefix ("" for the default namespace), the element itself takes
+    // it when its name uses that prefix
+    pub fn xmlns(mut self, prefix: &str, uri: &str) -> Self {
+        let attr_name = if prefix.is_empty() {
+            String::from("xmlns")
+        } else {
+            format!("xmlns:{prefix}")
+        };
+
+        if QName::split(&self.name).0 == prefix {
+            self.namespace = Some(uri.to_string());
+        }
+
+        self.attributes.insert(attr_name, uri.to_string());
+        self
+    }
+
+    // Sets the namespace uri of the element, without declaring it
+    pub fn namespace(mut self, uri: &str) -> Self {
+        self.namespace = Some(uri.to_string());
+        self
+    }
+
+    pub fn attr(mut self, name: &str, value: &str) -> Self {
+        self.attributes.insert(name.to_string(), value.to_string());
+        self
+    }
+
+    pub fn child(mut self, child: impl Into<Component>) -> Self {
+        self.children.push(child.into());
+        self
+    }
+
+    pub fn children<I, C>(mut self, children: I) -> Self
+    where
+        I: IntoIterator<Item = C>,
+        C: Into<Component>,
+    {
+        self.children.extend(children.into_iter().map(Into::into));
+        self
+    }
+
+    pub fn text(self, value: &str) -> Self {
+        self.child(Component::Text {
+            value: value.to_string(),
+            is_cdata: false,
+        })
+    }
+
+    // Prefixed attribute names resolve to the namespaces declared on this element
+    pub fn build(self) -> Component {
+        let attribute_namespaces = self
+            .attributes
+            .keys()
+            .filter_map(|name| {
+                let (prefix, _) = QName::split(name);
+                let uri = self.attributes.get(&format!("xmlns:{prefix}"))?;
+
+                Some((name.clone(), uri.clone()))
+            })
+            .collect();
+
+        Component::Element {
+            name: self.name,
+            namespace: self.namespace,
+            attributes: self.attributes,
+            attribute_namespaces,
+            children: self.children,
+        }
+    }
+}
+
+impl From<ElementBuilder> for Component {
+    fn from(builder: ElementBuilder) -> Self {
+        builder.build()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Short description of an event, to compare them easily
+    fn describe(event: &Event) -> String {
+        match event {
+            Event::Declaration { version, .. } => format!("?xml {version}"),
+            Event::StartElement(name) => format!("<{name} {:?}", name.namespace),
+            Event::Attribute { name, value } => format!("@{name}={value} {:?}", name.namespace),
+            Event::EndElement(name) => format!("</{name}"),
+            Event::Text { value, is_cdata } => format!("text {value:?} {is_cdata}"),
+            Event::Comment(text) => format!("comment {text:?}"),
+            Event::ProcessingInstruction { target, data } => format!("?{target} {data:?}"),
+            Event::Other(text) => format!("other {text:?}"),
+        }
+    }
+
+    #[test]
+    fn test_pull_parser() {
+        let data = br#"<?xml version="1.0" encoding="UTF-8"?>
+<sheet a="1 &amp; 2">
+  <!-- note -->
+  <row r="1"><c>x &lt; y</c><c/></row>
+  <![CDATA[<raw>]]>
+</sheet>"#;
+
+        let mut parser = PullParser::new(data);
+        let mut events = Vec::new();
+
+        while let Some(event) = parser.next() {
+            let event = event.unwrap();
+
+            if let Event::StartElement(QName {
+                local_name: "c", ..
+            }) = event
+            {
+                assert!(parser.path_ends_with(&["sheet", "row", "c"]));
+            }
+
+            match event {
+                Event::Text { value, .. } if value.trim().is_empty() => {}
+                _ => events.push(describe(&event)),
+            }
+        }
+
+        assert_eq!(
+            events,
+            [
+                "?xml 1.0",
+                "<sheet None",
+                "@a=1 & 2 None",
+                "comment \" note \"",
+                "<row None",
+                "@r=1 None",
+                "<c None",
+                "text \"x < y\" false",
+                "</c",
+                "<c None",
+                "</c",
+                "</row",
+                "text \"<raw>\" true",
+                "</sheet",
+            ]
+        );
+
+        let mut parser = PullParser::new(b"<a><b></a>");
+        assert!(parser.any(|e| matches!(e, Err(XMLError::Invalid { .. }))));
+
+        let mut parser = PullParser::new(b"<a><b>");
+        assert!(parser.any(|e| matches!(e, Err(XMLError::EndOfFile { .. }))));
+    }
+
+    #[test]
+    fn test_namespaces() {
+        let data = br#"<x:worksheet xmlns:x="urn:main" xmlns="urn:default" xmlns:r="urn:rel">
+  <x:row r:id="a" x:n="1"><c xmlns=""/></x:row>
+  <d xmlns:x="urn:other"><x:e/></d>
+</x:worksheet>"#;
+
+        let events = PullParser::new(data)
+            .map(|e| e.unwrap())
+            .filter(|e| !matches!(e, Event::Text { .. } | Event::EndElement(_)))
+            .map(|e| describe(&e))
+            .collect::<Vec<_>>();
+
+        assert_eq!(
+            events,
+            [
+                "<x:worksheet Some(\"urn:main\")",
+                "@xmlns:x=urn:main Some(\"http://www.w3.org/2000/xmlns/\")",
+                "@xmlns=urn:default Some(\"http://www.w3.org/2000/xmlns/\")",
+                "@xmlns:r=urn:rel Some(\"http://www.w3.org/2000/xmlns/\")",
+                "<x:row Some(\"urn:main\")",
+                "@r:id=a Some(\"urn:rel\")",
+                "@x:n=1 Some(\"urn:main\")",
+                "<c None",
+                "@xmlns= Some(\"http://www.w3.org/2000/xmlns/\")",
+                "<d Some(\"urn:default\")",
+                "@xmlns:x=urn:other Some(\"http://www.w3.org/2000/xmlns/\")",
+                "<x:e Some(\"urn:other\")",
+            ]
+        );
+
+        let doc = Document::from_data(data.to_vec()).unwrap();
+        let root = &doc.children[0];
+
+        assert!(root.is_element_ns("urn:main", "worksheet"));
+        assert_eq!(root.filter_elements_ns("urn:main", "row").count(), 1);
+        assert!(root.find_element_ns("urn:default", "row").is_none());
+        assert!(root.find_element_ns("urn:default", "d").is_some());
+    }
+
+    #[test]
+    fn test_attr_ns() {
+        let data = br#"<w xmlns:rel="urn:r"><s x:id="x1" rel:id="r1" id="1" xmlns:x="urn:x"/></w>"#;
+
+        let doc = Document::from_data(data.to_vec()).unwrap();
+        let s = doc.select_first("w/s").unwrap();
+
+        assert_eq!(s.attr_ns("urn:r", "id"), Some("r1"));
+        assert_eq!(s.attr_ns("urn:x", "id"), Some("x1"));
+        assert_eq!(s.attr_ns("urn:y", "id"), None);
+        assert_eq!(s.attr("id"), Some("1"));
+
+        let built = ElementBuilder::new("s")
+            .xmlns("r", "urn:r")
+            .attr("r:id", "r2")
+            .build();
+
+        assert_eq!(built.attr_ns("urn:r", "id"), Some("r2"));
+    }
+
+    #[test]
+    fn test_references() {
+        let data = b"<t a=\"x\ty\r\nz&#9;&#x41;\">Caf&#233; &#x2019;q&#x2019;&#10;a\r\nb&amp;</t>";
+
+        let doc = Document::from_data(data.to_vec()).unwrap();
+
+        let Component::Element {
+            attributes,
+            children,
+            ..
+        } = &doc.children[0]
+        else {
+            panic!("expecting an element");
+        };
+
+        assert_eq!(attributes["a"], "x y z\tA");
+        assert!(matches!(
+            &children[0],
+            Component::Text { value, .. } if value == "Caf\u{e9} \u{2019}q\u{2019}\na\nb&"
+        ));
+
+        for (data, line, column) in [
+            (&b"<t>\n  &nbsp;</t>"[..], 2, 3),
+            (b"<t>&#0;</t>", 1, 4),
+            (b"<t>&#xd800;</t>", 1, 4),
+            (b"<t a='1 & 2'/>", 1, 9),
+        ] {
+            match Document::from_data(data.to_vec()) {
+                Err(XMLError::Invalid { loc, .. }) => {
+                    assert_eq!((loc.line, loc.column), (line, column))
+                }
+                r => panic!("expecting an invalid entity error, got {:?}", r.err()),
+            }
+        }
+    }
+
+    #[test]
+    fn test_serialize() {
+        let root = ElementBuilder::new("x:sheet")
+            .xmlns("x", "urn:main")
+            .attr("name", "a \"b\" <c>\n")
+            .child(
+                ElementBuilder::new("x:row")
+                    .child(ElementBuilder::new("x:c").text("1 < 2 & ]]>"))
+                    .child(ElementBuilder::new("x:c").child(Component::Text {
+                        value: String::from("a]]>b"),
+                        is_cdata: true,
+                    })),
+            )
+            .child(Component::Comment(String::from(" end ")))
+            .build();
+
+        assert!(root.is_element_ns("urn:main", "sheet"));
+
+        let doc = Document::with_root(root);
+
+        let compact = String::from_utf8(doc.to_bytes(false)).unwrap();
+
+        assert_eq!(
+            compact,
+            "<?xml version=\"1.0\" encoding=\"UTF-8\" standalone=\"yes\"?>\n\
+             <x:sheet xmlns:x=\"urn:main\" name=\"a &quot;b&quot; &lt;c>&#10;\">\
+             <x:row><x:c>1 &lt; 2 &amp; ]]&gt;</x:c>\
+             <x:c><![CDATA[a]]]]><![CDATA[>b]]></x:c></x:row><!-- end --></x:sheet>"
+        );
+
+        let pretty = String::from_utf8(doc.to_bytes(true)).unwrap();
+
+        assert!(pretty.contains("\n  <x:row>\n    <x:c>1 &lt; 2 &amp; ]]&gt;</x:c>\n"));
+
+        // Parsing the output gives back the same document
+        for bytes in [compact.into_bytes(), pretty.into_bytes()] {
+            let parsed = Document::from_data(bytes).unwrap();
+
+            assert_eq!(
+                String::from_utf8(parsed.to_bytes(false)).unwrap(),
+                String::from_utf8(doc.to_bytes(false)).unwrap()
+            );
+        }
+
+        // Blank text is kept where `xml:space="preserve"` is in scope
+        let data = "<si><r xml:space=\"preserve\"><t> </t><t xml:space=\"default\"> </t></r>\
+                    <t> </t></si>";
+        let doc = Document::from_data(data.as_bytes().to_vec()).unwrap();
+
+        assert_eq!(
+            String::from_utf8(doc.to_bytes(false)).unwrap(),
+            "<si><r xml:space=\"preserve\"><t> </t><t xml:space=\"default\"/></r><t/></si>"
+        );
+        assert!(
+            String::from_utf8(doc.to_bytes(true))
+                .unwrap()
+                .contains("\n  <r xml:space=\"preserve\"><t> </t><t xml:space=\"default\"/></r>\n")
+        );
+    }
+
+    #[test]
+    fn test_processing_instructions() {
+        let data = br#"<?xml version="1.0"?>
+<?mso-application progid="Excel.Sheet"?>
+<?xml-stylesheet href="a.xsl"?>
+<a><?empty?></a>"#;
+
+        let events = PullParser::new(data)
+            .map(|e| describe(&e.unwrap()))
+            .filter(|e| e.starts_with('?'))
+            .collect::<Vec<_>>();
+
+        assert_eq!(
+            events,
+            [
+                "?xml 1.0",
+                "?mso-application \"progid=\\\"Excel.Sheet\\\"\"",
+                "?xml-stylesheet \"href=\\\"a.xsl\\\"\"",
+                "?empty \"\"",
+            ]
+        );
+
+        let doc = Document::from_data(data.to_vec()).unwrap();
+
+        assert_eq!(
+            String::from_utf8(doc.to_bytes(false)).unwrap(),
+            "<?xml version=\"1.0\" encoding=\"UTF-8\" standalone=\"yes\"?>\n\
+             <?mso-application progid=\"Excel.Sheet\"?>\n\
+             <?xml-stylesheet href=\"a.xsl\"?>\n\
+             <a><?empty?></a>"
+        );
+
+        assert!(Document::from_data(b"<a/><?XML x?>".to_vec()).is_err());
+    }
+
+    #[test]
+    fn test_select() {
+        let data = br#"<x:worksheet xmlns:x="urn:main">
+  <x:sheetData>
+    <x:row r="1"><x:c r="A1" t="s"><x:v>0</x:v></x:c><x:c><x:v>2</x:v></x:c></x:row>
+    <x:row r="2"><x:c r="A2"><x:is><x:t>in</x:t><x:t>line</x:t></x:is></x:c></x:row>
+  </x:sheetData>
+</x:worksheet>"#;
+
+        let doc = Document::from_data(data.to_vec()).unwrap();
+
+        let cells = doc.select("worksheet/sheetData/row/c[@r]");
+        assert_eq!(cells.len(), 2);
+        assert_eq!(cells[0].attr("r"), Some("A1"));
+        assert_eq!(cells[1].text(), "inline");
+
+        assert_eq!(doc.select("//c").len(), 3);
+        assert_eq!(doc.select("//c[@t='s']/v")[0].text(), "0");
+        assert_eq!(
+            doc.select("worksheet/sheetData/row[2]")[0].attr("r"),
+            Some("2")
+        );
+        assert_eq!(doc.select("x:worksheet/*/row/x:c[2]")[0].text(), "2");
+        assert_eq!(doc.select("worksheet//t").len(), 2);
+
+        let sheet_data = doc.select_first("worksheet/sheetData").unwrap();
+        assert_eq!(sheet_data.select("row/c/v").len(), 2);
+        assert_eq!(
+            sheet_data
+                .descendants()
+                .filter(|c| c.name().is_some())
+                .count(),
+            10
+        );
+
+        assert!(doc.select("worksheet/row").is_empty());
+        assert!(doc.select("worksheet[@r").is_empty());
+        assert_eq!(Component::Comment(String::new()).text(), "");
+    }
+
+    #[test]
+    fn test_encodings() {
+        let text = "<?xml version=\"1.0\" encoding=\"UTF-16\"?><a b=\"\u{e9}\">\u{2019}</a>";
+
+        let mut utf16 = vec![0xff, 0xfe];
+        utf16.extend(text.encode_utf16().flat_map(|u| u.to_le_bytes()));
+
+        let no_bom = utf16[2..].to_vec();
+
+        let mut utf8_bom = vec![0xef, 0xbb, 0xbf];
+        utf8_bom.extend_from_slice(text.as_bytes());
+
+        for data in [utf16, no_bom, utf8_bom] {
+            let doc = Document::from_data(data).unwrap();
+            let root = doc.root().unwrap();
+
+            assert_eq!(root.attr("b"), Some("\u{e9}"));
+            assert_eq!(root.text(), "\u{2019}");
+        }
+
+        let latin1 = b"<?xml version='1.0' encoding='ISO-8859-1'?><a>\xe9</a>".to_vec();
+        assert_eq!(
+            Document::from_data(latin1).unwrap().root().unwrap().text(),
+            "\u{e9}"
+        );
+
+        let unknown = b"<?xml version='1.0' encoding='EBCDIC'?><a/>".to_vec();
+        assert!(matches!(
+            Document::from_data(unknown),
+            Err(XMLError::Encoding(EncodingError::Unsupported(_)))
+        ));
+    }
+}
@@ -2,34 +2,124 @@
 
 use std::convert::TryInto;
 use std::fs;
+use std::io::Write;
+use std::time::{SystemTime, UNIX_EPOCH};
 
+pub mod crc32;
+pub mod deflate;
+pub mod gzip;
 pub mod inflate;
+pub mod zlib;
 // use inflate;
 
-struct Reader {
-    data: Vec<u8>,
+const LOCAL_FILE_HEADER_SIGNATURE: u32 = 0x04034b50;
+const DATA_DESCRIPTOR_SIGNATURE: u32 = 0x08074b50;
+const CENTRAL_DIRECTORY_HEADER_SIGNATURE: u32 = 0x02014b50;
+const END_OF_CENTRAL_DIRECTORY_SIGNATURE: u32 = 0x06054b50;
+const ZIP64_END_OF_CENTRAL_DIRECTORY_SIGNATURE: u32 = 0x06064b50;
+const ZIP64_END_OF_CENTRAL_DIRECTORY_LOCATOR_SIGNATURE: u32 = 0x07064b50;
+
+// Header id of the "ZIP64 extended information" extra field
+const ZIP64_EXTRA_FIELD_ID: u16 = 0x0001;
+
+// General purpose flag bit 3: crc32 and sizes are zero in the local header and the
+// correct values are written in a data descriptor right after the compressed data.
+const FLAG_DATA_DESCRIPTOR: u16 = 1 << 3;
+
+// General purpose flag bit 0: the entry is encrypted
+const FLAG_ENCRYPTED: u16 = 1;
+
+// General purpose flag bit 11: file name is encoded in UTF-8
+const FLAG_UTF8: u16 = 1 << 11;
+
+const METHOD_STORED: u16 = 0;
+const METHOD_DEFLATE: u16 = 8;
+
+// Version 2.0 is enough for deflate, ZIP64 needs 4.5
+const VERSION_DEFAULT: u16 = 20;
+const VERSION_ZIP64: u16 = 45;
+
+// Fixed part of the end of central directory record, it's followed by a comment of at
+// most 65535 bytes.
+const END_OF_CENTRAL_DIRECTORY_SIZE: usize = 22;
+const MAX_COMMENT_SIZE: usize = u16::MAX as usize;
+
+#[derive(Debug)]
+pub enum ZipError {
+    Io(std::io::Error),
+    NotAZip,
+    TruncatedCentralDirectory,
+    UnsupportedCompression { file_name: String, method: u16 },
+    EncryptedEntry { file_name: String },
+    BadOffset { what: &'static str, offset: u64 },
+    CorruptedEntry { file_name: String, what: String },
+    FileNotFound(String),
+    NotUtf8 { file_name: String },
+}
+
+impl std::fmt::Display for ZipError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ZipError::Io(e) => write!(f, "{e}"),
+            ZipError::NotAZip => {
+                write!(f, "not a zip file, end of central directory not found")
+            }
+            ZipError::TruncatedCentralDirectory => {
+                write!(f, "truncated central directory")
+            }
+            ZipError::UnsupportedCompression { file_name, method } => {
+                write!(f, "{file_name}: unsupported compression method {method}")
+            }
+            ZipError::EncryptedEntry { file_name } => {
+                write!(f, "{file_name}: encrypted entries are not supported")
+            }
+            ZipError::BadOffset { what, offset } => {
+                write!(f, "bad offset {offset} for {what}")
+            }
+            ZipError::CorruptedEntry { file_name, what } => {
+                write!(f, "{file_name}: {what}")
+            }
+            ZipError::FileNotFound(file_name) => {
+                write!(f, "{file_name}: no such file in archive")
+            }
+            ZipError::NotUtf8 { file_name } => {
+                write!(f, "{file_name}: content is not valid UTF-8")
+            }
+        }
+    }
+}
+
+impl From<std::io::Error> for ZipError {
+    fn from(e: std::io::Error) -> Self {
+        ZipError::Io(e)
+    }
+}
+
+struct Reader<'a> {
+    data: &'a [u8],
     cursor: usize,
 }
 
-impl Reader {
-    fn new(data: Vec<u8>) -> Self {
+impl<'a> Reader<'a> {
+    fn new(data: &'a [u8]) -> Self {
         Self { data, cursor: 0 }
     }
 
-    // Finds the first occurence of the prefix in the data, starting from the end until
-    // the cursor.
-    fn find_prefix_rev(&self, prefix: &[u8]) -> Option<usize> {
-        for i in (self.cursor..(self.data.len() - prefix.len())).rev() {
-            let mut found = true;
+    // Finds the first occurence of the prefix in the data that starts before `end`,
+    // searching backwards until the cursor.
+    fn find_prefix_rev(&self, prefix: &[u8], end: usize) -> Option<usize> {
+        if self.data.len() < prefix.len() {
+            return None;
+        }
 
-            for (j, v) in prefix.iter().enumerate() {
-                if *v != self.data[i + j] {
-                    found = false;
-                    break;
-                }
-            }
+        let last = (self.data.len() - prefix.len()).min(end.saturating_sub(1));
 
-            if found {
+        if last < self.cursor {
+            return None;
+        }
+
+        for i in (self.cursor..=last).rev() {
+            if self.data[i..].starts_with(prefix) {
                 return Some(i);
             }
         }
@@ -37,34 +127,41 @@ impl Reader {
         None
     }
 
-    fn read_u16(&mut self) -> Option<u16> {
-        let r: Result<[u8; 2], _> = self.data[self.cursor..(self.cursor + 2)].try_into();
+    // Moves to an offset read from the archive, None when it's past the end of the data
+    fn seek(&mut self, offset: u64) -> Option<()> {
+        self.cursor = usize::try_from(offset)
+            .ok()
+            .filter(|&offset| offset <= self.data.len())?;
+
+        Some(())
+    }
 
-        match r {
-            Ok(bytes) => {
-                self.cursor += 2;
+    fn read_bytes<const N: usize>(&mut self) -> Option<[u8; N]> {
+        let bytes: [u8; N] = self
+            .data
+            .get(self.cursor..self.cursor.checked_add(N)?)?
+            .try_into()
+            .ok()?;
 
-                Some(u16::from_le_bytes(bytes))
-            }
-            Err(_) => None,
-        }
+        self.cursor += N;
+
+        Some(bytes)
     }
 
-    fn read_u32(&mut self) -> Option<u32> {
-        let r: Result<[u8; 4], _> = self.data[self.cursor..(self.cursor + 4)].try_into();
+    fn read_u16(&mut self) -> Option<u16> {
+        self.read_bytes().map(u16::from_le_bytes)
+    }
 
-        match r {
-            Ok(bytes) => {
-                self.cursor += 4;
+    fn read_u32(&mut self) -> Option<u32> {
+        self.read_bytes().map(u32::from_le_bytes)
+    }
 
-                Some(u32::from_le_bytes(bytes))
-            }
-            Err(_) => None,
-        }
+    fn read_u64(&mut self) -> Option<u64> {
+        self.read_bytes().map(u64::from_le_bytes)
     }
 
     fn read_string(&mut self, size: usize) -> Option<String> {
-        let v = str::from_utf8(self.data[self.cursor..(self.cursor + size)].into());
+        let v = str::from_utf8(self.data.get(self.cursor..self.cursor.checked_add(size)?)?);
 
         self.cursor += size;
 
@@ -72,11 +169,10 @@ impl Reader {
     }
 
     fn read_vec(&mut self, size: usize) -> Option<Vec<u8>> {
-        if self.cursor + size >= self.data.len() {
-            return None;
-        }
-
-        let v: Vec<u8> = self.data[self.cursor..(self.cursor + size)].to_owned();
+        let v: Vec<u8> = self
+            .data
+            .get(self.cursor..self.cursor.checked_add(size)?)?
+            .to_owned();
 
         self.cursor += size;
 
@@ -84,6 +180,98 @@ impl Reader {
     }
 }
 
+// Values read from a ZIP64 extended information extra field. The field only contains the
+// values whose 32-bit counterparts in the header are set to 0xFFFFFFFF, always in this
+// order:
+//   8  Uncompressed size
+//   8  Compressed size
+//   8  Relative offset of local file header (central directory only)
+//   4  Disk number where file starts (central directory only)
+#[derive(Debug, Default)]
+struct Zip64ExtraField {
+    uncompressed_size: Option<u64>,
+    compressed_size: Option<u64>,
+    offset: Option<u64>,
+}
+
+impl Zip64ExtraField {
+    // Looks for the ZIP64 block among the extra fields. `need_*` tell which values were
+    // saturated in the header, since only those are present in the block.
+    fn from_extra(
+        extra: &[u8],
+        need_uncompressed_size: bool,
+        need_compressed_size: bool,
+        need_offset: bool,
+    ) -> Option<Self> {
+        let mut reader = Reader::new(extra);
+
+        while reader.cursor + 4 <= extra.len() {
+            let id = reader.read_u16()?;
+            let size = reader.read_u16()? as usize;
+
+            if id != ZIP64_EXTRA_FIELD_ID {
+                reader.cursor += size;
+                continue;
+            }
+
+            let block = reader.read_vec(size)?;
+            let mut block_reader = Reader::new(&block);
+
+            let mut field = Self::default();
+
+            if need_uncompressed_size {
+                field.uncompressed_size = block_reader.read_u64();
+            }
+
+            if need_compressed_size {
+                field.compressed_size = block_reader.read_u64();
+            }
+
+            if need_offset {
+                field.offset = block_reader.read_u64();
+            }
+
+            return Some(field);
+        }
+
+        None
+    }
+}
+
+#[derive(Debug)]
+struct DataDescriptor {
+    // size
+    // 4  Optional data descriptor signature. 50 4B 07 08.
+    crc32: u32,             // 4  CRC-32 of uncompressed data.
+    compressed_size: u64,   // 4  Compressed size (8 in ZIP64).
+    uncompressed_size: u64, // 4  Uncompressed size (8 in ZIP64).
+}
+
+impl DataDescriptor {
+    fn from_reader(reader: &mut Reader, zip64: bool) -> Option<Self> {
+        let saved_cursor = reader.cursor;
+
+        if reader.read_u32()? != DATA_DESCRIPTOR_SIGNATURE {
+            // The signature is optional
+            reader.cursor = saved_cursor;
+        }
+
+        let crc32 = reader.read_u32()?;
+
+        let (compressed_size, uncompressed_size) = if zip64 {
+            (reader.read_u64()?, reader.read_u64()?)
+        } else {
+            (reader.read_u32()? as u64, reader.read_u32()? as u64)
+        };
+
+        Some(Self {
+            crc32,
+            compressed_size,
+            uncompressed_size,
+        })
+    }
+}
+
 #[derive(Debug)]
 struct LocalFileHeader {
     // size
@@ -94,8 +282,8 @@ struct LocalFileHeader {
     // 2  File last modification time.
     // 2  File last modification date.
     crc32: u32,             // 4  CRC-32 of uncompressed data.
-    compressed_size: u32,   // 4  Compressed size
-    uncompressed_size: u32, // 4  Uncompressed size
+    compressed_size: u64,   // 4  Compressed size (0xFFFFFFFF when in the ZIP64 extra field)
+    uncompressed_size: u64, // 4  Uncompressed size (0xFFFFFFFF when in the ZIP64 extra field)
     // 2  File name length (n).
     // 2  Extra field length (m).
     // n  File name
@@ -104,44 +292,109 @@ struct LocalFileHeader {
 }
 
 impl LocalFileHeader {
-    fn from_reader(reader: &mut Reader, cdh: &CentralDirectoryHeader) -> Option<Self> {
-        let signature = reader.read_u32()?;
+    fn from_reader(reader: &mut Reader, cdh: &CentralDirectoryHeader) -> Result<Self, ZipError> {
+        let bad_offset = || ZipError::BadOffset {
+            what: "local file header",
+            offset: cdh.offset,
+        };
 
-        if signature != 0x04034b50 {
-            return None;
+        let truncated = || ZipError::CorruptedEntry {
+            file_name: cdh.file_name.clone(),
+            what: String::from("entry data is truncated"),
+        };
+
+        let signature = reader.read_u32().ok_or_else(bad_offset)?;
+
+        if signature != LOCAL_FILE_HEADER_SIGNATURE {
+            return Err(bad_offset());
         }
 
-        let min_version = reader.read_u16()?;
-        let flag = reader.read_u16()?;
-        let compression = reader.read_u16()?;
+        let min_version = reader.read_u16().ok_or_else(truncated)?;
+        let flag = reader.read_u16().ok_or_else(truncated)?;
+        let compression = reader.read_u16().ok_or_else(truncated)?;
+
+        if flag & FLAG_ENCRYPTED != 0 || cdh.flag & FLAG_ENCRYPTED != 0 {
+            return Err(ZipError::EncryptedEntry {
+                file_name: cdh.file_name.clone(),
+            });
+        }
+
+        if compression != METHOD_STORED && compression != METHOD_DEFLATE {
+            return Err(ZipError::UnsupportedCompression {
+                file_name: cdh.file_name.clone(),
+                method: compression,
+            });
+        }
 
         reader.cursor += 4;
 
-        // TODO: Test if data's CRC-32 matches
-        let crc32 = reader.read_u32()?;
-        let compressed_size = reader.read_u32()?;
-        let uncompressed_size = reader.read_u32()?;
+        let mut crc32 = reader.read_u32().ok_or_else(truncated)?;
+        let mut compressed_size = reader.read_u32().ok_or_else(truncated)? as u64;
+        let mut uncompressed_size = reader.read_u32().ok_or_else(truncated)? as u64;
 
-        let file_name_len = reader.read_u16()?;
-        let extra_len = reader.read_u16()?;
+        let file_name_len = reader.read_u16().ok_or_else(truncated)?;
+        let extra_len = reader.read_u16().ok_or_else(truncated)?;
 
         // let file_name = reader.read_string(file_name_len as usize).unwrap();
 
         reader.cursor += file_name_len as usize;
-        reader.cursor += extra_len as usize;
 
-        // TODO: For some reason the compressed_size, uncompressed_size and crc32
-        // can be zero, but they are not zero in CentralDirectoryHeader
-        let data = if compression != 8 {
-            reader.read_vec(cdh.compressed_size as usize)?
+        let extra = reader.read_vec(extra_len as usize).ok_or_else(truncated)?;
+
+        // A local ZIP64 extra field always carries both sizes
+        let zip64 = Zip64ExtraField::from_extra(&extra, true, true, false);
+
+        if let Some(z64) = &zip64 {
+            compressed_size = z64.compressed_size.unwrap_or(compressed_size);
+            uncompressed_size = z64.uncompressed_size.unwrap_or(uncompressed_size);
+        }
+
+        // When the entry was written in streaming mode the sizes and crc32 are zero here,
+        // so the sizes from the central directory are the ones to trust.
+        let content = reader
+            .read_vec(cdh.compressed_size as usize)
+            .ok_or_else(truncated)?;
+
+        if flag & FLAG_DATA_DESCRIPTOR != 0 {
+            let is_zip64 = zip64.is_some()
+                || cdh.compressed_size > u32::MAX as u64
+                || cdh.uncompressed_size > u32::MAX as u64;
+
+            let dd = DataDescriptor::from_reader(reader, is_zip64).ok_or_else(truncated)?;
+
+            if dd.compressed_size != cdh.compressed_size
+                || dd.uncompressed_size != cdh.uncompressed_size
+            {
+                return Err(ZipError::CorruptedEntry {
+                    file_name: cdh.file_name.clone(),
+                    what: String::from("data descriptor does not match the central directory"),
+                });
+            }
+
+            crc32 = dd.crc32;
+            compressed_size = dd.compressed_size;
+            uncompressed_size = dd.uncompressed_size;
+        }
+
+        let data = if compression == METHOD_STORED {
+            content
         } else if cdh.uncompressed_size == 0 {
             Vec::new()
         } else {
-            let content = reader.read_vec(cdh.compressed_size as usize)?;
-            inflate::decompress(&content).unwrap()
+            inflate::decompress(&content).map_err(|what| ZipError::CorruptedEntry {
+                file_name: cdh.file_name.clone(),
+                what,
+            })?
         };
 
-        Some(Self {
+        if crc32::crc32(&data) != cdh.crc32 {
+            return Err(ZipError::CorruptedEntry {
+                file_name: cdh.file_name.clone(),
+                what: String::from("crc-32 does not match"),
+            });
+        }
+
+        Ok(Self {
             min_version,
             flag,
             compression,
@@ -164,15 +417,15 @@ struct CentralDirectoryHeader {
     // 12      2   File last modification time.
     // 14      2   File last modification date.
     crc32: u32,             // 16      4   CRC-32 of uncompressed data.
-    compressed_size: u32,   // 20      4   Compressed size
-    uncompressed_size: u32, // 24      4   Uncompressed size
+    compressed_size: u64,   // 20      4   Compressed size (0xFFFFFFFF when in ZIP64 extra)
+    uncompressed_size: u64, // 24      4   Uncompressed size (0xFFFFFFFF when in ZIP64 extra)
     // 28      2   File name length (n).
     // 30      2   Extra field length (m).
     // 32      2   File comment length (k).
     // 34      2   Disk number where file starts
     // 36      2   Internal file attributes.
     // 38      4   External file attributes.
-    offset: u32, // 42      4   Relative offset of local file header.
+    offset: u64, // 42      4   Relative offset of local file header (0xFFFFFFFF when in ZIP64 extra).
     file_name: String, // 46      n   File name.
                  // 46+n    m   Extra field.
                  // 46+n+m  k   File comment.
@@ -182,7 +435,7 @@ impl CentralDirectoryHeader {
     fn from_reader(reader: &mut Reader) -> Option<Self> {
         let signature = reader.read_u32()?;
 
-        if signature != 0x02014b50 {
+        if signature != CENTRAL_DIRECTORY_HEADER_SIGNATURE {
             return None;
         }
 
@@ -207,7 +460,17 @@ impl CentralDirectoryHeader {
 
         let file_name = reader.read_string(file_name_len as usize)?;
 
-        reader.cursor += (extra_len + comment_len) as usize;
+        let extra = reader.read_vec(extra_len as usize)?;
+
+        reader.cursor += comment_len as usize;
+
+        let zip64 = Zip64ExtraField::from_extra(
+            &extra,
+            uncompressed_size == u32::MAX,
+            compressed_size == u32::MAX,
+            offset == u32::MAX,
+        )
+        .unwrap_or_default();
 
         Some(Self {
             version,
@@ -215,9 +478,9 @@ impl CentralDirectoryHeader {
             flag,
             compression,
             crc32,
-            compressed_size,
-            uncompressed_size,
-            offset,
+            compressed_size: zip64.compressed_size.unwrap_or(compressed_size as u64),
+            uncompressed_size: zip64.uncompressed_size.unwrap_or(uncompressed_size as u64),
+            offset: zip64.offset.unwrap_or(offset as u64),
             file_name,
         })
     }
@@ -240,7 +503,7 @@ impl EndOfCentralDirectoryHeader {
     fn from_reader(reader: &mut Reader) -> Option<Self> {
         let signature = reader.read_u32()?;
 
-        if signature != 0x06054b50 {
+        if signature != END_OF_CENTRAL_DIRECTORY_SIGNATURE {
             return None;
         }
 
@@ -265,10 +528,83 @@ impl EndOfCentralDirectoryHeader {
     }
 }
 
+#[derive(Debug)]
+struct Zip64EndOfCentralDirectoryLocator {
+    // off  size
+    // 0       4   ZIP64 end of central directory locator signature. Must be 50 4B 06 07.
+    disk_number: u32, // 4       4   Disk number where the ZIP64 EOCD record starts
+    offset: u64,      // 8       8   Offset of the ZIP64 EOCD record
+    total_disks: u32, // 16      4   Total number of disks
+}
+
+impl Zip64EndOfCentralDirectoryLocator {
+    // The locator size, it is placed immediately before the end of central directory
+    const SIZE: usize = 20;
+
+    fn from_reader(reader: &mut Reader) -> Option<Self> {
+        let signature = reader.read_u32()?;
+
+        if signature != ZIP64_END_OF_CENTRAL_DIRECTORY_LOCATOR_SIGNATURE {
+            return None;
+        }
+
+        let disk_number = reader.read_u32()?;
+        let offset = reader.read_u64()?;
+        let total_disks = reader.read_u32()?;
+
+        Some(Self {
+            disk_number,
+            offset,
+            total_disks,
+        })
+    }
+}
+
+#[derive(Debug)]
+struct Zip64EndOfCentralDirectoryRecord {
+    // off  size
+    // 0       4   ZIP64 end of central directory signature. Must be 50 4B 06 06.
+    // 4       8   Size of the remaining record
+    version: u16,                         // 12      2   Version made by
+    min_version: u16,                     // 14      2   Version needed to extract (minimum)
+    disk_number: u32,                     // 16      4   Number of this disk
+    disk_number_start: u32,               // 20      4   Disk where central directory starts
+    num_central_directories_on_disk: u64, // 24      8   Number of central directory records on this disk
+    total_num_central_directories: u64,   // 32      8   Total number of central directory records
+    central_directory_size: u64,          // 40      8   Size of central directory (bytes)
+    offset_to_start_of_central_directory: u64, // 48      8   Offset to start of central directory
+                                          // 56      n   Extensible data sector
+}
+
+impl Zip64EndOfCentralDirectoryRecord {
+    fn from_reader(reader: &mut Reader) -> Option<Self> {
+        let signature = reader.read_u32()?;
+
+        if signature != ZIP64_END_OF_CENTRAL_DIRECTORY_SIGNATURE {
+            return None;
+        }
+
+        let _record_size = reader.read_u64()?;
+
+        Some(Self {
+            version: reader.read_u16()?,
+            min_version: reader.read_u16()?,
+            disk_number: reader.read_u32()?,
+            disk_number_start: reader.read_u32()?,
+            num_central_directories_on_disk: reader.read_u64()?,
+            total_num_central_directories: reader.read_u64()?,
+            central_directory_size: reader.read_u64()?,
+            offset_to_start_of_central_directory: reader.read_u64()?,
+        })
+    }
+}
+
+// Only the central directory is parsed when opening, entries are decompressed on demand.
 pub struct Zip {
+    data: Vec<u8>,
     eocd: EndOfCentralDirectoryHeader,
+    zip64_eocd: Option<Zip64EndOfCentralDirectoryRecord>,
     central_directory_headers: Vec<CentralDirectoryHeader>,
-    local_file_headers: Vec<LocalFileHeader>,
 }
 
 #[derive(Debug)]
@@ -278,62 +614,476 @@ pub struct ZipFile {
 }
 
 impl Zip {
-    pub fn from_file(filepath: &str) -> Option<Zip> {
-        let data = fs::read(filepath).ok()?;
+    pub fn from_file(filepath: &str) -> Result<Zip, ZipError> {
+        let data = fs::read(filepath)?;
+
+        Self::from_data(data)
+    }
+
+    // The end of central directory record can only start in the last 22 + 65535 bytes.
+    // Its signature may also appear inside the archive comment, so a candidate is only
+    // accepted when its comment length reaches exactly the end of the data.
+    fn find_end_of_central_directory(reader: &mut Reader) -> Option<usize> {
+        let len = reader.data.len();
+
+        reader.cursor = len.saturating_sub(END_OF_CENTRAL_DIRECTORY_SIZE + MAX_COMMENT_SIZE);
+
+        let window_start = reader.cursor;
+        let mut end = len;
+
+        while let Some(pidx) = reader.find_prefix_rev(&[0x50, 0x4b, 0x05, 0x06], end) {
+            reader.cursor = pidx + END_OF_CENTRAL_DIRECTORY_SIZE - 2;
+
+            if let Some(comment_len) = reader.read_u16()
+                && pidx + END_OF_CENTRAL_DIRECTORY_SIZE + comment_len as usize == len
+            {
+                return Some(pidx);
+            }
+
+            reader.cursor = window_start;
+            end = pidx;
+        }
 
-        let mut reader = Reader::new(data);
+        None
+    }
 
-        let pidx = reader.find_prefix_rev(&[0x50, 0x4b, 0x05, 0x06])?;
+    pub fn from_data(data: Vec<u8>) -> Result<Zip, ZipError> {
+        let mut reader = Reader::new(&data);
+
+        let pidx = Self::find_end_of_central_directory(&mut reader).ok_or(ZipError::NotAZip)?;
 
         reader.cursor = pidx;
 
-        let eocd = EndOfCentralDirectoryHeader::from_reader(&mut reader)?;
+        let eocd =
+            EndOfCentralDirectoryHeader::from_reader(&mut reader).ok_or(ZipError::NotAZip)?;
 
-        reader.cursor = eocd.offset_to_start_of_central_directory as usize;
+        // A ZIP64 archive has its real counts and offsets in the ZIP64 end of central
+        // directory record, pointed to by a locator right before the regular one.
+        let zip64_eocd = if pidx >= Zip64EndOfCentralDirectoryLocator::SIZE {
+            reader.cursor = pidx - Zip64EndOfCentralDirectoryLocator::SIZE;
 
-        let mut central_directory_headers = Vec::new();
-        let mut local_file_headers = Vec::new();
+            match Zip64EndOfCentralDirectoryLocator::from_reader(&mut reader) {
+                Some(locator) => {
+                    let bad_offset = || ZipError::BadOffset {
+                        what: "zip64 end of central directory",
+                        offset: locator.offset,
+                    };
+
+                    reader.seek(locator.offset).ok_or_else(bad_offset)?;
+
+                    let z64 = Zip64EndOfCentralDirectoryRecord::from_reader(&mut reader)
+                        .ok_or_else(bad_offset)?;
 
-        for _ in 0..eocd.total_num_central_directories {
-            let cdh = CentralDirectoryHeader::from_reader(&mut reader)?;
+                    Some(z64)
+                }
+                None => None,
+            }
+        } else {
+            None
+        };
+
+        let (
+            total_num_central_directories,
+            central_directory_size,
+            offset_to_start_of_central_directory,
+        ) = match &zip64_eocd {
+            Some(z64) => (
+                z64.total_num_central_directories,
+                z64.central_directory_size,
+                z64.offset_to_start_of_central_directory,
+            ),
+            None => (
+                eocd.total_num_central_directories as u64,
+                eocd.central_directory_size as u64,
+                eocd.offset_to_start_of_central_directory as u64,
+            ),
+        };
 
-            let saved_cursor = reader.cursor;
+        if offset_to_start_of_central_directory > pidx as u64 {
+            return Err(ZipError::BadOffset {
+                what: "central directory",
+                offset: offset_to_start_of_central_directory,
+            });
+        }
 
-            reader.cursor = cdh.offset as usize;
-            let lfh = LocalFileHeader::from_reader(&mut reader, &cdh)?;
+        if offset_to_start_of_central_directory.saturating_add(central_directory_size) > pidx as u64
+        {
+            return Err(ZipError::TruncatedCentralDirectory);
+        }
 
-            reader.cursor = saved_cursor;
+        reader
+            .seek(offset_to_start_of_central_directory)
+            .ok_or(ZipError::BadOffset {
+                what: "central directory",
+                offset: offset_to_start_of_central_directory,
+            })?;
+
+        let mut central_directory_headers = Vec::new();
+
+        for _ in 0..total_num_central_directories {
+            let cdh = CentralDirectoryHeader::from_reader(&mut reader)
+                .ok_or(ZipError::TruncatedCentralDirectory)?;
 
             central_directory_headers.push(cdh);
-            local_file_headers.push(lfh);
         }
 
-        Some(Zip {
+        Ok(Zip {
+            data,
             eocd,
+            zip64_eocd,
             central_directory_headers,
-            local_file_headers,
         })
     }
 
-    pub fn extract_files(&self) -> Result<Vec<ZipFile>, std::str::Utf8Error> {
+    // Names of all entries, in central directory order. Directories end with '/'.
+    pub fn file_names(&self) -> Vec<&str> {
+        self.central_directory_headers
+            .iter()
+            .map(|cdh| cdh.file_name.as_str())
+            .collect()
+    }
+
+    fn read_local_file(&self, cdh: &CentralDirectoryHeader) -> Result<LocalFileHeader, ZipError> {
+        let mut reader = Reader::new(&self.data);
+
+        reader.seek(cdh.offset).ok_or(ZipError::BadOffset {
+            what: "local file header",
+            offset: cdh.offset,
+        })?;
+
+        LocalFileHeader::from_reader(&mut reader, cdh)
+    }
+
+    // Decompresses a single entry
+    pub fn read_file(&self, name: &str) -> Result<Vec<u8>, ZipError> {
+        let cdh = self
+            .central_directory_headers
+            .iter()
+            .find(|cdh| cdh.file_name == name)
+            .ok_or_else(|| ZipError::FileNotFound(name.to_string()))?;
+
+        Ok(self.read_local_file(cdh)?.data)
+    }
+
+    pub fn extract_files(&self) -> Result<Vec<ZipFile>, ZipError> {
         let mut files = Vec::new();
 
-        for i in 0..self.central_directory_headers.len() {
-            let name = self.central_directory_headers[i].file_name.clone();
+        for cdh in self.central_directory_headers.iter() {
+            let name = cdh.file_name.clone();
 
-            let lfh = &self.local_file_headers[i];
-            let content = str::from_utf8(&lfh.data[..])?;
+            let lfh = self.read_local_file(cdh)?;
+            let content = String::from_utf8(lfh.data).map_err(|_| ZipError::NotUtf8 {
+                file_name: name.clone(),
+            })?;
 
-            files.push(ZipFile {
-                name,
-                content: content.to_string(),
-            });
+            files.push(ZipFile { name, content });
         }
 
         Ok(files)
     }
 }
 
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Compression {
+    Stored,
+    Deflate,
+}
+
+// Date and time in MS-DOS format, as stored in zip headers. It has a 2 seconds resolution
+// and can't represent dates before 1980.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct DosDateTime {
+    pub date: u16,
+    pub time: u16,
+}
+
+impl DosDateTime {
+    pub fn new(year: u16, month: u16, day: u16, hour: u16, minute: u16, second: u16) -> Self {
+        if year < 1980 {
+            return Self::new(1980, 1, 1, 0, 0, 0);
+        }
+
+        Self {
+            date: ((year - 1980) << 9) | (month << 5) | day,
+            time: (hour << 11) | (minute << 5) | (second / 2),
+        }
+    }
+
+    // Converts to the UTC civil date, see http://howardhinnant.github.io/date_algorithms.html
+    pub fn from_system_time(t: SystemTime) -> Self {
+        let secs = t
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_secs())
+            .unwrap_or(0);
+
+        let days = secs / 86400;
+        let rem = secs % 86400;
+
+        let z = days + 719468;
+        let era = z / 146097;
+        let doe = z - era * 146097;
+        let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+        let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+        let mp = (5 * doy + 2) / 153;
+        let day = doy - (153 * mp + 2) / 5 + 1;
+        let month = if mp < 10 { mp + 3 } else { mp - 9 };
+        let year = yoe + era * 400 + if month <= 2 { 1 } else { 0 };
+
+        Self::new(
+            year.min(2107) as u16,
+            month as u16,
+            day as u16,
+            (rem / 3600) as u16,
+            (rem % 3600 / 60) as u16,
+            (rem % 60) as u16,
+        )
+    }
+}
+
+struct WrittenEntry {
+    name: String,
+    flag: u16,
+    compression: u16,
+    modified: DosDateTime,
+    crc32: u32,
+    compressed_size: u64,
+    uncompressed_size: u64,
+    offset: u64,
+}
+
+impl WrittenEntry {
+    fn needs_zip64(&self) -> bool {
+        self.compressed_size >= u32::MAX as u64
+            || self.uncompressed_size >= u32::MAX as u64
+            || self.offset >= u32::MAX as u64
+    }
+}
+
+// Values that don't fit in the 32-bit header fields are replaced by 0xFFFFFFFF and moved
+// to the ZIP64 extra field.
+fn saturate_u32(v: u64) -> u32 {
+    if v >= u32::MAX as u64 {
+        u32::MAX
+    } else {
+        v as u32
+    }
+}
+
+fn put_u16(buf: &mut Vec<u8>, v: u16) {
+    buf.extend_from_slice(&v.to_le_bytes());
+}
+
+fn put_u32(buf: &mut Vec<u8>, v: u32) {
+    buf.extend_from_slice(&v.to_le_bytes());
+}
+
+fn put_u64(buf: &mut Vec<u8>, v: u64) {
+    buf.extend_from_slice(&v.to_le_bytes());
+}
+
+// Writes a zip archive. Each file is compressed in memory before being written, so the
+// local headers always have the right sizes and no data descriptors are needed.
+pub struct ZipWriter<W: Write> {
+    writer: W,
+    offset: u64,
+    entries: Vec<WrittenEntry>,
+    modified: DosDateTime,
+}
+
+impl<W: Write> ZipWriter<W> {
+    pub fn new(writer: W) -> Self {
+        Self {
+            writer,
+            offset: 0,
+            entries: Vec::new(),
+            modified: DosDateTime::from_system_time(SystemTime::now()),
+        }
+    }
+
+    // Modification time used for the files added from now on
+    pub fn set_modified(&mut self, modified: DosDateTime) {
+        self.modified = modified;
+    }
+
+    fn write(&mut self, data: &[u8]) -> Result<(), ZipError> {
+        self.writer.write_all(data)?;
+        self.offset += data.len() as u64;
+
+        Ok(())
+    }
+
+    pub fn add_file(
+        &mut self,
+        name: &str,
+        data: &[u8],
+        compression: Compression,
+    ) -> Result<(), ZipError> {
+        let (method, content) = match compression {
+            Compression::Stored => (METHOD_STORED, None),
+            Compression::Deflate => (METHOD_DEFLATE, Some(deflate::compress(data))),
+        };
+
+        let content = content.as_deref().unwrap_or(data);
+
+        let entry = WrittenEntry {
+            name: name.to_string(),
+            flag: if name.is_ascii() { 0 } else { FLAG_UTF8 },
+            compression: method,
+            modified: self.modified,
+            crc32: crc32::crc32(data),
+            compressed_size: content.len() as u64,
+            uncompressed_size: data.len() as u64,
+            offset: self.offset,
+        };
+
+        let zip64 =
+            entry.compressed_size >= u32::MAX as u64 || entry.uncompressed_size >= u32::MAX as u64;
+
+        let mut extra = Vec::new();
+
+        if zip64 {
+            put_u16(&mut extra, ZIP64_EXTRA_FIELD_ID);
+            put_u16(&mut extra, 16);
+            put_u64(&mut extra, entry.uncompressed_size);
+            put_u64(&mut extra, entry.compressed_size);
+        }
+
+        let mut header = Vec::new();
+
+        put_u32(&mut header, LOCAL_FILE_HEADER_SIGNATURE);
+        put_u16(
+            &mut header,
+            if zip64 {
+                VERSION_ZIP64
+            } else {
+                VERSION_DEFAULT
+            },
+        );
+        put_u16(&mut header, entry.flag);
+        put_u16(&mut header, entry.compression);
+        put_u16(&mut header, entry.modified.time);
+        put_u16(&mut header, entry.modified.date);
+        put_u32(&mut header, entry.crc32);
+        put_u32(&mut header, saturate_u32(entry.compressed_size));
+        put_u32(&mut header, saturate_u32(entry.uncompressed_size));
+        put_u16(&mut header, name.len() as u16);
+        put_u16(&mut header, extra.len() as u16);
+        header.extend_from_slice(name.as_bytes());
+        header.extend_from_slice(&extra);
+
+        self.write(&header)?;
+        self.write(content)?;
+
+        self.entries.push(entry);
+
+        Ok(())
+    }
+
+    // Writes the central directory and the end of central directory, returning the
+    // underlying writer.
+    pub fn finish(mut self) -> Result<W, ZipError> {
+        let central_directory_offset = self.offset;
+
+        let mut cd = Vec::new();
+
+        for entry in self.entries.iter() {
+            let mut extra = Vec::new();
+
+            if entry.needs_zip64() {
+                let mut values = Vec::new();
+
+                if entry.uncompressed_size >= u32::MAX as u64 {
+                    put_u64(&mut values, entry.uncompressed_size);
+                }
+
+                if entry.compressed_size >= u32::MAX as u64 {
+                    put_u64(&mut values, entry.compressed_size);
+                }
+
+                if entry.offset >= u32::MAX as u64 {
+                    put_u64(&mut values, entry.offset);
+                }
+
+                put_u16(&mut extra, ZIP64_EXTRA_FIELD_ID);
+                put_u16(&mut extra, values.len() as u16);
+                extra.extend_from_slice(&values);
+            }
+
+            let version = if entry.needs_zip64() {
+                VERSION_ZIP64
+            } else {
+                VERSION_DEFAULT
+            };
+
+            put_u32(&mut cd, CENTRAL_DIRECTORY_HEADER_SIGNATURE);
+            put_u16(&mut cd, VERSION_ZIP64);
+            put_u16(&mut cd, version);
+            put_u16(&mut cd, entry.flag);
+            put_u16(&mut cd, entry.compression);
+            put_u16(&mut cd, entry.modified.time);
+            put_u16(&mut cd, entry.modified.date);
+            put_u32(&mut cd, entry.crc32);
+            put_u32(&mut cd, saturate_u32(entry.compressed_size));
+            put_u32(&mut cd, saturate_u32(entry.uncompressed_size));
+            put_u16(&mut cd, entry.name.len() as u16);
+            put_u16(&mut cd, extra.len() as u16);
+            put_u16(&mut cd, 0); // comment length
+            put_u16(&mut cd, 0); // disk number start
+            put_u16(&mut cd, 0); // internal attributes
+            put_u32(&mut cd, 0); // external attributes
+            put_u32(&mut cd, saturate_u32(entry.offset));
+            cd.extend_from_slice(entry.name.as_bytes());
+            cd.extend_from_slice(&extra);
+        }
+
+        self.write(&cd)?;
+
+        let num_entries = self.entries.len() as u64;
+        let central_directory_size = cd.len() as u64;
+
+        let mut eocd = Vec::new();
+
+        if num_entries >= u16::MAX as u64
+            || central_directory_size >= u32::MAX as u64
+            || central_directory_offset >= u32::MAX as u64
+        {
+            let zip64_eocd_offset = self.offset;
+
+            put_u32(&mut eocd, ZIP64_END_OF_CENTRAL_DIRECTORY_SIGNATURE);
+            put_u64(&mut eocd, 44);
+            put_u16(&mut eocd, VERSION_ZIP64);
+            put_u16(&mut eocd, VERSION_ZIP64);
+            put_u32(&mut eocd, 0);
+            put_u32(&mut eocd, 0);
+            put_u64(&mut eocd, num_entries);
+            put_u64(&mut eocd, num_entries);
+            put_u64(&mut eocd, central_directory_size);
+            put_u64(&mut eocd, central_directory_offset);
+
+            put_u32(&mut eocd, ZIP64_END_OF_CENTRAL_DIRECTORY_LOCATOR_SIGNATURE);
+            put_u32(&mut eocd, 0);
+            put_u64(&mut eocd, zip64_eocd_offset);
+            put_u32(&mut eocd, 1);
+        }
+
+        let num_entries = num_entries.min(u16::MAX as u64) as u16;
+
+        put_u32(&mut eocd, END_OF_CENTRAL_DIRECTORY_SIGNATURE);
+        put_u16(&mut eocd, 0);
+        put_u16(&mut eocd, 0);
+        put_u16(&mut eocd, num_entries);
+        put_u16(&mut eocd, num_entries);
+        put_u32(&mut eocd, saturate_u32(central_directory_size));
+        put_u32(&mut eocd, saturate_u32(central_directory_offset));
+        put_u16(&mut eocd, 0); // comment length
+
+        self.write(&eocd)?;
+        self.writer.flush()?;
+
+        Ok(self.writer)
+    }
+}
+
 /*
 fn main() {
     // let data = fs::read("file.xlsx").unwrap();
@@ -348,3 +1098,145 @@ fn main() {
     }
 }
 */
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_zip64_streamed_entry() {
+        // Deflated entry written in streaming mode (flag bit 3) with a ZIP64 data
+        // descriptor, sizes and offset in ZIP64 extra fields, and a ZIP64 end of central
+        // directory record and locator.
+        let data = vec![
+            80, 75, 3, 4, 45, 0, 8, 0, 8, 0, 0, 0, 33, 0, 0, 0, 0, 0, 255, 255, 255, 255, 255, 255,
+            255, 255, 5, 0, 20, 0, 97, 46, 116, 120, 116, 1, 0, 16, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0,
+            0, 0, 0, 0, 0, 0, 0, 203, 72, 205, 201, 201, 87, 168, 202, 44, 48, 51, 1, 0, 80, 75, 7,
+            8, 172, 186, 10, 248, 13, 0, 0, 0, 0, 0, 0, 0, 11, 0, 0, 0, 0, 0, 0, 0, 80, 75, 1, 2,
+            45, 0, 45, 0, 8, 0, 8, 0, 0, 0, 33, 0, 172, 186, 10, 248, 255, 255, 255, 255, 255, 255,
+            255, 255, 5, 0, 28, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 255, 255, 255, 255, 97, 46, 116,
+            120, 116, 1, 0, 24, 0, 11, 0, 0, 0, 0, 0, 0, 0, 13, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0,
+            0, 0, 0, 80, 75, 6, 6, 44, 0, 0, 0, 0, 0, 0, 0, 45, 0, 45, 0, 0, 0, 0, 0, 0, 0, 0, 0,
+            1, 0, 0, 0, 0, 0, 0, 0, 1, 0, 0, 0, 0, 0, 0, 0, 79, 0, 0, 0, 0, 0, 0, 0, 92, 0, 0, 0,
+            0, 0, 0, 0, 80, 75, 6, 7, 0, 0, 0, 0, 171, 0, 0, 0, 0, 0, 0, 0, 1, 0, 0, 0, 80, 75, 5,
+            6, 0, 0, 0, 0, 255, 255, 255, 255, 255, 255, 255, 255, 255, 255, 255, 255, 0, 0,
+        ];
+
+        let zip = Zip::from_data(data).unwrap();
+
+        assert!(zip.zip64_eocd.is_some());
+
+        let cdh = &zip.central_directory_headers[0];
+        assert_eq!(cdh.compressed_size, 13);
+        assert_eq!(cdh.uncompressed_size, 11);
+        assert_eq!(cdh.offset, 0);
+
+        let lfh = zip.read_local_file(cdh).unwrap();
+        assert_eq!(lfh.crc32, 0xf80abaac);
+
+        let files = zip.extract_files().unwrap();
+        assert_eq!(files.len(), 1);
+        assert_eq!(files[0].name, "a.txt");
+        assert_eq!(files[0].content, "hello zip64");
+    }
+
+    #[test]
+    fn test_bad_offsets() {
+        let zip = {
+            let mut writer = ZipWriter::new(Vec::new());
+            writer
+                .add_file("a.csv", b"x,y\n", Compression::Stored)
+                .unwrap();
+            writer.finish().unwrap()
+        };
+
+        let find = |data: &[u8], signature: &[u8]| {
+            data.windows(signature.len())
+                .position(|w| w == signature)
+                .unwrap()
+        };
+
+        // Local file header offset of the central directory header
+        let mut data = zip.clone();
+        let at = find(&data, b"PK\x01\x02") + 42;
+        data[at..at + 4].copy_from_slice(&0xfffffff0u32.to_le_bytes());
+
+        assert!(matches!(
+            Zip::from_data(data).unwrap().read_file("a.csv"),
+            Err(ZipError::BadOffset {
+                what: "local file header",
+                ..
+            })
+        ));
+
+        // A zip64 locator pointing past the end
+        let mut data = zip.clone();
+        let at = find(&data, b"PK\x05\x06");
+        let mut locator = b"PK\x06\x07".to_vec();
+        locator.extend_from_slice(&0u32.to_le_bytes());
+        locator.extend_from_slice(&u64::MAX.to_le_bytes());
+        locator.extend_from_slice(&1u32.to_le_bytes());
+        data.splice(at..at, locator);
+
+        assert!(matches!(
+            Zip::from_data(data),
+            Err(ZipError::BadOffset {
+                what: "zip64 end of central directory",
+                ..
+            })
+        ));
+    }
+
+    #[test]
+    fn test_eocd_signature_in_comment() {
+        // Stored entry whose archive comment starts with "PK\5\6"
+        let data = vec![
+            80, 75, 3, 4, 20, 0, 0, 0, 0, 0, 42, 5, 83, 93, 74, 195, 176, 160, 4, 0, 0, 0, 4, 0, 0,
+            0, 5, 0, 0, 0, 98, 46, 99, 115, 118, 120, 44, 121, 10, 80, 75, 1, 2, 20, 3, 20, 0, 0,
+            0, 0, 0, 42, 5, 83, 93, 74, 195, 176, 160, 4, 0, 0, 0, 4, 0, 0, 0, 5, 0, 0, 0, 0, 0, 0,
+            0, 0, 0, 0, 0, 128, 1, 0, 0, 0, 0, 98, 46, 99, 115, 118, 80, 75, 5, 6, 0, 0, 0, 0, 1,
+            0, 1, 0, 51, 0, 0, 0, 39, 0, 0, 0, 17, 0, 80, 75, 5, 6, 32, 110, 111, 116, 32, 97, 32,
+            114, 101, 99, 111, 114, 100,
+        ];
+
+        let files = Zip::from_data(data).unwrap().extract_files().unwrap();
+        assert_eq!(files[0].name, "b.csv");
+        assert_eq!(files[0].content, "x,y\n");
+
+        assert!(matches!(
+            Zip::from_data(b"x,y\n".to_vec()),
+            Err(ZipError::NotAZip)
+        ));
+    }
+
+    #[test]
+    fn test_writer_roundtrip() {
+        let csv = "a,b,c\n1,2,3\n".repeat(100);
+
+        let mut writer = ZipWriter::new(Vec::new());
+        writer.set_modified(DosDateTime::new(2024, 5, 17, 13, 45, 30));
+        writer
+            .add_file("stored.csv", csv.as_bytes(), Compression::Stored)
+            .unwrap();
+        writer
+            .add_file("planilha/ção.csv", csv.as_bytes(), Compression::Deflate)
+            .unwrap();
+        writer
+            .add_file("empty.csv", b"", Compression::Deflate)
+            .unwrap();
+        let data = writer.finish().unwrap();
+
+        let zip = Zip::from_data(data).unwrap();
+
+        assert_eq!(zip.central_directory_headers[1].flag, FLAG_UTF8);
+        assert!(zip.central_directory_headers[1].compressed_size < csv.len() as u64);
+
+        let files = zip.extract_files().unwrap();
+        assert_eq!(files.len(), 3);
+        assert_eq!(files[0].name, "stored.csv");
+        assert_eq!(files[0].content, csv);
+        assert_eq!(files[1].name, "planilha/ção.csv");
+        assert_eq!(files[1].content, csv);
+        assert_eq!(files[2].content, "");
+    }
+}
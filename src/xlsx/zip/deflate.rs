@@ -0,0 +1,281 @@
+// A dead simple deflate compressor, the counterpart of inflate.rs.
+// It finds LZ77 matches using hash chains and encodes them with the fixed Huffman codes
+// (block type 1). Data that does not compress is emitted as stored blocks (type 0).
+//
+// It won't beat zlib's ratio, but it's small and good enough for writing spreadsheets.
+//
+use super::inflate::{SYMBOL_TO_DIST_MAP, SYMBOL_TO_LL_MAP};
+
+const WINDOW_SIZE: usize = 32768;
+const MIN_MATCH: usize = 3;
+const MAX_MATCH: usize = 258;
+
+const HASH_BITS: u32 = 15;
+const MAX_CHAIN: usize = 128;
+
+const NO_POS: usize = usize::MAX;
+
+// Largest payload of a stored block
+const MAX_STORED_SIZE: usize = 65535;
+
+struct BitWriter {
+    data: Vec<u8>,
+
+    bit_buff: u32,
+    bit_buff_count: usize,
+}
+
+impl BitWriter {
+    fn new() -> Self {
+        BitWriter {
+            data: Vec::new(),
+            bit_buff: 0,
+            bit_buff_count: 0,
+        }
+    }
+
+    // Writes the n lowest bits of value, least significant bit first
+    fn putbits(&mut self, value: u32, n: usize) {
+        self.bit_buff |= value << self.bit_buff_count;
+        self.bit_buff_count += n;
+
+        while self.bit_buff_count >= 8 {
+            self.data.push(self.bit_buff as u8);
+            self.bit_buff >>= 8;
+            self.bit_buff_count -= 8;
+        }
+    }
+
+    // Huffman codes are packed starting from their most significant bit
+    fn putcode(&mut self, code: u32, n: usize) {
+        let mut reversed = 0;
+
+        for i in 0..n {
+            reversed = (reversed << 1) | ((code >> i) & 1);
+        }
+
+        self.putbits(reversed, n);
+    }
+
+    // Pads the current byte with zeros
+    fn align(&mut self) {
+        if self.bit_buff_count > 0 {
+            self.putbits(0, 8 - self.bit_buff_count);
+        }
+    }
+
+    fn finish(mut self) -> Vec<u8> {
+        self.align();
+
+        self.data
+    }
+}
+
+fn write_fixed_symbol(writer: &mut BitWriter, symbol: usize) {
+    let symbol = symbol as u32;
+
+    match symbol {
+        0..=143 => writer.putcode(0x30 + symbol, 8),
+        144..=255 => writer.putcode(0x190 + symbol - 144, 9),
+        256..=279 => writer.putcode(symbol - 256, 7),
+        _ => writer.putcode(0xc0 + symbol - 280, 8),
+    }
+}
+
+fn write_match(writer: &mut BitWriter, length: usize, distance: usize) {
+    // The first 29 entries are the valid length symbols, 257 to 285
+    let idx = (0..29)
+        .rev()
+        .find(|&i| SYMBOL_TO_LL_MAP[i].0 <= length)
+        .unwrap();
+    let (base, extra_bits) = SYMBOL_TO_LL_MAP[idx];
+
+    write_fixed_symbol(writer, 257 + idx);
+    writer.putbits((length - base) as u32, extra_bits);
+
+    // The first 30 entries are the valid distance symbols
+    let idx = (0..30)
+        .rev()
+        .find(|&i| SYMBOL_TO_DIST_MAP[i].0 <= distance)
+        .unwrap();
+    let (base, extra_bits) = SYMBOL_TO_DIST_MAP[idx];
+
+    writer.putcode(idx as u32, 5);
+    writer.putbits((distance - base) as u32, extra_bits);
+}
+
+// Finds previous occurences of 3 bytes sequences inside the window
+struct HashChain {
+    head: Vec<usize>,
+    prev: Vec<usize>,
+}
+
+impl HashChain {
+    fn new() -> Self {
+        HashChain {
+            head: vec![NO_POS; 1 << HASH_BITS],
+            prev: vec![NO_POS; WINDOW_SIZE],
+        }
+    }
+
+    fn hash(data: &[u8], pos: usize) -> usize {
+        let v = ((data[pos] as u32) << 16) | ((data[pos + 1] as u32) << 8) | data[pos + 2] as u32;
+
+        (v.wrapping_mul(2654435761) >> (32 - HASH_BITS)) as usize
+    }
+
+    fn insert(&mut self, data: &[u8], pos: usize) {
+        if pos + MIN_MATCH > data.len() {
+            return;
+        }
+
+        let h = Self::hash(data, pos);
+
+        self.prev[pos % WINDOW_SIZE] = self.head[h];
+        self.head[h] = pos;
+    }
+
+    // Returns the (length, distance) of the longest match for the bytes at pos
+    fn longest_match(&self, data: &[u8], pos: usize) -> (usize, usize) {
+        if pos + MIN_MATCH > data.len() {
+            return (0, 0);
+        }
+
+        let max_len = MAX_MATCH.min(data.len() - pos);
+
+        let mut best = (0, 0);
+        let mut candidate = self.head[Self::hash(data, pos)];
+
+        for _ in 0..MAX_CHAIN {
+            if candidate == NO_POS || pos - candidate > WINDOW_SIZE {
+                break;
+            }
+
+            let len = data[candidate..]
+                .iter()
+                .zip(&data[pos..pos + max_len])
+                .take_while(|(a, b)| a == b)
+                .count();
+
+            if len > best.0 {
+                best = (len, pos - candidate);
+
+                if len == max_len {
+                    break;
+                }
+            }
+
+            let next = self.prev[candidate % WINDOW_SIZE];
+
+            // The slot was reused by a newer position, the chain ends here
+            if next == NO_POS || next >= candidate {
+                break;
+            }
+
+            candidate = next;
+        }
+
+        best
+    }
+}
+
+fn compress_fixed(data: &[u8]) -> Vec<u8> {
+    let mut writer = BitWriter::new();
+    let mut chain = HashChain::new();
+
+    // Last block, fixed Huffman codes
+    writer.putbits(1, 1);
+    writer.putbits(1, 2);
+
+    let mut i = 0;
+
+    while i < data.len() {
+        let (length, distance) = chain.longest_match(data, i);
+
+        if length >= MIN_MATCH {
+            write_match(&mut writer, length, distance);
+
+            for k in i..(i + length) {
+                chain.insert(data, k);
+            }
+
+            i += length;
+        } else {
+            write_fixed_symbol(&mut writer, data[i] as usize);
+
+            chain.insert(data, i);
+
+            i += 1;
+        }
+    }
+
+    // End of block
+    write_fixed_symbol(&mut writer, 256);
+
+    writer.finish()
+}
+
+fn compress_stored(data: &[u8]) -> Vec<u8> {
+    let mut result = Vec::with_capacity(data.len() + 5 * (data.len() / MAX_STORED_SIZE + 1));
+
+    let mut chunks = data.chunks(MAX_STORED_SIZE).peekable();
+
+    if chunks.peek().is_none() {
+        // Empty input still needs one (last) block
+        result.extend_from_slice(&[1, 0, 0, 0xff, 0xff]);
+    }
+
+    while let Some(chunk) = chunks.next() {
+        let last = chunks.peek().is_none();
+        let len = chunk.len() as u16;
+
+        result.push(last as u8);
+        result.extend_from_slice(&len.to_le_bytes());
+        result.extend_from_slice(&(!len).to_le_bytes());
+        result.extend_from_slice(chunk);
+    }
+
+    result
+}
+
+pub fn compress(data: &[u8]) -> Vec<u8> {
+    let compressed = compress_fixed(data);
+
+    if compressed.len() > data.len() + 5 * (data.len() / MAX_STORED_SIZE + 1) {
+        compress_stored(data)
+    } else {
+        compressed
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::inflate::decompress;
+    use super::*;
+
+    #[test]
+    fn test_compress_roundtrip() {
+        let text = "It started with a low light,
+Next thing I knew they ripped from my bed
+And then they took my blood type
+It left a strange impression on my head
+";
+
+        for data in [
+            Vec::new(),
+            b"a".to_vec(),
+            text.as_bytes().to_vec(),
+            text.repeat(500).into_bytes(),
+            (0..200_000u32)
+                .map(|i| (i.wrapping_mul(2654435761) >> 13) as u8)
+                .collect(),
+        ] {
+            let compressed = compress(&data);
+
+            assert_eq!(decompress(&compressed).unwrap(), data);
+        }
+
+        let repeated = text.repeat(500);
+        assert!(compress(repeated.as_bytes()).len() < repeated.len() / 20);
+    }
+}
@@ -0,0 +1,291 @@
+// gzip file format (RFC 1952). A gzip file is a series of members, each one is a header,
+// a raw deflate stream and a trailer with the CRC-32 and size of the uncompressed data.
+//
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use super::crc32;
+use super::deflate;
+use super::inflate;
+
+const MAGIC: [u8; 2] = [0x1f, 0x8b];
+const METHOD_DEFLATE: u8 = 8;
+
+// Header flags
+const FTEXT: u8 = 1;
+const FHCRC: u8 = 1 << 1;
+const FEXTRA: u8 = 1 << 2;
+const FNAME: u8 = 1 << 3;
+const FCOMMENT: u8 = 1 << 4;
+
+const OS_UNIX: u8 = 3;
+
+#[derive(Debug)]
+pub enum GzipError {
+    NotGzip,
+    UnsupportedMethod(u8),
+    Truncated,
+    Inflate(String),
+    HeaderCrcMismatch,
+    CrcMismatch,
+    SizeMismatch,
+}
+
+impl std::fmt::Display for GzipError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            GzipError::NotGzip => write!(f, "not a gzip file"),
+            GzipError::UnsupportedMethod(method) => {
+                write!(f, "unsupported gzip compression method {method}")
+            }
+            GzipError::Truncated => write!(f, "truncated gzip file"),
+            GzipError::Inflate(what) => write!(f, "invalid deflate data: {what}"),
+            GzipError::HeaderCrcMismatch => write!(f, "gzip header crc does not match"),
+            GzipError::CrcMismatch => write!(f, "gzip crc-32 does not match"),
+            GzipError::SizeMismatch => write!(f, "gzip size does not match"),
+        }
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct GzipHeader {
+    pub text: bool,
+    pub mtime: u32,
+    pub extra: Option<Vec<u8>>,
+    pub name: Option<String>,
+    pub comment: Option<String>,
+    pub os: u8,
+}
+
+#[derive(Debug)]
+pub struct GzipMember {
+    pub header: GzipHeader,
+    pub data: Vec<u8>,
+}
+
+struct Reader<'a> {
+    data: &'a [u8],
+    cursor: usize,
+}
+
+impl<'a> Reader<'a> {
+    fn read_slice(&mut self, size: usize) -> Result<&'a [u8], GzipError> {
+        let v = self
+            .data
+            .get(self.cursor..(self.cursor + size))
+            .ok_or(GzipError::Truncated)?;
+
+        self.cursor += size;
+
+        Ok(v)
+    }
+
+    fn read_u8(&mut self) -> Result<u8, GzipError> {
+        Ok(self.read_slice(1)?[0])
+    }
+
+    fn read_u16(&mut self) -> Result<u16, GzipError> {
+        let v = self.read_slice(2)?;
+
+        Ok(u16::from_le_bytes([v[0], v[1]]))
+    }
+
+    fn read_u32(&mut self) -> Result<u32, GzipError> {
+        let v = self.read_slice(4)?;
+
+        Ok(u32::from_le_bytes([v[0], v[1], v[2], v[3]]))
+    }
+
+    // Zero terminated ISO 8859-1 string
+    fn read_latin1_string(&mut self) -> Result<String, GzipError> {
+        let rest = self.data.get(self.cursor..).ok_or(GzipError::Truncated)?;
+        let len = rest
+            .iter()
+            .position(|&b| b == 0)
+            .ok_or(GzipError::Truncated)?;
+
+        let s = rest[..len].iter().map(|&b| b as char).collect();
+
+        self.cursor += len + 1;
+
+        Ok(s)
+    }
+}
+
+pub fn is_gzip(data: &[u8]) -> bool {
+    data.starts_with(&MAGIC)
+}
+
+fn read_header(reader: &mut Reader) -> Result<GzipHeader, GzipError> {
+    let start = reader.cursor;
+
+    if reader.read_slice(2)? != MAGIC {
+        return Err(GzipError::NotGzip);
+    }
+
+    let method = reader.read_u8()?;
+
+    if method != METHOD_DEFLATE {
+        return Err(GzipError::UnsupportedMethod(method));
+    }
+
+    let flags = reader.read_u8()?;
+    let mtime = reader.read_u32()?;
+    let _xfl = reader.read_u8()?;
+    let os = reader.read_u8()?;
+
+    let mut header = GzipHeader {
+        text: flags & FTEXT != 0,
+        mtime,
+        os,
+        ..Default::default()
+    };
+
+    if flags & FEXTRA != 0 {
+        let len = reader.read_u16()? as usize;
+        header.extra = Some(reader.read_slice(len)?.to_vec());
+    }
+
+    if flags & FNAME != 0 {
+        header.name = Some(reader.read_latin1_string()?);
+    }
+
+    if flags & FCOMMENT != 0 {
+        header.comment = Some(reader.read_latin1_string()?);
+    }
+
+    if flags & FHCRC != 0 {
+        // The two least significant bytes of the CRC-32 of the header so far
+        let expected = crc32::crc32(&reader.data[start..reader.cursor]) as u16;
+
+        if reader.read_u16()? != expected {
+            return Err(GzipError::HeaderCrcMismatch);
+        }
+    }
+
+    Ok(header)
+}
+
+// Reads all members of a gzip file, checking their CRC-32 and sizes
+pub fn read_members(data: &[u8]) -> Result<Vec<GzipMember>, GzipError> {
+    if !is_gzip(data) {
+        return Err(GzipError::NotGzip);
+    }
+
+    let mut reader = Reader { data, cursor: 0 };
+    let mut members = Vec::new();
+
+    // Some writers pad the file with zeros after the last member
+    while data[reader.cursor..].iter().any(|&b| b != 0) {
+        let header = read_header(&mut reader)?;
+
+        let (content, consumed) =
+            inflate::decompress_partial(&data[reader.cursor..]).map_err(GzipError::Inflate)?;
+
+        reader.cursor += consumed;
+
+        let crc = reader.read_u32()?;
+        let size = reader.read_u32()?;
+
+        if crc32::crc32(&content) != crc {
+            return Err(GzipError::CrcMismatch);
+        }
+
+        // The size is stored modulo 2^32
+        if content.len() as u32 != size {
+            return Err(GzipError::SizeMismatch);
+        }
+
+        members.push(GzipMember {
+            header,
+            data: content,
+        });
+    }
+
+    Ok(members)
+}
+
+// Decompresses a gzip file, concatenating the content of all its members
+pub fn decompress(data: &[u8]) -> Result<Vec<u8>, GzipError> {
+    let members = read_members(data)?;
+
+    if members.len() == 1 {
+        return Ok(members.into_iter().next().unwrap().data);
+    }
+
+    Ok(members.into_iter().flat_map(|m| m.data).collect())
+}
+
+// Compresses data as a single member gzip file
+pub fn compress(data: &[u8], name: Option<&str>) -> Vec<u8> {
+    let mtime = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs() as u32)
+        .unwrap_or(0);
+
+    let mut result = Vec::new();
+
+    result.extend_from_slice(&MAGIC);
+    result.push(METHOD_DEFLATE);
+    result.push(if name.is_some() { FNAME } else { 0 });
+    result.extend_from_slice(&mtime.to_le_bytes());
+    result.push(0);
+    result.push(OS_UNIX);
+
+    if let Some(name) = name {
+        // Characters outside ISO 8859-1 can't be represented
+        result.extend(
+            name.chars()
+                .map(|c| if (c as u32) < 256 { c as u8 } else { b'_' }),
+        );
+        result.push(0);
+    }
+
+    result.extend_from_slice(&deflate::compress(data));
+    result.extend_from_slice(&crc32::crc32(data).to_le_bytes());
+    result.extend_from_slice(&(data.len() as u32).to_le_bytes());
+
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_multi_member() {
+        // First member has FTEXT, FEXTRA, FNAME, FCOMMENT and FHCRC set
+        let data = vec![
+            31, 139, 8, 31, 0, 241, 83, 101, 0, 3, 6, 0, 65, 66, 2, 0, 104, 105, 112, 97, 114, 116,
+            49, 46, 99, 115, 118, 0, 102, 105, 114, 115, 116, 0, 61, 144, 75, 212, 73, 226, 50,
+            212, 49, 226, 2, 0, 123, 7, 151, 10, 8, 0, 0, 0, 31, 139, 8, 0, 0, 241, 83, 101, 0, 3,
+            51, 214, 49, 225, 2, 0, 62, 45, 114, 169, 4, 0, 0, 0,
+        ];
+
+        let members = read_members(&data).unwrap();
+
+        assert_eq!(members.len(), 2);
+        assert!(members[0].header.text);
+        assert_eq!(members[0].header.mtime, 1700000000);
+        assert_eq!(
+            members[0].header.extra.as_deref(),
+            Some(&b"AB\x02\x00hi"[..])
+        );
+        assert_eq!(members[0].header.name.as_deref(), Some("part1.csv"));
+        assert_eq!(members[0].header.comment.as_deref(), Some("first"));
+        assert_eq!(members[1].header.name, None);
+
+        assert_eq!(decompress(&data).unwrap(), b"a,b\n1,2\n3,4\n");
+
+        let mut corrupted = data.clone();
+        corrupted[48] ^= 1;
+        assert!(matches!(
+            decompress(&corrupted),
+            Err(GzipError::CrcMismatch)
+        ));
+
+        let compressed = compress(b"x,y\n", Some("out.csv"));
+        let members = read_members(&compressed).unwrap();
+        assert_eq!(members[0].header.name.as_deref(), Some("out.csv"));
+        assert_eq!(members[0].data, b"x,y\n");
+    }
+}
@@ -554,7 +554,7 @@ static CODE_TO_LL_MAP: [LLValue; 512] = [
 ];
 
 // (dist, extra bits) pair
-static SYMBOL_TO_DIST_MAP: [(usize, usize); 32] = [
+pub(super) static SYMBOL_TO_DIST_MAP: [(usize, usize); 32] = [
     (1, 0),
     (2, 0),
     (3, 0),
@@ -591,7 +591,7 @@ static SYMBOL_TO_DIST_MAP: [(usize, usize); 32] = [
 
 // (length, extra bits) pair
 // offset of 257: symbol - 257
-static SYMBOL_TO_LL_MAP: [(usize, usize); 32] = [
+pub(super) static SYMBOL_TO_LL_MAP: [(usize, usize); 32] = [
     (3, 0),
     (4, 0),
     (5, 0),
@@ -735,11 +735,7 @@ fn read_block_type1(stream: &mut Bitstream, bytes_window: &mut Vec<u8>) -> Resul
 
                         // println!("len = '{}', dist: {}, bits = {}, v = {}", len2, dist2, b, v);
 
-                        let start = bytes_window.len() - dist2;
-
-                        for k in 0..len2 {
-                            bytes_window.push(bytes_window[start + k]);
-                        }
+                        copy_match(bytes_window, len2, dist2)?;
 
                         break;
                     }
@@ -760,6 +756,83 @@ fn read_block_type1(stream: &mut Bitstream, bytes_window: &mut Vec<u8>) -> Resul
     Ok(())
 }
 
+// Repeats `len` bytes from `dist` bytes back, the unused symbols map to a length or distance of 0
+fn copy_match(bytes_window: &mut Vec<u8>, len: usize, dist: usize) -> Result<(), String> {
+    if len == 0 || dist == 0 {
+        return Err("invalid length or distance symbol".to_string());
+    }
+
+    if dist > bytes_window.len() {
+        return Err(format!("distance {dist} is out of the window"));
+    }
+
+    let start = bytes_window.len() - dist;
+
+    for k in 0..len {
+        bytes_window.push(bytes_window[start + k]);
+    }
+
+    Ok(())
+}
+
+// Reads the code lengths of a dynamic block into `code_lens`, the lengths of the
+// literal/length codes and then the ones of the distance codes
+fn read_code_lens(
+    stream: &mut Bitstream,
+    cl_code_map: &HashMap<(usize, usize), usize>,
+    (min_cl_code_length, max_cl_code_length): (usize, usize),
+    code_lens: &mut [usize],
+    last_code: &mut usize,
+) -> Result<(), String> {
+    let mut i = 0usize;
+
+    while i < code_lens.len() {
+        let mut found = false;
+
+        for b in min_cl_code_length..=max_cl_code_length {
+            stream.needbits(b)?;
+
+            let v = stream.getbits(b) as usize;
+
+            if let Some(&cl_code_len) = cl_code_map.get(&(v, b)) {
+                stream.dumpbits(b);
+
+                let (value, repeat) = match cl_code_len {
+                    0..=15 => (cl_code_len, 1),
+                    16 => (*last_code, 3 + stream.readbits(2)? as usize),
+                    17 => (0, 3 + stream.readbits(3)? as usize),
+                    _ => (0, 11 + stream.readbits(7)? as usize),
+                };
+
+                if i + repeat > code_lens.len() {
+                    return Err(format!(
+                        "Expected {} code lengths, got {}",
+                        code_lens.len(),
+                        i + repeat
+                    ));
+                }
+
+                code_lens[i..i + repeat].fill(value);
+
+                if cl_code_len <= 15 {
+                    *last_code = cl_code_len;
+                }
+
+                i += repeat;
+
+                found = true;
+                break;
+            }
+        }
+
+        if !found {
+            return Err("cl_code_len not found".to_string());
+        }
+    }
+
+    Ok(())
+}
+
 fn gen_code_map(code_lens: &[usize]) -> (usize, usize, HashMap<(usize, usize), usize>) {
     // bit_length_count contains the number of codes of each length
     let mut bit_length_count = [0usize; 16];
@@ -845,68 +918,17 @@ fn read_block_type2(stream: &mut Bitstream, bytes_window: &mut Vec<u8>) -> Resul
     // println!("min_cl_code_len = {}", min_cl_code_length);
     // println!("max_cl_code_len = {}", max_cl_code_length);
 
-    let mut ll_code_lens = [0usize; 288];
+    let cl_code_range = (min_cl_code_length, max_cl_code_length);
     let mut last_code = 0;
-    let mut i = 0usize;
-
-    while i < num_lit_len_codes {
-        let mut found = false;
-
-        for b in min_cl_code_length..=max_cl_code_length {
-            stream.needbits(b)?;
-
-            let v = stream.getbits(b) as usize;
-
-            if let Some(&cl_code_len) = cl_code_map.get(&(v, b)) {
-                // println!("cl_code_len = {}, bits = {}, v = {}", cl_code_len, b, v);
-                stream.dumpbits(b);
-
-                if cl_code_len <= 15 {
-                    ll_code_lens[i] = cl_code_len;
-                    last_code = cl_code_len;
-
-                    // println!("  code len '{}'", last_code);
-                    i += 1;
-                } else if cl_code_len == 16 {
-                    let repeat = 3 + stream.readbits(2)? as usize;
-
-                    for k in 0..repeat {
-                        ll_code_lens[i + k] = last_code;
-                    }
-
-                    i += repeat as usize;
-
-                    // println!("  repeat '{}' {} times", last_code, repeat);
-                } else if cl_code_len == 17 {
-                    let repeat = 3 + stream.readbits(3)? as usize;
-
-                    i += repeat as usize;
-                    // println!("  repeat '0' {} times", repeat);
-                } else if cl_code_len == 18 {
-                    let repeat = 11 + stream.readbits(7)? as usize;
 
-                    i += repeat as usize;
-                    // println!("  repeat '0' {} times", repeat);
-                } else {
-                    unreachable!();
-                }
-
-                found = true;
-                break;
-            }
-        }
-
-        if !found {
-            return Err("cl_code_len not found".to_string());
-        }
-    }
-
-    if i != num_lit_len_codes {
-        return Err(format!(
-            "Expected {} lit_len codes, got {}",
-            num_lit_len_codes, i
-        ));
-    }
+    let mut ll_code_lens = [0usize; 288];
+    read_code_lens(
+        stream,
+        &cl_code_map,
+        cl_code_range,
+        &mut ll_code_lens[..num_lit_len_codes],
+        &mut last_code,
+    )?;
 
     let (min_ll_code_len, max_ll_code_len, ll_code_map) = gen_code_map(&ll_code_lens);
 
@@ -914,62 +936,13 @@ fn read_block_type2(stream: &mut Bitstream, bytes_window: &mut Vec<u8>) -> Resul
     // println!("max_ll_code_len = {}", max_ll_code_len);
 
     let mut dist_code_lens = [0usize; 32];
-
-    i = 0;
-
-    while i < num_dist_codes {
-        let mut found = false;
-
-        for b in min_cl_code_length..=max_cl_code_length {
-            stream.needbits(b)?;
-
-            let v = stream.getbits(b) as usize;
-
-            if let Some(&cl_code_len) = cl_code_map.get(&(v, b)) {
-                // println!("cl_code_len = {}, bits = {}, v = {}", cl_code_len, b, v);
-                stream.dumpbits(b);
-
-                if cl_code_len <= 15 {
-                    dist_code_lens[i] = cl_code_len;
-                    last_code = cl_code_len;
-
-                    // println!("  code len '{}'", last_code);
-                    i += 1;
-                } else if cl_code_len == 16 {
-                    let repeat = 3 + stream.readbits(2)? as usize;
-
-                    for k in 0..repeat {
-                        dist_code_lens[i + k] = last_code;
-                    }
-
-                    i += repeat as usize;
-
-                    // println!("  repeat '{}' {} times", last_code, repeat);
-                } else if cl_code_len == 17 {
-                    let repeat = 3 + stream.readbits(3)? as usize;
-
-                    i += repeat as usize;
-                    // println!("  repeat '0' {} times", repeat);
-                } else if cl_code_len == 18 {
-                    let repeat = 11 + stream.readbits(7)? as usize;
-
-                    i += repeat as usize;
-                    // println!("  repeat '0' {} times", repeat);
-                } else {
-                    unreachable!();
-                }
-
-                found = true;
-                break;
-            }
-        }
-
-        if !found {
-            return Err("cl_code_len not found".to_string());
-        }
-    }
-
-    assert_eq!(i, num_dist_codes);
+    read_code_lens(
+        stream,
+        &cl_code_map,
+        cl_code_range,
+        &mut dist_code_lens[..num_dist_codes],
+        &mut last_code,
+    )?;
 
     let (min_dist_code_len, max_dist_code_len, dist_code_map) = gen_code_map(&dist_code_lens);
 
@@ -1010,10 +983,7 @@ fn read_block_type2(stream: &mut Bitstream, bytes_window: &mut Vec<u8>) -> Resul
                             let dist2 = dist + extra_value;
 
                             // println!("len = '{}', dist: {}, bits = {}, v = {}, bits2 = {}, v2 = {}, window len = {}", len2, dist2, b, v, b2, v2, bytes_window.len());
-                            let start = bytes_window.len() - dist2;
-                            for k in 0..len2 {
-                                bytes_window.push(bytes_window[start + k]);
-                            }
+                            copy_match(bytes_window, len2, dist2)?;
 
                             break;
                         }
@@ -1043,7 +1013,9 @@ fn read_block_type0(stream: &mut Bitstream, bytes_window: &mut Vec<u8>) -> Resul
     let len = stream.readbits(16)?;
     let nlen = stream.readbits(16)?;
 
-    assert_eq!(len, !nlen);
+    if len != !nlen {
+        return Err("stored block length doesn't match its complement".to_string());
+    }
 
     for _ in 0..len {
         let byte = stream.readbits(8)? as u8;
@@ -1054,6 +1026,12 @@ fn read_block_type0(stream: &mut Bitstream, bytes_window: &mut Vec<u8>) -> Resul
 }
 
 pub fn decompress(data: &[u8]) -> Result<Vec<u8>, String> {
+    decompress_partial(data).map(|(bytes_window, _)| bytes_window)
+}
+
+// Decompresses a deflate stream that may be followed by other data, also returning how
+// many bytes of the input the stream used.
+pub fn decompress_partial(data: &[u8]) -> Result<(Vec<u8>, usize), String> {
     let mut stream = Bitstream::new(data.to_vec());
     let mut bytes_window = Vec::new();
 
@@ -1072,7 +1050,9 @@ pub fn decompress(data: &[u8]) -> Result<Vec<u8>, String> {
             Method::Dynamic => {
                 read_block_type2(&mut stream, &mut bytes_window)?;
             }
-            Method::Invalid => unreachable!(),
+            Method::Invalid => {
+                return Err("invalid block type".to_string());
+            }
         }
 
         if last_block {
@@ -1080,7 +1060,10 @@ pub fn decompress(data: &[u8]) -> Result<Vec<u8>, String> {
         }
     }
 
-    Ok(bytes_window)
+    // Whole bytes still in the bit buffer were read ahead and don't belong to the stream
+    let consumed = stream.pos - stream.bit_buff_count / 8;
+
+    Ok((bytes_window, consumed))
 }
 
 #[cfg(test)]
@@ -1095,6 +1078,29 @@ mod tests {
         assert_eq!(str::from_utf8(&bytes_window[..]).unwrap(), text.to_string());
     }
 
+    #[test]
+    fn test_decompress_corrupted() {
+        // Stored block whose length doesn't match its complement
+        assert!(decompress(&[1, 8, 0, 0, 0, 82, 97, 119]).is_err());
+
+        // Fixed block starting with a match, there's nothing to copy from
+        let e = decompress(&[3, 2, 0]).unwrap_err();
+        assert!(e.contains("window"), "{e}");
+
+        // Flipped bits and cut streams make errors or garbage, not panics
+        let data = [
+            243, 72, 205, 201, 201, 215, 81, 240, 192, 70, 85, 101, 22, 40, 114, 1, 0,
+        ];
+
+        for i in 0..data.len() * 8 {
+            let mut corrupted = data;
+            corrupted[i / 8] ^= 1 << (i % 8);
+
+            let _ = decompress(&corrupted);
+            let _ = decompress(&data[..i / 8]);
+        }
+    }
+
     #[test]
     fn test_decompress_block_type1() {
         let data = vec![
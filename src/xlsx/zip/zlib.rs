@@ -0,0 +1,142 @@
+// zlib data format (RFC 1950): a two bytes header, a raw deflate stream and the Adler-32
+// checksum of the uncompressed data.
+//
+use super::deflate;
+use super::inflate;
+
+const METHOD_DEFLATE: u8 = 8;
+
+// FLG bit 5: a preset dictionary id follows the header
+const FDICT: u8 = 1 << 5;
+
+#[derive(Debug)]
+pub enum ZlibError {
+    InvalidHeader,
+    UnsupportedMethod(u8),
+    PresetDictionary,
+    Truncated,
+    Inflate(String),
+    Adler32Mismatch,
+}
+
+impl std::fmt::Display for ZlibError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ZlibError::InvalidHeader => write!(f, "invalid zlib header"),
+            ZlibError::UnsupportedMethod(method) => {
+                write!(f, "unsupported zlib compression method {method}")
+            }
+            ZlibError::PresetDictionary => write!(f, "zlib preset dictionaries are not supported"),
+            ZlibError::Truncated => write!(f, "truncated zlib data"),
+            ZlibError::Inflate(what) => write!(f, "invalid deflate data: {what}"),
+            ZlibError::Adler32Mismatch => write!(f, "zlib adler-32 does not match"),
+        }
+    }
+}
+
+pub fn adler32(data: &[u8]) -> u32 {
+    const MOD_ADLER: u32 = 65521;
+
+    let mut a = 1u32;
+    let mut b = 0u32;
+
+    // 5552 is the largest block that can't overflow b before the modulo
+    for chunk in data.chunks(5552) {
+        for &byte in chunk {
+            a += byte as u32;
+            b += a;
+        }
+
+        a %= MOD_ADLER;
+        b %= MOD_ADLER;
+    }
+
+    (b << 16) | a
+}
+
+pub fn is_zlib(data: &[u8]) -> bool {
+    data.len() >= 2
+        && data[0] & 0x0f == METHOD_DEFLATE
+        && data[0] >> 4 <= 7
+        && u16::from_be_bytes([data[0], data[1]]).is_multiple_of(31)
+}
+
+pub fn decompress(data: &[u8]) -> Result<Vec<u8>, ZlibError> {
+    if data.len() < 2 {
+        return Err(ZlibError::Truncated);
+    }
+
+    let cmf = data[0];
+    let flg = data[1];
+
+    if !u16::from_be_bytes([cmf, flg]).is_multiple_of(31) {
+        return Err(ZlibError::InvalidHeader);
+    }
+
+    if cmf & 0x0f != METHOD_DEFLATE {
+        return Err(ZlibError::UnsupportedMethod(cmf & 0x0f));
+    }
+
+    // CINFO is the base-2 logarithm of the window size minus 8, at most 32K
+    if cmf >> 4 > 7 {
+        return Err(ZlibError::InvalidHeader);
+    }
+
+    if flg & FDICT != 0 {
+        return Err(ZlibError::PresetDictionary);
+    }
+
+    let (content, consumed) =
+        inflate::decompress_partial(&data[2..]).map_err(ZlibError::Inflate)?;
+
+    let trailer = data
+        .get((2 + consumed)..(2 + consumed + 4))
+        .ok_or(ZlibError::Truncated)?;
+
+    let checksum = u32::from_be_bytes([trailer[0], trailer[1], trailer[2], trailer[3]]);
+
+    if adler32(&content) != checksum {
+        return Err(ZlibError::Adler32Mismatch);
+    }
+
+    Ok(content)
+}
+
+pub fn compress(data: &[u8]) -> Vec<u8> {
+    // 32K window, deflate, fastest compression level (only fixed Huffman codes are used)
+    let mut result = vec![0x78, 0x01];
+
+    result.extend_from_slice(&deflate::compress(data));
+    result.extend_from_slice(&adler32(data).to_be_bytes());
+
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_zlib() {
+        let data = [
+            120, 156, 171, 202, 201, 76, 82, 40, 47, 74, 44, 40, 72, 77, 81, 72, 73, 44, 73, 68,
+            16, 0, 142, 73, 9, 243,
+        ];
+
+        assert!(is_zlib(&data));
+        assert_eq!(decompress(&data).unwrap(), b"zlib wrapped data data data");
+
+        let mut corrupted = data;
+        corrupted[25] ^= 1;
+        assert!(matches!(
+            decompress(&corrupted),
+            Err(ZlibError::Adler32Mismatch)
+        ));
+
+        assert_eq!(adler32(b"Wikipedia"), 0x11e60398);
+
+        let compressed = compress(b"x,y\n1,2\n");
+        assert!(is_zlib(&compressed));
+        assert_eq!(decompress(&compressed).unwrap(), b"x,y\n1,2\n");
+    }
+}
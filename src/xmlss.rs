@@ -0,0 +1,361 @@
+// SpreadsheetML 2003, the "XML Spreadsheet" format of Excel 2002/2003:
+//
+//     <?mso-application progid="Excel.Sheet"?>
+//     <Workbook xmlns="urn:schemas-microsoft-com:office:spreadsheet"
+//               xmlns:ss="urn:schemas-microsoft-com:office:spreadsheet">
+//       <Worksheet ss:Name="Sheet1">
+//         <Table>
+//           <Row>
+//             <Cell><Data ss:Type="String">Name</Data></Cell>
+//             <Cell ss:Index="3"><Data ss:Type="Number">2</Data></Cell>
+//           </Row>
+//           <Row ss:Index="5">...</Row>
+//
+// Rows and cells are positioned one after the other, `ss:Index` (1-based) skips to a given
+// position and `ss:MergeAcross` makes a cell span the next columns.
+//
+use std::collections::HashMap;
+use std::fs;
+use std::str::FromStr;
+
+use crate::cell::{Cell, CellMap, DateTime, MAX_COLS, MAX_ROWS};
+use crate::xlsx::xml;
+
+pub const SPREADSHEET_NAMESPACE: &str = "urn:schemas-microsoft-com:office:spreadsheet";
+
+#[derive(Debug)]
+pub enum XMLSSError {
+    Io(std::io::Error),
+    Xml(xml::XMLError),
+    NotAWorkbook,
+    WorksheetNotFound(String),
+}
+
+impl std::fmt::Display for XMLSSError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            XMLSSError::Io(e) => write!(f, "{e}"),
+            XMLSSError::Xml(e) => write!(f, "{e}"),
+            XMLSSError::NotAWorkbook => write!(f, "not a SpreadsheetML 2003 workbook"),
+            XMLSSError::WorksheetNotFound(name) => write!(f, "worksheet {name} not found"),
+        }
+    }
+}
+
+impl From<std::io::Error> for XMLSSError {
+    fn from(e: std::io::Error) -> Self {
+        XMLSSError::Io(e)
+    }
+}
+
+impl From<xml::XMLError> for XMLSSError {
+    fn from(e: xml::XMLError) -> Self {
+        XMLSSError::Xml(e)
+    }
+}
+
+pub struct XMLSSMap {
+    pub filename: String,
+    pub worksheet: String,
+    pub map: CellMap,
+}
+
+fn is_ss(name: &xml::QName, local_name: &str) -> bool {
+    name.is(SPREADSHEET_NAMESPACE, local_name)
+}
+
+// Attributes are usually written as `ss:Index`, but some producers leave them unprefixed
+fn is_ss_attribute(name: &xml::QName, local_name: &str) -> bool {
+    name.local_name == local_name
+        && (name.namespace.is_none() || name.namespace.as_deref() == Some(SPREADSHEET_NAMESPACE))
+}
+
+// 1-based index to 0-based position
+fn parse_index(value: &str, limit: usize) -> Option<usize> {
+    Some(
+        usize::from_str(value.trim())
+            .ok()?
+            .checked_sub(1)?
+            .min(limit),
+    )
+}
+
+// Dates are written as `2024-03-01T12:00:00.000`
+fn cell_value(data_type: &str, text: String) -> Cell {
+    match data_type {
+        "Number" => match f64::from_str(text.trim()) {
+            Ok(number) => Cell::Number(number),
+            Err(_) => Cell::Text(text),
+        },
+        "Boolean" => Cell::Bool(text.trim() == "1"),
+        "DateTime" => match DateTime::parse(text.trim()) {
+            Some(datetime) => Cell::DateTime(datetime),
+            None => Cell::Text(text),
+        },
+        "Error" => Cell::Error(text),
+        _ => Cell::Text(text),
+    }
+}
+
+// Reads the worksheet with the given name, or the first one. `filename` is only used as a label
+pub fn read_xmlss_data(
+    filename: &str,
+    data: &[u8],
+    worksheet_name: Option<&str>,
+) -> Result<XMLSSMap, XMLSSError> {
+    let data = xml::to_utf8(data)?;
+    let mut parser = xml::PullParser::new(&data);
+
+    let mut map = HashMap::new();
+    let mut found_workbook = false;
+
+    // Name of the worksheet being read, and whether it's the one wanted
+    let mut current_name = String::new();
+    let mut selected: Option<String> = None;
+    let mut reading = false;
+
+    let mut row = 0;
+    let mut next_row = 0;
+
+    let mut col = 0;
+    let mut next_col = 0;
+    let mut merge_across = 0;
+
+    let mut data_type = String::new();
+    let mut text = String::new();
+    let mut formula = String::new();
+    let mut has_data = false;
+
+    while let Some(event) = parser.next() {
+        let depth = parser.open_elements().len();
+
+        match event? {
+            xml::Event::StartElement(name) if depth == 1 => {
+                if !is_ss(&name, "Workbook") {
+                    return Err(XMLSSError::NotAWorkbook);
+                }
+
+                found_workbook = true;
+            }
+            xml::Event::StartElement(name) if is_ss(&name, "Worksheet") => {
+                current_name.clear();
+            }
+            xml::Event::Attribute { name, value }
+                if is_ss_attribute(&name, "Name") && parser.path_ends_with(&["Worksheet"]) =>
+            {
+                current_name.push_str(&value);
+            }
+            xml::Event::StartElement(name)
+                if is_ss(&name, "Table") && parser.path_ends_with(&["Worksheet", "Table"]) =>
+            {
+                reading = selected.is_none()
+                    && worksheet_name.is_none_or(|wanted| wanted == current_name);
+
+                if reading {
+                    selected = Some(current_name.clone());
+                }
+
+                next_row = 0;
+            }
+            xml::Event::EndElement(name) if is_ss(&name, "Table") => reading = false,
+            _ if !reading => {}
+            xml::Event::StartElement(name) if is_ss(&name, "Row") => {
+                row = next_row;
+                next_col = 0;
+            }
+            xml::Event::Attribute { name, value } if parser.path_ends_with(&["Table", "Row"]) => {
+                if is_ss_attribute(&name, "Index")
+                    && let Some(index) = parse_index(&value, MAX_ROWS)
+                {
+                    row = index;
+                }
+            }
+            xml::Event::EndElement(name) if is_ss(&name, "Row") => {
+                next_row = (row + 1).min(MAX_ROWS)
+            }
+            xml::Event::StartElement(name) if is_ss(&name, "Cell") => {
+                col = next_col;
+                merge_across = 0;
+                formula.clear();
+                has_data = false;
+            }
+            xml::Event::Attribute { name, value } if parser.path_ends_with(&["Row", "Cell"]) => {
+                if is_ss_attribute(&name, "Index")
+                    && let Some(index) = parse_index(&value, MAX_COLS)
+                {
+                    col = index;
+                } else if is_ss_attribute(&name, "MergeAcross") {
+                    merge_across = usize::from_str(value.trim()).unwrap_or(0).min(MAX_COLS);
+                } else if is_ss_attribute(&name, "Formula") {
+                    formula.push_str(&value);
+                }
+            }
+            xml::Event::StartElement(name)
+                if is_ss(&name, "Data") && parser.path_ends_with(&["Cell", "Data"]) =>
+            {
+                has_data = true;
+                data_type.clear();
+                text.clear();
+            }
+            xml::Event::Attribute { name, value }
+                if is_ss_attribute(&name, "Type") && parser.path_ends_with(&["Cell", "Data"]) =>
+            {
+                data_type.push_str(&value);
+            }
+            // Rich text has html elements inside Data, all their text is kept
+            xml::Event::Text { value, .. }
+                if parser.open_elements().iter().any(|e| is_ss(e, "Data")) =>
+            {
+                text.push_str(&value);
+            }
+            xml::Event::EndElement(name) if is_ss(&name, "Cell") => {
+                let value = if has_data && !text.is_empty() {
+                    cell_value(&data_type, std::mem::take(&mut text))
+                } else {
+                    Cell::Empty
+                };
+
+                if row >= MAX_ROWS || col >= MAX_COLS {
+                    // Past the end of the sheet
+                } else if !formula.is_empty() {
+                    let formula = std::mem::take(&mut formula);
+
+                    map.insert(
+                        (row, col),
+                        Cell::Formula {
+                            formula,
+                            value: Box::new(value),
+                        },
+                    );
+                } else if !value.is_empty() {
+                    map.insert((row, col), value);
+                }
+
+                next_col = (col + 1 + merge_across).min(MAX_COLS);
+            }
+            _ => {}
+        }
+    }
+
+    if !found_workbook {
+        return Err(XMLSSError::NotAWorkbook);
+    }
+
+    match selected {
+        Some(worksheet) => Ok(XMLSSMap {
+            filename: filename.to_string(),
+            worksheet,
+            map,
+        }),
+        None => Err(XMLSSError::WorksheetNotFound(
+            worksheet_name.unwrap_or("").to_string(),
+        )),
+    }
+}
+
+pub fn read_xmlss_file_as_hashmap(
+    filename: &str,
+    worksheet_name: Option<&str>,
+) -> Result<XMLSSMap, XMLSSError> {
+    let data = fs::read(filename)?;
+
+    read_xmlss_data(filename, &data, worksheet_name)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_read_xmlss() {
+        let data = br#"<?xml version="1.0"?>
+<?mso-application progid="Excel.Sheet"?>
+<Workbook xmlns="urn:schemas-microsoft-com:office:spreadsheet"
+ xmlns:ss="urn:schemas-microsoft-com:office:spreadsheet"
+ xmlns:html="http://www.w3.org/TR/REC-html40">
+ <Worksheet ss:Name="First">
+  <Table>
+   <Row>
+    <Cell><Data ss:Type="String">Name</Data></Cell>
+    <Cell ss:Index="3"><Data ss:Type="Number">2.5</Data></Cell>
+    <Cell><Data ss:Type="Boolean">1</Data></Cell>
+    <Cell ss:Formula="=RC[-2]*2"><Data ss:Type="Number">5</Data></Cell>
+   </Row>
+   <Row ss:Index="4">
+    <Cell ss:MergeAcross="1"><ss:Data ss:Type="String"><html:B>bold</html:B> text</ss:Data></Cell>
+    <Cell><Data ss:Type="String">after merge</Data></Cell>
+    <Cell><Data ss:Type="String"></Data></Cell>
+   </Row>
+   <Row>
+    <Cell><Data ss:Type="Number">5</Data></Cell>
+    <Cell><Data ss:Type="DateTime">2024-03-01T12:00:00.000</Data></Cell>
+    <Cell><Data ss:Type="Error">#N/A</Data></Cell>
+   </Row>
+  </Table>
+ </Worksheet>
+ <Worksheet ss:Name="Second">
+  <Table><Row><Cell><Data ss:Type="String">other</Data></Cell></Row></Table>
+ </Worksheet>
+</Workbook>"#;
+
+        let xmlss = read_xmlss_data("book.xml", data, None).unwrap();
+
+        let mut cells = xmlss.map.into_iter().collect::<Vec<_>>();
+        cells.sort_by_key(|&(position, _)| position);
+
+        assert_eq!(xmlss.worksheet, "First");
+        assert_eq!(
+            cells,
+            [
+                ((0, 0), Cell::text("Name")),
+                ((0, 2), Cell::Number(2.5)),
+                ((0, 3), Cell::Bool(true)),
+                (
+                    (0, 4),
+                    Cell::Formula {
+                        formula: String::from("=RC[-2]*2"),
+                        value: Box::new(Cell::Number(5.0)),
+                    }
+                ),
+                ((3, 0), Cell::text("bold text")),
+                ((3, 2), Cell::text("after merge")),
+                ((4, 0), Cell::Number(5.0)),
+                (
+                    (4, 1),
+                    Cell::DateTime(DateTime::parse("2024-03-01T12:00:00").unwrap())
+                ),
+                ((4, 2), Cell::Error(String::from("#N/A"))),
+            ]
+        );
+
+        let xmlss = read_xmlss_data("book.xml", data, Some("Second")).unwrap();
+
+        assert_eq!(xmlss.worksheet, "Second");
+        assert_eq!(xmlss.map[&(0, 0)], Cell::text("other"));
+
+        // Positions past the end of the sheet are dropped
+        let huge = br#"<Workbook xmlns="urn:schemas-microsoft-com:office:spreadsheet"
+ xmlns:ss="urn:schemas-microsoft-com:office:spreadsheet">
+ <Worksheet ss:Name="Huge"><Table>
+  <Row ss:Index="18446744073709551615">
+   <Cell ss:Index="18446744073709551615" ss:MergeAcross="18446744073709551615"><Data ss:Type="Number">1</Data></Cell>
+   <Cell><Data ss:Type="Number">2</Data></Cell>
+  </Row>
+  <Row><Cell ss:MergeAcross="18446744073709551615"><Data ss:Type="Number">3</Data></Cell></Row>
+ </Table></Worksheet>
+</Workbook>"#;
+
+        let xmlss = read_xmlss_data("huge.xml", huge, None).unwrap();
+
+        assert!(xmlss.map.is_empty());
+
+        assert!(matches!(
+            read_xmlss_data("book.xml", data, Some("Third")),
+            Err(XMLSSError::WorksheetNotFound(_))
+        ));
+        assert!(matches!(
+            read_xmlss_data("page.html", b"<html/>", None),
+            Err(XMLSSError::NotAWorkbook)
+        ));
+    }
+}